@@ -0,0 +1,139 @@
+use std::mem;
+
+use futures::{Future, Async};
+use tk_http::Status;
+use tk_http::server::{Codec, Error, RecvMode};
+use tk_http::server as http;
+use void::{self, Void};
+
+use crate::default_error_page::{error_page, error_page_body};
+use crate::incoming::{Request, Reply, Encoder, Context, AuthHeaders};
+
+
+pub enum Verdict {
+    /// Request is allowed, headers are added to the request
+    Allow(Vec<(String, Vec<u8>)>),
+    /// Credentials are missing or invalid, string is a reason for debug
+    Unauthorized(String),
+    /// Authorization can't be done, e.g. authorization server is down
+    Error(Status),
+}
+
+/// An authorization decision that is not known at the time headers are
+/// received
+pub struct Deferred {
+    future: Box<dyn Future<Item=Verdict, Error=Void>>,
+    headers: AuthHeaders,
+}
+
+enum State {
+    Pending(Box<dyn Future<Item=Verdict, Error=Void>>),
+    Allowed,
+    Rejected(Verdict),
+    Done,
+}
+
+/// Codec that holds request body until authorization is done
+///
+/// Handler is created before authorization is complete, but it doesn't
+/// get a single byte of the body (and so can't start its job) until the
+/// request is allowed.
+struct Gate<S> {
+    state: State,
+    headers: AuthHeaders,
+    inner: Request<S>,
+    context: Option<Context>,
+}
+
+
+impl Deferred {
+    pub fn new<F>(future: F, headers: &AuthHeaders) -> Deferred
+        where F: Future<Item=Verdict, Error=Void> + 'static,
+    {
+        Deferred {
+            future: Box::new(future),
+            headers: headers.clone(),
+        }
+    }
+    /// Wraps handler's codec, context is used to serve error page
+    pub fn wrap<S: 'static>(self, inner: Request<S>, context: Context)
+        -> Request<S>
+    {
+        Box::new(Gate {
+            state: State::Pending(self.future),
+            headers: self.headers,
+            inner: inner,
+            context: Some(context),
+        })
+    }
+}
+
+impl<S> Gate<S> {
+    fn poll_verdict(&mut self) -> Async<()> {
+        let verdict = match self.state {
+            State::Pending(ref mut future) => match future.poll() {
+                Ok(Async::Ready(verdict)) => verdict,
+                Ok(Async::NotReady) => return Async::NotReady,
+                Err(e) => void::unreachable(e),
+            },
+            _ => return Async::Ready(()),
+        };
+        self.state = match verdict {
+            Verdict::Allow(headers) => {
+                self.headers.set(headers);
+                State::Allowed
+            }
+            verdict => State::Rejected(verdict),
+        };
+        Async::Ready(())
+    }
+}
+
+impl<S: 'static> Codec<S> for Gate<S> {
+    type ResponseFuture = Reply<S>;
+    fn recv_mode(&mut self) -> RecvMode {
+        self.inner.recv_mode()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        if self.poll_verdict().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+        match self.state {
+            State::Allowed => self.inner.data_received(data, end),
+            // Body of rejected request is discarded
+            _ => Ok(Async::Ready(data.len())),
+        }
+    }
+    fn start_response(&mut self, e: http::Encoder<S>) -> Reply<S> {
+        match mem::replace(&mut self.state, State::Done) {
+            State::Allowed => {
+                self.state = State::Allowed;
+                self.inner.start_response(e)
+            }
+            State::Rejected(verdict) => {
+                let (cfg, mut debug) = self.context.take()
+                    .expect("start response called once");
+                match verdict {
+                    Verdict::Unauthorized(reason) => {
+                        debug.set_deny(reason);
+                        let mut e = Encoder::new(e, (cfg, debug));
+                        e.status(Status::Unauthorized);
+                        e.add_header("WWW-Authenticate",
+                                     "Basic realm=\"swindon\"");
+                        Box::new(error_page_body(Status::Unauthorized, e))
+                    }
+                    Verdict::Error(status) => {
+                        Box::new(error_page(status,
+                            Encoder::new(e, (cfg, debug))))
+                    }
+                    Verdict::Allow(..) => unreachable!(),
+                }
+            }
+            State::Pending(..) | State::Done => {
+                unreachable!("response started before request is received");
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::str::from_utf8;
+use std::sync::Arc;
+
+use futures::Future;
+use futures::future::{ok, err, loop_fn, Loop, Either};
+use tk_http::Status;
+use tk_http::server::{Error, Head};
+use tokio_core::reactor::Timeout;
+use void::Void;
+
+use crate::authorizers::{Deferred, Verdict};
+use crate::base64;
+use crate::config::ldap::Ldap;
+use crate::incoming::Input;
+use crate::ldap::{self, Connection, Filter, parse_filter};
+
+
+/// Special attribute list meaning "do not return any attributes" (RFC 4511)
+const NO_ATTRIBUTES: &str = "1.1";
+const VALUE_SEPARATOR: &str = ", ";
+
+
+pub fn check(cfg: &Arc<Ldap>, input: &mut Input) -> Result<Deferred, Error> {
+    let dest = input.config.ldap_destinations.get(&cfg.destination);
+    let future: Box<dyn Future<Item=Verdict, Error=Void>> =
+        match (credentials(input.headers), dest) {
+            (Some((login, password)), Some(dest)) => {
+                let timeout = Timeout::new(dest.max_request_timeout,
+                                           input.handle)
+                    .expect("can always create timeout");
+                let dest_name = cfg.destination.clone();
+                Box::new(authorize(cfg.clone(), login, password,
+                    ldap::connect(&dest.addresses,
+                        &input.runtime.resolver, input.handle))
+                    .select2(timeout)
+                    .then(move |res| Ok(match res {
+                        Ok(Either::A((verdict, _))) => verdict,
+                        Ok(Either::B(((), _))) => {
+                            error!("LDAP request to {} timed out",
+                                dest_name);
+                            Verdict::Error(Status::GatewayTimeout)
+                        }
+                        Err(Either::A((e, _))) => {
+                            error!("LDAP request to {} failed: {}",
+                                dest_name, e);
+                            Verdict::Error(Status::ServiceUnavailable)
+                        }
+                        Err(Either::B((e, _))) => {
+                            error!("Timer error: {}", e);
+                            Verdict::Error(Status::InternalServerError)
+                        }
+                    })))
+            }
+            (None, _) => {
+                Box::new(ok(Verdict::Unauthorized(
+                    "no-credentials".into())))
+            }
+            (Some(_), None) => {
+                // config validator ensures this doesn't happen
+                error!("No LDAP destination {}", cfg.destination);
+                Box::new(ok(Verdict::Error(Status::InternalServerError)))
+            }
+        };
+    Ok(Deferred::new(future, &input.auth_headers))
+}
+
+/// Returns login and password from `Authorization: Basic` header
+fn credentials(head: &Head) -> Option<(String, String)> {
+    let value = head.headers()
+        .find(|&(name, _)| name.eq_ignore_ascii_case("Authorization"))
+        .and_then(|(_, value)| from_utf8(value).ok())?
+        .trim();
+    if value.len() < 6 || !value[..6].eq_ignore_ascii_case("basic ") {
+        return None;
+    }
+    let decoded = base64::decode(value[6..].trim().as_bytes())
+        .and_then(|x| String::from_utf8(x).ok())?;
+    let mut pair = decoded.splitn(2, ':');
+    let login = pair.next()?;
+    let password = pair.next()?;
+    // Empty password means "unauthenticated bind" which always succeeds
+    if login.len() == 0 || password.len() == 0 {
+        return None;
+    }
+    Some((login.to_string(), password.to_string()))
+}
+
+fn authorize<F>(cfg: Arc<Ldap>, login: String, password: String, conn: F)
+    -> Box<dyn Future<Item=Verdict, Error=ldap::Error>>
+    where F: Future<Item=Connection, Error=ldap::Error> + 'static,
+{
+    let user_filter = Filter::Equal(cfg.login_attribute.clone(),
+                                    login.clone());
+    let c1 = cfg.clone();
+    Box::new(conn
+        .and_then(move |conn| {
+            conn.search(&c1.search_base, &user_filter, &[NO_ATTRIBUTES])
+        })
+        .and_then(move |(conn, mut entries)| {
+            if entries.len() != 1 {
+                debug!("LDAP: {} entries found for {:?}",
+                    entries.len(), login);
+                return Either::A(ok(Verdict::Unauthorized(
+                    format!("ldap-no-user {}", login))));
+            }
+            let dn = entries.pop().unwrap().dn;
+            let checked: Box<dyn Future<Item=(Connection, bool),
+                                        Error=ldap::Error>> =
+                match cfg.password_attribute {
+                    Some(ref attr) => conn.compare(&dn, attr, &password),
+                    None => Box::new(conn.bind(&dn, &password)
+                        .and_then(|(conn, res)| match res.code {
+                            ldap::SUCCESS => Ok((conn, true)),
+                            ldap::INVALID_CREDENTIALS => Ok((conn, false)),
+                            code => Err(ldap::Error::Ldap(code, res.message)),
+                        })),
+                };
+            Either::B(checked.and_then(move |(conn, valid)| {
+                if !valid {
+                    return Either::A(ok(Verdict::Unauthorized(
+                        format!("ldap-invalid-password {}", login))));
+                }
+                Either::B(queries(conn, cfg, login, dn)
+                    .map(Verdict::Allow))
+            }))
+        }))
+}
+
+/// Runs `additional-queries` and makes headers out of the results
+fn queries(conn: Connection, cfg: Arc<Ldap>, login: String, dn: String)
+    -> Box<dyn Future<Item=Vec<(String, Vec<u8>)>, Error=ldap::Error>>
+{
+    let mut headers = Vec::new();
+    if let Some(ref name) = cfg.login_header {
+        headers.push((name.clone(), login.as_bytes().to_vec()));
+    }
+    let names = cfg.additional_queries.keys().cloned().collect::<Vec<_>>();
+    Box::new(loop_fn((conn, names, headers),
+                     move |(conn, mut names, mut headers)| {
+        let name = match names.pop() {
+            Some(name) => name,
+            None => {
+                return Either::A(conn.unbind()
+                    .then(move |_| Ok(Loop::Break(headers))));
+            }
+        };
+        let query = &cfg.additional_queries[&name];
+        let filter = match parse_filter(&query.filter) {
+            Ok(filter) => {
+                filter.substitute(&[("dn", &dn), ("login", &login)])
+            }
+            Err(e) => {
+                // config validator ensures this doesn't happen
+                error!("Bad LDAP filter {:?}: {}", query.filter, e);
+                return Either::B(Either::A(err(
+                    ldap::Error::Protocol("bad filter"))));
+            }
+        };
+        let fetch_dn = query.fetch_attribute.eq_ignore_ascii_case("dn");
+        let attr = if fetch_dn {
+            NO_ATTRIBUTES.to_string()
+        } else {
+            query.fetch_attribute.clone()
+        };
+        let strip_base = query.dn_attribute_strip_base.clone();
+        let search = conn.search(&query.search_base, &filter, &[&attr]);
+        Either::B(Either::B(search
+            .map(move |(conn, entries)| {
+                let mut values = Vec::new();
+                for entry in entries {
+                    if fetch_dn {
+                        values.push(entry.dn);
+                    } else {
+                        values.extend(entry.attributes.into_iter()
+                            .filter(|&(ref a, _)| {
+                                a.eq_ignore_ascii_case(&attr)
+                            })
+                            .flat_map(|(_, vals)| vals));
+                    }
+                }
+                let value = values.iter()
+                    .map(|v| match strip_base {
+                        Some(ref base) => strip_dn_base(v, base),
+                        None => &v[..],
+                    })
+                    .collect::<Vec<_>>()
+                    .join(VALUE_SEPARATOR);
+                headers.push((name, value.into_bytes()));
+                Loop::Continue((conn, names, headers))
+            })))
+    }))
+}
+
+/// Strips `,<base>` suffix of the distinguished name and the attribute name
+/// of the remaining relative name, i.e. `cn=admins,cn=Group,dc=example,dc=org`
+/// becomes `admins` when stripping `cn=Group,dc=example,dc=org`
+///
+/// Values that are not within the base are returned as is.
+fn strip_dn_base<'x>(value: &'x str, base: &str) -> &'x str {
+    let suffix_start = value.len().saturating_sub(base.len() + 1);
+    if value.len() <= base.len() ||
+        !value.is_char_boundary(suffix_start) ||
+        !value[suffix_start..].starts_with(',') ||
+        !value[suffix_start+1..].eq_ignore_ascii_case(base)
+    {
+        return value;
+    }
+    let rdn = &value[..suffix_start];
+    if rdn.contains(',') || rdn.contains('+') {
+        return rdn;
+    }
+    match rdn.find('=') {
+        Some(idx) => &rdn[idx+1..],
+        None => rdn,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::strip_dn_base;
+
+    #[test]
+    fn strip_base() {
+        let base = "cn=Group,dc=example,dc=org";
+        assert_eq!(strip_dn_base("cn=admins,cn=Group,dc=example,dc=org",
+                                 base),
+                   "admins");
+        assert_eq!(strip_dn_base("CN=admins,CN=group,DC=example,DC=org",
+                                 base),
+                   "admins");
+        assert_eq!(strip_dn_base("cn=x,ou=y,cn=Group,dc=example,dc=org",
+                                 base),
+                   "cn=x,ou=y");
+        assert_eq!(strip_dn_base("cn=admins,dc=other,dc=org", base),
+                   "cn=admins,dc=other,dc=org");
+        assert_eq!(strip_dn_base(base, base), base);
+    }
+}
@@ -1 +1,5 @@
 pub mod source_ip;
+pub mod ldap;
+mod deferred;
+
+pub use self::deferred::{Deferred, Verdict};
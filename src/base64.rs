@@ -68,3 +68,37 @@ impl<'a> fmt::Display for Base64<'a> {
         Ok(())
     }
 }
+
+/// Decodes standard base64 (with padding), returns `None` on invalid input
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    let data = match data.iter().position(|&x| x == b'=') {
+        Some(pos) if data.len() - pos <= 2 &&
+                     data[pos..].iter().all(|&x| x == b'=') => &data[..pos],
+        Some(_) => return None,
+        None => data,
+    };
+    if data.len() % 4 == 1 {
+        return None;
+    }
+    let mut result = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc = 0u32;
+    for (idx, &c) in data.iter().enumerate() {
+        let val = STANDARD_CHARS.iter().position(|&x| x == c)? as u32;
+        acc = acc << 6 | val;
+        if idx % 4 == 3 {
+            result.push((acc >> 16) as u8);
+            result.push((acc >> 8) as u8);
+            result.push(acc as u8);
+            acc = 0;
+        }
+    }
+    match data.len() % 4 {
+        2 => result.push((acc >> 4) as u8),
+        3 => {
+            result.push((acc >> 10) as u8);
+            result.push((acc >> 2) as u8);
+        }
+        _ => {}
+    }
+    Some(result)
+}
@@ -3,7 +3,7 @@ use std::mem;
 use std::sync::Arc;
 
 use futures::Async;
-use futures::future::{FutureResult, ok};
+use futures::future::ok;
 use tk_http::{Status, Version};
 use tk_http::client as http;
 use serde::ser::Serialize;
@@ -19,6 +19,7 @@ use crate::chat::replication::{RemotePool, RemoteAction};
 use crate::chat::tangle_auth::{TangleAuth, SwindonAuth};
 use crate::config::SessionPool;
 use crate::config::http_destinations::Destination;
+use crate::http_pools::{HttpFuture, BackendCodec};
 use crate::runtime::{ServerId};
 use crate::intern::SessionId;
 use crate::proxy::{Response};
@@ -195,8 +196,10 @@ fn write_json_request<S, E>(mut e: http::Encoder<S>, data: &E)
 }
 
 
-impl<S> http::Codec<S> for AuthCodec {
-    type Future = FutureResult<http::EncoderDone<S>, http::Error>;
+impl BackendCodec for AuthCodec {}
+
+impl<S: 'static> http::Codec<S> for AuthCodec {
+    type Future = HttpFuture<S>;
 
     fn start_write(&mut self, mut e: http::Encoder<S>) -> Self::Future {
         use self::AuthState::*;
@@ -210,8 +213,8 @@ impl<S> http::Codec<S> for AuthCodec {
             self.add_request_id(&mut e);
             e.add_header("User-Agent", format!(
                 "swindon/{}", env!("CARGO_PKG_VERSION"))).unwrap();
-            ok(write_json_request(e,
-                &Auth(&self.conn_id, &self.server_id, &i)))
+            Box::new(ok(write_json_request(e,
+                &Auth(&self.conn_id, &self.server_id, &i))))
         } else {
             panic!("wrong state");
         }
@@ -311,8 +314,10 @@ impl<S> http::Codec<S> for AuthCodec {
     }
 }
 
-impl<S> http::Codec<S> for CallCodec {
-    type Future = FutureResult<http::EncoderDone<S>, http::Error>;
+impl BackendCodec for CallCodec {}
+
+impl<S: 'static> http::Codec<S> for CallCodec {
+    type Future = HttpFuture<S>;
 
     fn start_write(&mut self, mut e: http::Encoder<S>) -> Self::Future {
         use self::CallState::*;
@@ -332,7 +337,7 @@ impl<S> http::Codec<S> for CallCodec {
             let done = write_json_request(e, &Call(
                 &*self.meta, &self.conn_id, &self.server_id, &args, &kw));
             self.state = Wait;
-            ok(done)
+            Box::new(ok(done))
         } else {
             panic!("wrong state");
         }
@@ -413,8 +418,10 @@ impl<S> http::Codec<S> for CallCodec {
     }
 }
 
-impl<S> http::Codec<S> for InactivityCodec {
-    type Future = FutureResult<http::EncoderDone<S>, http::Error>;
+impl BackendCodec for InactivityCodec {}
+
+impl<S: 'static> http::Codec<S> for InactivityCodec {
+    type Future = HttpFuture<S>;
 
     fn start_write(&mut self, mut e: http::Encoder<S>) -> Self::Future {
         e.request_line("POST", &self.path, Version::Http11);
@@ -435,7 +442,7 @@ impl<S> http::Codec<S> for InactivityCodec {
         e.add_length(INACTIVITY_PAYLOAD.len() as u64).unwrap();
         e.done_headers().unwrap();
         e.write_body(INACTIVITY_PAYLOAD);
-        ok(e.done())
+        Box::new(ok(e.done()))
     }
     fn headers_received(&mut self, _: &http::Head)
         -> Result<http::RecvMode, http::Error>
@@ -47,6 +47,12 @@ impl fmt::Display for Cid {
     }
 }
 
+impl fmt::Display for PubCid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.1, self.0)
+    }
+}
+
 impl FromStr for PubCid {
     type Err = ();
 
@@ -4,4 +4,22 @@ pub enum CloseReason {
     PoolStopped,
     /// Closed by peer, we just propagate the message here
     PeerClose(u16, String),
+    /// Outbound queue of the connection is full
+    SlowConsumer,
+    /// Client repeatedly exceeded rate limits
+    RateLimited,
+    /// Connection or session is disconnected by backend
+    Backend(u16, String),
+}
+
+impl CloseReason {
+    /// Code and reason of the close frame if we close the websocket
+    pub fn close_frame(&self) -> Option<(u16, &str)> {
+        match *self {
+            CloseReason::SlowConsumer => Some((1008, "slow_consumer")),
+            CloseReason::RateLimited => Some((1008, "rate_limit")),
+            CloseReason::Backend(code, ref reason) => Some((code, reason)),
+            _ => None,
+        }
+    }
 }
@@ -1,24 +1,265 @@
-use futures::sync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
+//! Outbound message queue of a websocket connection
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 
-use crate::chat::{ConnectionMessage};
+use futures::{Async, Stream};
+use futures::task::{self, Task};
 
-pub type Receiver = UnboundedReceiver<ConnectionMessage>;
+use crate::chat::{ConnectionMessage, CloseReason};
+use crate::config::QueueOverflow;
+use crate::intern::Lattice as Namespace;
+use crate::metrics::Counter;
 
-#[derive(Clone)]
-pub struct ConnectionSender {
-    sender: UnboundedSender<ConnectionMessage>,
+lazy_static! {
+    pub static ref SLOW_CONSUMERS: Counter = Counter::new();
+}
+
+
+struct Inner {
+    messages: VecDeque<ConnectionMessage>,
+    limit: Option<(usize, QueueOverflow)>,
+    senders: usize,
+    receiver_alive: bool,
+    /// Queue limit was exceeded at least once
+    overflowed: bool,
+    /// Connection is closed as a slow consumer, the rest is discarded
+    closing: bool,
+    reader: Option<Task>,
+}
+
+pub struct ConnectionSender(Arc<Mutex<Inner>>);
+
+/// Yields messages to be sent to the websocket
+pub struct ConnectionReceiver(Arc<Mutex<Inner>>);
+
+
+fn channel(limit: Option<(usize, QueueOverflow)>)
+    -> (ConnectionSender, ConnectionReceiver)
+{
+    let inner = Arc::new(Mutex::new(Inner {
+        messages: VecDeque::new(),
+        limit: limit,
+        senders: 1,
+        receiver_alive: true,
+        overflowed: false,
+        closing: false,
+        reader: None,
+    }));
+    (ConnectionSender(inner.clone()), ConnectionReceiver(inner))
 }
 
 impl ConnectionSender {
-    pub fn new() -> (ConnectionSender, Receiver) {
-        let (tx, rx) = unbounded();
-        (ConnectionSender {
-            sender: tx,
-        }, rx)
+    pub fn new() -> (ConnectionSender, ConnectionReceiver) {
+        channel(None)
+    }
+    /// Creates a queue that holds at most `size` messages
+    pub fn bounded(size: usize, overflow: QueueOverflow)
+        -> (ConnectionSender, ConnectionReceiver)
+    {
+        channel(Some((size, overflow)))
     }
     pub fn send(&self, msg: ConnectionMessage) {
-        self.sender.unbounded_send(msg)
-        .map_err(|e| debug!("Error sending connection message: {}. \
-            usually these means connection has been closed to soon", e)).ok();
+        let mut inner = self.0.lock().expect("queue is not poisoned");
+        if !inner.receiver_alive {
+            debug!("Error sending connection message: receiver is gone. \
+                usually these means connection has been closed to soon");
+            return;
+        }
+        if inner.closing {
+            return;
+        }
+        inner.messages.push_back(msg);
+        if let Some((size, overflow)) = inner.limit {
+            if inner.messages.len() > size {
+                inner.overflow(size, overflow);
+            }
+        }
+        inner.reader.take().map(|t| t.notify());
+    }
+}
+
+impl Inner {
+    fn overflow(&mut self, size: usize, policy: QueueOverflow) {
+        if !self.overflowed {
+            self.overflowed = true;
+            SLOW_CONSUMERS.incr(1);
+        }
+        match policy {
+            QueueOverflow::drop_oldest => {
+                let oldest = self.messages.iter().position(|m| {
+                    matches!(*m, ConnectionMessage::Publish(..))
+                });
+                if let Some(pos) = oldest {
+                    self.messages.remove(pos);
+                    return;
+                }
+            }
+            QueueOverflow::coalesce_lattices => {
+                self.coalesce();
+                if self.messages.len() <= size {
+                    return;
+                }
+            }
+            QueueOverflow::close => {}
+        }
+        debug!("Outbound queue is full, closing connection");
+        self.messages.clear();
+        self.messages.push_back(
+            ConnectionMessage::StopSock(CloseReason::SlowConsumer));
+        self.closing = true;
+    }
+    /// Merges lattice updates into the first update of the same namespace
+    fn coalesce(&mut self) {
+        let mut result = VecDeque::<ConnectionMessage>::with_capacity(
+            self.messages.len());
+        let mut first = HashMap::<Namespace, usize>::new();
+        for msg in self.messages.drain(..) {
+            let pos = match msg {
+                ConnectionMessage::Lattice(ref ns, _) => {
+                    first.get(ns).cloned()
+                }
+                _ => None,
+            };
+            match pos {
+                Some(pos) => {
+                    let merged = result[pos].merge_lattice(&msg);
+                    debug_assert!(merged);
+                }
+                None => {
+                    if let ConnectionMessage::Lattice(ref ns, _) = msg {
+                        first.insert(ns.clone(), result.len());
+                    }
+                    result.push_back(msg);
+                }
+            }
+        }
+        self.messages = result;
+    }
+}
+
+impl Clone for ConnectionSender {
+    fn clone(&self) -> ConnectionSender {
+        self.0.lock().expect("queue is not poisoned").senders += 1;
+        ConnectionSender(self.0.clone())
+    }
+}
+
+impl Drop for ConnectionSender {
+    fn drop(&mut self) {
+        let mut inner = self.0.lock().expect("queue is not poisoned");
+        inner.senders -= 1;
+        if inner.senders == 0 {
+            inner.reader.take().map(|t| t.notify());
+        }
+    }
+}
+
+impl Stream for ConnectionReceiver {
+    type Item = ConnectionMessage;
+    type Error = ();
+    fn poll(&mut self) -> Result<Async<Option<ConnectionMessage>>, ()> {
+        let mut inner = self.0.lock().expect("queue is not poisoned");
+        if let Some(msg) = inner.messages.pop_front() {
+            return Ok(Async::Ready(Some(msg)));
+        }
+        if inner.senders == 0 {
+            return Ok(Async::Ready(None));
+        }
+        inner.reader = Some(task::current());
+        Ok(Async::NotReady)
+    }
+}
+
+impl Drop for ConnectionReceiver {
+    fn drop(&mut self) {
+        let mut inner = self.0.lock().expect("queue is not poisoned");
+        inner.receiver_alive = false;
+        inner.messages.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::collections::HashMap;
+
+    use futures::{Async, Future, Stream};
+    use futures::future::lazy;
+
+    use crate::chat::{ConnectionMessage, CloseReason};
+    use crate::config::QueueOverflow;
+    use crate::intern::{Topic, Lattice as Namespace};
+    use super::{ConnectionSender, ConnectionReceiver};
+
+    fn publish(tx: &ConnectionSender, topic: &'static str) {
+        tx.send(ConnectionMessage::Publish(Topic::from(topic),
+                                           Arc::new(json!(null)), None));
+    }
+
+    fn lattice(tx: &ConnectionSender, namespace: &'static str) {
+        tx.send(ConnectionMessage::Lattice(Namespace::from(namespace),
+                                           Arc::new(HashMap::new())));
+    }
+
+    fn collect(rx: &mut ConnectionReceiver) -> Vec<String> {
+        // polling an empty queue requires a task
+        lazy(|| {
+            let mut result = Vec::new();
+            while let Async::Ready(Some(msg)) = rx.poll()? {
+                result.push(match msg {
+                    ConnectionMessage::Publish(t, _, _) => t.to_string(),
+                    ConnectionMessage::Lattice(ns, _) => ns.to_string(),
+                    ConnectionMessage::StopSock(CloseReason::SlowConsumer)
+                    => String::from("stop"),
+                    msg => panic!("unexpected message {:?}", msg),
+                });
+            }
+            Ok::<_, ()>(result)
+        }).wait().unwrap()
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (tx, mut rx) = ConnectionSender::bounded(2,
+            QueueOverflow::drop_oldest);
+        publish(&tx, "a");
+        lattice(&tx, "b");
+        publish(&tx, "c");
+        publish(&tx, "d");
+        assert_eq!(collect(&mut rx), vec!["b", "d"]);
+        lattice(&tx, "e");
+        lattice(&tx, "f");
+        lattice(&tx, "g");
+        assert_eq!(collect(&mut rx), vec!["stop"]);
+    }
+
+    #[test]
+    fn coalesce() {
+        let (tx, mut rx) = ConnectionSender::bounded(3,
+            QueueOverflow::coalesce_lattices);
+        lattice(&tx, "a");
+        publish(&tx, "b");
+        lattice(&tx, "c");
+        lattice(&tx, "a");
+        lattice(&tx, "c");
+        assert_eq!(collect(&mut rx), vec!["a", "b", "c"]);
+        publish(&tx, "d");
+        publish(&tx, "e");
+        publish(&tx, "f");
+        publish(&tx, "g");
+        assert_eq!(collect(&mut rx), vec!["stop"]);
+        publish(&tx, "h");
+        drop(tx);
+        assert_matches!(rx.poll(), Ok(Async::Ready(None)));
+    }
+
+    #[test]
+    fn close() {
+        let (tx, mut rx) = ConnectionSender::bounded(1, QueueOverflow::close);
+        publish(&tx, "a");
+        assert_eq!(collect(&mut rx), vec!["a"]);
+        publish(&tx, "b");
+        publish(&tx, "c");
+        assert_eq!(collect(&mut rx), vec!["stop"]);
     }
 }
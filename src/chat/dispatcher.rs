@@ -9,6 +9,7 @@ use tk_http::websocket;
 use tk_http::websocket::{Error as WsError};
 use tk_http::websocket::Frame::{self, Text, Binary, Ping, Pong, Close};
 use tokio_core::reactor::Handle;
+use rmp_serde::decode::{Error as MsgpackError};
 use serde_json::{Error as JsonError};
 
 use crate::http_pools::{REQUESTS, FAILED_503};
@@ -17,9 +18,11 @@ use crate::intern::SessionId;
 use crate::config::chat::Chat;
 use crate::config::SessionPool;
 use crate::chat::{Cid, ConnectionSender, CloseReason, CONNECTIONS};
+use crate::chat::Encoding;
 use crate::chat::message::{self, Meta, Args, Kwargs};
 use crate::chat::processor::{Action, ProcessorPool, ConnectionMessage};
 use crate::chat::backend::CallCodec;
+use crate::chat::rate_limit::{Limiter, Verdict};
 use crate::chat::error::MessageError;
 use crate::chat::replication::{RemotePool, RemoteAction};
 
@@ -40,6 +43,8 @@ pub struct Dispatcher {
     pub remote: RemotePool,
     pub handle: Handle, // Does it belong here?
     pub channel: ConnectionSender,
+    pub encoding: Encoding,
+    pub rate_limit: Limiter,
 }
 
 quick_error! {
@@ -50,8 +55,13 @@ quick_error! {
             cause(e)
             from()
         }
+        Msgpack(e: MsgpackError) {
+            description(e.description())
+            cause(e)
+            from()
+        }
         Binary {
-            description("binary messages are not supported yet")
+            description("binary messages require msgpack subprotocol")
         }
     }
 }
@@ -71,9 +81,20 @@ impl websocket::Dispatcher for Dispatcher {
                     err(WsError::custom(Error::from(e)))
                 }
             },
+            Binary(data) if self.encoding == Encoding::Msgpack => {
+                match message::decode_msgpack(data) {
+                    Ok((method, meta, args, kwargs)) => {
+                        self.method_call(method, meta, args, kwargs);
+                        ok(())
+                    }
+                    Err(e) => {
+                        debug!("Message error: {}", e);
+                        err(WsError::custom(Error::from(e)))
+                    }
+                }
+            }
             Binary(_) => {
-                debug!("Binary messages are not supported yet");
-                // TODO(tailhook) better error
+                debug!("Binary message without msgpack subprotocol");
                 err(WsError::custom(Error::Binary))
             }
             Ping(_)|Pong(_) => unreachable!(),
@@ -88,7 +109,9 @@ impl websocket::Dispatcher for Dispatcher {
 }
 
 impl Dispatcher {
-    fn method_call(&self, name: String, meta: Meta, args: Args, kw: Kwargs) {
+    fn method_call(&mut self, name: String, meta: Meta,
+        args: Args, kw: Kwargs)
+    {
         let meta = Arc::new(meta);
         if !message::valid_method(&name) {
             self.channel.send(ConnectionMessage::Error(meta,
@@ -102,6 +125,24 @@ impl Dispatcher {
                     "invalid request id".to_string())));
             return;
         }
+        match self.rate_limit.check(&name) {
+            Verdict::Allow => {}
+            Verdict::Reject => {
+                debug!("Rate limit exceeded for {:?}", name);
+                self.channel.send(ConnectionMessage::Error(meta,
+                    MessageError::RateLimited));
+                return;
+            }
+            Verdict::Close => {
+                info!("Closing connection of {:?}: too many calls",
+                    self.session_id);
+                self.channel.send(ConnectionMessage::Error(meta,
+                    MessageError::RateLimited));
+                self.channel.send(ConnectionMessage::StopSock(
+                    CloseReason::RateLimited));
+                return;
+            }
+        }
         if let Some(duration) = message::get_active(&meta) {
             self.update_activity(duration);
         }
@@ -0,0 +1,48 @@
+//! Encoding of the messages of client websocket
+use serde::Serialize;
+use serde_json::to_string as json_encode;
+use rmp_serde::to_vec_named as msgpack_encode;
+use tk_http::websocket::Packet;
+
+pub const JSON_PROTOCOL: &str = "v1.swindon-lattice+json";
+pub const MSGPACK_PROTOCOL: &str = "v1.swindon-lattice+msgpack";
+
+
+/// Encoding negotiated by `Sec-WebSocket-Protocol`
+///
+/// JSON is sent in text frames and MessagePack in binary frames.
+/// Structure of messages is the same in both cases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Msgpack,
+}
+
+impl Encoding {
+    pub fn from_protocol(proto: &str) -> Option<Encoding> {
+        match proto {
+            JSON_PROTOCOL => Some(Encoding::Json),
+            MSGPACK_PROTOCOL => Some(Encoding::Msgpack),
+            _ => None,
+        }
+    }
+    pub fn protocol(&self) -> &'static str {
+        match *self {
+            Encoding::Json => JSON_PROTOCOL,
+            Encoding::Msgpack => MSGPACK_PROTOCOL,
+        }
+    }
+    pub fn packet<T: Serialize>(&self, msg: &T) -> Packet {
+        match *self {
+            Encoding::Json => {
+                Packet::Text(json_encode(msg)
+                    .expect("any data can be serialized"))
+            }
+            Encoding::Msgpack => {
+                // structs are encoded as maps, same as in json
+                Packet::Binary(msgpack_encode(msg)
+                    .expect("any data can be serialized"))
+            }
+        }
+    }
+}
@@ -54,6 +54,10 @@ quick_error! {
         PoolError {
             description("error sending message to worker pool")
         }
+        /// Call exceeds rate limits of the connection or the session
+        RateLimited {
+            description("rate limit exceeded")
+        }
     }
 }
 
@@ -96,6 +100,9 @@ impl Serialize for MessageError {
             PoolError => {
                 serializer.serialize_str("unexpected_pool_error")
             }
+            RateLimited => {
+                serializer.serialize_str("rate_limit_exceeded")
+            }
         }
     }
 }
@@ -3,8 +3,9 @@ use std::mem;
 use std::sync::Arc;
 use std::net::SocketAddr;
 
-use futures::Async;
-use futures::future::{FutureResult, ok};
+use futures::{Async, Future};
+use futures::future::ok;
+use futures::sync::oneshot;
 use tk_http::Status;
 use tk_http::server::{Dispatcher, Error, Head};
 use tk_http::server as http;
@@ -13,7 +14,8 @@ use serde_json::{self, Value as Json};
 
 use crate::intern::{Topic, Lattice as Namespace, SessionId};
 use crate::chat::cid::PubCid;
-use crate::chat::processor::Action;
+use crate::chat::CloseReason;
+use crate::chat::processor::{Action, Query};
 use crate::chat::processor::Delta;
 use crate::chat::listener::spawn::WorkerData;
 use crate::chat::replication::RemoteAction;
@@ -26,6 +28,10 @@ pub struct Handler {
 
 pub enum State {
     Query(Route),
+    /// Waiting for processor to reply to introspection request
+    Inspect(oneshot::Receiver<Option<Json>>),
+    /// Respond with JSON body
+    Reply(Status, Json),
     Done,
     Error(Status),
 }
@@ -36,10 +42,14 @@ pub struct Request {
 }
 
 pub enum Route {
-    /// `PUT /v1/connection/<conn_id>/subscriptions/<path>`
-    Subscribe(PubCid, Topic),
+    /// `PUT /v1/connection/<conn_id>/subscriptions/<path>[?since=<seq>]`
+    Subscribe(PubCid, Topic, Option<u64>),
     /// `DELETE /v1/connection/<conn_id>/subscriptions/<path>`
     Unsubscribe(PubCid, Topic),
+    /// `PUT /v1/connection/<conn_id>/subscriptions/<path>/*[?since=<seq>]`
+    SubscribePrefix(PubCid, Topic, Option<u64>),
+    /// `DELETE /v1/connection/<conn_id>/subscriptions/<path>/*`
+    UnsubscribePrefix(PubCid, Topic),
     /// `POST /v1/publish/<path>`
     Publish(Topic),
     /// `PUT /v1/connection/<conn_id>/lattices/<namespace>`
@@ -54,14 +64,61 @@ pub enum Route {
     UsersDetach(PubCid),
     /// `POST /v1/lattice/<namespace>`
     Lattice(Namespace),
+    /// `DELETE /v1/connection/<conn_id>`
+    CloseConnection(PubCid),
+    /// `DELETE /v1/session/<session_id>`
+    CloseSession(SessionId),
+    /// `POST /v1/connection/<conn_id>/message`
+    DirectConnection(PubCid),
+    /// `POST /v1/session/<session_id>/message`
+    DirectSession(SessionId),
+    /// `GET /v1/session/<session_id>`
+    InspectSession(SessionId),
+    /// `GET /v1/connection/<conn_id>`
+    InspectConnection(PubCid),
+    /// `GET /v1/topic/<path>`
+    InspectTopic(Topic),
+    /// `GET /v1/lattice/<namespace>[?session_id=<session_id>]`
+    InspectLattice(Namespace, Option<SessionId>),
+    /// `POST /v1/batch`
+    Batch,
 }
 
+/// Actions of the request to send to the processor and to the peers
+struct Actions {
+    local: Vec<Action>,
+    remote: Vec<RemoteAction>,
+}
+
+/// Single operation of the batch request
+#[derive(Deserialize)]
+struct Operation {
+    method: String,
+    path: String,
+    #[serde(default)]
+    body: Option<Json>,
+}
+
+/// Optional body of the close connection and close session requests
+#[derive(Deserialize)]
+struct CloseBody {
+    #[serde(default="default_close_code")]
+    code: u16,
+    #[serde(default="default_close_reason")]
+    reason: String,
+}
+
+fn default_close_code() -> u16 { 1000 }
+fn default_close_reason() -> String { String::from("disconnected") }
+
 impl Route {
     pub fn has_body(&self) -> bool {
         use self::Route::*;
         match *self {
             Subscribe(..) => false,
             Unsubscribe(..) => false,
+            SubscribePrefix(..) => false,
+            UnsubscribePrefix(..) => false,
             Publish(..) => true,
             LatticeSubscribe(..) => true,
             Detach(..) => false,
@@ -69,6 +126,16 @@ impl Route {
             UsersUpdate(..) => true,
             UsersDetach(..) => false,
             Lattice(..) => true,
+            // body is optional
+            CloseConnection(..) => false,
+            CloseSession(..) => false,
+            DirectConnection(..) => true,
+            DirectSession(..) => true,
+            InspectSession(..) => false,
+            InspectConnection(..) => false,
+            InspectTopic(..) => false,
+            InspectLattice(..) => false,
+            Batch => true,
         }
     }
 }
@@ -77,12 +144,21 @@ impl fmt::Display for Route {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::Route::*;
         match *self {
-            Subscribe(ref cid, ref tpc) => {
+            Subscribe(ref cid, ref tpc, None) => {
                 write!(f, "Subscribe {:#?} {:?}", cid.0, tpc)
             }
+            Subscribe(ref cid, ref tpc, Some(seq)) => {
+                write!(f, "Subscribe {:#?} {:?} since {}", cid.0, tpc, seq)
+            }
             Unsubscribe(ref cid, ref tpc) => {
                 write!(f, "Unsubscribe {:#?} {:?}", cid.0, tpc)
             }
+            SubscribePrefix(ref cid, ref prefix, _) => {
+                write!(f, "Subscribe {:#?} {:?}.*", cid.0, prefix)
+            }
+            UnsubscribePrefix(ref cid, ref prefix) => {
+                write!(f, "Unsubscribe {:#?} {:?}.*", cid.0, prefix)
+            }
             Publish(ref topic) => write!(f, "Publish {:?}", topic),
             LatticeSubscribe(ref cid, ref ns) => {
                 write!(f, "Lattice subscribe {:#?} {:?}", cid.0, ns)
@@ -100,6 +176,29 @@ impl fmt::Display for Route {
                 write!(f, "Users detach {:#?}", cid.0)
             }
             Lattice(ref ns) => write!(f, "Lattice update {:?}", ns),
+            CloseConnection(ref cid) => {
+                write!(f, "Close connection {:#?}", cid.0)
+            }
+            CloseSession(ref session_id) => {
+                write!(f, "Close session {:#?}", session_id)
+            }
+            DirectConnection(ref cid) => {
+                write!(f, "Message to connection {:#?}", cid.0)
+            }
+            DirectSession(ref session_id) => {
+                write!(f, "Message to session {:#?}", session_id)
+            }
+            InspectSession(ref session_id) => {
+                write!(f, "Inspect session {:#?}", session_id)
+            }
+            InspectConnection(ref cid) => {
+                write!(f, "Inspect connection {:#?}", cid.0)
+            }
+            InspectTopic(ref topic) => write!(f, "Inspect topic {:?}", topic),
+            InspectLattice(ref ns, _) => {
+                write!(f, "Inspect lattice {:?}", ns)
+            }
+            Batch => write!(f, "Batch"),
         }
     }
 }
@@ -115,47 +214,43 @@ impl Handler {
     }
 }
 
-impl<S> Dispatcher<S> for Handler {
+impl<S: 'static> Dispatcher<S> for Handler {
     type Codec = Request;
     fn headers_received(&mut self, headers: &Head)
         -> Result<Self::Codec, Error>
     {
         let query = match headers.path() {
             Some(path) => {
-                if !path.starts_with("/v1/") {
-                    State::Error(Status::NotFound)
-                } else {
-                    match self.dispatch(&path[4..], headers.method()) {
-                        State::Query(q) => {
-                            if q.has_body() {
-                                use crate::chat::content_type::check_json;
-                                use crate::chat::content_type::ContentType::*;
-                                let weak_type = self.wdata.settings
-                                    .weak_content_type.unwrap_or(false);
-                                match check_json(headers.headers()) {
-                                    Absent | Invalid if weak_type => {
-                                        warn!("Requests without a \
-                                            Content-Type are deprecated");
-                                        State::Query(q)
-                                    }
-                                    Absent => {
-                                        info!("Request without \
-                                            a content-type");
-                                        State::Error(Status::BadRequest)
-                                    }
-                                    Valid => State::Query(q),
-                                    Invalid => {
-                                        info!("Request with \
-                                            bad content-type");
-                                        State::Error(Status::BadRequest)
-                                    }
+                match route(path, headers.method()) {
+                    State::Query(q) => {
+                        if q.has_body() {
+                            use crate::chat::content_type::check_json;
+                            use crate::chat::content_type::ContentType::*;
+                            let weak_type = self.wdata.settings
+                                .weak_content_type.unwrap_or(false);
+                            match check_json(headers.headers()) {
+                                Absent | Invalid if weak_type => {
+                                    warn!("Requests without a \
+                                        Content-Type are deprecated");
+                                    State::Query(q)
+                                }
+                                Absent => {
+                                    info!("Request without \
+                                        a content-type");
+                                    State::Error(Status::BadRequest)
+                                }
+                                Valid => State::Query(q),
+                                Invalid => {
+                                    info!("Request with \
+                                        bad content-type");
+                                    State::Error(Status::BadRequest)
                                 }
-                            } else {
-                                State::Query(q)
                             }
+                        } else {
+                            State::Query(q)
                         }
-                        state => state,
                     }
+                    state => state,
                 }
             }
             None => {
@@ -167,7 +262,9 @@ impl<S> Dispatcher<S> for Handler {
                 info!("{:?} received {} (ip: {})",
                     self.wdata.name, route, self.addr);
             }
-            State::Done => unreachable!(),
+            State::Inspect(..) | State::Reply(..) | State::Done => {
+                unreachable!();
+            }
             State::Error(status) => {
                 info!("{:?} path {:?} gets {:?} (ip: {})",
                     self.wdata.name, headers.path(), status, self.addr);
@@ -180,8 +277,20 @@ impl<S> Dispatcher<S> for Handler {
     }
 }
 
+/// Finds route by the path with `/v1/` prefix and the query string
+fn route(path: &str, method: &str) -> State {
+    if !path.starts_with("/v1/") {
+        State::Error(Status::NotFound)
+    } else {
+        let mut iter = path[4..].splitn(2, '?');
+        let path = iter.next().unwrap();
+        let query = iter.next();
+        Handler::dispatch(path, query, method)
+    }
+}
+
 impl Handler {
-    fn dispatch(&mut self, path: &str, method: &str) -> State {
+    fn dispatch(path: &str, query: Option<&str>, method: &str) -> State {
         let mut iter = path.splitn(2, '/');
         let head = iter.next().unwrap();
         let tail = iter.next();
@@ -193,6 +302,13 @@ impl Handler {
                 let tail = p.next();
                 match middle {
                     Some("subscriptions") => {
+                        // `<prefix>/*` is for all topics in the prefix
+                        let (tail, wildcard) = match tail {
+                            Some(x) if x.ends_with("/*") => {
+                                (Some(&x[..x.len()-2]), true)
+                            }
+                            x => (x, false),
+                        };
                         let topic = tail.and_then(|x| {
                             if !x.contains('.') {
                                 x.replace("/", ".").parse().ok()
@@ -200,13 +316,24 @@ impl Handler {
                                 None
                             }
                         });
-                        match (method, cid, topic) {
-                            ("PUT", Some(cid), Some(t)) => {
-                                State::Query(Route::Subscribe(cid, t))
+                        match (method, cid, topic, wildcard) {
+                            ("PUT", Some(cid), Some(t), _) => {
+                                match parse_since(query) {
+                                    Ok(since) if wildcard => State::Query(
+                                        Route::SubscribePrefix(cid, t, since)),
+                                    Ok(since) => State::Query(
+                                        Route::Subscribe(cid, t, since)),
+                                    Err(()) => {
+                                        State::Error(Status::BadRequest)
+                                    }
+                                }
                             }
-                            ("DELETE", Some(cid), Some(t)) => {
+                            ("DELETE", Some(cid), Some(t), false) => {
                                 State::Query(Route::Unsubscribe(cid, t))
                             }
+                            ("DELETE", Some(cid), Some(t), true) => {
+                                State::Query(Route::UnsubscribePrefix(cid, t))
+                            }
                             _ => State::Error(Status::NotFound),
                         }
                     }
@@ -243,6 +370,41 @@ impl Handler {
                             _ => State::Error(Status::NotFound),
                         }
                     }
+                    Some("message") if tail.is_none() => {
+                        match (method, cid) {
+                            ("POST", Some(cid)) => {
+                                State::Query(Route::DirectConnection(cid))
+                            }
+                            _ => State::Error(Status::NotFound),
+                        }
+                    }
+                    None => {
+                        match (method, cid) {
+                            ("GET", Some(cid)) => {
+                                State::Query(Route::InspectConnection(cid))
+                            }
+                            ("DELETE", Some(cid)) => {
+                                State::Query(Route::CloseConnection(cid))
+                            }
+                            _ => State::Error(Status::NotFound),
+                        }
+                    }
+                    _ => State::Error(Status::NotFound),
+                }
+            }
+            (_, "session", Some(tail)) => {
+                let mut p = tail.splitn(2, '/');
+                let session_id = p.next().and_then(|x| x.parse().ok());
+                match (method, session_id, p.next()) {
+                    ("GET", Some(sid), None) => {
+                        State::Query(Route::InspectSession(sid))
+                    }
+                    ("DELETE", Some(sid), None) => {
+                        State::Query(Route::CloseSession(sid))
+                    }
+                    ("POST", Some(sid), Some("message")) => {
+                        State::Query(Route::DirectSession(sid))
+                    }
                     _ => State::Error(Status::NotFound),
                 }
             }
@@ -270,6 +432,35 @@ impl Handler {
                     State::Error(Status::NotFound)
                 }
             }
+            ("POST", "batch", None) => {
+                State::Query(Route::Batch)
+            }
+            ("GET", "topic", Some(tail)) => {
+                let topic = if !tail.contains('.') {
+                    tail.replace("/", ".").parse().ok()
+                } else {
+                    None
+                };
+                if let Some(topic) = topic {
+                    State::Query(Route::InspectTopic(topic))
+                } else {
+                    State::Error(Status::NotFound)
+                }
+            }
+            ("GET", "lattice", Some(tail)) => {
+                let ns = if !tail.contains('.') {
+                    tail.replace("/", ".").parse().ok()
+                } else {
+                    None
+                };
+                match (ns, parse_session_id(query)) {
+                    (Some(ns), Ok(sid)) => {
+                        State::Query(Route::InspectLattice(ns, sid))
+                    }
+                    (Some(_), Err(())) => State::Error(Status::BadRequest),
+                    (None, _) => State::Error(Status::NotFound),
+                }
+            }
             ("PUT", "user", Some(tail)) => {
                 let mut p = tail.splitn(3, '/');
                 let session_id = p.next().and_then(|x| x.parse().ok());
@@ -288,84 +479,250 @@ impl Handler {
     }
 }
 
-impl<S> http::Codec<S> for Request {
-    type ResponseFuture = FutureResult<EncoderDone<S>, Error>;
-    fn recv_mode(&mut self) -> RecvMode {
-        RecvMode::buffered_upfront(self.wdata.settings.max_payload_size)
+/// Parses close code and reason, body may be empty
+fn parse_close(data: &[u8]) -> Result<(u16, String), ()> {
+    let body = if data.len() == 0 {
+        CloseBody {
+            code: default_close_code(),
+            reason: default_close_reason(),
+        }
+    } else {
+        serde_json::from_slice(data).map_err(|e| {
+            info!("Error decoding json for close request: {:?}", e);
+        })?
+    };
+    // only codes that can be sent by endpoint, and reason must fit
+    // into a control frame
+    match body.code {
+        1000 | 3000..=4999 if body.reason.len() <= 123 => {
+            Ok((body.code, body.reason))
+        }
+        _ => {
+            info!("Invalid close code {} or reason {:?}",
+                body.code, body.reason);
+            Err(())
+        }
     }
-    fn data_received(&mut self, data: &[u8], end: bool)
-        -> Result<Async<usize>, Error>
+}
+
+/// Parses `since=<seq>` from the query string of subscribe request
+fn parse_since(query: Option<&str>) -> Result<Option<u64>, ()> {
+    let mut since = None;
+    for pair in query.unwrap_or("").split('&').filter(|x| x.len() > 0) {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("since"), Some(value)) => {
+                since = Some(value.parse().map_err(|_| ())?);
+            }
+            _ => {}
+        }
+    }
+    Ok(since)
+}
+
+/// Parses `session_id=<id>` from the query string of lattice inspection
+fn parse_session_id(query: Option<&str>) -> Result<Option<SessionId>, ()> {
+    let mut session_id = None;
+    for pair in query.unwrap_or("").split('&').filter(|x| x.len() > 0) {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("session_id"), Some(value)) => {
+                session_id = Some(value.parse().map_err(|_| ())?);
+            }
+            _ => {}
+        }
+    }
+    Ok(session_id)
+}
+
+impl Actions {
+    fn new() -> Actions {
+        Actions {
+            local: Vec::new(),
+            remote: Vec::new(),
+        }
+    }
+}
+
+impl Request {
+    /// Asks processor for the part of the pool state
+    fn inspect(&self, query: Query) -> State {
+        let (tx, rx) = oneshot::channel();
+        self.wdata.processor.send(Action::Inspect {
+            query: query,
+            server_id: self.wdata.runtime.server_id,
+            reply: tx,
+        });
+        State::Inspect(rx)
+    }
+    /// Validates all operations first, and if they are fine, applies
+    /// them within a single processor event
+    fn batch(&self, data: &[u8]) -> State {
+        let operations: Vec<Operation> = match serde_json::from_slice(data) {
+            Ok(operations) => operations,
+            Err(e) => {
+                info!("Error decoding json for '/v1/batch': {:?}", e);
+                return State::Error(Status::BadRequest);
+            }
+        };
+        let mut out = Actions::new();
+        let mut failed = false;
+        let results = operations.into_iter().map(|op| {
+            let status = match self.operation(op, &mut out) {
+                Ok(()) => Status::NoContent,
+                Err(status) => {
+                    failed = true;
+                    status
+                }
+            };
+            json!({"status": status.code()})
+        }).collect::<Vec<_>>();
+        if failed {
+            return State::Reply(Status::BadRequest, Json::from(results));
+        }
+        if out.remote.len() > 0 {
+            self.wdata.remote.send(RemoteAction::Batch {
+                actions: out.remote,
+            });
+        }
+        if out.local.len() > 0 {
+            self.wdata.processor.send(Action::Batch {
+                actions: out.local,
+            });
+        }
+        State::Reply(Status::Ok, Json::from(results))
+    }
+    fn operation(&self, op: Operation, out: &mut Actions)
+        -> Result<(), Status>
+    {
+        let route = match route(&op.path, &op.method) {
+            State::Query(route) => route,
+            State::Error(status) => return Err(status),
+            _ => unreachable!(),
+        };
+        debug!("{:?} batch operation {}", self.wdata.name, route);
+        let body = op.body
+            .map(|x| serde_json::to_vec(&x).expect("json is serializable"))
+            .unwrap_or_else(Vec::new);
+        self.actions(route, &body, out)
+    }
+    /// Converts request into actions, nothing is sent yet
+    ///
+    /// Introspection and batch requests are not allowed here
+    fn actions(&self, route: Route, data: &[u8], out: &mut Actions)
+        -> Result<(), Status>
     {
         use self::Route::*;
-        assert!(end);
         let my_srv_id = self.wdata.runtime.server_id;
-        let query = mem::replace(&mut self.state,
-                                 State::Error(Status::InternalServerError));
-        self.state = match query {
-            State::Query(Subscribe(PubCid(cid, srv_id), topic)) => {
+        match route {
+            Subscribe(PubCid(cid, srv_id), topic, since) => {
                 if data.len() == 0 {
                     if srv_id == my_srv_id {
-                        self.wdata.processor.send(Action::Subscribe {
+                        out.local.push(Action::Subscribe {
                             conn_id: cid,
                             topic: topic.clone(),
+                            since: since,
                         });
                     } else {
                         debug!("Skipping action with non-local cid");
                     }
-                    self.wdata.remote.send(RemoteAction::Subscribe {
+                    out.remote.push(RemoteAction::Subscribe {
                         conn_id: cid,
                         server_id: srv_id,
                         topic: topic,
+                        since: since,
                     });
-                    State::Done
+                    Ok(())
                 } else {
-                    State::Error(Status::BadRequest)
+                    Err(Status::BadRequest)
                 }
             }
-            State::Query(Unsubscribe(PubCid(cid, srv_id), topic)) => {
+            Unsubscribe(PubCid(cid, srv_id), topic) => {
                 if data.len() == 0 {
                     if srv_id == my_srv_id {
-                        self.wdata.processor.send(Action::Unsubscribe {
+                        out.local.push(Action::Unsubscribe {
                             conn_id: cid,
                             topic: topic.clone(),
                         });
                     } else {
                         debug!("Skipping action with non-local cid");
                     }
-                    self.wdata.remote.send(RemoteAction::Unsubscribe {
+                    out.remote.push(RemoteAction::Unsubscribe {
                         conn_id: cid,
                         server_id: srv_id,
                         topic: topic,
                     });
-                    State::Done
+                    Ok(())
+                } else {
+                    Err(Status::BadRequest)
+                }
+            }
+            SubscribePrefix(PubCid(cid, srv_id), prefix, since) => {
+                if data.len() == 0 {
+                    if srv_id == my_srv_id {
+                        out.local.push(Action::SubscribePrefix {
+                            conn_id: cid,
+                            prefix: prefix.clone(),
+                            since: since,
+                        });
+                    } else {
+                        debug!("Skipping action with non-local cid");
+                    }
+                    out.remote.push(RemoteAction::SubscribePrefix {
+                        conn_id: cid,
+                        server_id: srv_id,
+                        prefix: prefix,
+                        since: since,
+                    });
+                    Ok(())
+                } else {
+                    Err(Status::BadRequest)
+                }
+            }
+            UnsubscribePrefix(PubCid(cid, srv_id), prefix) => {
+                if data.len() == 0 {
+                    if srv_id == my_srv_id {
+                        out.local.push(Action::UnsubscribePrefix {
+                            conn_id: cid,
+                            prefix: prefix.clone(),
+                        });
+                    } else {
+                        debug!("Skipping action with non-local cid");
+                    }
+                    out.remote.push(RemoteAction::UnsubscribePrefix {
+                        conn_id: cid,
+                        server_id: srv_id,
+                        prefix: prefix,
+                    });
+                    Ok(())
                 } else {
-                    State::Error(Status::BadRequest)
+                    Err(Status::BadRequest)
                 }
             }
-            State::Query(Publish(topic)) => {
+            Publish(topic) => {
                 // TODO(tailhook) check content-type
                 match serde_json::from_slice(data) {
                     Ok(json) => {
                         // Send this Action to Replication Queue
                         let data: Arc<Json> = Arc::new(json);
-                        self.wdata.remote.send(RemoteAction::Publish {
+                        out.remote.push(RemoteAction::Publish {
                             topic: topic.clone(),
                             data: data.clone(),
                         });
-                        self.wdata.processor.send(Action::Publish {
+                        out.local.push(Action::Publish {
                             topic: topic,
                             data: data,
                         });
-                        State::Done
+                        Ok(())
                     }
                     Err(e) => {
                         info!("Error decoding json for '/v1/publish': \
                             {:?}", e);
-                        State::Error(Status::BadRequest)
+                        Err(Status::BadRequest)
                     }
                 }
             }
-            State::Query(LatticeSubscribe(PubCid(cid, srv_id), ns)) => {
+            LatticeSubscribe(PubCid(cid, srv_id), ns) => {
                 // TODO(tailhook) check content-type
                 let data: Result<Delta,_> = serde_json::from_slice(data)
                     .map_err(|e| {
@@ -375,51 +732,51 @@ impl<S> http::Codec<S> for Request {
                     });
                 match data {
                     Ok(delta) => {
-                        self.wdata.remote.send(RemoteAction::Lattice {
+                        out.remote.push(RemoteAction::Lattice {
                             namespace: ns.clone(),
                             delta: delta.clone(),
                         });
-                        self.wdata.remote.send(RemoteAction::Attach {
+                        out.remote.push(RemoteAction::Attach {
                             namespace: ns.clone(),
                             conn_id: cid,
                             server_id: srv_id,
                         });
-                        self.wdata.processor.send(Action::Lattice {
+                        out.local.push(Action::Lattice {
                             namespace: ns.clone(),
                             delta: delta,
                         });
                         if srv_id == my_srv_id {
-                            self.wdata.processor.send(Action::Attach {
+                            out.local.push(Action::Attach {
                                 namespace: ns.clone(),
                                 conn_id: cid,
                             });
                         } else {
                             debug!("Skipping action with non-local cid");
                         }
-                        State::Done
+                        Ok(())
                     }
                     Err(_) => {
-                        State::Error(Status::BadRequest)
+                        Err(Status::BadRequest)
                     }
                 }
             }
-            State::Query(Detach(PubCid(cid, srv_id), ns)) => {
-                self.wdata.remote.send(RemoteAction::Detach {
+            Detach(PubCid(cid, srv_id), ns) => {
+                out.remote.push(RemoteAction::Detach {
                     namespace: ns.clone(),
                     conn_id: cid.clone(),
                     server_id: srv_id,
                 });
                 if srv_id == my_srv_id {
-                    self.wdata.processor.send(Action::Detach {
+                    out.local.push(Action::Detach {
                         namespace: ns.clone(),
                         conn_id: cid,
                     });
                 } else {
                     debug!("Skipping action with non-local cid");
                 }
-                State::Done
+                Ok(())
             }
-            State::Query(Lattice(ns)) => {
+            Lattice(ns) => {
                 // TODO(tailhook) check content-type
                 let data: Result<Delta,_> = serde_json::from_slice(data)
                     .map_err(|e| {
@@ -430,22 +787,22 @@ impl<S> http::Codec<S> for Request {
                 match data {
                     Ok(delta) => {
                         // Send this Action to Replication Queue
-                        self.wdata.remote.send(RemoteAction::Lattice {
+                        out.remote.push(RemoteAction::Lattice {
                             namespace: ns.clone(),
                             delta: delta.clone(),
                         });
-                        self.wdata.processor.send(Action::Lattice {
+                        out.local.push(Action::Lattice {
                             namespace: ns.clone(),
                             delta: delta,
                         });
-                        State::Done
+                        Ok(())
                     }
                     Err(_) => {
-                        State::Error(Status::BadRequest)
+                        Err(Status::BadRequest)
                     }
                 }
             }
-            State::Query(UsersSubscribe(PubCid(cid, srv_id))) => {
+            UsersSubscribe(PubCid(cid, srv_id)) => {
                 // TODO(tailhook) check content-type
                 let data: Result<Vec<SessionId>,_> =
                     serde_json::from_slice(data)
@@ -456,27 +813,27 @@ impl<S> http::Codec<S> for Request {
                     });
                 match data {
                     Ok(list) => {
-                        self.wdata.remote.send(RemoteAction::AttachUsers {
+                        out.remote.push(RemoteAction::AttachUsers {
                             conn_id: cid.clone(),
                             server_id: srv_id,
                             list: list.clone(),
                         });
                         if srv_id == my_srv_id {
-                            self.wdata.processor.send(Action::AttachUsers {
+                            out.local.push(Action::AttachUsers {
                                 conn_id: cid,
                                 list: list,
                             });
                         } else {
                             debug!("Skipping action with non-local cid");
                         }
-                        State::Done
+                        Ok(())
                     }
                     Err(_) => {
-                        State::Error(Status::BadRequest)
+                        Err(Status::BadRequest)
                     }
                 }
             }
-            State::Query(UsersUpdate(session_id)) => {
+            UsersUpdate(session_id) => {
                 // TODO(tailhook) check content-type
                 let data: Result<Vec<SessionId>,_> =
                     serde_json::from_slice(data)
@@ -487,36 +844,198 @@ impl<S> http::Codec<S> for Request {
                     });
                 match data {
                     Ok(list) => {
-                        self.wdata.remote.send(RemoteAction::UpdateUsers {
+                        out.remote.push(RemoteAction::UpdateUsers {
                             session_id: session_id.clone(),
                             list: list.clone(),
                         });
-                        self.wdata.processor.send(Action::UpdateUsers {
+                        out.local.push(Action::UpdateUsers {
                             session_id: session_id,
                             list: list,
                         });
-                        State::Done
+                        Ok(())
                     }
                     Err(_) => {
-                        State::Error(Status::BadRequest)
+                        Err(Status::BadRequest)
                     }
                 }
             }
-            State::Query(UsersDetach(PubCid(cid, srv_id))) => {
-                self.wdata.remote.send(RemoteAction::DetachUsers {
+            UsersDetach(PubCid(cid, srv_id)) => {
+                out.remote.push(RemoteAction::DetachUsers {
                     conn_id: cid.clone(),
                     server_id: srv_id,
                 });
                 if srv_id == my_srv_id {
-                    self.wdata.processor.send(Action::DetachUsers {
+                    out.local.push(Action::DetachUsers {
                         conn_id: cid,
                     });
                 } else {
                     debug!("Skipping action with non-local cid");
                 }
-                State::Done
+                Ok(())
+            }
+            DirectConnection(PubCid(cid, srv_id)) => {
+                match serde_json::from_slice(data) {
+                    Ok(json) => {
+                        let data: Arc<Json> = Arc::new(json);
+                        out.remote.push(
+                            RemoteAction::DirectConnection {
+                                conn_id: cid,
+                                server_id: srv_id,
+                                data: data.clone(),
+                            });
+                        if srv_id == my_srv_id {
+                            out.local.push(
+                                Action::DirectConnection {
+                                    conn_id: cid,
+                                    data: data,
+                                });
+                        } else {
+                            debug!("Skipping action with non-local cid");
+                        }
+                        Ok(())
+                    }
+                    Err(e) => {
+                        info!("Error decoding json for \
+                            '/v1/connection/_/message': {:?}", e);
+                        Err(Status::BadRequest)
+                    }
+                }
+            }
+            DirectSession(session_id) => {
+                match serde_json::from_slice(data) {
+                    Ok(json) => {
+                        let data: Arc<Json> = Arc::new(json);
+                        out.remote.push(RemoteAction::DirectSession {
+                            session_id: session_id.clone(),
+                            data: data.clone(),
+                        });
+                        out.local.push(Action::DirectSession {
+                            session_id: session_id,
+                            data: data,
+                        });
+                        Ok(())
+                    }
+                    Err(e) => {
+                        info!("Error decoding json for \
+                            '/v1/session/_/message': {:?}", e);
+                        Err(Status::BadRequest)
+                    }
+                }
+            }
+            CloseConnection(PubCid(cid, srv_id)) => {
+                match parse_close(data) {
+                    Ok((code, reason)) => {
+                        out.remote.push(
+                            RemoteAction::CloseConnection {
+                                conn_id: cid,
+                                server_id: srv_id,
+                                code: code,
+                                reason: reason.clone(),
+                            });
+                        if srv_id == my_srv_id {
+                            out.local.push(
+                                Action::CloseConnection {
+                                    conn_id: cid,
+                                    reason: CloseReason::Backend(code, reason),
+                                });
+                        } else {
+                            debug!("Skipping action with non-local cid");
+                        }
+                        Ok(())
+                    }
+                    Err(()) => Err(Status::BadRequest),
+                }
+            }
+            CloseSession(session_id) => {
+                match parse_close(data) {
+                    Ok((code, reason)) => {
+                        out.remote.push(RemoteAction::CloseSession {
+                            session_id: session_id.clone(),
+                            code: code,
+                            reason: reason.clone(),
+                        });
+                        out.local.push(Action::CloseSession {
+                            session_id: session_id,
+                            reason: CloseReason::Backend(code, reason),
+                        });
+                        Ok(())
+                    }
+                    Err(()) => Err(Status::BadRequest),
+                }
+            }
+            InspectSession(..) | InspectConnection(..) |
+            InspectTopic(..) | InspectLattice(..) | Batch => {
+                Err(Status::BadRequest)
+            }
+        }
+    }
+}
+
+fn json_reply<S>(mut e: http::Encoder<S>, status: Status, json: &Json)
+    -> EncoderDone<S>
+{
+    let body = serde_json::to_vec(json).expect("json is serializable");
+    e.status(status);
+    e.add_header("Content-Type", "application/json").unwrap();
+    e.add_length(body.len() as u64).unwrap();
+    if e.done_headers().unwrap() {
+        e.write_body(&body);
+    }
+    e.done()
+}
+
+impl<S: 'static> http::Codec<S> for Request {
+    type ResponseFuture = Box<dyn Future<Item=EncoderDone<S>, Error=Error>>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::buffered_upfront(self.wdata.settings.max_payload_size)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        use self::Route::*;
+        assert!(end);
+        let my_srv_id = self.wdata.runtime.server_id;
+        let query = mem::replace(&mut self.state,
+                                 State::Error(Status::InternalServerError));
+        self.state = match query {
+            // Introspection requests are answered by this instance only,
+            // so nothing is replicated. Body of GET request is ignored.
+            State::Query(InspectSession(session_id)) => {
+                self.inspect(Query::Session(session_id))
+            }
+            State::Query(InspectConnection(PubCid(cid, srv_id))) => {
+                if srv_id == my_srv_id {
+                    self.inspect(Query::Connection(cid))
+                } else {
+                    debug!("Can't inspect non-local cid");
+                    State::Error(Status::NotFound)
+                }
+            }
+            State::Query(InspectTopic(topic)) => {
+                self.inspect(Query::Topic(topic))
+            }
+            State::Query(InspectLattice(ns, session_id)) => {
+                self.inspect(Query::Lattice(ns, session_id))
+            }
+            State::Query(Batch) => self.batch(data),
+            State::Query(route) => {
+                let mut out = Actions::new();
+                match self.actions(route, data, &mut out) {
+                    Ok(()) => {
+                        for action in out.remote {
+                            self.wdata.remote.send(action);
+                        }
+                        for action in out.local {
+                            self.wdata.processor.send(action);
+                        }
+                        State::Done
+                    }
+                    Err(status) => State::Error(status),
+                }
+            }
+            State::Inspect(..) | State::Reply(..) | State::Done => {
+                unreachable!();
             }
-            State::Done => unreachable!(),
             State::Error(e) => State::Error(e),
         };
         Ok(Async::Ready(data.len()))
@@ -524,16 +1043,38 @@ impl<S> http::Codec<S> for Request {
     fn start_response(&mut self, mut e: http::Encoder<S>)
         -> Self::ResponseFuture
     {
-        if let State::Error(status) = self.state {
-            e.status(status);
-            // TODO(tailhook) add some body describing the error
-            e.add_length(0).unwrap();
-            e.done_headers().unwrap();
-            ok(e.done())
-        } else {
-            e.status(Status::NoContent);
-            e.done_headers().unwrap();
-            ok(e.done())
+        match mem::replace(&mut self.state, State::Done) {
+            State::Error(status) => {
+                e.status(status);
+                // TODO(tailhook) add some body describing the error
+                e.add_length(0).unwrap();
+                e.done_headers().unwrap();
+                Box::new(ok(e.done()))
+            }
+            State::Reply(status, json) => {
+                Box::new(ok(json_reply(e, status, &json)))
+            }
+            State::Inspect(rx) => {
+                Box::new(rx.then(move |result| {
+                    match result {
+                        Ok(Some(json)) => {
+                            Ok(json_reply(e, Status::Ok, &json))
+                        }
+                        // nothing found or the pool is already stopped
+                        Ok(None) | Err(_) => {
+                            e.status(Status::NotFound);
+                            e.add_length(0).unwrap();
+                            e.done_headers().unwrap();
+                            Ok::<_, Error>(e.done())
+                        }
+                    }
+                }))
+            }
+            _ => {
+                e.status(Status::NoContent);
+                e.done_headers().unwrap();
+                Box::new(ok(e.done()))
+            }
         }
     }
 }
@@ -1,8 +1,6 @@
 use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
 
-use async_slot as slot;
-use futures::Stream;
 use futures::sync::oneshot::{Sender};
 use futures::sync::mpsc::{unbounded as channel};
 use tokio_core::reactor::Handle;
@@ -12,11 +10,11 @@ use crate::intern::SessionPoolName;
 use crate::chat::listener::spawn::{listen, WorkerData};
 use crate::chat::inactivity_handler;
 use crate::chat::processor::{Processor};
+use crate::chat::rate_limit;
 use crate::chat::Shutdown;
 use crate::chat::replication::RemoteSender;
-use crate::config::listen::Listen;
 use crate::config::{SessionPool};
-use void::Void;
+use crate::listener::Listener;
 
 
 #[derive(Clone)]
@@ -24,10 +22,11 @@ pub struct SessionPools {
     pools: Arc<RwLock<HashMap<SessionPoolName, Worker>>>,
     pub processor: Processor,
     pub remote_sender: RemoteSender,
+    pub rate_limits: rate_limit::Sessions,
 }
 
 struct Worker {
-    listener_channel: slot::Sender<Listen>,
+    listener: Listener,
     inactivity_shutter: Sender<Shutdown>,
 }
 
@@ -39,6 +38,7 @@ impl SessionPools {
             pools: Arc::new(RwLock::new(HashMap::new())),
             processor: processor,
             remote_sender: remote_sender,
+            rate_limits: rate_limit::Sessions::new(),
         }
     }
     pub fn update(&self, cfg: &HashMap<SessionPoolName, Arc<SessionPool>>,
@@ -56,7 +56,7 @@ impl SessionPools {
         for k in to_delete {
             if let Some(wrk) = pools.remove(&k) {
                 self.processor.destroy_pool(&k);
-                drop(wrk.listener_channel);
+                drop(wrk.listener);
                 wrk.inactivity_shutter.send(Shutdown).ok();
             }
         }
@@ -64,7 +64,7 @@ impl SessionPools {
         // Create new pools
         for (name, settings) in cfg {
             if let Some(ref mut pool) = pools.get(name) {
-                pool.listener_channel.swap(settings.listen.clone())
+                pool.listener.update(&settings.listen)
                     .map_err(|_| error!("Can't update addresses for {}",
                                         name))
                     .ok();
@@ -75,8 +75,9 @@ impl SessionPools {
             let in_shutter = inactivity_handler::run(
                 runtime, settings, handle, rx);
 
-            let (listen_tx, listen_rx) = slot::channel();
-            listen_tx.swap(settings.listen.clone()).unwrap();
+            let (listener, incoming) = Listener::new(&runtime.resolver,
+                &runtime.tls, settings.listen_error_timeout, handle, false);
+            listener.update(&settings.listen).unwrap();
             let wdata = Arc::new(WorkerData {
                 name: name.clone(),
                 runtime: runtime.clone(),
@@ -85,13 +86,10 @@ impl SessionPools {
                 remote: self.remote_sender.pool(name),
                 handle: handle.clone(),
             });
-            listen(
-                runtime.resolver.subscribe_stream(
-                    listen_rx.map_err(|()| -> Void { unreachable!() }), 80),
-                &wdata);
+            listen(incoming, &wdata);
 
             pools.insert(name.clone(), Worker {
-                listener_channel: listen_tx,
+                listener: listener,
                 inactivity_shutter: in_shutter,
             });
         }
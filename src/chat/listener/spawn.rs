@@ -3,10 +3,9 @@ use std::sync::Arc;
 use futures::stream::Stream;
 use tk_http;
 use tk_http::server::Proto;
-use tk_listen::{BindMany, ListenExt};
+use tk_listen::ListenExt;
 use futures::future::{Future};
 use tokio_core::reactor::{Handle};
-use ns_router::future::AddrStream;
 
 use crate::intern::SessionPoolName;
 use crate::config::SessionPool;
@@ -14,6 +13,7 @@ use crate::runtime::Runtime;
 use crate::chat::listener::codec::Handler;
 use crate::chat::processor::{ProcessorPool};
 use crate::chat::replication::RemotePool;
+use crate::listener::Incoming;
 
 
 pub struct WorkerData {
@@ -26,11 +26,9 @@ pub struct WorkerData {
     pub handle: Handle, // Does it belong here?
 }
 
-pub fn listen(addr_stream: AddrStream, worker_data: &Arc<WorkerData>) {
+pub fn listen(incoming: Incoming, worker_data: &Arc<WorkerData>) {
     let w1 = worker_data.clone();
-    let w2 = worker_data.clone();
-    let runtime = worker_data.runtime.clone();
-    let h1 = runtime.handle.clone();
+    let h1 = worker_data.runtime.handle.clone();
 
     // TODO(tailhook) how to update?
     let hcfg = tk_http::server::Config::new()
@@ -40,11 +38,15 @@ pub fn listen(addr_stream: AddrStream, worker_data: &Arc<WorkerData>) {
         .done();
 
     worker_data.handle.spawn(
-        BindMany::new(addr_stream.map(|addr| addr.addresses_at(0)), &h1)
-        .sleep_on_error(w1.settings.listen_error_timeout, &runtime.handle)
-        .map(move |(socket, saddr)| {
-             Proto::new(socket, &hcfg, Handler::new(saddr, w2.clone()), &h1)
-             .map_err(|e| debug!("Chat backend protocol error: {}", e))
+        incoming
+        .map(move |accept| {
+            let hcfg = hcfg.clone();
+            let w1 = w1.clone();
+            let h1 = h1.clone();
+            accept.and_then(move |(socket, saddr)| {
+                Proto::new(socket, &hcfg, Handler::new(saddr, w1), &h1)
+                .map_err(|e| debug!("Chat backend protocol error: {}", e))
+            })
         })
         .listen(worker_data.settings.max_connections)
         .map(move |()| error!("Replication listener exited"))
@@ -4,6 +4,7 @@
 /// ["chat.send_message", {"request_id": "123"}, ["text"], {}]
 /// ```
 use std::str;
+use rmp_serde::{self, decode::Error as MsgpackError};
 use serde_json::{self, Value as Json, Map, Error as JsonError};
 use serde::ser::{Serialize, Serializer, SerializeTuple};
 
@@ -25,6 +26,15 @@ pub fn decode_message(s: &str)
     Ok((method, meta, args, kwargs))
 }
 
+/// Decode Websocket msgpack message into Meta & Message structs.
+pub fn decode_msgpack(data: &[u8])
+    -> Result<(String, Meta, Args, Kwargs), MsgpackError>
+{
+    let res = rmp_serde::from_slice::<Request>(data)?;
+    let Request(method, meta, args, kwargs) = res;
+    Ok((method, meta, args, kwargs))
+}
+
 
 /// Returns true if Meta object contains 'active' key and
 /// it either set to true or uint timeout (in seconds).
@@ -117,9 +127,12 @@ impl<'a> Serialize for MetaWithExtra<'a> {
         where S: Serializer
     {
         if let Json::Object(ref extra) = self.extra {
-            s.collect_map(extra.iter()
+            // collected first, as msgpack needs to know length of the map
+            let items = extra.iter()
                 .chain(self.meta.iter()
-                    .filter(|&(&ref k,_)| extra.get(k).is_none())))
+                    .filter(|&(&ref k,_)| extra.get(k).is_none()))
+                .collect::<Vec<_>>();
+            s.collect_map(items)
         } else {
             s.collect_map(self.meta.iter())
         }
@@ -5,11 +5,13 @@ mod close_reason;
 mod connection_sender;
 mod content_type;
 mod dispatcher;
+mod encoding;
 mod error;
 mod inactivity_handler;
 mod listener;
 mod message;
 mod processor;
+mod rate_limit;
 mod replication;
 pub mod tangle_auth;
 
@@ -21,7 +23,8 @@ pub use self::close_reason::CloseReason;
 pub use self::listener::SessionPools;
 pub use self::processor::{Processor, ConnectionMessage, json_err};
 pub use self::dispatcher::Dispatcher;
-pub use self::connection_sender::ConnectionSender;
+pub use self::encoding::Encoding;
+pub use self::connection_sender::{ConnectionSender, ConnectionReceiver};
 pub use self::replication::ReplicationSession;
 
 use crate::metrics::{Counter, Integer, List, Metric};
@@ -41,6 +44,8 @@ pub fn metrics() -> List {
         (Metric("websockets.swindon_chat", "frames_received"),
             &*dispatcher::FRAMES_RECEIVED),
         (Metric("websockets.swindon_chat", "frames_sent"), &*FRAMES_SENT),
+        (Metric("websockets.swindon_chat", "slow_consumers"),
+            &*connection_sender::SLOW_CONSUMERS),
         (Metric("websockets.swindon_chat", "session_pools"),
             &*processor::SESSION_POOLS),
         (Metric("websockets.swindon_chat", "active_sessions"),
@@ -53,6 +58,8 @@ pub fn metrics() -> List {
             &*processor::PUBSUB_OUTPUT),
         (Metric("websockets.swindon_chat.pubsub", "topics"),
             &*processor::TOPICS),
+        (Metric("websockets.swindon_chat.pubsub", "history_messages"),
+            &*processor::HISTORY_MESSAGES),
         (Metric("websockets.swindon_chat.lattice", "namespaces"),
             &*processor::LATTICES),
         (Metric("websockets.swindon_chat.lattice.shared", "keys"),
@@ -63,6 +70,8 @@ pub fn metrics() -> List {
             &*processor::SHARED_SETS),
         (Metric("websockets.swindon_chat.lattice.shared", "registers"),
             &*processor::SHARED_REGISTERS),
+        (Metric("websockets.swindon_chat.lattice.shared", "orsets"),
+            &*processor::SHARED_ORSETS),
         (Metric("websockets.swindon_chat.lattice.private", "keys"),
             &*processor::PRIVATE_KEYS),
         (Metric("websockets.swindon_chat.lattice.private", "counters"),
@@ -71,6 +80,8 @@ pub fn metrics() -> List {
             &*processor::PRIVATE_SETS),
         (Metric("websockets.swindon_chat.lattice.private", "registers"),
             &*processor::PRIVATE_REGISTERS),
+        (Metric("websockets.swindon_chat.lattice.private", "orsets"),
+            &*processor::PRIVATE_ORSETS),
         (Metric("websockets.swindon_chat.lattice", "set_items"),
             &*processor::SET_ITEMS),
         (Metric("replication", "connections"),
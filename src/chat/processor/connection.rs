@@ -12,9 +12,12 @@ use super::lattice;
 pub struct NewConnection {
     pub cid: Cid,
     pub topics: HashSet<Topic>,
+    /// Wildcard subscriptions, i.e. `prefix.*`
+    pub prefixes: HashSet<Topic>,
     pub lattices: HashSet<Namespace>,
     pub users_lattice: HashSet<SessionId>,
-    pub message_buffer: Vec<(Topic, Arc<Json>)>,
+    /// Messages sent before connection is associated with a session
+    pub message_buffer: Vec<ConnectionMessage>,
     pub channel: ConnectionSender,
 }
 
@@ -23,6 +26,7 @@ pub struct Connection {
     pub cid: Cid,
     pub session_id: SessionId,
     pub topics: HashSet<Topic>,
+    pub prefixes: HashSet<Topic>,
     pub lattices: HashSet<Namespace>,
     pub users_lattice: bool,
     pub channel: ConnectionSender,
@@ -35,6 +39,7 @@ impl NewConnection {
         NewConnection {
             cid: conn_id,
             topics: HashSet::new(),
+            prefixes: HashSet::new(),
             lattices: HashSet::new(),
             users_lattice: HashSet::new(),
             message_buffer: Vec::new(),
@@ -44,21 +49,28 @@ impl NewConnection {
     pub fn associate(self, session_id: SessionId)
         -> (Connection, HashSet<SessionId>)
     {
-        let mut conn = Connection {
+        let conn = Connection {
             cid: self.cid,
             session_id: session_id,
             topics: self.topics,
+            prefixes: self.prefixes,
             lattices: self.lattices,
             users_lattice: self.users_lattice.len() > 0,
             channel: self.channel,
         };
-        for (t, m) in self.message_buffer {
-            conn.message(t, m);
+        for msg in self.message_buffer {
+            conn.channel.send(msg);
         }
         return (conn, self.users_lattice);
     }
-    pub fn message(&mut self, topic: Topic, data: Arc<Json>) {
-        self.message_buffer.push((topic, data));
+    pub fn message(&mut self, topic: Topic, data: Arc<Json>,
+        seq: Option<u64>)
+    {
+        self.message_buffer.push(
+            ConnectionMessage::Publish(topic, data, seq));
+    }
+    pub fn direct(&mut self, data: Arc<Json>) {
+        self.message_buffer.push(ConnectionMessage::Direct(data));
     }
     pub fn stop(&mut self, reason: CloseReason) {
         self.channel.send(ConnectionMessage::StopSock(reason));
@@ -67,8 +79,13 @@ impl NewConnection {
 
 impl Connection {
 
-    pub fn message(&mut self, topic: Topic, data: Arc<Json>) {
-        self.channel.send(ConnectionMessage::Publish(topic, data));
+    pub fn message(&mut self, topic: Topic, data: Arc<Json>,
+        seq: Option<u64>)
+    {
+        self.channel.send(ConnectionMessage::Publish(topic, data, seq));
+    }
+    pub fn direct(&mut self, data: Arc<Json>) {
+        self.channel.send(ConnectionMessage::Direct(data));
     }
 
     pub fn lattice(&mut self, namespace: &Namespace,
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use std::time::Instant;
+use std::collections::{HashMap, VecDeque};
+
+use serde_json::Value as Json;
+
+use crate::config::TopicHistory;
+use crate::intern::Topic;
+use crate::metrics::Integer;
+
+lazy_static! {
+    pub static ref HISTORY_MESSAGES: Integer = Integer::new();
+}
+
+
+struct Item {
+    seq: u64,
+    timestamp: Instant,
+    data: Arc<Json>,
+}
+
+/// Recently published messages of every topic in the pool
+///
+/// Sequence numbers are shared by all topics of the pool, so they never
+/// repeat even if history of the topic is removed completely.
+pub struct History {
+    settings: TopicHistory,
+    last_seq: u64,
+    topics: HashMap<Topic, VecDeque<Item>>,
+}
+
+impl History {
+    pub fn new(settings: TopicHistory) -> History {
+        History {
+            settings: settings,
+            last_seq: 0,
+            topics: HashMap::new(),
+        }
+    }
+    /// Stores the message and returns sequence number assigned to it
+    pub fn push(&mut self, topic: &Topic, data: &Arc<Json>,
+        timestamp: Instant)
+        -> u64
+    {
+        self.last_seq += 1;
+        let items = self.topics.entry(topic.clone())
+            .or_insert_with(VecDeque::new);
+        items.push_back(Item {
+            seq: self.last_seq,
+            timestamp: timestamp,
+            data: data.clone(),
+        });
+        HISTORY_MESSAGES.incr(1);
+        if items.len() > self.settings.max_messages {
+            items.pop_front();
+            HISTORY_MESSAGES.decr(1);
+        }
+        return self.last_seq;
+    }
+    /// Messages of the topic published after the one with sequence `seq`
+    pub fn since<'x>(&'x self, topic: &Topic, seq: u64)
+        -> impl Iterator<Item=(u64, &'x Arc<Json>)> + 'x
+    {
+        self.topics.get(topic).into_iter()
+            .flat_map(|items| items.iter())
+            .filter(move |item| item.seq > seq)
+            .map(|item| (item.seq, &item.data))
+    }
+    /// Messages of all topics starting with `prefix.` published after
+    /// the one with sequence `seq`, in the order of publishing
+    pub fn since_prefix(&self, prefix: &Topic, seq: u64)
+        -> Vec<(u64, Topic, Arc<Json>)>
+    {
+        let mut result = self.topics.iter()
+            .filter(|&(topic, _)| {
+                topic.starts_with(&prefix[..]) &&
+                topic[prefix.len()..].starts_with('.')
+            })
+            .flat_map(|(topic, items)| {
+                items.iter()
+                .filter(move |item| item.seq > seq)
+                .map(move |item| (item.seq, topic.clone(), item.data.clone()))
+            })
+            .collect::<Vec<_>>();
+        result.sort_by_key(|&(seq, _, _)| seq);
+        return result;
+    }
+    /// Removes expired messages, returns when the next one expires
+    pub fn cleanup(&mut self, timestamp: Instant) -> Option<Instant> {
+        let max_age = self.settings.max_age;
+        let mut removed = 0;
+        let mut next = None::<Instant>;
+        self.topics.retain(|_, items| {
+            while items.front()
+                .map(|x| x.timestamp + max_age <= timestamp)
+                .unwrap_or(false)
+            {
+                items.pop_front();
+                removed += 1;
+            }
+            if let Some(item) = items.front() {
+                let expires = item.timestamp + max_age;
+                if next.map(|x| expires < x).unwrap_or(true) {
+                    next = Some(expires);
+                }
+            }
+            items.len() > 0
+        });
+        HISTORY_MESSAGES.decr(removed);
+        return next;
+    }
+}
+
+impl Drop for History {
+    fn drop(&mut self) {
+        let total = self.topics.values().map(|x| x.len()).sum::<usize>();
+        HISTORY_MESSAGES.decr(total as i64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use crate::config::TopicHistory;
+    use crate::intern::Topic;
+    use super::History;
+
+    fn history() -> History {
+        History::new(TopicHistory {
+            max_messages: 2,
+            max_age: Duration::from_secs(60),
+        })
+    }
+
+    fn seqs(history: &History, topic: &'static str, since: u64) -> Vec<u64> {
+        history.since(&Topic::from(topic), since).map(|(s, _)| s).collect()
+    }
+
+    #[test]
+    fn max_messages() {
+        let mut h = history();
+        let data = Arc::new(json!(null));
+        let now = Instant::now();
+        assert_eq!(h.push(&Topic::from("a"), &data, now), 1);
+        assert_eq!(h.push(&Topic::from("b"), &data, now), 2);
+        assert_eq!(h.push(&Topic::from("a"), &data, now), 3);
+        assert_eq!(h.push(&Topic::from("a"), &data, now), 4);
+        assert_eq!(seqs(&h, "a", 0), vec![3, 4]);
+        assert_eq!(seqs(&h, "a", 3), vec![4]);
+        assert_eq!(seqs(&h, "b", 0), vec![2]);
+        assert_eq!(seqs(&h, "c", 0), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn prefix() {
+        let mut h = history();
+        let data = Arc::new(json!(null));
+        let now = Instant::now();
+        h.push(&Topic::from("orders.1"), &data, now);
+        h.push(&Topic::from("orders2.1"), &data, now);
+        h.push(&Topic::from("orders"), &data, now);
+        h.push(&Topic::from("orders.2"), &data, now);
+        h.push(&Topic::from("orders.1"), &data, now);
+        let items = h.since_prefix(&Topic::from("orders"), 1).into_iter()
+            .map(|(seq, topic, _)| (seq, topic.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![
+            (4, String::from("orders.2")),
+            (5, String::from("orders.1")),
+        ]);
+    }
+
+    #[test]
+    fn max_age() {
+        let mut h = history();
+        let data = Arc::new(json!(null));
+        let now = Instant::now();
+        h.push(&Topic::from("a"), &data, now);
+        h.push(&Topic::from("b"), &data, now + Duration::from_secs(30));
+        assert_eq!(h.cleanup(now + Duration::from_secs(10)),
+                   Some(now + Duration::from_secs(60)));
+        assert_eq!(h.cleanup(now + Duration::from_secs(60)),
+                   Some(now + Duration::from_secs(90)));
+        assert_eq!(seqs(&h, "a", 0), Vec::<u64>::new());
+        assert_eq!(seqs(&h, "b", 0), vec![2]);
+        assert_eq!(h.cleanup(now + Duration::from_secs(90)), None);
+        assert_eq!(h.push(&Topic::from("a"), &data, now), 3);
+    }
+}
@@ -3,13 +3,14 @@ use std::hash::Hash;
 use std::fmt;
 use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::time::{Instant, Duration};
+use std::time::Instant;
 
 use humantime::parse_duration;
 use serde::ser::{Serialize, Serializer, SerializeMap};
 use serde::de::{self, Deserialize, Deserializer, Visitor, MapAccess};
 use serde_json::Value;
 
+use crate::config::LatticeExpiry;
 use crate::intern::{LatticeKey as Key, LatticeVar as Var, SessionId};
 use crate::metrics::{Integer};
 
@@ -18,10 +19,12 @@ lazy_static! {
     pub static ref SHARED_COUNTERS: Integer = Integer::new();
     pub static ref SHARED_SETS: Integer = Integer::new();
     pub static ref SHARED_REGISTERS: Integer = Integer::new();
+    pub static ref SHARED_ORSETS: Integer = Integer::new();
     pub static ref PRIVATE_KEYS: Integer = Integer::new();
     pub static ref PRIVATE_COUNTERS: Integer = Integer::new();
     pub static ref PRIVATE_SETS: Integer = Integer::new();
     pub static ref PRIVATE_REGISTERS: Integer = Integer::new();
+    pub static ref PRIVATE_ORSETS: Integer = Integer::new();
     pub static ref SET_ITEMS: Integer = Integer::new();
 }
 
@@ -32,11 +35,44 @@ pub struct Register(pub f64, pub Arc<Value>);
 #[derive(Debug, Clone)]
 pub struct Counter(pub u64);
 
+/// Counter which can be both incremented and decremented
+///
+/// Every writer has its own increment and decrement counts, each of them
+/// only grows. Value of the counter is sum of increments minus sum of
+/// decrements.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PnCounter {
+    #[serde(default)]
+    pub p: HashMap<String, u64>,
+    #[serde(default)]
+    pub n: HashMap<String, u64>,
+}
+
+/// Map of registers, each key is updated with last-write-wins semantics
+#[derive(Debug, Clone)]
+pub struct Map(pub Arc<HashMap<String, Register>>);
+
 // TODO(tailhook) implement some persistent hash set
 // TODO(tailhook) optimize set of only int-like values
 #[derive(Debug, Clone)]
 pub struct Set(pub Arc<HashSet<String>>);
 
+/// Observed-remove set
+///
+/// Every addition of an element is marked by a unique tag, removal
+/// removes tags which were observed. Element is in the set while it has
+/// tags that are not removed, so concurrent addition wins over removal.
+#[derive(Debug, Clone)]
+pub struct OrSet(pub Arc<HashMap<String, Tags>>);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Tags {
+    #[serde(default, skip_serializing_if="HashSet::is_empty")]
+    pub added: HashSet<String>,
+    #[serde(default, skip_serializing_if="HashSet::is_empty")]
+    pub removed: HashSet<String>,
+}
+
 trait Crdt: Clone + Sized {
     /// Updates returning `true` if value changed
     fn update(&mut self, other: &Self) -> bool;
@@ -52,8 +88,11 @@ pub enum Expires {
 #[derive(Debug, Clone)]
 pub struct Values {
     pub(in crate::chat::processor) counters: HashMap<Var, Counter>,
+    pub(in crate::chat::processor) pncounters: HashMap<Var, PnCounter>,
     pub(in crate::chat::processor) sets: HashMap<Var, Set>,
     pub(in crate::chat::processor) registers: HashMap<Var, Register>,
+    pub(in crate::chat::processor) maps: HashMap<Var, Map>,
+    pub(in crate::chat::processor) orsets: HashMap<Var, OrSet>,
     pub(in crate::chat::processor) expires: Expires,
 }
 
@@ -61,6 +100,7 @@ pub struct Lattice {
     pub shared: HashMap<Key, Values>,
     pub private: HashMap<SessionId, HashMap<Key, Values>>,
     pub subscriptions: HashMap<Key, HashSet<SessionId>>,
+    pub expiry: LatticeExpiry,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -73,8 +113,11 @@ impl Values {
     pub fn new() -> Values {
         Values {
             counters: HashMap::new(),
+            pncounters: HashMap::new(),
             sets: HashMap::new(),
             registers: HashMap::new(),
+            maps: HashMap::new(),
+            orsets: HashMap::new(),
             expires: Expires::WithSession,
         }
     }
@@ -82,18 +125,86 @@ impl Values {
         for (key, value) in &other.counters {
             self.counters.insert(key.clone(), value.clone());
         }
+        for (key, value) in &other.pncounters {
+            self.pncounters.insert(key.clone(), value.clone());
+        }
         for (key, value) in &other.sets {
             self.sets.insert(key.clone(), value.clone());
         }
         for (key, value) in &other.registers {
             self.registers.insert(key.clone(), value.clone());
         }
+        for (key, value) in &other.maps {
+            self.maps.insert(key.clone(), value.clone());
+        }
+        for (key, value) in &other.orsets {
+            self.orsets.insert(key.clone(), value.clone());
+        }
     }
     pub fn is_empty(&self) -> bool {
         self.counters.len() == 0 &&
+        self.pncounters.len() == 0 &&
         self.sets.len() == 0 &&
-        self.registers.len() == 0
+        self.registers.len() == 0 &&
+        self.maps.len() == 0 &&
+        self.orsets.len() == 0
+    }
+    /// Number of values counted as counters in metrics
+    pub fn counters_len(&self) -> usize {
+        self.counters.len() + self.pncounters.len()
+    }
+    /// Number of values counted as registers in metrics
+    pub fn registers_len(&self) -> usize {
+        self.registers.len() + self.maps.len()
     }
+    /// Sets expiration time of the key on update
+    ///
+    /// Explicit `expires_in` of the update wins, otherwise key expires
+    /// when it isn't used until `deadline`.
+    fn set_expires(&mut self, update: &Expires, deadline: Instant) {
+        self.expires = match *update {
+            Expires::At(x) => Expires::At(x),
+            _ => Expires::IfUnused(deadline),
+        };
+    }
+    /// Starts idle timer unless key has explicit expiration time
+    fn unused_since(&mut self, deadline: Instant) {
+        match self.expires {
+            Expires::At(..) => {}
+            _ => self.expires = Expires::IfUnused(deadline),
+        }
+    }
+    /// Returns true if key should be deleted
+    fn expired(&mut self, timestamp: Instant, in_use: bool) -> bool {
+        use self::Expires::*;
+        match self.expires {
+            WithSession => !in_use,
+            At(x) => x < timestamp,
+            IfUnused(x) if x < timestamp => {
+                if in_use {
+                    self.expires = WithSession;
+                }
+                !in_use
+            }
+            IfUnused(..) => false,
+        }
+    }
+}
+
+fn forget_shared(values: &Values) {
+    SHARED_KEYS.decr(1);
+    SHARED_COUNTERS.decr(values.counters_len() as i64);
+    SHARED_SETS.decr(values.sets.len() as i64);
+    SHARED_REGISTERS.decr(values.registers_len() as i64);
+    SHARED_ORSETS.decr(values.orsets.len() as i64);
+}
+
+fn forget_private(values: &Values) {
+    PRIVATE_KEYS.decr(1);
+    PRIVATE_COUNTERS.decr(values.counters_len() as i64);
+    PRIVATE_SETS.decr(values.sets.len() as i64);
+    PRIVATE_REGISTERS.decr(values.registers_len() as i64);
+    PRIVATE_ORSETS.decr(values.orsets.len() as i64);
 }
 
 impl Serialize for Values {
@@ -102,12 +213,19 @@ impl Serialize for Values {
     {
         let mut map = serialize.serialize_map(
             Some(self.counters.len() +
+                 self.pncounters.len() +
                  self.sets.len() +
-                 self.registers.len()))?;
+                 self.registers.len() +
+                 self.maps.len() +
+                 self.orsets.len()))?;
         for (k, counter) in &self.counters {
             map.serialize_key(&format!("{}_counter", k))?;
             map.serialize_value(&counter.0)?;
         }
+        for (k, counter) in &self.pncounters {
+            map.serialize_key(&format!("{}_pncounter", k))?;
+            map.serialize_value(&counter)?;
+        }
         for (k, set) in &self.sets {
             map.serialize_key(&format!("{}_set", k))?;
             map.serialize_value(&set.0)?;
@@ -116,22 +234,33 @@ impl Serialize for Values {
             map.serialize_key(&format!("{}_register", k))?;
             map.serialize_value(&reg)?;
         }
+        for (k, regs) in &self.maps {
+            map.serialize_key(&format!("{}_map", k))?;
+            map.serialize_value(&regs.0)?;
+        }
+        for (k, orset) in &self.orsets {
+            map.serialize_key(&format!("{}_orset", k))?;
+            map.serialize_value(&orset.0)?;
+        }
         map.end()
     }
 }
 
 impl Lattice {
-    pub fn new() -> Lattice {
+    pub fn new(expiry: LatticeExpiry) -> Lattice {
         Lattice {
             shared: HashMap::new(),
             private: HashMap::new(),
             subscriptions: HashMap::new(),
+            expiry: expiry,
         }
     }
     /// Updates lattice to be up to date with Delta and returns modified delta
     /// that contains only data that really changed and not out of date
     pub fn update(&mut self, mut delta: Delta) -> Delta {
-        use self::Expires::*;
+        let now = Instant::now();
+        let shared_deadline = now + self.expiry.shared_idle_timeout;
+        let private_deadline = now + self.expiry.private_grace_period;
 
         let mut del = Vec::new();
         for (room, values) in &mut delta.shared {
@@ -143,22 +272,20 @@ impl Lattice {
 
             crdt_update(&mut mine.counters, &mut values.counters,
                 &*SHARED_COUNTERS);
+            crdt_update(&mut mine.pncounters, &mut values.pncounters,
+                &*SHARED_COUNTERS);
             crdt_update(&mut mine.sets, &mut values.sets,
                 &*SHARED_SETS);
             crdt_update(&mut mine.registers, &mut values.registers,
                 &*SHARED_REGISTERS);
+            crdt_update(&mut mine.maps, &mut values.maps,
+                &*SHARED_REGISTERS);
+            crdt_update(&mut mine.orsets, &mut values.orsets,
+                &*SHARED_ORSETS);
+            mine.set_expires(&values.expires, shared_deadline);
 
             if values.is_empty() {
                 del.push(room.clone());
-            } else {
-                match values.expires {
-                    At(..) => {}
-                    WithSession | IfUnused(..) => {
-                        // TODO(tailhook) make it configurable
-                        values.expires = IfUnused(
-                            Instant::now() + Duration::new(60, 0));
-                    }
-                }
             }
         }
         for key in &del {
@@ -178,22 +305,20 @@ impl Lattice {
 
                 crdt_update(&mut mine.counters, &mut values.counters,
                     &*PRIVATE_COUNTERS);
+                crdt_update(&mut mine.pncounters, &mut values.pncounters,
+                    &*PRIVATE_COUNTERS);
                 crdt_update(&mut mine.sets, &mut values.sets,
                     &*PRIVATE_SETS);
                 crdt_update(&mut mine.registers, &mut values.registers,
                     &*PRIVATE_REGISTERS);
+                crdt_update(&mut mine.maps, &mut values.maps,
+                    &*PRIVATE_REGISTERS);
+                crdt_update(&mut mine.orsets, &mut values.orsets,
+                    &*PRIVATE_ORSETS);
+                mine.set_expires(&values.expires, private_deadline);
 
                 if values.is_empty() {
                     del_rooms.push(room.clone());
-                } else {
-                    match values.expires {
-                        At(..) => {}
-                        WithSession | IfUnused(..) => {
-                            // TODO(tailhook) make it configurable
-                            values.expires = IfUnused(
-                                Instant::now() + Duration::new(60, 0));
-                        }
-                    }
                 }
             }
             for key in &del_rooms {
@@ -203,33 +328,74 @@ impl Lattice {
         return delta
     }
 
-    // TODO(tailhook) maybe don't delete them, but rather set expiration time
+    /// Starts grace period for private keys of the session
+    ///
+    /// Keys are deleted by `cleanup` if session doesn't come back until
+    /// grace period ends.
     pub fn remove_session(&mut self, sid: &SessionId) {
-        if let Some(skeys) = self.private.remove(sid) {
-            PRIVATE_KEYS.decr(skeys.len() as i64);
-            PRIVATE_COUNTERS.decr(skeys.values()
-                .map(|v| v.counters.len() as i64).sum());
-            PRIVATE_SETS.decr(skeys.values()
-                .map(|v| v.sets.len() as i64).sum());
-            PRIVATE_REGISTERS.decr(skeys.values()
-                .map(|v| v.registers.len() as i64).sum());
-            for (key, _) in skeys {
-                if let Occupied(mut subs) = self.subscriptions.entry(key.clone()) {
-                    subs.get_mut().remove(sid);
+        let deadline = Instant::now() + self.expiry.private_grace_period;
+        if let Some(skeys) = self.private.get_mut(sid) {
+            for values in skeys.values_mut() {
+                values.unused_since(deadline);
+            }
+        }
+    }
+
+    /// Deletes expired keys
+    ///
+    /// Private keys are in use while their session exists. Shared keys are
+    /// in use while there is a private key with the same name.
+    pub fn cleanup<F>(&mut self, timestamp: Instant, has_session: F)
+        where F: Fn(&SessionId) -> bool
+    {
+        let mut unused_keys = Vec::new();
+        let mut empty_sessions = Vec::new();
+        for (sess_id, data) in &mut self.private {
+            let in_use = has_session(sess_id);
+            let mut delete_keys = Vec::new();
+            for (key, values) in data.iter_mut() {
+                if values.expired(timestamp, in_use) {
+                    delete_keys.push(key.clone());
+                }
+            }
+            for key in delete_keys {
+                if let Some(values) = data.remove(&key) {
+                    forget_private(&values);
+                }
+                if let Occupied(mut subs) = self.subscriptions.entry(key) {
+                    subs.get_mut().remove(sess_id);
                     if subs.get().is_empty() {
-                        subs.remove_entry();
-                        if let Some(vals) = self.shared.remove(&key) {
-                            SHARED_KEYS.decr(1);
-                            SHARED_COUNTERS.decr(vals.counters.len() as i64);
-                            SHARED_SETS.decr(vals.sets.len() as i64);
-                            SHARED_REGISTERS.decr(
-                                vals.registers.len() as i64);
-                        }
+                        unused_keys.push(subs.remove_entry().0);
                     }
                 } else {
-                    error!("Subscription inconsistency {:?} {:?}", sid, key);
+                    error!("Subscription inconsistency {:?}", sess_id);
                 }
             }
+            if data.is_empty() {
+                empty_sessions.push(sess_id.clone());
+            }
+        }
+        for sess_id in empty_sessions {
+            self.private.remove(&sess_id);
+        }
+
+        let deadline = timestamp + self.expiry.shared_idle_timeout;
+        for key in unused_keys {
+            if let Some(values) = self.shared.get_mut(&key) {
+                values.unused_since(deadline);
+            }
+        }
+        let mut delete_keys = Vec::new();
+        for (key, values) in &mut self.shared {
+            if values.expired(timestamp, self.subscriptions.contains_key(key))
+            {
+                delete_keys.push(key.clone());
+            }
+        }
+        for key in delete_keys {
+            if let Some(values) = self.shared.remove(&key) {
+                forget_shared(&values);
+            }
         }
     }
 
@@ -251,6 +417,34 @@ impl Crdt for Counter {
     }
 }
 
+impl PnCounter {
+    pub fn value(&self) -> i64 {
+        self.p.values().sum::<u64>() as i64 -
+        self.n.values().sum::<u64>() as i64
+    }
+}
+
+impl Crdt for PnCounter {
+    fn update(&mut self, other: &Self) -> bool {
+        fn merge(mine: &mut HashMap<String, u64>, other: &HashMap<String, u64>)
+            -> bool
+        {
+            let mut changed = false;
+            for (writer, &value) in other {
+                let cur = mine.entry(writer.clone()).or_insert(0);
+                if *cur < value {
+                    *cur = value;
+                    changed = true;
+                }
+            }
+            changed
+        }
+        let p = merge(&mut self.p, &other.p);
+        let n = merge(&mut self.n, &other.n);
+        p || n
+    }
+}
+
 impl Crdt for Set {
     fn update(&mut self, other: &Self) -> bool {
         let mut iter = other.0.iter();
@@ -273,6 +467,42 @@ impl Crdt for Set {
     }
 }
 
+impl Tags {
+    /// Element is present when some of its additions are not removed
+    pub fn is_present(&self) -> bool {
+        self.added.iter().any(|tag| !self.removed.contains(tag))
+    }
+    fn contains(&self, other: &Tags) -> bool {
+        other.added.is_subset(&self.added) &&
+        other.removed.is_subset(&self.removed)
+    }
+}
+
+impl OrSet {
+    pub fn contains(&self, item: &str) -> bool {
+        self.0.get(item).map(|tags| tags.is_present()).unwrap_or(false)
+    }
+}
+
+impl Crdt for OrSet {
+    fn update(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (item, tags) in other.0.iter() {
+            if self.0.get(item).map(|t| t.contains(tags)).unwrap_or(false) {
+                continue;
+            }
+            // tombstones are kept, so that removed element isn't
+            // resurrected by an outdated update
+            let mine = Arc::make_mut(&mut self.0)
+                .entry(item.clone()).or_insert_with(Tags::default);
+            mine.added.extend(tags.added.iter().cloned());
+            mine.removed.extend(tags.removed.iter().cloned());
+            changed = true;
+        }
+        return changed;
+    }
+}
+
 impl Crdt for Register {
     fn update(&mut self, other: &Self) -> bool {
         if self.0 < other.0 {
@@ -285,6 +515,20 @@ impl Crdt for Register {
     }
 }
 
+impl Crdt for Map {
+    fn update(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (key, reg) in other.0.iter() {
+            let newer = self.0.get(key).map(|r| r.0 < reg.0).unwrap_or(true);
+            if newer {
+                Arc::make_mut(&mut self.0).insert(key.clone(), reg.clone());
+                changed = true;
+            }
+        }
+        return changed;
+    }
+}
+
 fn crdt_update<K, V>(original: &mut HashMap<K, V>, delta: &mut HashMap<K, V>,
     number: &Integer)
     where K: Clone + Hash + Eq + ::std::fmt::Debug, V: Crdt
@@ -340,9 +584,11 @@ impl<'de> Visitor<'de> for ValuesVisitor {
     {
         let mut values = Values {
             counters: HashMap::new(),
+            pncounters: HashMap::new(),
             sets: HashMap::new(),
             registers: HashMap::new(),
-            // TODO(tailhook) parse `expires_in` key
+            maps: HashMap::new(),
+            orsets: HashMap::new(),
             expires: Expires::WithSession,
         };
         while let Some(key) = access.next_key::<&str>()? {
@@ -351,6 +597,11 @@ impl<'de> Visitor<'de> for ValuesVisitor {
                 let key = key[..key.len() - "_counter".len()].parse()
                     .map_err(de::Error::custom)?;
                 values.counters.insert(key, Counter(val));
+            } else if key.ends_with("_pncounter") {
+                let val = access.next_value()?;
+                let key = key[..key.len() - "_pncounter".len()].parse()
+                    .map_err(de::Error::custom)?;
+                values.pncounters.insert(key, val);
             } else if key.ends_with("_set") {
                 let val = access.next_value()?;
                 let key = key[..key.len() - "_set".len()].parse()
@@ -361,6 +612,16 @@ impl<'de> Visitor<'de> for ValuesVisitor {
                 let key = key[..key.len() - "_register".len()].parse()
                     .map_err(de::Error::custom)?;
                 values.registers.insert(key, val);
+            } else if key.ends_with("_map") {
+                let val = access.next_value()?;
+                let key = key[..key.len() - "_map".len()].parse()
+                    .map_err(de::Error::custom)?;
+                values.maps.insert(key, Map(val));
+            } else if key.ends_with("_orset") {
+                let val = access.next_value()?;
+                let key = key[..key.len() - "_orset".len()].parse()
+                    .map_err(de::Error::custom)?;
+                values.orsets.insert(key, OrSet(val));
             } else if key == "expires_in" {
                 let val: &str = access.next_value()?;
                 let dur = parse_duration(val)
@@ -394,7 +655,7 @@ impl<'de> Deserialize<'de> for Set {
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
-    use std::time::Instant;
+    use std::time::{Instant, Duration};
     use std::mem::size_of;
 
     use serde_json::{from_str as json_decode, Value};
@@ -403,6 +664,13 @@ mod test {
     use super::*;
     use crate::intern::{LatticeKey as Key, LatticeVar as Var};
 
+    fn lattice() -> Lattice {
+        Lattice::new(LatticeExpiry {
+            shared_idle_timeout: Duration::from_secs(10),
+            private_grace_period: Duration::from_secs(60),
+        })
+    }
+
     #[test]
     fn decode_delta() {
         let val = r#"{"shared": {"room_1": {"last_message_counter": 125}},
@@ -452,6 +720,110 @@ mod test {
             String::from(r#"{"status_register":[123.0,{"icon":"test"}]}"#));
     }
 
+    #[test]
+    fn serde_orset() {
+        let val = r#"{"members_orset": {"alice": {"added": ["a1"]}}}"#;
+        let val: Values = json_decode(val).unwrap();
+        assert_eq!(val.orsets.len(), 1);
+        assert_eq!(to_string(&val).unwrap(),
+            String::from(r#"{"members_orset":{"alice":{"added":["a1"]}}}"#));
+    }
+
+    #[test]
+    fn merge_orset() {
+        let add: OrSet = OrSet(json_decode(r#"{
+            "alice": {"added": ["a1"]},
+            "bob": {"added": ["b1"]}}"#).unwrap());
+        let remove: OrSet = OrSet(json_decode(r#"{
+            "alice": {"added": ["a1"], "removed": ["a1"]}}"#).unwrap());
+        let readd: OrSet = OrSet(json_decode(r#"{
+            "alice": {"added": ["a2"]}}"#).unwrap());
+
+        let mut set = add.clone();
+        assert!(set.update(&remove));
+        assert!(!set.contains("alice"));
+        assert!(set.contains("bob"));
+        // outdated addition doesn't resurrect the element
+        assert!(!set.update(&add));
+        assert!(!set.contains("alice"));
+        assert!(set.update(&readd));
+        assert!(set.contains("alice"));
+
+        // same result in a different order
+        let mut other = readd.clone();
+        assert!(other.update(&remove));
+        assert!(other.update(&add));
+        assert!(other.contains("alice"));
+        assert!(other.contains("bob"));
+    }
+
+    #[test]
+    fn merge_pncounter() {
+        let val = r#"{"unread_pncounter": {"p": {"a": 5}, "n": {"a": 2}}}"#;
+        let val: Values = json_decode(val).unwrap();
+        let mut counter = val.pncounters.values().next().unwrap().clone();
+        assert_eq!(counter.value(), 3);
+        let other: PnCounter = json_decode(
+            r#"{"p": {"a": 4, "b": 1}, "n": {"a": 4}}"#).unwrap();
+        assert!(counter.update(&other));
+        assert_eq!(counter.value(), 2);
+        assert!(!counter.update(&other));
+    }
+
+    #[test]
+    fn merge_map() {
+        let val = r#"{"roles_map": {"alice": [2, "admin"], "bob": [1, null]}}"#;
+        let val: Values = json_decode(val).unwrap();
+        let mut map = val.maps.values().next().unwrap().clone();
+        let other = Map(json_decode(
+            r#"{"alice": [1, "guest"], "bob": [3, "guest"]}"#).unwrap());
+        assert!(map.update(&other));
+        assert_eq!(map.0["alice"].1, Arc::new(json!("admin")));
+        assert_eq!(map.0["bob"].1, Arc::new(json!("guest")));
+        assert!(!map.update(&other));
+    }
+
+    #[test]
+    fn private_grace_period() {
+        let now = Instant::now();
+        let key = Key::from("room1");
+        let sid = SessionId::from("user1");
+        let mut lat = lattice();
+        lat.update(json_decode(r#"{
+            "shared": {"room1": {"last_message_counter": 1}},
+            "private": {"user1": {"room1": {"last_seen_counter": 1}}}
+            }"#).unwrap());
+        // subscriptions are maintained by the pool
+        lat.subscriptions.entry(key.clone()).or_insert_with(HashSet::new)
+            .insert(sid.clone());
+        lat.remove_session(&sid);
+        lat.cleanup(now + Duration::from_secs(30), |_| false);
+        assert!(lat.private.contains_key(&sid));
+        lat.cleanup(now + Duration::from_secs(61), |_| false);
+        assert!(!lat.private.contains_key(&sid));
+        // idle timer of the shared key starts when it isn't used
+        assert!(lat.shared.contains_key(&key));
+        lat.cleanup(now + Duration::from_secs(72), |_| false);
+        assert!(lat.is_empty());
+    }
+
+    #[test]
+    fn expires_in() {
+        let now = Instant::now();
+        let key = Key::from("room1");
+        let mut lat = lattice();
+        lat.update(json_decode(r#"{
+            "shared": {"room1": {"topic_register": [1, "hello"],
+                                 "expires_in": "1 hour"}}
+            }"#).unwrap());
+        lat.subscriptions.entry(key.clone()).or_insert_with(HashSet::new)
+            .insert(SessionId::from("user1"));
+        lat.cleanup(now + Duration::from_secs(1800), |_| true);
+        assert!(lat.shared.contains_key(&key));
+        lat.cleanup(now + Duration::from_secs(3601), |_| true);
+        assert!(!lat.shared.contains_key(&key));
+    }
+
     #[test]
     fn mem() {
         assert_eq!(size_of::<Expires>(), size_of::<Option<Instant>>());
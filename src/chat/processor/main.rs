@@ -30,15 +30,34 @@ fn pool_action(pool: &mut Pool, ts: Instant, action: Action) {
         Disconnect { conn_id } => {
             pool.del_connection(conn_id);
         }
+        CloseConnection { conn_id, reason } => {
+            pool.close_connection(conn_id, reason);
+        }
+        CloseSession { session_id, reason } => {
+            pool.close_session(&session_id, reason);
+        }
         // Subscriptions
-        Subscribe { conn_id, topic } => {
-            pool.subscribe(conn_id, topic);
+        Subscribe { conn_id, topic, since } => {
+            pool.subscribe(conn_id, topic, since);
         }
         Unsubscribe { conn_id, topic } => {
             pool.unsubscribe(conn_id, topic);
         }
+        SubscribePrefix { conn_id, prefix, since } => {
+            pool.subscribe_prefix(conn_id, prefix, since);
+        }
+        UnsubscribePrefix { conn_id, prefix } => {
+            pool.unsubscribe_prefix(conn_id, prefix);
+        }
         Publish { topic, data } => {
-            pool.publish(topic, data);
+            pool.publish(topic, data, ts);
+        }
+        // Direct messages
+        DirectConnection { conn_id, data } => {
+            pool.direct_connection(conn_id, data);
+        }
+        DirectSession { session_id, data } => {
+            pool.direct_session(&session_id, data);
         }
         // Lattices
         Attach { conn_id, namespace } => {
@@ -59,6 +78,16 @@ fn pool_action(pool: &mut Pool, ts: Instant, action: Action) {
         DetachUsers { conn_id } => {
             pool.users_detach(conn_id);
         }
+        Batch { actions } => {
+            for action in actions {
+                pool_action(pool, ts, action);
+            }
+        }
+        // Introspection
+        Inspect { query, server_id, reply } => {
+            // requester may be gone already
+            reply.send(pool.inspect(query, server_id)).ok();
+        }
     }
 }
 
@@ -109,5 +138,8 @@ pub fn run(rx: Receiver<Event>) {
                 }
             }
         }
+        for pool in pools.values_mut() {
+            pool.flush();
+        }
     }
 }
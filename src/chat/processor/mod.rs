@@ -16,6 +16,7 @@ use std::fmt;
 use serde_json::Value as Json;
 use serde::ser::{Serialize, Serializer, SerializeTuple};
 use futures::sync::mpsc::{UnboundedSender as Sender};
+use futures::sync::oneshot;
 
 use crate::config;
 use crate::intern::{Topic, SessionId, SessionPoolName, Lattice as Namespace};
@@ -23,6 +24,7 @@ use crate::intern::{LatticeKey, LatticeVar};
 use crate::chat::{Cid, ConnectionSender, CloseReason};
 use crate::chat::message::{Meta, MetaWithExtra};
 use crate::chat::error::MessageError;
+use crate::runtime::ServerId;
 
 mod main;
 mod pool;
@@ -33,6 +35,9 @@ mod heap;
 mod try_iter;  // temporary
 mod connection;
 mod lattice;
+mod persist;
+mod history;
+mod trie;
 
 pub use self::public::{Processor, ProcessorPool};
 pub use self::lattice::Delta;
@@ -40,10 +45,12 @@ pub use self::main::{SESSION_POOLS};
 pub use self::pool::{ACTIVE_SESSIONS, INACTIVE_SESSIONS};
 pub use self::pool::{PUBSUB_INPUT, PUBSUB_OUTPUT, TOPICS};
 pub use self::pool::{LATTICES};
+pub use self::history::{HISTORY_MESSAGES};
 pub use self::lattice::{SHARED_KEYS, PRIVATE_KEYS};
 pub use self::lattice::{SHARED_COUNTERS, PRIVATE_COUNTERS};
 pub use self::lattice::{SHARED_SETS, PRIVATE_SETS};
 pub use self::lattice::{SHARED_REGISTERS, PRIVATE_REGISTERS};
+pub use self::lattice::{SHARED_ORSETS, PRIVATE_ORSETS};
 pub use self::lattice::{SET_ITEMS};
 
 lazy_static! {
@@ -67,8 +74,13 @@ pub struct Event {
 #[derive(Debug)]
 pub enum ConnectionMessage {
     /// Topic publish message:
-    /// `["message", {"topic": topic}, data]`
-    Publish(Topic, Arc<Json>),
+    /// `["message", {"topic": topic, "seq": seq}, data]`
+    ///
+    /// Sequence number is only there if topic history is enabled
+    Publish(Topic, Arc<Json>, Option<u64>),
+    /// Message sent by backend directly to the connection or session:
+    /// `["direct", {}, data]`
+    Direct(Arc<Json>),
     /// Auth response message:
     /// `["hello", {}, json_data]`
     ///
@@ -96,6 +108,17 @@ pub enum PoolMessage {
     },
 }
 
+/// Part of the pool state requested by introspection API
+#[derive(Debug)]
+pub enum Query {
+    Session(SessionId),
+    Connection(Cid),
+    /// Number of subscribers of the topic
+    Topic(Topic),
+    /// Lattice data, private data is limited to the session if specified
+    Lattice(Namespace, Option<SessionId>),
+}
+
 pub enum Action {
 
     // ------ Session pool management ------
@@ -125,21 +148,54 @@ pub enum Action {
     Disconnect {
         conn_id: Cid,
     },
+    /// Closes the websocket on request of the backend
+    CloseConnection {
+        conn_id: Cid,
+        reason: CloseReason,
+    },
+    /// Closes all websockets of the session on request of the backend
+    CloseSession {
+        session_id: SessionId,
+        reason: CloseReason,
+    },
 
     // ------ Subscriptions ------
     Subscribe {
         conn_id: Cid,
         topic: Topic,
+        /// Replay messages from history published after this sequence number
+        since: Option<u64>,
     },
     Unsubscribe {
         conn_id: Cid,
         topic: Topic,
     },
+    /// Subscribes to all topics starting with `prefix.`
+    SubscribePrefix {
+        conn_id: Cid,
+        prefix: Topic,
+        since: Option<u64>,
+    },
+    UnsubscribePrefix {
+        conn_id: Cid,
+        prefix: Topic,
+    },
     Publish {
         topic: Topic,
         data: Arc<Json>,
     },
 
+    // ------ Direct messages ------
+    DirectConnection {
+        conn_id: Cid,
+        data: Arc<Json>,
+    },
+    /// Sends message to every connection of the session
+    DirectSession {
+        session_id: SessionId,
+        data: Arc<Json>,
+    },
+
     // ------ Lattices ------
     /// Attaches (subscribes to) lattice for this user
     ///
@@ -176,6 +232,44 @@ pub enum Action {
     DetachUsers {
         conn_id: Cid,
     },
+
+    /// Actions applied one after another within a single event
+    Batch {
+        actions: Vec<Action>,
+    },
+
+    // ------ Introspection ------
+    /// Replies with description of the pool state, `None` if not found
+    ///
+    /// Server id is only used to format public connection ids
+    Inspect {
+        query: Query,
+        server_id: ServerId,
+        reply: oneshot::Sender<Option<Json>>,
+    },
+}
+
+impl ConnectionMessage {
+    /// Merges a later lattice update of the same namespace into this one
+    ///
+    /// Returns `false` if messages aren't lattice updates of the same
+    /// namespace.
+    pub fn merge_lattice(&mut self, other: &ConnectionMessage) -> bool {
+        use self::ConnectionMessage::Lattice;
+        match (self, other) {
+            (&mut Lattice(ref ns, ref mut values), &Lattice(ref ons, ref upd))
+            if ns == ons => {
+                let values = Arc::make_mut(values);
+                for (key, update) in upd.iter() {
+                    values.entry(key.clone())
+                        .or_insert_with(lattice::Values::new)
+                        .update(update);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 // TODO(tailhook) optimize to not to create serde_json::Value
@@ -196,6 +290,9 @@ pub fn json_err(err: &MessageError) -> Json {
         &MessageError::ValidationError(_) => {
             json!({"error_kind": "validation_error"})
         }
+        &MessageError::RateLimited => {
+            json!({"error_kind": "rate_limit"})
+        }
         _ => {
             json!({"error_kind": "internal_error"})
         }
@@ -208,13 +305,20 @@ impl Serialize for ConnectionMessage {
         use self::ConnectionMessage::*;
         let mut tup = serializer.serialize_tuple(3)?;
         match *self {
-            Publish(ref topic, ref json) => {
+            Publish(ref topic, ref json, seq) => {
                 #[derive(Serialize)]
                 struct Meta<'a> {
                     topic: &'a Topic,
+                    #[serde(skip_serializing_if="Option::is_none")]
+                    seq: Option<u64>,
                 }
                 tup.serialize_element("message")?;
-                tup.serialize_element(&Meta { topic: topic })?;
+                tup.serialize_element(&Meta { topic: topic, seq: seq })?;
+                tup.serialize_element(json)?;
+            }
+            Direct(ref json) => {
+                tup.serialize_element("direct")?;
+                tup.serialize_element(&json!({}))?;
                 tup.serialize_element(json)?;
             }
             // We don't serialize session id, it's already in dict
@@ -286,15 +390,37 @@ impl fmt::Debug for Action {
             &Disconnect { ref conn_id } => {
                 write!(f, "Action::Disconnect({:?})", conn_id)
             }
-            &Subscribe { ref conn_id, ref topic } => {
+            &CloseConnection { ref conn_id, ref reason } => {
+                write!(f, "Action::CloseConnection({:?}, {:?})",
+                    conn_id, reason)
+            }
+            &CloseSession { ref session_id, ref reason } => {
+                write!(f, "Action::CloseSession({:?}, {:?})",
+                    session_id, reason)
+            }
+            &Subscribe { ref conn_id, ref topic, .. } => {
                 write!(f, "Action::Subscribe({:?}, {:?})", conn_id, topic)
             }
             &Unsubscribe { ref conn_id, ref topic } => {
                 write!(f, "Action::Unsubscribe({:?}, {:?})", conn_id, topic)
             }
+            &SubscribePrefix { ref conn_id, ref prefix, .. } => {
+                write!(f, "Action::SubscribePrefix({:?}, {:?})",
+                    conn_id, prefix)
+            }
+            &UnsubscribePrefix { ref conn_id, ref prefix } => {
+                write!(f, "Action::UnsubscribePrefix({:?}, {:?})",
+                    conn_id, prefix)
+            }
             &Publish { ref topic, .. } => {
                 write!(f, "Action::Publish({:?})", topic)
             }
+            &DirectConnection { ref conn_id, .. } => {
+                write!(f, "Action::DirectConnection({:?})", conn_id)
+            }
+            &DirectSession { ref session_id, .. } => {
+                write!(f, "Action::DirectSession({:?})", session_id)
+            }
             &Attach { ref conn_id, ref namespace } => {
                 write!(f, "Action::Attach({:?}, {:?})", conn_id, namespace)
             }
@@ -315,6 +441,12 @@ impl fmt::Debug for Action {
             &DetachUsers { ref conn_id } => {
                 write!(f, "Action::Detach({:?})", conn_id)
             }
+            &Batch { ref actions } => {
+                write!(f, "Action::Batch({:?})", actions)
+            }
+            &Inspect { ref query, .. } => {
+                write!(f, "Action::Inspect({:?})", query)
+            }
         }
     }
 }
@@ -0,0 +1,233 @@
+//! Snapshots and journal of the session pool state
+//!
+//! Full state of the pool is written to `<pool>.snapshot` every
+//! `snapshot-interval`, and every change made after that is appended to
+//! `<pool>.journal`, one json entry per line. Lattice updates are CRDT
+//! merges and session entries contain full state of the session, so
+//! replaying any entry twice is harmless.
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions, create_dir_all, rename};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use serde_json;
+
+use crate::config::Persistence;
+use crate::intern::{Topic, SessionId, SessionPoolName, Lattice as Namespace};
+use crate::intern::LatticeKey;
+use super::lattice::{Lattice, Delta, Values};
+
+
+/// Subscriptions of a session that are restored on reconnect
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionState {
+    pub topics: HashSet<Topic>,
+    #[serde(default)]
+    pub prefixes: HashSet<Topic>,
+    pub lattices: HashSet<Namespace>,
+    pub users: HashSet<SessionId>,
+}
+
+/// State read from disk on pool startup
+pub struct Restored {
+    /// Lattice updates in the order they should be applied
+    pub lattices: Vec<(Namespace, Delta)>,
+    pub sessions: HashMap<SessionId, SessionState>,
+}
+
+pub struct Journal {
+    snapshot_path: PathBuf,
+    journal_path: PathBuf,
+    settings: Persistence,
+    file: BufWriter<File>,
+    /// Last written state of each session, to skip unchanged ones
+    sessions: HashMap<SessionId, SessionState>,
+    next_snapshot: Instant,
+}
+
+#[derive(Deserialize)]
+enum Entry {
+    Lattice(Namespace, Delta),
+    Session(SessionId, SessionState),
+}
+
+#[derive(Serialize)]
+enum EntryRef<'a> {
+    Lattice(&'a Namespace, &'a Delta),
+    Session(&'a SessionId, &'a SessionState),
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    lattices: HashMap<Namespace, Delta>,
+    sessions: HashMap<SessionId, SessionState>,
+}
+
+/// Serializes the same way as `Snapshot` without cloning lattices
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    lattices: HashMap<&'a Namespace, DeltaRef<'a>>,
+    sessions: &'a HashMap<SessionId, SessionState>,
+}
+
+#[derive(Serialize)]
+struct DeltaRef<'a> {
+    shared: &'a HashMap<LatticeKey, Values>,
+    private: &'a HashMap<SessionId, HashMap<LatticeKey, Values>>,
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl Journal {
+    /// Reads snapshot and journal of the pool and opens journal for appending
+    pub fn open(name: &SessionPoolName, settings: &Persistence)
+        -> Result<(Journal, Restored), io::Error>
+    {
+        create_dir_all(&settings.directory)?;
+        let snapshot_path = settings.directory
+            .join(format!("{}.snapshot", &name[..]));
+        let journal_path = settings.directory
+            .join(format!("{}.journal", &name[..]));
+
+        let mut restored = Restored {
+            lattices: Vec::new(),
+            sessions: HashMap::new(),
+        };
+        match File::open(&snapshot_path) {
+            Ok(mut f) => {
+                // values are deserialized from borrowed strings,
+                // so can't use `from_reader`
+                let mut buf = String::new();
+                f.read_to_string(&mut buf)?;
+                let snapshot: Snapshot = serde_json::from_str(&buf)
+                    .map_err(invalid_data)?;
+                restored.lattices.extend(snapshot.lattices);
+                restored.sessions = snapshot.sessions;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        match File::open(&journal_path) {
+            Ok(f) => replay(&journal_path, f, &mut restored)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let file = OpenOptions::new().append(true).create(true)
+            .open(&journal_path)?;
+        let journal = Journal {
+            snapshot_path,
+            journal_path,
+            settings: settings.clone(),
+            file: BufWriter::new(file),
+            sessions: restored.sessions.clone(),
+            next_snapshot: Instant::now() + settings.snapshot_interval,
+        };
+        Ok((journal, restored))
+    }
+
+    pub fn lattice(&mut self, namespace: &Namespace, delta: &Delta) {
+        self.write(&EntryRef::Lattice(namespace, delta));
+    }
+
+    /// Records state of the session if it has changed since the last time
+    pub fn session(&mut self, session_id: &SessionId, state: SessionState) {
+        let old = self.sessions.get(session_id)
+            .unwrap_or(&SessionState::default()) == &state;
+        if old {
+            return;
+        }
+        self.write(&EntryRef::Session(session_id, &state));
+        if state == SessionState::default() {
+            self.sessions.remove(session_id);
+        } else {
+            self.sessions.insert(session_id.clone(), state);
+        }
+    }
+
+    fn write(&mut self, entry: &EntryRef) {
+        let result = serde_json::to_writer(&mut self.file, entry)
+            .map_err(io::Error::from)
+            .and_then(|()| self.file.write_all(b"\n"));
+        if let Err(e) = result {
+            error!("Can't write journal {:?}: {}", self.journal_path, e);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(e) = self.file.flush() {
+            error!("Can't write journal {:?}: {}", self.journal_path, e);
+        }
+    }
+
+    pub fn next_snapshot(&self) -> Instant {
+        self.next_snapshot
+    }
+
+    /// Writes full state and starts a new journal
+    pub fn snapshot(&mut self, timestamp: Instant,
+        lattices: &HashMap<Namespace, Lattice>)
+    {
+        self.next_snapshot = timestamp + self.settings.snapshot_interval;
+        match self.write_snapshot(lattices) {
+            Ok(()) => {
+                debug!("Written snapshot {:?}", self.snapshot_path);
+            }
+            Err(e) => {
+                // journal is kept, so no changes are lost
+                error!("Can't write snapshot {:?}: {}",
+                    self.snapshot_path, e);
+            }
+        }
+    }
+
+    fn write_snapshot(&mut self, lattices: &HashMap<Namespace, Lattice>)
+        -> Result<(), io::Error>
+    {
+        let tmp_path = self.snapshot_path.with_extension("snapshot.tmp");
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut file, &SnapshotRef {
+            lattices: lattices.iter().map(|(ns, lat)| (ns, DeltaRef {
+                shared: &lat.shared,
+                private: &lat.private,
+            })).collect(),
+            sessions: &self.sessions,
+        })?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        rename(&tmp_path, &self.snapshot_path)?;
+        // everything journaled so far is in the snapshot
+        self.file = BufWriter::new(File::create(&self.journal_path)?);
+        Ok(())
+    }
+}
+
+fn replay(path: &PathBuf, file: File, restored: &mut Restored)
+    -> Result<(), io::Error>
+{
+    for (lineno, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        match serde_json::from_str(&line) {
+            Ok(Entry::Lattice(namespace, delta)) => {
+                restored.lattices.push((namespace, delta));
+            }
+            Ok(Entry::Session(session_id, state)) => {
+                if state == SessionState::default() {
+                    restored.sessions.remove(&session_id);
+                } else {
+                    restored.sessions.insert(session_id, state);
+                }
+            }
+            Err(e) => {
+                // most likely the last write was interrupted
+                warn!("Journal {:?} is broken at line {}: {}. \
+                    The rest of it is skipped.", path, lineno + 1, e);
+                break;
+            }
+        }
+    }
+    Ok(())
+}
@@ -10,7 +10,9 @@ use crate::intern::{Topic, SessionId, SessionPoolName, Lattice as Namespace};
 use crate::intern::{LatticeKey};
 use crate::config;
 use crate::chat::{Cid, CloseReason, ConnectionSender};
-use super::{ConnectionMessage, PoolMessage};
+use crate::chat::cid::PubCid;
+use crate::runtime::ServerId;
+use super::{ConnectionMessage, PoolMessage, Query};
 use super::session::Session;
 use super::connection::{NewConnection, Connection};
 use super::heap::HeapMap;
@@ -20,8 +22,9 @@ use crate::chat::processor::{SWINDON_USER, STATUS_VAR};
 use crate::chat::processor::{ACTIVE_STATUS, INACTIVE_STATUS, OFFLINE_STATUS};
 use crate::chat::processor::pair::PairCollection;
 use crate::chat::processor::lattice::Expires;
-use crate::chat::processor::lattice::{PRIVATE_KEYS, PRIVATE_COUNTERS, PRIVATE_SETS};
-use crate::chat::processor::lattice::{PRIVATE_REGISTERS};
+use crate::chat::processor::persist::{Journal, SessionState};
+use crate::chat::processor::history::History;
+use crate::chat::processor::trie::PrefixTrie;
 
 lazy_static! {
     pub static ref ACTIVE_SESSIONS: Integer = Integer::new();
@@ -55,12 +58,19 @@ pub struct Pool {
     pending_connections: HashMap<Cid, NewConnection>,
     connections: HashMap<Cid, Connection>,
     topics: HashMap<Topic, HashMap<Cid, Subscription>>,
+    prefixes: PrefixTrie<Cid, Subscription>,
     lattices: HashMap<Namespace, Lattice>,
     user_listeners: HashMap<SessionId, HashSet<SessionId>>,
+    history: Option<History>,
+
+    journal: Option<Journal>,
+    /// Sessions read from disk that have not reconnected yet
+    restored: HashMap<SessionId, SessionState>,
 
     last_lattice_cleanup: Instant,
     // Setings
     new_connection_timeout: Duration,
+    config: Arc<config::SessionPool>,
 }
 
 impl Sessions {
@@ -94,21 +104,47 @@ impl Sessions {
 
 impl Pool {
 
-    pub fn new(_name: SessionPoolName, cfg: Arc<config::SessionPool>,
+    pub fn new(name: SessionPoolName, cfg: Arc<config::SessionPool>,
         channel: Sender<PoolMessage>)
         -> Pool
     {
-        Pool {
+        let mut pool = Pool {
             channel: channel,
             sessions: Sessions::new(),
             pending_connections: HashMap::new(),
             connections: HashMap::new(),
             topics: HashMap::new(),
+            prefixes: PrefixTrie::new(),
             lattices: HashMap::new(),
             user_listeners: HashMap::new(),
+            history: cfg.topic_history.map(History::new),
+            journal: None,
+            restored: HashMap::new(),
             new_connection_timeout: (cfg.new_connection_idle_timeout).clone(),
             last_lattice_cleanup: Instant::now(),
+            config: cfg.clone(),
+        };
+        if let Some(ref settings) = cfg.persistence {
+            match Journal::open(&name, settings) {
+                Ok((journal, restored)) => {
+                    // journal is not set yet, so restored data
+                    // isn't written back
+                    for (namespace, delta) in restored.lattices {
+                        pool.lattice_update(namespace, delta);
+                    }
+                    info!("Session pool {:?}: restored {} lattices \
+                        and {} sessions", name,
+                        pool.lattices.len(), restored.sessions.len());
+                    pool.restored = restored.sessions;
+                    pool.journal = Some(journal);
+                }
+                Err(e) => {
+                    error!("Session pool {:?}: can't restore state, \
+                        persistence is disabled: {}", name, e);
+                }
+            }
         }
+        return pool;
     }
 
     pub fn add_connection(&mut self, conn_id: Cid,
@@ -123,6 +159,9 @@ impl Pool {
     pub fn associate(&mut self, conn_id: Cid, session_id: SessionId,
         timestamp: Instant, metadata: Arc<Json>)
     {
+        if let Some(state) = self.restored.remove(&session_id) {
+            self.restore_session(conn_id, state);
+        }
         let (mut conn, users_lattice) =
             if let Some(p) = self.pending_connections.remove(&conn_id) {
                 p.associate(session_id.clone())
@@ -135,10 +174,8 @@ impl Pool {
             if let Some(lat) = self.lattices.get(namespace) {
                 lattice_from(&mut conn.channel, namespace, &session_id, lat);
             } else {
-                error!("No lattice {:?} at the time \
-                    of connection association (connection {:?})",
-                    namespace, conn_id);
-                return
+                // empty namespaces are removed on cleanup
+                lattice_empty(&mut conn.channel, namespace);
             };
         }
 
@@ -149,6 +186,11 @@ impl Pool {
                 .expect("topics are consistent");
             *ptr = Subscription::Session;
         }
+        for prefix in &conn.prefixes {
+            let ptr = self.prefixes.get_mut(prefix, &conn_id)
+                .expect("prefixes are consistent");
+            *ptr = Subscription::Session;
+        }
         let now = SystemTime::now();
 
         let expire = timestamp + self.new_connection_timeout;
@@ -194,6 +236,60 @@ impl Pool {
         }
         let ins = self.connections.insert(conn_id, conn);
         debug_assert!(ins.is_none());
+        self.persist_session(&session_id);
+    }
+
+    /// Subscribes pending connection to everything the session had
+    /// before restart
+    fn restore_session(&mut self, conn_id: Cid, state: SessionState) {
+        for topic in state.topics {
+            self.subscribe(conn_id, topic, None);
+        }
+        for prefix in state.prefixes {
+            self.subscribe_prefix(conn_id, prefix, None);
+        }
+        for namespace in state.lattices {
+            self.lattice_attach(conn_id, namespace);
+        }
+        if state.users.len() > 0 {
+            self.users_attach(conn_id, state.users.into_iter().collect());
+        }
+    }
+
+    fn session_state(&self, session_id: &SessionId) -> SessionState {
+        if let Some(state) = self.restored.get(session_id) {
+            return state.clone();
+        }
+        let mut state = SessionState::default();
+        if let Some(sess) = self.sessions.get(session_id) {
+            for cid in &sess.connections {
+                if let Some(conn) = self.connections.get(cid) {
+                    state.topics.extend(conn.topics.iter().cloned());
+                    state.prefixes.extend(conn.prefixes.iter().cloned());
+                    state.lattices.extend(conn.lattices.iter().cloned());
+                }
+            }
+            state.users.extend(sess.users_lattice.peers.iter().cloned());
+        }
+        return state;
+    }
+
+    fn persist_session(&mut self, session_id: &SessionId) {
+        if self.journal.is_none() {
+            return;
+        }
+        let state = self.session_state(session_id);
+        if let Some(ref mut journal) = self.journal {
+            journal.session(session_id, state);
+        }
+    }
+
+    fn persist_connection(&mut self, cid: Cid) {
+        let session_id = self.connections.get(&cid)
+            .map(|conn| conn.session_id.clone());
+        if let Some(session_id) = session_id {
+            self.persist_session(&session_id);
+        }
     }
 
     fn publish_status(&self, session_id: &SessionId,
@@ -221,6 +317,9 @@ impl Pool {
             for top in &conn.topics {
                 unsubscribe(&mut self.topics, top, conn_id);
             }
+            for prefix in &conn.prefixes {
+                self.prefixes.remove(prefix, &conn_id);
+            }
             return;
         }
         let conn = self.connections.remove(&conn_id)
@@ -228,6 +327,9 @@ impl Pool {
         for topic in &conn.topics {
             unsubscribe(&mut self.topics, &topic, conn_id);
         }
+        for prefix in &conn.prefixes {
+            self.prefixes.remove(prefix, &conn_id);
+        }
         let session_id = conn.session_id;
 
         if self.sessions.inactive.contains_key(&session_id) {
@@ -267,6 +369,33 @@ impl Pool {
             session.connections.remove(&conn_id);
             // We delete it on inactivation
         }
+        self.persist_session(&session_id);
+    }
+
+    /// Stops the websocket, it's removed from the pool when closed
+    pub fn close_connection(&mut self, conn_id: Cid, reason: CloseReason) {
+        if let Some(conn) = self.connections.get_mut(&conn_id) {
+            conn.stop(reason);
+        } else if let Some(conn) = self.pending_connections.get_mut(&conn_id)
+        {
+            conn.stop(reason);
+        } else {
+            debug!("Connection {:?} does not exist any more", conn_id);
+        }
+    }
+
+    pub fn close_session(&mut self, session_id: &SessionId,
+        reason: CloseReason)
+    {
+        if let Some(sess) = self.sessions.get(session_id) {
+            for cid in &sess.connections {
+                if let Some(conn) = self.connections.get_mut(cid) {
+                    conn.stop(reason.clone());
+                }
+            }
+        } else {
+            debug!("Session {:?} does not exist any more", session_id);
+        }
     }
 
     pub fn update_activity(&mut self, sess_id: SessionId, activity_ts: Instant)
@@ -302,11 +431,30 @@ impl Pool {
     pub fn cleanup(&mut self, timestamp: Instant) -> Option<Instant> {
         let sess_ts = self.clean_sessions(timestamp);
         let lattice_ts = self.lattice_cleanup(timestamp);
-        sess_ts.iter().chain(lattice_ts.iter()).min().cloned()
+        let snapshot_ts = self.snapshot(timestamp);
+        let history_ts = self.history.as_mut()
+            .and_then(|h| h.cleanup(timestamp));
+        sess_ts.iter().chain(lattice_ts.iter()).chain(snapshot_ts.iter())
+            .chain(history_ts.iter())
+            .min().cloned()
+    }
+
+    fn snapshot(&mut self, timestamp: Instant) -> Option<Instant> {
+        let journal = self.journal.as_mut()?;
+        if journal.next_snapshot() <= timestamp {
+            journal.snapshot(timestamp, &self.lattices);
+        }
+        Some(journal.next_snapshot())
+    }
+
+    /// Writes buffered journal entries to disk
+    pub fn flush(&mut self) {
+        if let Some(ref mut journal) = self.journal {
+            journal.flush();
+        }
     }
 
     fn lattice_cleanup(&mut self, timestamp: Instant) -> Option<Instant> {
-        use crate::chat::processor::lattice::Expires::*;
         let interval = Duration::from_millis(LATTICE_CLEANUP_INTERVAL);
         if timestamp < self.last_lattice_cleanup + interval {
             if self.lattices.len() > 0 {
@@ -314,50 +462,20 @@ impl Pool {
             }
             return None;
         }
+        let sessions = &self.sessions;
         for (_name, lat) in &mut self.lattices {
-            let mut to_delete = Vec::new();
-            for (sess_id, data) in &mut lat.private {
-                let has_session = self.sessions.get(sess_id).is_some();
-                let mut delete_keys = Vec::new();
-                for (key, values) in data.iter_mut() {
-                    match values.expires {
-                        WithSession if !has_session => {
-                            delete_keys.push(key.clone());
-                        }
-                        WithSession => {}
-                        At(x) if x < timestamp => {
-                            delete_keys.push(key.clone());
-                        }
-                        At(..) => {}
-                        IfUnused(x) if x < timestamp => {
-                            if !has_session {
-                                delete_keys.push(key.clone());
-                            } else {
-                                values.expires = WithSession;
-                            }
-                        }
-                        IfUnused(..) => {}
-                    }
-                }
-                for key in delete_keys {
-                    if let Some(v) = data.remove(&key) {
-                        PRIVATE_KEYS.decr(1);
-                        PRIVATE_COUNTERS.decr(v.counters.len() as i64);
-                        PRIVATE_SETS.decr(v.sets.len() as i64);
-                        PRIVATE_REGISTERS.decr(v.registers.len() as i64);
-                    }
-                }
-                if data.is_empty() {
-                    to_delete.push(sess_id.clone());
-                }
-            }
-            for sess_id in to_delete {
-                lat.private.remove(&sess_id);
-            }
-            // TODO(tailhook) clean public data too
+            lat.cleanup(timestamp, |sess_id| sessions.get(sess_id).is_some());
+        }
+        let before = self.lattices.len();
+        self.lattices.retain(|_, lat| !lat.is_empty());
+        LATTICES.decr((before - self.lattices.len()) as i64);
+        // sessions that didn't reconnect since restart are forgotten
+        let restored = self.restored.drain().map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        for sess_id in restored {
+            self.persist_session(&sess_id);
         }
         self.last_lattice_cleanup = timestamp;
-        // TODO(tailhook) remove empty namespaces
         if self.lattices.len() > 0 {
             return Some(timestamp + interval);
         }
@@ -393,17 +511,34 @@ impl Pool {
         self.sessions.active.peek().map(|(_, &x, _)| x)
     }
 
-    pub fn subscribe(&mut self, cid: Cid, topic: Topic) {
+    pub fn subscribe(&mut self, cid: Cid, topic: Topic, since: Option<u64>)
+    {
+        let replay: Vec<(u64, Arc<Json>)> = match (since, &self.history) {
+            (Some(seq), &Some(ref history)) => {
+                history.since(&topic, seq)
+                    .map(|(seq, data)| (seq, data.clone()))
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
         if let Some(conn) = self.connections.get_mut(&cid) {
             conn.topics.insert(topic.clone());
+            for (seq, data) in replay {
+                conn.message(topic.clone(), data, Some(seq));
+            }
             self.topics.entry(topic)
                 .or_insert_with(|| {
                     TOPICS.incr(1);
                     HashMap::new()
                 })
                 .insert(cid, Subscription::Session);
+            let session_id = conn.session_id.clone();
+            self.persist_session(&session_id);
         } else if let Some(conn) = self.pending_connections.get_mut(&cid) {
             conn.topics.insert(topic.clone());
+            for (seq, data) in replay {
+                conn.message(topic.clone(), data, Some(seq));
+            }
             self.topics.entry(topic)
                 .or_insert_with(|| {
                     TOPICS.incr(1);
@@ -415,6 +550,55 @@ impl Pool {
         }
     }
 
+    /// Subscribes connection to all topics starting with `prefix.`
+    pub fn subscribe_prefix(&mut self, cid: Cid, prefix: Topic,
+        since: Option<u64>)
+    {
+        let replay = match (since, &self.history) {
+            (Some(seq), &Some(ref history)) => {
+                history.since_prefix(&prefix, seq)
+            }
+            _ => Vec::new(),
+        };
+        if let Some(conn) = self.connections.get_mut(&cid) {
+            conn.prefixes.insert(prefix.clone());
+            for (seq, topic, data) in replay {
+                conn.message(topic, data, Some(seq));
+            }
+            self.prefixes.insert(&prefix, cid, Subscription::Session);
+            let session_id = conn.session_id.clone();
+            self.persist_session(&session_id);
+        } else if let Some(conn) = self.pending_connections.get_mut(&cid) {
+            conn.prefixes.insert(prefix.clone());
+            for (seq, topic, data) in replay {
+                conn.message(topic, data, Some(seq));
+            }
+            self.prefixes.insert(&prefix, cid, Subscription::Pending);
+        } else {
+            debug!("Connection {:?} does not exist any more", cid);
+        }
+    }
+
+    pub fn unsubscribe_prefix(&mut self, cid: Cid, prefix: Topic) {
+        match self.prefixes.remove(&prefix, &cid) {
+            Some(Subscription::Pending) => {
+                self.pending_connections.get_mut(&cid)
+                    .expect("pending conns and prefixes are in sync")
+                    .prefixes.remove(&prefix);
+            }
+            Some(Subscription::Session) => {
+                let conn = self.connections.get_mut(&cid)
+                    .expect("conns and prefixes are in sync");
+                conn.prefixes.remove(&prefix);
+                let session_id = conn.session_id.clone();
+                self.persist_session(&session_id);
+            }
+            None => {
+                debug!("Connection {:?} does not exist any more", cid);
+            }
+        }
+    }
+
     pub fn unsubscribe(&mut self, cid: Cid, topic: Topic) {
         match unsubscribe(&mut self.topics, &topic, cid) {
             Some(Subscription::Pending) => {
@@ -423,9 +607,11 @@ impl Pool {
                     .topics.remove(&topic);
             }
             Some(Subscription::Session) => {
-                self.connections.get_mut(&cid)
-                    .expect("pending conns and topics are in sync")
-                    .topics.remove(&topic);
+                let conn = self.connections.get_mut(&cid)
+                    .expect("pending conns and topics are in sync");
+                conn.topics.remove(&topic);
+                let session_id = conn.session_id.clone();
+                self.persist_session(&session_id);
             }
             None => {
                 debug!("Connection {:?} does not exist any more", cid);
@@ -433,24 +619,61 @@ impl Pool {
         }
     }
 
-    pub fn publish(&mut self, topic: Topic, data: Arc<Json>) {
+    pub fn publish(&mut self, topic: Topic, data: Arc<Json>,
+        timestamp: Instant)
+    {
         PUBSUB_INPUT.incr(1);
-        if let Some(cids) = self.topics.get(&topic) {
-            for (cid, typ) in cids {
-                PUBSUB_OUTPUT.incr(1);
-                match *typ {
-                    Subscription::Pending => {
-                        self.pending_connections.get_mut(cid)
-                            .expect("subscriptions out of sync")
-                            .message(topic.clone(), data.clone())
-                    }
-                    Subscription::Session => {
-                        self.connections.get_mut(cid)
-                            .expect("subscriptions out of sync")
-                            .message(topic.clone(), data.clone())
-                    }
+        let seq = self.history.as_mut()
+            .map(|h| h.push(&topic, &data, timestamp));
+        let mut wildcard = HashMap::new();
+        self.prefixes.matches(&topic, |&cid, &typ| {
+            wildcard.insert(cid, typ);
+        });
+        let exact = self.topics.get(&topic);
+        if let Some(cids) = exact {
+            // connection subscribed both to the topic and to the prefix
+            // receives the message once
+            wildcard.retain(|cid, _| !cids.contains_key(cid));
+        }
+        let cids = exact.into_iter().flat_map(|x| x.iter())
+            .chain(wildcard.iter());
+        for (cid, typ) in cids {
+            PUBSUB_OUTPUT.incr(1);
+            match *typ {
+                Subscription::Pending => {
+                    self.pending_connections.get_mut(cid)
+                        .expect("subscriptions out of sync")
+                        .message(topic.clone(), data.clone(), seq)
+                }
+                Subscription::Session => {
+                    self.connections.get_mut(cid)
+                        .expect("subscriptions out of sync")
+                        .message(topic.clone(), data.clone(), seq)
+                }
+            }
+        }
+    }
+
+    pub fn direct_connection(&mut self, cid: Cid, data: Arc<Json>) {
+        if let Some(conn) = self.connections.get_mut(&cid) {
+            conn.direct(data);
+        } else if let Some(conn) = self.pending_connections.get_mut(&cid) {
+            conn.direct(data);
+        } else {
+            debug!("Connection {:?} does not exist any more", cid);
+        }
+    }
+
+    pub fn direct_session(&mut self, session_id: &SessionId, data: Arc<Json>)
+    {
+        if let Some(sess) = self.sessions.get(session_id) {
+            for cid in &sess.connections {
+                if let Some(conn) = self.connections.get_mut(cid) {
+                    conn.direct(data.clone());
                 }
             }
+        } else {
+            debug!("Session {:?} does not exist any more", session_id);
         }
     }
 
@@ -483,10 +706,10 @@ impl Pool {
         if let Some(lat) = self.lattices.get(&namespace) {
             lattice_from(&mut conn.channel, &namespace, &conn.session_id, lat);
         } else {
-            error!("No lattice {:?} at the time of attach (connection {:?})",
-                   namespace, cid);
-            return
+            // empty namespaces are removed on cleanup
+            lattice_empty(&mut conn.channel, &namespace);
         };
+        self.persist_connection(cid);
     }
 
     pub fn lattice_detach(&mut self, cid: Cid, namespace: Namespace) {
@@ -516,17 +739,22 @@ impl Pool {
         conn.lattices.remove(&namespace);
 
         // TODO(tailhook) maybe cleanup lattices now
+        self.persist_connection(cid);
     }
 
     pub fn lattice_update(&mut self,
         namespace: Namespace, delta: Delta)
     {
+        if let Some(ref mut journal) = self.journal {
+            journal.lattice(&namespace, &delta);
+        }
         let mut new_keys = HashMap::new();
+        let expiry = self.config.lattice_expiry(&namespace);
         let delta = {
             let lat = self.lattices.entry(namespace.clone())
                 .or_insert_with(|| {
                     LATTICES.incr(1);
-                    Lattice::new()
+                    Lattice::new(expiry)
                 });
 
             // Update subscriptions on **original delta**
@@ -644,6 +872,7 @@ impl Pool {
         let msg = ConnectionMessage::Lattice(SWINDON_USER.clone(),
             Arc::new(statuses));
         conn.channel.send(msg);
+        self.persist_connection(cid);
     }
     pub fn users_update(&mut self, session_id: SessionId, uids: Vec<SessionId>)
     {
@@ -664,6 +893,7 @@ impl Pool {
                 conn.channel.send(msg);
             }
         }
+        self.persist_session(&session_id);
     }
     pub fn users_detach(&mut self, cid: Cid) {
         let conn = if let Some(conn) = self.connections.get_mut(&cid) {
@@ -692,9 +922,89 @@ impl Pool {
         // of users anyway
         sess.users_lattice.peers.clear();
         sess.users_lattice.peers.shrink_to_fit();
+        self.persist_connection(cid);
     }
 
-    pub fn stop(self) {
+    /// Describes part of the pool state for the introspection API
+    ///
+    /// Connection ids are formatted as public ones using `server_id`
+    pub fn inspect(&self, query: Query, server_id: ServerId) -> Option<Json>
+    {
+        let pub_cid = |cid: &Cid| PubCid(*cid, server_id).to_string();
+        match query {
+            Query::Session(session_id) => {
+                let active = self.sessions.active.get(&session_id).is_some();
+                let sess = self.sessions.get(&session_id)?;
+                let mut connections = sess.connections.iter()
+                    .map(pub_cid).collect::<Vec<_>>();
+                connections.sort();
+                Some(json!({
+                    "active": active,
+                    "connections": connections,
+                    "lattices": sorted(sess.lattices.keys().map(|x| &x[..])),
+                    "users_lattice": sess.users_lattice.connections.len() > 0,
+                    "metadata": &*sess.metadata,
+                }))
+            }
+            Query::Connection(cid) => {
+                if let Some(conn) = self.connections.get(&cid) {
+                    Some(json!({
+                        "session_id": &conn.session_id[..],
+                        "topics": sorted(conn.topics.iter().map(|x| &x[..])),
+                        "prefixes":
+                            sorted(conn.prefixes.iter().map(|x| &x[..])),
+                        "lattices":
+                            sorted(conn.lattices.iter().map(|x| &x[..])),
+                        "users_lattice": conn.users_lattice,
+                    }))
+                } else if let Some(conn) = self.pending_connections.get(&cid)
+                {
+                    Some(json!({
+                        "session_id": null,
+                        "topics": sorted(conn.topics.iter().map(|x| &x[..])),
+                        "prefixes":
+                            sorted(conn.prefixes.iter().map(|x| &x[..])),
+                        "lattices":
+                            sorted(conn.lattices.iter().map(|x| &x[..])),
+                        "users_lattice": conn.users_lattice.len() > 0,
+                    }))
+                } else {
+                    None
+                }
+            }
+            Query::Topic(topic) => {
+                // same connections that receive a message published
+                let exact = self.topics.get(&topic);
+                let mut wildcard = HashSet::new();
+                self.prefixes.matches(&topic, |cid, _| {
+                    if !exact.map(|x| x.contains_key(cid)).unwrap_or(false) {
+                        wildcard.insert(*cid);
+                    }
+                });
+                Some(json!({
+                    "subscribers": exact.map(|x| x.len()).unwrap_or(0),
+                    "wildcard_subscribers": wildcard.len(),
+                }))
+            }
+            Query::Lattice(namespace, session_id) => {
+                let lattice = self.lattices.get(&namespace)?;
+                let private = lattice.private.iter()
+                    .filter(|&(sid, _)| {
+                        session_id.as_ref().map(|x| x == sid).unwrap_or(true)
+                    })
+                    .collect::<HashMap<_, _>>();
+                Some(json!({
+                    "shared": &lattice.shared,
+                    "private": private,
+                }))
+            }
+        }
+    }
+
+    pub fn stop(mut self) {
+        if let Some(mut journal) = self.journal.take() {
+            journal.snapshot(Instant::now(), &self.lattices);
+        }
         for (_, mut conn) in self.pending_connections {
             conn.stop(CloseReason::PoolStopped);
         }
@@ -713,7 +1023,6 @@ fn remove_lattice(session: &mut Session, session_id: &SessionId,
         e.get_mut().remove(&cid);
         if e.get().len() == 0 {
             e.remove_entry();
-            // TODO(tailhook) cleanup keys from lattice
             if let Occupied(mut lat) = lattices.entry(namespace.clone()) {
                 lat.get_mut().remove_session(session_id);
                 if lat.get().is_empty() {
@@ -767,6 +1076,12 @@ fn lattice_from(channel: &mut ConnectionSender,
     channel.send(msg);
 }
 
+fn lattice_empty(channel: &mut ConnectionSender, namespace: &Namespace) {
+    let msg = ConnectionMessage::Lattice(namespace.clone(),
+        Arc::new(HashMap::new()));
+    channel.send(msg);
+}
+
 fn get_user_statuses<'x, I>(peers: I, sessions: &Sessions)
     -> HashMap<LatticeKey, Values>
     where I: IntoIterator<Item=&'x SessionId>
@@ -805,14 +1120,23 @@ fn user_status_update(value: &Arc<Json>, timestamp: SystemTime)
 
     Values {
         counters: Default::default(),
+        pncounters: Default::default(),
         sets: Default::default(),
         registers: vec![
             (STATUS_VAR.clone(), Register(to_f64(timestamp), value.clone())),
         ].into_iter().collect(),
+        maps: Default::default(),
+        orsets: Default::default(),
         expires: Expires::WithSession,
     }
 }
 
+fn sorted<'x, I: Iterator<Item=&'x str>>(items: I) -> Vec<&'x str> {
+    let mut items = items.collect::<Vec<_>>();
+    items.sort();
+    items
+}
+
 fn copy_attachments(sess: &mut Session, conn: &Connection, cid: Cid,
     users_lattice: HashSet<SessionId>)
 {
@@ -830,6 +1154,9 @@ fn copy_attachments(sess: &mut Session, conn: &Connection, cid: Cid,
 
 #[cfg(test)]
 mod test {
+    use std::env;
+    use std::fs;
+    use std::process;
     use std::sync::Arc;
     use std::time::{Instant, Duration};
     use std::collections::HashMap;
@@ -838,18 +1165,28 @@ mod test {
     use futures::sync::mpsc::{unbounded as channel};
     use futures::sync::mpsc::{UnboundedReceiver as Receiver};
     use crate::intern::{SessionId, SessionPoolName, Lattice as Ns};
+    use crate::intern::{Topic, LatticeVar};
 
     use string_intern::{Symbol, Validator};
     use crate::config;
     use crate::config::listen::Listen;
-    use crate::chat::{Cid, ConnectionSender};
+    use crate::config::{Persistence, TopicHistory};
+    use crate::chat::{Cid, CloseReason, ConnectionSender, ConnectionReceiver};
+    use crate::request_id;
 
     use super::Pool;
-    use super::super::lattice::{Delta, Values};
-    use super::super::{PoolMessage, ConnectionMessage};
+    use super::super::lattice::{Delta, Values, Counter};
+    use super::super::{PoolMessage, ConnectionMessage, Query};
 
 
     fn pool() -> (Pool, Receiver<PoolMessage>) {
+        pool_with(None, None)
+    }
+
+    fn pool_with(persistence: Option<Persistence>,
+        history: Option<TopicHistory>)
+        -> (Pool, Receiver<PoolMessage>)
+    {
         let (tx, rx) = channel();
         let pool = Pool::new(SessionPoolName::from("test_pool"),
             Arc::new(config::SessionPool {
@@ -868,12 +1205,19 @@ mod test {
                 use_tangle_prefix: Some(false),
                 use_tangle_auth: Some(false),
                 weak_content_type: Some(false),
+                lattice_shared_idle_timeout: Duration::from_secs(60),
+                lattice_private_grace_period: Duration::from_secs(60),
+                lattice_namespaces: HashMap::new(),
+                persistence: persistence,
+                outbound_queue_size: 10_000,
+                outbound_queue_overflow: config::QueueOverflow::close,
+                topic_history: history,
             }),
             tx);
         return (pool, rx);
     }
 
-    fn add_u1(pool: &mut Pool) -> (Cid, Receiver<ConnectionMessage>) {
+    fn add_u1(pool: &mut Pool) -> (Cid, ConnectionReceiver) {
         let cid = Cid::new();
         let (tx, rx) = ConnectionSender::new();
         pool.add_connection(cid, tx);
@@ -882,7 +1226,7 @@ mod test {
         return (cid, rx);
     }
 
-    fn add_u2(pool: &mut Pool) -> (Cid, Receiver<ConnectionMessage>) {
+    fn add_u2(pool: &mut Pool) -> (Cid, ConnectionReceiver) {
         let cid = Cid::new();
         let (tx, rx) = ConnectionSender::new();
         pool.add_connection(cid, tx);
@@ -1021,4 +1365,170 @@ mod test {
         });
         // TODO(tailhook)
     }
+
+    #[test]
+    fn replay_history() {
+        let (mut pool, _rx) = pool_with(None, Some(TopicHistory {
+            max_messages: 10,
+            max_age: Duration::from_secs(60),
+        }));
+        let topic = Topic::from("room1");
+        let (c1, mut rx1) = add_u1(&mut pool);
+        pool.subscribe(c1, topic.clone(), None);
+        for i in 0..3 {
+            pool.publish(topic.clone(), Arc::new(json!(i)), Instant::now());
+        }
+        for i in 1..4 {
+            assert_matches!(get_item(&mut rx1),
+                ConnectionMessage::Publish(_, _, Some(seq)) if seq == i);
+        }
+        let (c2, mut rx2) = add_u2(&mut pool);
+        pool.subscribe(c2, topic.clone(), Some(1));
+        assert_matches!(get_item(&mut rx2),
+            ConnectionMessage::Publish(_, ref data, Some(2))
+            if **data == json!(1));
+        assert_matches!(get_item(&mut rx2),
+            ConnectionMessage::Publish(_, ref data, Some(3))
+            if **data == json!(2));
+        pool.publish(topic.clone(), Arc::new(json!(3)), Instant::now());
+        assert_matches!(get_item(&mut rx2),
+            ConnectionMessage::Publish(_, _, Some(4)));
+    }
+
+    #[test]
+    fn prefix_subscription() {
+        let (mut pool, _rx) = pool();
+        let (c1, mut rx1) = add_u1(&mut pool);
+        pool.subscribe_prefix(c1, Topic::from("orders"), None);
+        pool.subscribe(c1, Topic::from("orders.1"), None);
+        pool.publish(Topic::from("orders.1"), Arc::new(json!(1)),
+            Instant::now());
+        pool.publish(Topic::from("orders"), Arc::new(json!(2)),
+            Instant::now());
+        pool.publish(Topic::from("orders.2.items"), Arc::new(json!(3)),
+            Instant::now());
+        // subscribed twice, but message is received once
+        assert_matches!(get_item(&mut rx1),
+            ConnectionMessage::Publish(ref topic, _, None)
+            if *topic == Topic::from("orders.1"));
+        assert_matches!(get_item(&mut rx1),
+            ConnectionMessage::Publish(ref topic, _, None)
+            if *topic == Topic::from("orders.2.items"));
+        pool.unsubscribe_prefix(c1, Topic::from("orders"));
+        assert!(pool.prefixes.is_empty());
+        pool.del_connection(c1);
+        assert!(pool.topics.is_empty());
+    }
+
+    #[test]
+    fn close_session() {
+        let (mut pool, _rx) = pool();
+        let (_, mut rx1) = add_u1(&mut pool);
+        let (_, mut rx2) = add_u1(&mut pool);
+        let (c3, mut rx3) = add_u2(&mut pool);
+        pool.close_session(&SessionId::from("user1"),
+            CloseReason::Backend(4003, String::from("banned")));
+        for rx in vec![&mut rx1, &mut rx2] {
+            assert_matches!(get_item(rx),
+                ConnectionMessage::StopSock(CloseReason::Backend(4003, _)));
+        }
+        pool.close_connection(c3, CloseReason::Backend(1000, String::new()));
+        assert_matches!(get_item(&mut rx3),
+            ConnectionMessage::StopSock(CloseReason::Backend(1000, _)));
+    }
+
+    #[test]
+    fn direct_messages() {
+        let (mut pool, _rx) = pool();
+        let cid = Cid::new();
+        let (tx, mut rx) = ConnectionSender::new();
+        pool.add_connection(cid, tx);
+        pool.direct_connection(cid, Arc::new(json!("before")));
+        pool.associate(cid, SessionId::from("user1"), Instant::now(),
+            Arc::new(json!({"user_id": "user1"})));
+        let (_, mut rx2) = add_u1(&mut pool);
+        pool.direct_session(&SessionId::from("user1"),
+            Arc::new(json!("after")));
+        assert_matches!(get_item(&mut rx),
+            ConnectionMessage::Direct(ref data) if **data == json!("before"));
+        for rx in vec![&mut rx, &mut rx2] {
+            assert_matches!(get_item(rx),
+                ConnectionMessage::Direct(ref data)
+                if **data == json!("after"));
+        }
+    }
+
+    #[test]
+    fn inspect() {
+        let (mut pool, _rx) = pool();
+        let server_id = request_id::with_generator(request_id::new);
+        let (c1, _rx1) = add_u1(&mut pool);
+        let (c2, _rx2) = add_u2(&mut pool);
+        pool.subscribe(c1, Topic::from("room1.messages"), None);
+        pool.subscribe_prefix(c1, Topic::from("room1"), None);
+        pool.subscribe_prefix(c2, Topic::from("room1"), None);
+        let topic = Query::Topic(Topic::from("room1.messages"));
+        assert_eq!(pool.inspect(topic, server_id), Some(json!({
+            "subscribers": 1,
+            "wildcard_subscribers": 1,
+        })));
+        let session = Query::Session(SessionId::from("user1"));
+        assert_eq!(pool.inspect(session, server_id).unwrap()["connections"],
+            json!([format!("{}-{}", server_id, c1)]));
+        let conn = pool.inspect(Query::Connection(c1), server_id).unwrap();
+        assert_eq!(conn["session_id"], json!("user1"));
+        assert_eq!(conn["prefixes"], json!(["room1"]));
+        let lattice = Query::Lattice(Ns::from("rooms"), None);
+        assert_eq!(pool.inspect(lattice, server_id), None);
+        pool.lattice_update(Ns::from("rooms"), Delta {
+            shared: builder()
+                .add("room1", Values::new()),
+            private: builder()
+                .add("user1", builder()
+                    .add("room1", Values::new()))
+                .add("user2", builder()
+                    .add("room1", Values::new())),
+        });
+        let lattice = Query::Lattice(Ns::from("rooms"),
+            Some(SessionId::from("user2")));
+        assert_eq!(pool.inspect(lattice, server_id), Some(json!({
+            "shared": {"room1": {}},
+            "private": {"user2": {"room1": {}}},
+        })));
+    }
+
+    #[test]
+    fn restore_after_restart() {
+        let dir = env::temp_dir()
+            .join(format!("swindon-test-persist-{}", process::id()));
+        let settings = Persistence {
+            directory: dir.clone(),
+            snapshot_interval: Duration::from_secs(300),
+        };
+        let lat = Ns::from("rooms");
+        let mut values = Values::new();
+        values.counters.insert(LatticeVar::from("last_seen"), Counter(1));
+        {
+            let (mut pool, _rx) = pool_with(Some(settings.clone()), None);
+            let (c1, _rx1) = add_u1(&mut pool);
+            pool.subscribe(c1, Topic::from("room1"), None);
+            pool.lattice_update(lat.clone(), Delta {
+                shared: builder(),
+                private: builder()
+                    .add("user1", builder()
+                        .add("room1", values)),
+            });
+            pool.lattice_attach(c1, lat.clone());
+            // no snapshot is written, so state is read from the journal
+            pool.flush();
+        }
+        let (mut pool, _rx) = pool_with(Some(settings), None);
+        assert!(pool.lattices.contains_key(&lat));
+        let (c1, mut rx1) = add_u1(&mut pool);
+        assert!(pool.connections[&c1].topics.contains(&Topic::from("room1")));
+        assert!(pool.connections[&c1].lattices.contains(&lat));
+        assert_matches!(get_item(&mut rx1),
+            ConnectionMessage::Lattice(..));
+        fs::remove_dir_all(&dir).ok();
+    }
 }
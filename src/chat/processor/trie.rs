@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::intern::Topic;
+
+
+/// Wildcard subscriptions keyed by dot-separated components of the prefix
+///
+/// Subscription for prefix `orders` (i.e. `orders.*`) matches `orders.1`
+/// and `orders.1.items`, but not `orders` itself. Matching subscriptions
+/// are found in a single walk over the components of the published topic.
+pub struct PrefixTrie<K, V> {
+    subscribers: HashMap<K, V>,
+    children: HashMap<String, PrefixTrie<K, V>>,
+}
+
+impl<K: Hash + Eq, V> PrefixTrie<K, V> {
+    pub fn new() -> PrefixTrie<K, V> {
+        PrefixTrie {
+            subscribers: HashMap::new(),
+            children: HashMap::new(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty() && self.children.is_empty()
+    }
+    pub fn insert(&mut self, prefix: &Topic, key: K, value: V) -> Option<V> {
+        let mut node = self;
+        for part in prefix.split('.') {
+            node = node.children.entry(part.to_string())
+                .or_insert_with(PrefixTrie::new);
+        }
+        node.subscribers.insert(key, value)
+    }
+    pub fn get_mut(&mut self, prefix: &Topic, key: &K) -> Option<&mut V> {
+        let mut node = self;
+        for part in prefix.split('.') {
+            node = node.children.get_mut(part)?;
+        }
+        node.subscribers.get_mut(key)
+    }
+    /// Removes subscription and the nodes which become empty
+    pub fn remove(&mut self, prefix: &Topic, key: &K) -> Option<V> {
+        let parts = prefix.split('.').collect::<Vec<_>>();
+        self.remove_parts(&parts, key)
+    }
+    fn remove_parts(&mut self, parts: &[&str], key: &K) -> Option<V> {
+        if parts.len() == 0 {
+            return self.subscribers.remove(key);
+        }
+        let (value, empty) = {
+            let child = self.children.get_mut(parts[0])?;
+            let value = child.remove_parts(&parts[1..], key);
+            (value, child.is_empty())
+        };
+        if empty {
+            self.children.remove(parts[0]);
+        }
+        value
+    }
+    /// Calls `f` for every subscription matching the topic
+    pub fn matches<F: FnMut(&K, &V)>(&self, topic: &Topic, mut f: F) {
+        let mut node = self;
+        let mut parts = topic.split('.').peekable();
+        while let Some(part) = parts.next() {
+            // the last component of the topic must match the wildcard
+            if parts.peek().is_none() {
+                break;
+            }
+            node = match node.children.get(part) {
+                Some(child) => child,
+                None => break,
+            };
+            for (key, value) in &node.subscribers {
+                f(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::intern::Topic;
+    use super::PrefixTrie;
+
+    fn matches(trie: &PrefixTrie<u32, ()>, topic: &'static str) -> Vec<u32> {
+        let mut result = Vec::new();
+        trie.matches(&Topic::from(topic), |&k, _| result.push(k));
+        result.sort();
+        result
+    }
+
+    #[test]
+    fn prefixes() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(&Topic::from("orders"), 1, ());
+        trie.insert(&Topic::from("orders.1"), 2, ());
+        trie.insert(&Topic::from("users"), 3, ());
+        assert_eq!(matches(&trie, "orders"), Vec::<u32>::new());
+        assert_eq!(matches(&trie, "orders.1"), vec![1]);
+        assert_eq!(matches(&trie, "orders.1.items"), vec![1, 2]);
+        assert_eq!(matches(&trie, "orders2.1"), Vec::<u32>::new());
+        assert_eq!(matches(&trie, "users.x"), vec![3]);
+    }
+
+    #[test]
+    fn remove() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(&Topic::from("orders.1"), 1, ());
+        trie.insert(&Topic::from("orders"), 2, ());
+        assert_eq!(trie.remove(&Topic::from("orders"), &1), None);
+        assert_eq!(trie.remove(&Topic::from("orders.1"), &1), Some(()));
+        assert_eq!(matches(&trie, "orders.1.items"), vec![2]);
+        assert_eq!(trie.remove(&Topic::from("orders"), &2), Some(()));
+        assert!(trie.is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! Token bucket limits of websocket method calls
+use std::cmp::max;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+use crate::config::chat::{Chat, Bucket, Pattern};
+use crate::intern::{SessionId, SessionPoolName};
+
+
+/// Buckets are keyed by method pattern, `None` is for all methods
+type Buckets = HashMap<Option<Pattern>, TokenBucket>;
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Buckets of the sessions shared by their connections
+#[derive(Clone)]
+pub struct Sessions(Arc<Mutex<SessionsInner>>);
+
+struct SessionsInner {
+    buckets: HashMap<(SessionPoolName, SessionId), Weak<Mutex<Buckets>>>,
+    cleanup_at: usize,
+}
+
+pub enum Verdict {
+    Allow,
+    Reject,
+    /// Reject and close connection because of too many rejected calls
+    Close,
+}
+
+/// Rate limits state of a single connection
+pub struct Limiter {
+    settings: Arc<Chat>,
+    connection: Buckets,
+    session: Arc<Mutex<Buckets>>,
+    rejected: u32,
+}
+
+impl TokenBucket {
+    fn new(settings: &Bucket, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: settings.capacity() as f64,
+            updated: now,
+        }
+    }
+    fn refill(&mut self, settings: &Bucket, now: Instant) -> f64 {
+        if now > self.updated {
+            let elapsed = now - self.updated;
+            let elapsed = elapsed.as_secs() as f64 +
+                elapsed.subsec_nanos() as f64 / 1e9;
+            let per = settings.per.as_secs() as f64 +
+                settings.per.subsec_nanos() as f64 / 1e9;
+            self.tokens += elapsed * settings.calls as f64 / per;
+            if self.tokens > settings.capacity() as f64 {
+                self.tokens = settings.capacity() as f64;
+            }
+            self.updated = now;
+        }
+        self.tokens
+    }
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions(Arc::new(Mutex::new(SessionsInner {
+            buckets: HashMap::new(),
+            cleanup_at: 1024,
+        })))
+    }
+    /// Creates limiter for a new connection of the session
+    pub fn limiter(&self, settings: &Arc<Chat>, session_id: &SessionId)
+        -> Limiter
+    {
+        let mut inner = self.0.lock().expect("rate limits not poisoned");
+        let key = (settings.session_pool.clone(), session_id.clone());
+        let existing = inner.buckets.get(&key).and_then(|x| x.upgrade());
+        let session = match existing {
+            Some(session) => session,
+            None => {
+                let session = Arc::new(Mutex::new(HashMap::new()));
+                inner.buckets.insert(key, Arc::downgrade(&session));
+                session
+            }
+        };
+        // entries are removed when all connections of the session are gone
+        if inner.buckets.len() > inner.cleanup_at {
+            inner.buckets.retain(|_, x| x.upgrade().is_some());
+            inner.cleanup_at = max(inner.buckets.len()*2, 1024);
+        }
+        Limiter {
+            settings: settings.clone(),
+            connection: HashMap::new(),
+            session: session,
+            rejected: 0,
+        }
+    }
+}
+
+impl Limiter {
+    pub fn check(&mut self, method: &str) -> Verdict {
+        let limits = match self.settings.rate_limits {
+            Some(ref limits) => limits,
+            None => return Verdict::Allow,
+        };
+        let now = Instant::now();
+        let mut list = vec![
+            (None, false, limits.connection.as_ref()),
+            (None, true, limits.session.as_ref()),
+        ];
+        if let Some((pattern, m)) = limits.method(method) {
+            list.push((Some(pattern), false, m.connection.as_ref()));
+            list.push((Some(pattern), true, m.session.as_ref()));
+        }
+        let mut session = self.session.lock()
+            .expect("rate limits not poisoned");
+        let connection = &mut self.connection;
+        let buckets = list.into_iter()
+            .filter_map(|(pattern, is_session, settings)| {
+                settings.map(|s| (pattern.cloned(), is_session, s))
+            })
+            .collect::<Vec<_>>();
+        // tokens are taken only if the call fits all the buckets
+        let allowed = buckets.iter().all(|&(ref key, is_session, s)| {
+            let map = if is_session { &mut *session }
+                      else { &mut *connection };
+            map.entry(key.clone())
+                .or_insert_with(|| TokenBucket::new(s, now))
+                .refill(s, now) >= 1.0
+        });
+        if allowed {
+            for (key, is_session, _) in buckets {
+                let map = if is_session { &mut *session }
+                          else { &mut *connection };
+                map.get_mut(&key).map(|b| b.tokens -= 1.0);
+            }
+            self.rejected = 0;
+            return Verdict::Allow;
+        }
+        self.rejected += 1;
+        match limits.close_after {
+            Some(n) if self.rejected >= n => Verdict::Close,
+            _ => Verdict::Reject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+
+    use crate::config::chat::{Bucket, Pattern, MethodLimits, RateLimits};
+    use super::TokenBucket;
+
+    fn bucket(calls: u32, burst: Option<u32>) -> Bucket {
+        Bucket {
+            calls: calls,
+            per: Duration::from_secs(1),
+            burst: burst,
+        }
+    }
+
+    #[test]
+    fn refill() {
+        let settings = bucket(10, Some(2));
+        let start = Instant::now();
+        let mut b = TokenBucket::new(&settings, start);
+        assert_eq!(b.refill(&settings, start), 2.0);
+        b.tokens = 0.0;
+        let later = start + Duration::from_millis(150);
+        assert!((b.refill(&settings, later) - 1.5).abs() < 1e-6);
+        let later = start + Duration::from_secs(10);
+        assert_eq!(b.refill(&settings, later), 2.0);
+    }
+
+    #[test]
+    fn most_specific_method() {
+        let mut methods = BTreeMap::new();
+        methods.insert(Pattern::Glob("chat.".into()), MethodLimits {
+            connection: Some(bucket(1, None)),
+            session: None,
+        });
+        methods.insert(Pattern::Exact("chat.typing".into()), MethodLimits {
+            connection: Some(bucket(5, None)),
+            session: None,
+        });
+        let limits = RateLimits {
+            connection: None,
+            session: None,
+            methods: methods,
+            close_after: None,
+        };
+        assert_eq!(limits.method("chat.typing").unwrap().0,
+                   &Pattern::Exact("chat.typing".into()));
+        assert_eq!(limits.method("chat.send").unwrap().0,
+                   &Pattern::Glob("chat.".into()));
+        assert!(limits.method("user.info").is_none());
+    }
+}
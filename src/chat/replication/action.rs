@@ -5,7 +5,7 @@ use serde_json::Value as Json;
 use crate::runtime::ServerId;
 use crate::intern::{SessionId, SessionPoolName, Topic, Lattice as Namespace};
 use crate::config::Replication;
-use crate::chat::Cid;
+use crate::chat::{Cid, CloseReason};
 use crate::chat::processor::{Action, Delta};
 use super::OutgoingChannel;
 
@@ -40,12 +40,26 @@ pub enum RemoteAction {
         conn_id: Cid,
         server_id: ServerId,
         topic: Topic,
+        #[serde(default)]
+        since: Option<u64>,
     },
     Unsubscribe {
         conn_id: Cid,
         server_id: ServerId,
         topic: Topic,
     },
+    SubscribePrefix {
+        conn_id: Cid,
+        server_id: ServerId,
+        prefix: Topic,
+        #[serde(default)]
+        since: Option<u64>,
+    },
+    UnsubscribePrefix {
+        conn_id: Cid,
+        server_id: ServerId,
+        prefix: Topic,
+    },
     Publish {
         topic: Topic,
         // TODO: probably use String here
@@ -81,11 +95,35 @@ pub enum RemoteAction {
         server_id: ServerId,
     },
 
+    DirectConnection {
+        conn_id: Cid,
+        server_id: ServerId,
+        data: Arc<Json>,
+    },
+    DirectSession {
+        session_id: SessionId,
+        data: Arc<Json>,
+    },
+    CloseConnection {
+        conn_id: Cid,
+        server_id: ServerId,
+        code: u16,
+        reason: String,
+    },
+    CloseSession {
+        session_id: SessionId,
+        code: u16,
+        reason: String,
+    },
+
     // NOTE: In remote action we send original duration, not timestamp;
     UpdateActivity {
         session_id: SessionId,
         duration: Duration,
     },
+    Batch {
+        actions: Vec<RemoteAction>,
+    },
 }
 
 impl Into<Action> for RemoteAction {
@@ -94,10 +132,11 @@ impl Into<Action> for RemoteAction {
         // server_id is ignored here, because it's filtered
         // in Watcher::local_send
         match self {
-            Subscribe { conn_id, topic, server_id: _ } => {
+            Subscribe { conn_id, topic, since, server_id: _ } => {
                 Action::Subscribe {
                     conn_id: conn_id,
                     topic: topic,
+                    since: since,
                 }
             }
             Unsubscribe { conn_id, topic, server_id: _ } => {
@@ -106,6 +145,19 @@ impl Into<Action> for RemoteAction {
                     topic: topic,
                 }
             }
+            SubscribePrefix { conn_id, prefix, since, server_id: _ } => {
+                Action::SubscribePrefix {
+                    conn_id: conn_id,
+                    prefix: prefix,
+                    since: since,
+                }
+            }
+            UnsubscribePrefix { conn_id, prefix, server_id: _ } => {
+                Action::UnsubscribePrefix {
+                    conn_id: conn_id,
+                    prefix: prefix,
+                }
+            }
             Publish { topic, data } => {
                 Action::Publish {
                     topic: topic,
@@ -150,6 +202,35 @@ impl Into<Action> for RemoteAction {
                     conn_id: conn_id,
                 }
             }
+            DirectConnection { conn_id, data, server_id: _ } => {
+                Action::DirectConnection {
+                    conn_id: conn_id,
+                    data: data,
+                }
+            }
+            DirectSession { session_id, data } => {
+                Action::DirectSession {
+                    session_id: session_id,
+                    data: data,
+                }
+            }
+            CloseConnection { conn_id, code, reason, server_id: _ } => {
+                Action::CloseConnection {
+                    conn_id: conn_id,
+                    reason: CloseReason::Backend(code, reason),
+                }
+            }
+            CloseSession { session_id, code, reason } => {
+                Action::CloseSession {
+                    session_id: session_id,
+                    reason: CloseReason::Backend(code, reason),
+                }
+            }
+            Batch { actions } => {
+                Action::Batch {
+                    actions: actions.into_iter().map(|a| a.into()).collect(),
+                }
+            }
         }
     }
 }
@@ -2,7 +2,6 @@ use std::sync::Arc;
 use std::collections::HashMap;
 use std::time::{Instant, Duration};
 
-use async_slot as slot;
 use tokio_core::reactor::Handle;
 use tokio_core::reactor::Interval;
 use futures::{self, Future, Stream, Async, AsyncSink};
@@ -11,13 +10,13 @@ use futures::sync::oneshot::{channel as oneshot, Sender};
 use tk_http::websocket::Packet;
 use serde_json::to_string as json_encode;
 use ns_router::{Router};
-use void::Void;
 
 use crate::intern::SessionPoolName;
 use crate::runtime::{Runtime, ServerId};
-use crate::config::listen::Listen;
 use crate::config::{Replication};
 use crate::chat::processor::Processor;
+use crate::listener::Listener;
+use crate::tls::Acceptor;
 
 use super::{ReplAction, RemoteAction, IncomingChannel, OutgoingChannel};
 use super::action::Message;
@@ -27,7 +26,7 @@ use super::spawn::{listen, connect};
 pub struct ReplicationSession {
     pub remote_sender: RemoteSender,
     tx: IncomingChannel,
-    listener_channel: slot::Sender<Listen>,
+    listener: Listener,
     reconnect_shutter: Option<Sender<()>>,
 }
 
@@ -64,8 +63,8 @@ pub struct RemotePool {
 }
 
 impl ReplicationSession {
-    pub fn new(processor: Processor, resolver: &Router, handle: &Handle,
-        server_id: &ServerId, cfg: &Arc<Replication>)
+    pub fn new(processor: Processor, resolver: &Router, tls: &Acceptor,
+        handle: &Handle, server_id: &ServerId, cfg: &Arc<Replication>)
         -> ReplicationSession
     {
         let (tx, rx) = unbounded();
@@ -82,16 +81,14 @@ impl ReplicationSession {
             .map(|_| debug!("rx stopped"))
             .map_err(|_| debug!("watcher error")));
 
-        let (listen_tx, listen_rx) = slot::channel();
-        listen(
-            resolver.subscribe_stream(
-                listen_rx.map_err(|()| -> Void { unreachable!() }), 80),
-            tx.clone(), server_id, cfg, handle);
+        let (listener, incoming) = Listener::new(resolver, tls,
+            cfg.listen_error_timeout, handle, false);
+        listen(incoming, tx.clone(), server_id, cfg, handle);
 
         ReplicationSession {
             tx: tx.clone(),
             remote_sender: RemoteSender { queue: tx },
-            listener_channel: listen_tx,
+            listener: listener,
             reconnect_shutter: None,
         }
     }
@@ -99,7 +96,7 @@ impl ReplicationSession {
     pub fn update(&mut self, cfg: &Arc<Replication>,
         handle: &Handle, _runtime: &Arc<Runtime>)
     {
-        self.listener_channel.swap(cfg.listen.clone())
+        self.listener.update(&cfg.listen)
             .map_err(|_| error!("Can't update replication listener")).ok();
         // stop reconnecting
         if let Some(tx) = self.reconnect_shutter.take() {
@@ -136,20 +133,40 @@ impl Watcher {
     }
 
     fn local_send(&self, msg: Message) {
-        use super::RemoteAction::*;
         let Message(pool, action) = msg;
-        match action {
+        let action = match action {
+            RemoteAction::Batch { actions } => {
+                // only actions of local connections are applied
+                RemoteAction::Batch {
+                    actions: actions.into_iter()
+                        .filter(|a| self.is_local(a))
+                        .collect(),
+                }
+            }
+            action => {
+                if !self.is_local(&action) {
+                    debug!("Skipping remote action with non-local cid");
+                    return;
+                }
+                action
+            }
+        };
+        self.processor.send(&pool, action.into());
+    }
+
+    fn is_local(&self, action: &RemoteAction) -> bool {
+        use super::RemoteAction::*;
+        match *action {
             Subscribe { server_id, .. } |
             Unsubscribe { server_id, .. } |
+            SubscribePrefix { server_id, .. } |
+            UnsubscribePrefix { server_id, .. } |
+            CloseConnection { server_id, .. } |
+            DirectConnection { server_id, .. } |
             Attach { server_id, .. } |
-            Detach { server_id, .. } if self.server_id != server_id =>
-            {
-                debug!("Skipping remote action with non-local cid");
-                return;
-            }
-            _ => {}
+            Detach { server_id, .. } => self.server_id == server_id,
+            _ => true,
         }
-        self.processor.send(&pool, action.into());
     }
 
     fn remote_send(&mut self, msg: Message) {
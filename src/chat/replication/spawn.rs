@@ -11,10 +11,9 @@ use tk_http::server::{Proto, Config};
 use tk_http::websocket::client::HandshakeProto;
 use tk_http::websocket::{Config as WsConfig, Loop};
 use tk_http::websocket::{Dispatcher, Frame, Error};
-use tk_listen::{BindMany, ListenExt};
+use tk_listen::ListenExt;
 use tokio_core::net::{TcpStream};
 use tokio_core::reactor::{Timeout, Handle};
-use ns_router::future::AddrStream;
 
 use crate::config::Replication;
 use crate::runtime::ServerId;
@@ -22,9 +21,10 @@ use super::server::Incoming;
 use super::client::Authorizer;
 use super::{IncomingChannel, ReplAction};
 use crate::chat::replication::{CONNECTIONS, FRAMES_SENT, FRAMES_RECEIVED};
+use crate::listener;
 
 
-pub fn listen(addr_stream: AddrStream, sender: IncomingChannel,
+pub fn listen(incoming: listener::Incoming, sender: IncomingChannel,
     server_id: &ServerId, settings: &Arc<Replication>, handle: &Handle)
 {
     // TODO: setup proper configuration;
@@ -33,12 +33,15 @@ pub fn listen(addr_stream: AddrStream, sender: IncomingChannel,
     let srv_id = server_id.clone();
 
     handle.spawn(
-        BindMany::new(addr_stream.map(|addr| addr.addresses_at(0)), &h1)
-        .sleep_on_error(settings.listen_error_timeout, &handle)
-        .map(move |(socket, _)| {
+        incoming
+        .map(move |accept| {
             let disp = Incoming::new(sender.clone(), srv_id, &h1);
-            Proto::new(socket, &hcfg, disp, &h1)
-            .map_err(|e| debug!("Http protocol error: {}", e))
+            let hcfg = hcfg.clone();
+            let h1 = h1.clone();
+            accept.and_then(move |(socket, _)| {
+                Proto::new(socket, &hcfg, disp, &h1)
+                .map_err(|e| debug!("Http protocol error: {}", e))
+            })
         })
         .listen(settings.max_connections)
         .map(move |()| error!("Replication listener exited"))
@@ -1,11 +1,13 @@
 use std::collections::BTreeMap;
 use std::ops::Deref;
 use std::str::FromStr;
+use std::time::Duration;
 
 use serde::de::{Deserialize, Deserializer, Error};
-use quire::validate::{Structure, Scalar, Mapping};
+use quire::validate::{Structure, Scalar, Mapping, Numeric};
 
 use super::http;
+use super::websocket::{self, Deflate};
 use crate::intern::{HandlerName, SessionPoolName};
 use crate::config::visitors::FromStrVisitor;
 use crate::config::version::Version;
@@ -30,9 +32,11 @@ pub struct Chat {
     pub session_pool: SessionPoolName,
     pub http_route: Option<HandlerName>,
     pub message_handlers: RoutingTable,
+    pub permessage_deflate: Option<Deflate>,
+    pub rate_limits: Option<RateLimits>,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum Pattern {
     Default,
     Glob(String),
@@ -45,6 +49,35 @@ pub struct RoutingTable {
     pub map: BTreeMap<Pattern, http::Destination>,
 }
 
+/// Token bucket refilled with `calls` tokens every `per` interval
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Bucket {
+    pub calls: u32,
+    #[serde(with="::quire::duration")]
+    pub per: Duration,
+    /// Defaults to `calls`
+    pub burst: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct MethodLimits {
+    pub connection: Option<Bucket>,
+    pub session: Option<Bucket>,
+}
+
+/// Limits of websocket method calls
+///
+/// A call must fit both in the limits for all methods and in the limits
+/// of the most specific method pattern matching the call.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct RateLimits {
+    pub connection: Option<Bucket>,
+    pub session: Option<Bucket>,
+    pub methods: BTreeMap<Pattern, MethodLimits>,
+    /// Close websocket after this many rejected calls in a row
+    pub close_after: Option<u32>,
+}
+
 impl Chat {
     pub fn allow_empty_subprotocol(&self) -> bool {
         self.compatibility <= Compatibility::v0_5_4
@@ -68,6 +101,35 @@ pub fn validator<'x>() -> Structure<'x> {
     .member("http_route", http::destination_validator().optional())
     .member("message_handlers",
         Mapping::new(Scalar::new(), http::destination_validator()))
+    .member("permessage_deflate", websocket::deflate_validator().optional())
+    .member("rate_limits", Structure::new()
+        .member("connection", bucket_validator().optional())
+        .member("session", bucket_validator().optional())
+        .member("methods", Mapping::new(Scalar::new(), Structure::new()
+            .member("connection", bucket_validator().optional())
+            .member("session", bucket_validator().optional())))
+        .member("close_after", Numeric::new().min(1).optional())
+        .optional())
+}
+
+fn bucket_validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("calls", Numeric::new().min(1))
+    .member("per", Scalar::new().min_length(1).default("1s"))
+    .member("burst", Numeric::new().min(1).optional())
+}
+
+impl Bucket {
+    pub fn capacity(&self) -> u32 {
+        self.burst.unwrap_or(self.calls)
+    }
+}
+
+impl RateLimits {
+    /// Returns the most specific pattern matching the method, if any
+    pub fn method(&self, method: &str) -> Option<(&Pattern, &MethodLimits)> {
+        self.methods.iter().rev().find(|&(k, _)| k.matches(method))
+    }
 }
 
 impl FromStr for Pattern {
@@ -146,6 +208,8 @@ impl<'a> Deserialize<'a> for Chat {
             session_pool: SessionPoolName,
             http_route: Option<HandlerName>,
             message_handlers: RoutingTable,
+            permessage_deflate: Option<Deflate>,
+            rate_limits: Option<RateLimits>,
         }
 
         let int = Internal::deserialize(d)?;
@@ -164,6 +228,8 @@ impl<'a> Deserialize<'a> for Chat {
             session_pool: int.session_pool,
             http_route: int.http_route,
             message_handlers: int.message_handlers,
+            permessage_deflate: int.permessage_deflate,
+            rate_limits: int.rate_limits,
         })
     }
 }
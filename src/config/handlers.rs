@@ -8,6 +8,7 @@ use super::proxy;
 use super::redirect;
 use super::self_status;
 use super::static_files;
+use super::websocket;
 
 
 #[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -24,7 +25,7 @@ pub enum Handler {
     /// guaranteed to work in forward compatible manner. We use it for
     /// autobahn tests, but we might choose to change test suite, so don't use
     /// it for something serious.
-    WebsocketEcho,
+    WebsocketEcho(Arc<websocket::WebsocketEcho>),
     BaseRedirect(Arc<redirect::BaseRedirect>),
     StripWWWRedirect,
     SelfStatus(Arc<self_status::SelfStatus>),
@@ -39,7 +40,7 @@ pub fn validator<'x>() -> Enum<'x> {
     .option("Proxy", proxy::validator())
     .option("HttpBin", Nothing)
     .option("EmptyGif", empty_gif::validator())
-    .option("WebsocketEcho", Nothing)
+    .option("WebsocketEcho", websocket::echo_validator())
     .option("BaseRedirect", redirect::base_redirect())
     .option("StripWWWRedirect", Nothing)
     .option("SelfStatus", self_status::validator())
@@ -1,17 +1,56 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use quire::validate::{Structure, Scalar, Enum, Numeric, Nothing};
-use quire::validate::{Sequence};
+use quire::validate::{Sequence, Mapping};
 
-#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum LoadBalancing {
+    /// Backends with spare connections first, then the least busy ones
     queue,
+    least_outstanding,
+    /// Smooth weighted round-robin
+    weighted,
+    /// Consistent hashing on the `hash-by` key
+    hash,
+}
+
+/// A part of the request which selects the backend for `hash` balancing
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum HashBy {
+    Header(String),
+    Cookie(String),
+    ClientIp,
+}
+
+/// Requests sent periodically to every backend to find out whether it's up
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct HealthCheck {
+    pub path: String,
+    #[serde(with="::quire::duration")]
+    pub interval: Duration,
+    #[serde(with="::quire::duration")]
+    pub timeout: Duration,
+    pub healthy_threshold: u32,
+    pub unhealthy_threshold: u32,
+}
+
+/// Ejection of backends which fail actual requests
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct OutlierDetection {
+    pub consecutive_failures: u32,
+    #[serde(with="::quire::duration")]
+    pub ejection_time: Duration,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
 pub struct Destination {
     pub load_balancing: LoadBalancing,
+    pub hash_by: Option<HashBy>,
+    /// Weights of the addresses, as they are written in `addresses`
+    pub weights: HashMap<String, u32>,
     pub queue_size_for_503: usize,
     pub backend_connections_per_ip_port: u32,
     pub in_flight_requests_per_backend_connection: usize,
@@ -24,14 +63,61 @@ pub struct Destination {
     pub safe_pipeline_timeout: Duration,
     pub override_host_header: Option<String>,
     pub request_id_header: Option<String>,
+    pub tls: bool,
+    pub tls_ca_bundle: Option<PathBuf>,
+    pub tls_client_certificate: Option<PathBuf>,
+    pub tls_client_key: Option<PathBuf>,
+    pub tls_server_name: Option<String>,
+    pub max_retries: u32,
+    #[serde(with="::quire::duration")]
+    pub retry_timeout: Duration,
+    pub retry_unsafe_methods: bool,
+    pub health_check: Option<HealthCheck>,
+    pub outlier_detection: Option<OutlierDetection>,
+}
+
+/// Returns path of the socket if address is in `unix:/path` form
+pub fn unix_path(address: &str) -> Option<&Path> {
+    if address.starts_with("unix:") {
+        Some(Path::new(&address["unix:".len()..]))
+    } else {
+        None
+    }
+}
+
+impl Destination {
+    /// Weight of the address by its index in `addresses`
+    pub fn weight(&self, index: usize) -> u32 {
+        self.weights.get(&self.addresses[index]).cloned().unwrap_or(1)
+    }
+    /// Returns paths of unix sockets, if destination refers to them
+    ///
+    /// Config validator ensures that either all or none of the addresses
+    /// are unix sockets.
+    pub fn unix_sockets(&self) -> Option<Vec<PathBuf>> {
+        self.addresses.iter()
+            .map(|a| unix_path(a).map(|p| p.to_path_buf()))
+            .collect()
+    }
 }
 
 pub fn validator<'x>() -> Structure<'x> {
     Structure::new()
     .member("load_balancing", Enum::new()
         .option("queue", Nothing)
+        .option("least_outstanding", Nothing)
+        .option("weighted", Nothing)
+        .option("hash", Nothing)
         .allow_plain()
         .plain_default("queue"))
+    .member("hash_by", Enum::new()
+        .option("Header", Scalar::new())
+        .option("Cookie", Scalar::new())
+        .option("ClientIp", Nothing)
+        .optional())
+    .member("weights", Mapping::new(
+        Scalar::new(),
+        Numeric::new().min(0).max(1000)))
     .member("queue_size_for_503",
         Numeric::new().min(0).max(1 << 32).default(100_000))
     .member("backend_connections_per_ip_port",
@@ -44,4 +130,26 @@ pub fn validator<'x>() -> Structure<'x> {
     .member("safe_pipeline_timeout", Scalar::new().default("300 ms"))
     .member("override_host_header", Scalar::new().optional())
     .member("request_id_header", Scalar::new().optional())
+    .member("tls", Scalar::new().default(false))
+    .member("tls_ca_bundle", Scalar::new().optional())
+    .member("tls_client_certificate", Scalar::new().optional())
+    .member("tls_client_key", Scalar::new().optional())
+    .member("tls_server_name", Scalar::new().optional())
+    .member("max_retries", Numeric::new().min(0).max(100).default(0))
+    .member("retry_timeout", Scalar::new().default("5 sec"))
+    .member("retry_unsafe_methods", Scalar::new().default(false))
+    .member("health_check", Structure::new()
+        .member("path", Scalar::new())
+        .member("interval", Scalar::new().default("5 sec"))
+        .member("timeout", Scalar::new().default("2 sec"))
+        .member("healthy_threshold",
+            Numeric::new().min(1).max(100).default(2))
+        .member("unhealthy_threshold",
+            Numeric::new().min(1).max(100).default(3))
+        .optional())
+    .member("outlier_detection", Structure::new()
+        .member("consecutive_failures",
+            Numeric::new().min(1).max(1000).default(5))
+        .member("ejection_time", Scalar::new().default("30 sec"))
+        .optional())
 }
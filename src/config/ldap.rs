@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use quire::validate::{Structure, Sequence, Mapping, Scalar};
 
@@ -8,6 +9,8 @@ use crate::intern::LdapUpstream;
 #[derive(Deserialize, PartialEq, Eq, Debug)]
 pub struct Destination {
     pub addresses: Vec<String>,
+    #[serde(with="::quire::duration")]
+    pub max_request_timeout: Duration,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
@@ -23,7 +26,7 @@ pub struct Ldap {
     pub destination: LdapUpstream,
     pub search_base: String,
     pub login_attribute: String,
-    pub password_attribute: String,
+    pub password_attribute: Option<String>,
     pub login_header: Option<String>,
     pub additional_queries: HashMap<String, Query>,
 }
@@ -32,6 +35,7 @@ pub struct Ldap {
 pub fn destination_validator<'x>() -> Structure<'x> {
     Structure::new()
     .member("addresses", Sequence::new(Scalar::new()).min_length(1))
+    .member("max_request_timeout", Scalar::new().default("10 sec"))
 }
 
 pub fn authorizer_validator<'x>() -> Structure<'x> {
@@ -39,7 +43,7 @@ pub fn authorizer_validator<'x>() -> Structure<'x> {
     .member("destination", Scalar::new())
     .member("search_base", Scalar::new())
     .member("login_attribute", Scalar::new())
-    .member("password_attribute", Scalar::new())
+    .member("password_attribute", Scalar::new().optional())
     .member("login_header", Scalar::new().optional())
     .member("additional_queries", Mapping::new(
         Scalar::new(),
@@ -1,15 +1,30 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use ns_router::AutoName;
-use quire::validate::{Enum, Scalar};
+use quire::ast::{Ast, Tag};
+use quire::validate::{Enum, Scalar, Structure, Numeric};
 
 
-#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize)]
 pub enum ListenSocket {
     Tcp(String),
-    // TODO(tailhook)
-    // Fd(u32)
-    // Unix(PathBuf)
+    /// TCP socket with TLS termination, certificates are taken from
+    /// the `tls-certificates` section
+    Tls(String),
+    Unix(UnixSocket),
+    /// Already listening socket passed by supervisor (socket activation)
+    Fd(u32),
+}
+
+/// Unix socket, permissions are applied right after socket is bound
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+    pub user: Option<String>,
+    pub group: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
@@ -22,6 +37,28 @@ impl Listen {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+    /// Returns only plain TCP sockets
+    pub fn plain(&self) -> Listen {
+        self.filter(|s| matches!(*s, ListenSocket::Tcp(_)))
+    }
+    /// Returns only sockets with TLS termination
+    pub fn tls(&self) -> Listen {
+        self.filter(|s| matches!(*s, ListenSocket::Tls(_)))
+    }
+    /// Returns unix sockets and file descriptors, i.e. sockets that don't
+    /// need name resolution
+    pub fn local(&self) -> Vec<ListenSocket> {
+        self.0.iter()
+            .filter(|s| matches!(**s,
+                ListenSocket::Unix(_) | ListenSocket::Fd(_)))
+            .cloned().collect()
+    }
+    pub fn has_tls(&self) -> bool {
+        self.0.iter().any(|s| matches!(*s, ListenSocket::Tls(_)))
+    }
+    fn filter<F: Fn(&ListenSocket) -> bool>(&self, f: F) -> Listen {
+        Listen::new(self.0.iter().filter(|s| f(s)).cloned().collect())
+    }
 }
 
 impl<'a> IntoIterator for &'a Listen {
@@ -31,12 +68,37 @@ impl<'a> IntoIterator for &'a Listen {
     fn into_iter(self) -> Self::IntoIter {
         self.0.iter().map(|x| match *x {
             ListenSocket::Tcp(ref s) => AutoName::Auto(s),
+            ListenSocket::Tls(ref s) => AutoName::Auto(s),
+            ListenSocket::Unix(_) | ListenSocket::Fd(_) => {
+                unreachable!("only tcp sockets are resolved")
+            }
         })
     }
 }
 
+/// Allows `!Unix /path` shortcut for a socket with default permissions
+fn unix_path(ast: Ast) -> BTreeMap<String, Ast> {
+    let mut res = BTreeMap::new();
+    match ast {
+        Ast::Scalar(pos, _, kind, path) => {
+            res.insert("path".to_string(),
+                Ast::Scalar(pos, Tag::NonSpecific, kind, path));
+        }
+        _ => unreachable!(),
+    }
+    return res;
+}
+
 pub fn validator<'x>() -> Enum<'x> {
     Enum::new()
     .option("Tcp", Scalar::new())
+    .option("Tls", Scalar::new())
+    .option("Unix", Structure::new()
+        .member("path", Scalar::new())
+        .member("mode", Numeric::new().min(0).max(0o7777).optional())
+        .member("user", Scalar::new().optional())
+        .member("group", Scalar::new().optional())
+        .parser(unix_path))
+    .option("Fd", Numeric::new().min(0))
     .default_tag("Tcp")
 }
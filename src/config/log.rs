@@ -1,6 +1,8 @@
+use std::path::PathBuf;
+
 use trimmer::{Template, Options, ParseError};
 use serde::de::{Deserialize, Deserializer, Error};
-use quire::validate::{Structure, Scalar};
+use quire::validate::{Structure, Scalar, Enum, Nothing};
 
 use crate::template;
 
@@ -15,6 +17,15 @@ lazy_static! {
 pub struct Format {
     pub template_source: String,
     pub template: Template,
+    pub output: Output,
+}
+
+/// Where log lines are written
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum Output {
+    Stdout,
+    /// File is opened in append mode, and reopened on `SIGHUP`
+    File(PathBuf),
 }
 
 impl<'a> Deserialize<'a> for Format {
@@ -22,16 +33,22 @@ impl<'a> Deserialize<'a> for Format {
         #[derive(Deserialize)]
         struct FormatRaw {
             template: String,
+            output: Option<Output>,
         }
         let raw = FormatRaw::deserialize(d)?;
-        Format::from_string(raw.template)
-            .map_err(|e| D::Error::custom(&format!("{}", e)))
+        let mut format = Format::from_string(raw.template)
+            .map_err(|e| D::Error::custom(&format!("{}", e)))?;
+        if let Some(output) = raw.output {
+            format.output = output;
+        }
+        Ok(format)
     }
 }
 
 impl PartialEq for Format {
     fn eq(&self, other: &Format) -> bool {
-        self.template_source == other.template_source
+        self.template_source == other.template_source &&
+        self.output == other.output
     }
 }
 
@@ -40,6 +57,10 @@ impl Eq for Format { }
 pub fn format_validator<'x>() -> Structure<'x> {
     Structure::new()
     .member("template", Scalar::new())
+    .member("output", Enum::new()
+        .option("Stdout", Nothing)
+        .option("File", Scalar::new())
+        .optional())
 }
 
 impl Format {
@@ -48,6 +69,7 @@ impl Format {
             template: template::PARSER
                 .parse_with_options(&*OPTIONS, &template)?,
             template_source: template,
+            output: Output::Stdout,
         })
     }
 }
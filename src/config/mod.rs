@@ -18,7 +18,9 @@ pub mod listen;
 pub mod log;
 pub mod networks;
 pub mod routing;
+pub mod tls;
 pub mod visitors;
+pub mod websocket;
 // handlers
 pub mod chat;
 pub mod static_files;
@@ -35,7 +37,8 @@ pub use self::handlers::Handler;
 pub use self::authorizers::Authorizer;
 pub use self::disk::Disk;
 pub use self::empty_gif::EmptyGif;
-pub use self::session_pools::{SessionPool};
+pub use self::session_pools::{SessionPool, LatticeExpiry, Persistence};
+pub use self::session_pools::{QueueOverflow, TopicHistory};
 pub use self::http::Destination;
 pub use self::redirect::BaseRedirect;
 pub use self::replication::Replication;
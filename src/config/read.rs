@@ -1,5 +1,5 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap, BTreeSet};
 use std::fmt;
 use std::fs::{File, Metadata, metadata};
 use std::io::{self, Read};
@@ -13,13 +13,17 @@ use quire::ast::{Ast, process as process_ast};
 use regex;
 
 use crate::config::authorizers::Authorizer;
+use crate::config::http_destinations::{self, LoadBalancing};
 use crate::config::root::{ConfigData, ConfigSource, Mixin};
 use crate::config::root::{config_validator, mixin_validator};
+use crate::config::routing::{Host, HostPath};
 use super::Handler;
 use crate::config::static_files::Mode;
 use crate::config::log;
 use crate::intern::{LogFormatName, Authorizer as AuthorizerName, HandlerName};
+use crate::ldap::parse_filter;
 use crate::routing::RoutingTable;
+use crate::tls::{self, KeyPair};
 
 
 quick_error! {
@@ -74,6 +78,10 @@ quick_error! {
             display("authorizer {:?} not found", name)
             description("authorizer not found")
         }
+        NoLogFormat(name: LogFormatName) {
+            display("log format {:?} not found", name)
+            description("log format not found")
+        }
     }
 }
 
@@ -198,6 +206,18 @@ pub fn read_config<P: AsRef<Path>>(filename: P)
         parse_config(filename, &config_validator(), &opt)?
     };
 
+    // Certificates are tracked like config files, so they are reloaded
+    // when changed
+    for (_, cert) in &src.tls_certificates {
+        for path in &[&cert.certificate, &cert.key] {
+            files.push((
+                path.to_path_buf(),
+                path.display().to_string(),
+                metadata(path)?,
+            ));
+        }
+    }
+
     for (prefix, ref incl) in &src.mixins {
         let incl_path = join_filename(filename, &Path::new(incl))
             .map_err(|()| Error::BadMixinPath(filename.to_path_buf()))?;
@@ -259,6 +279,16 @@ pub fn postprocess_config(mut src: ConfigSource)
                                Authorizer::AllowAll);
     }
 
+    let mut tls_certificates = BTreeMap::new();
+    for (name, cert) in &src.tls_certificates {
+        match KeyPair::read(&cert.certificate, &cert.key) {
+            Ok(pair) => {
+                tls_certificates.insert(name.clone(), pair);
+            }
+            Err(e) => err!("tls certificate {:?}: {}", name, e),
+        }
+    }
+
     let mut cfg = ConfigData {
         routing: RoutingTable::new(&src)?,
 
@@ -282,6 +312,7 @@ pub fn postprocess_config(mut src: ConfigSource)
         networks: src.networks,
         log_formats: src.log_formats,
         disk_pools: src.disk_pools,
+        tls_certificates: tls_certificates,
 
         replication: src.replication,
         debug_routing: src.debug_routing,
@@ -391,6 +422,23 @@ pub fn postprocess_config(mut src: ConfigSource)
             _ => {}
         }
     }
+    for (name, auth) in &cfg.authorizers {
+        match auth {
+            &Authorizer::Ldap(ref ldap) => {
+                if !cfg.ldap_destinations.contains_key(&ldap.destination) {
+                    err!("{:?}: unknown ldap destination {:?}",
+                         name, ldap.destination)
+                }
+                for (header, query) in &ldap.additional_queries {
+                    if let Err(e) = parse_filter(&query.filter) {
+                        err!("{:?}: invalid filter in {:?}: {}",
+                             name, header, e)
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
     // TODO: verify session_pool inactivity handlers
     for (name, s) in &cfg.session_pools {
         for dest in &s.inactivity_handlers {
@@ -400,6 +448,69 @@ pub fn postprocess_config(mut src: ConfigSource)
             }
         }
     }
+    for (name, dest) in &cfg.http_destinations {
+        let unix = dest.addresses.iter()
+            .filter(|a| http_destinations::unix_path(a).is_some())
+            .count();
+        if unix > 0 && unix != dest.addresses.len() {
+            err!("{:?}: unix sockets can't be mixed with \
+                  tcp addresses", name)
+        }
+        if unix > 0 && dest.tls {
+            err!("{:?}: `tls` can't be used with unix sockets", name)
+        }
+        match (dest.load_balancing, &dest.hash_by) {
+            (LoadBalancing::hash, &None) => {
+                err!("{:?}: `load-balancing: hash` requires `hash-by`", name)
+            }
+            (LoadBalancing::hash, &Some(_)) | (_, &None) => {}
+            (_, &Some(_)) => {
+                err!("{:?}: `hash-by` requires `load-balancing: hash`", name)
+            }
+        }
+        for addr in dest.weights.keys() {
+            if !dest.addresses.contains(addr) {
+                err!("{:?}: weight for unknown address {:?}", name, addr)
+            }
+        }
+        if let Some(ref check) = dest.health_check {
+            if !check.path.starts_with("/") {
+                err!("{:?}: health check path must start with a slash",
+                     name)
+            }
+        }
+        if dest.tls {
+            if let Err(e) = tls::Client::new(dest) {
+                err!("{:?}: {}", name, e)
+            }
+        } else if dest.tls_ca_bundle.is_some() ||
+            dest.tls_client_certificate.is_some() ||
+            dest.tls_client_key.is_some() ||
+            dest.tls_server_name.is_some()
+        {
+            err!("{:?}: `tls-*` settings require `tls: true`", name)
+        }
+    }
+
+    let has_tls = cfg.listen.has_tls() ||
+        cfg.replication.listen.has_tls() ||
+        cfg.session_pools.values().any(|p| p.listen.has_tls());
+    if has_tls {
+        if cfg.tls_certificates.len() == 0 {
+            return Err(Error::Validation(String::from(
+                "`!Tls` listening sockets require `tls-certificates`")));
+        }
+        let hosts = src.routing.keys()
+            .filter(|&&HostPath(Host(wildcard, _), _)| !wildcard)
+            .map(|&HostPath(Host(_, ref host), _)| host)
+            .collect::<BTreeSet<_>>();
+        for host in hosts {
+            if !cfg.tls_certificates.values().any(|p| p.valid_for(host)) {
+                warn!("No TLS certificate matches host {:?}, \
+                       fallback certificate will be used", host);
+            }
+        }
+    }
 
     Ok(cfg)
 }
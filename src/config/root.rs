@@ -20,7 +20,9 @@ use crate::config::log;
 use crate::config::networks;
 use crate::config::disk::{self, Disk};
 use crate::config::replication::{self, Replication};
+use crate::config::tls;
 use crate::routing::RoutingTable;
+use crate::tls::KeyPair;
 
 
 #[derive(Deserialize, PartialEq, Eq, Debug)]
@@ -73,6 +75,7 @@ pub struct ConfigSource {
     /// do is to update it's pool size, It's pool size can't be less than
     /// one, however.
     pub disk_pools: HashMap<DiskPoolName, Disk>,
+    pub tls_certificates: BTreeMap<String, tls::Certificate>,
 
     pub replication: Arc<Replication>,
     pub debug_routing: bool,
@@ -111,6 +114,9 @@ pub struct ConfigData {
     pub networks: HashMap<Network, networks::NetworkList>,
     pub log_formats: HashMap<LogFormatName, log::Format>,
     pub disk_pools: HashMap<DiskPoolName, Disk>,
+    /// Certificates are read while config is loaded, so that changes in
+    /// certificate files are picked up as config changes
+    pub tls_certificates: BTreeMap<String, KeyPair>,
 
     pub replication: Arc<Replication>,
     pub debug_routing: bool,
@@ -176,6 +182,9 @@ pub fn config_validator<'a>() -> Structure<'a> {
     .member("set_user", Scalar::new().optional())
     .member("set_group", Scalar::new().optional())
 
+    .member("tls_certificates",
+        Mapping::new(Scalar::new(), tls::validator()))
+
     .member("mixins", Mapping::new(Scalar::new(), Scalar::new()))
     .add_sections()
 }
@@ -5,7 +5,7 @@ use serde::de::{Deserializer, Deserialize};
 use quire::validate::{Mapping, Scalar};
 
 use crate::config::visitors::FromStrVisitor;
-use crate::intern::{HandlerName, Authorizer};
+use crate::intern::{HandlerName, Authorizer, LogFormatName};
 
 lazy_static! {
     static ref ROUTING_RE: Regex = Regex::new(
@@ -18,6 +18,7 @@ lazy_static! {
 pub struct RouteDef {
     pub handler: HandlerName,
     pub authorizer: Option<Authorizer>,
+    pub log: Option<LogFormatName>,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -75,7 +76,8 @@ pub fn validator<'x>() -> Mapping<'x> {
 
 impl<'a> Deserialize<'a> for RouteDef {
     fn deserialize<D: Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
-        d.deserialize_str(FromStrVisitor::new("route [@authorizer]"))
+        d.deserialize_str(FromStrVisitor::new(
+            "route [@authorizer] [->log-format]"))
     }
 }
 
@@ -85,6 +87,7 @@ impl FromStr for RouteDef {
         let mut val = val.trim();
         let mut handler = None;
         let mut authorizer = None;
+        let mut log = None;
         while val.len() > 0 {
             if let Some(m) = ROUTING_RE.captures(val) {
                 if let Some(dest) = m.get(5) {
@@ -101,8 +104,13 @@ impl FromStr for RouteDef {
                     } else {
                         authorizer = Some(auth.as_str().parse().unwrap());
                     }
-                } else if let Some(_) = m.get(2) {
-                    panic!("Logs are not implemented yet");
+                } else if let Some(name) = m.get(2) {
+                    if let Some(old) = log {
+                        return Err(format!("Two log formats {:?} and {:?}",
+                            old, name.as_str()));
+                    } else {
+                        log = Some(name.as_str().parse().unwrap());
+                    }
                 } else if let Some(name) = m.get(3) {
                     panic!("Key {:?} is not implemented yet", name);
                 }
@@ -115,6 +123,7 @@ impl FromStr for RouteDef {
             return Ok(RouteDef {
                 handler: dest,
                 authorizer: authorizer,
+                log: log,
             })
         } else {
             return Err(String::from("handler is required"));
@@ -133,6 +142,7 @@ mod test {
         assert_eq!(RouteDef::from_str("handler").unwrap(), RouteDef {
             handler: Symbol::from("handler"),
             authorizer: None,
+            log: None,
         });
     }
 
@@ -141,17 +151,43 @@ mod test {
         assert_eq!(RouteDef::from_str("handler@auth").unwrap(), RouteDef {
             handler: Symbol::from("handler"),
             authorizer: Some(Symbol::from("auth")),
+            log: None,
         });
         assert_eq!(RouteDef::from_str("handler   @auth").unwrap(),
             RouteDef {
                 handler: Symbol::from("handler"),
                 authorizer: Some(Symbol::from("auth")),
+                log: None,
             });
         assert_eq!(RouteDef::from_str("handler @auth").unwrap(), RouteDef {
             handler: Symbol::from("handler"),
             authorizer: Some(Symbol::from("auth")),
+            log: None,
         });
     }
+
+    #[test]
+    fn parse_log() {
+        assert_eq!(RouteDef::from_str("handler ->access").unwrap(),
+            RouteDef {
+                handler: Symbol::from("handler"),
+                authorizer: None,
+                log: Some(Symbol::from("access")),
+            });
+        assert_eq!(RouteDef::from_str("handler @auth ->access").unwrap(),
+            RouteDef {
+                handler: Symbol::from("handler"),
+                authorizer: Some(Symbol::from("auth")),
+                log: Some(Symbol::from("access")),
+            });
+        assert_eq!(RouteDef::from_str("->access handler").unwrap(),
+            RouteDef {
+                handler: Symbol::from("handler"),
+                authorizer: None,
+                log: Some(Symbol::from("access")),
+            });
+        assert!(RouteDef::from_str("handler ->a ->b").is_err());
+    }
 }
 
 #[cfg(test)]
@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Duration;
-use quire::validate::{Structure, Sequence, Scalar, Numeric};
+use quire::validate::{Structure, Sequence, Scalar, Numeric, Mapping};
+use quire::validate::{Enum, Nothing};
 
+use crate::intern::Lattice as Namespace;
 use super::listen::{self, Listen};
 use super::http;
 
@@ -22,6 +26,15 @@ pub struct SessionPool {
     // XXX: never used
     #[serde(with="::quire::duration")]
     pub client_default_idle_timeout: Duration,
+    #[serde(with="::quire::duration")]
+    pub lattice_shared_idle_timeout: Duration,
+    #[serde(with="::quire::duration")]
+    pub lattice_private_grace_period: Duration,
+    pub lattice_namespaces: HashMap<Namespace, LatticeExpiry>,
+    pub persistence: Option<Persistence>,
+    pub outbound_queue_size: usize,
+    pub outbound_queue_overflow: QueueOverflow,
+    pub topic_history: Option<TopicHistory>,
     #[serde(skip)]
     pub use_tangle_prefix: Option<bool>,
     #[serde(skip)]
@@ -30,6 +43,51 @@ pub struct SessionPool {
     pub weak_content_type: Option<bool>,
 }
 
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LatticeExpiry {
+    #[serde(with="::quire::duration")]
+    pub shared_idle_timeout: Duration,
+    #[serde(with="::quire::duration")]
+    pub private_grace_period: Duration,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Persistence {
+    pub directory: PathBuf,
+    #[serde(with="::quire::duration")]
+    pub snapshot_interval: Duration,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TopicHistory {
+    pub max_messages: usize,
+    #[serde(with="::quire::duration")]
+    pub max_age: Duration,
+}
+
+/// What to do when outbound queue of a connection is full
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum QueueOverflow {
+    /// Drop the oldest publish message, close if there is none
+    drop_oldest,
+    /// Merge lattice updates of the same namespace, close if it doesn't help
+    coalesce_lattices,
+    close,
+}
+
+
+impl SessionPool {
+    /// Expiry settings of the lattice namespace
+    pub fn lattice_expiry(&self, namespace: &Namespace) -> LatticeExpiry {
+        self.lattice_namespaces.get(namespace).cloned()
+            .unwrap_or(LatticeExpiry {
+                shared_idle_timeout: self.lattice_shared_idle_timeout,
+                private_grace_period: self.lattice_private_grace_period,
+            })
+    }
+}
+
 
 pub fn validator<'x>() -> Structure<'x> {
     Structure::new()
@@ -51,4 +109,30 @@ pub fn validator<'x>() -> Structure<'x> {
         Scalar::new().min_length(1).default("2h"))
     .member("client_default_idle_timeout",
         Scalar::new().min_length(1).default("1s"))
+    .member("lattice_shared_idle_timeout",
+        Scalar::new().min_length(1).default("60s"))
+    .member("lattice_private_grace_period",
+        Scalar::new().min_length(1).default("60s"))
+    .member("lattice_namespaces", Mapping::new(Scalar::new(),
+        Structure::new()
+        .member("shared_idle_timeout", Scalar::new().min_length(1))
+        .member("private_grace_period", Scalar::new().min_length(1))))
+    .member("persistence", Structure::new()
+        .member("directory", Scalar::new())
+        .member("snapshot_interval",
+            Scalar::new().min_length(1).default("5min"))
+        .optional())
+    .member("outbound_queue_size",
+        Numeric::new().min(1).max(1 << 31).default(10_000))
+    .member("outbound_queue_overflow", Enum::new()
+        .option("drop_oldest", Nothing)
+        .option("coalesce_lattices", Nothing)
+        .option("close", Nothing)
+        .allow_plain()
+        .plain_default("close"))
+    .member("topic_history", Structure::new()
+        .member("max_messages",
+            Numeric::new().min(1).max(1 << 31).default(100))
+        .member("max_age", Scalar::new().min_length(1).default("1h"))
+        .optional())
 }
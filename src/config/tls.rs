@@ -0,0 +1,17 @@
+use std::path::PathBuf;
+
+use quire::validate::{Structure, Scalar};
+
+
+/// Certificate used for TLS termination on `!Tls` listening sockets
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct Certificate {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+}
+
+pub fn validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("certificate", Scalar::new())
+    .member("key", Scalar::new())
+}
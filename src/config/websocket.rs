@@ -0,0 +1,35 @@
+use quire::validate::{Structure, Scalar, Numeric};
+
+
+/// Settings of the `permessage-deflate` extension (RFC 7692)
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Deflate {
+    pub server_max_window_bits: u8,
+    pub client_max_window_bits: u8,
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+    /// Smaller messages are sent uncompressed
+    pub min_message_size: usize,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct WebsocketEcho {
+    pub permessage_deflate: Option<Deflate>,
+}
+
+pub fn deflate_validator<'x>() -> Structure<'x> {
+    Structure::new()
+    // zlib doesn't support window of 256 bytes, so minimum is 9
+    .member("server_max_window_bits",
+        Numeric::new().min(9).max(15).default(15))
+    .member("client_max_window_bits",
+        Numeric::new().min(9).max(15).default(15))
+    .member("server_no_context_takeover", Scalar::new().default(false))
+    .member("client_no_context_takeover", Scalar::new().default(false))
+    .member("min_message_size", Numeric::new().min(0).default(256))
+}
+
+pub fn echo_validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("permessage_deflate", deflate_validator().optional())
+}
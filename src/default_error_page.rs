@@ -26,6 +26,14 @@ pub fn error_page<S: 'static>(status: Status, mut e: Encoder<S>)
     -> FutureResult<EncoderDone<S>, Error>
 {
     e.status(status);
+    error_page_body(status, e)
+}
+
+/// Same as `error_page` but status line (and possibly some headers) must be
+/// already written to the encoder
+pub fn error_page_body<S: 'static>(status: Status, mut e: Encoder<S>)
+    -> FutureResult<EncoderDone<S>, Error>
+{
     if status.response_has_body() {
         let status_var = StatusVar(status);
         let mut ctx = Context::new();
@@ -0,0 +1,18 @@
+//! Support of `permessage-deflate` websocket extension (RFC 7692)
+use tk_bufstream::{IoBuf, ReadBuf, WriteBuf};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+mod negotiate;
+mod stream;
+
+pub use self::negotiate::{negotiate, Params};
+pub use self::stream::DeflateStream;
+
+
+/// Wraps hijacked connection, so that messages are compressed transparently
+pub fn wrap<S: AsyncRead + AsyncWrite>(write_buf: WriteBuf<S>,
+    read_buf: ReadBuf<S>, params: Params)
+    -> (WriteBuf<DeflateStream<S>>, ReadBuf<DeflateStream<S>>)
+{
+    IoBuf::new(DeflateStream::new(write_buf, read_buf, params)).split()
+}
@@ -0,0 +1,171 @@
+use std::cmp::min;
+
+use tk_http::server::Head;
+
+use crate::config::websocket::Deflate;
+
+
+/// Parameters of `permessage-deflate` agreed with the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Params {
+    pub server_max_window_bits: u8,
+    /// Is `None` if the client didn't offer to limit its window
+    pub client_max_window_bits: Option<u8>,
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+    pub min_message_size: usize,
+}
+
+/// Picks the first acceptable `permessage-deflate` offer of the client
+pub fn negotiate(headers: &Head, settings: &Deflate) -> Option<Params> {
+    for (name, value) in headers.headers() {
+        if !name.eq_ignore_ascii_case("Sec-WebSocket-Extensions") {
+            continue;
+        }
+        let value = match ::std::str::from_utf8(value) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for offer in value.split(',') {
+            let mut parts = offer.split(';').map(|x| x.trim());
+            if parts.next() != Some("permessage-deflate") {
+                continue;
+            }
+            if let Some(params) = accept(parts, settings) {
+                return Some(params);
+            }
+        }
+    }
+    None
+}
+
+fn accept<'x, I>(offer: I, settings: &Deflate) -> Option<Params>
+    where I: Iterator<Item=&'x str>
+{
+    let mut params = Params {
+        server_max_window_bits: settings.server_max_window_bits,
+        client_max_window_bits: None,
+        server_no_context_takeover: settings.server_no_context_takeover,
+        client_no_context_takeover: settings.client_no_context_takeover,
+        min_message_size: settings.min_message_size,
+    };
+    let mut seen = Vec::new();
+    for param in offer {
+        let mut pair = param.splitn(2, '=');
+        let name = pair.next().unwrap_or("").trim();
+        let value = pair.next().map(|x| x.trim().trim_matches('"'));
+        // duplicate parameters make the offer invalid
+        if seen.contains(&name) {
+            return None;
+        }
+        seen.push(name);
+        match (name, value) {
+            ("server_no_context_takeover", None) => {
+                params.server_no_context_takeover = true;
+            }
+            ("client_no_context_takeover", None) => {
+                params.client_no_context_takeover = true;
+            }
+            ("server_max_window_bits", Some(bits)) => {
+                // zlib can't compress with window smaller than 9 bits
+                let bits = window_bits(bits).filter(|&x| x >= 9)?;
+                params.server_max_window_bits =
+                    min(params.server_max_window_bits, bits);
+            }
+            ("client_max_window_bits", None) => {
+                params.client_max_window_bits =
+                    Some(settings.client_max_window_bits);
+            }
+            ("client_max_window_bits", Some(bits)) => {
+                params.client_max_window_bits =
+                    Some(min(settings.client_max_window_bits,
+                             window_bits(bits)?));
+            }
+            _ => return None,
+        }
+    }
+    Some(params)
+}
+
+fn window_bits(value: &str) -> Option<u8> {
+    value.parse().ok().filter(|&x| x >= 8 && x <= 15)
+}
+
+impl Params {
+    /// Value of `Sec-WebSocket-Extensions` header of the response
+    pub fn response_header(&self) -> String {
+        let mut result = String::from("permessage-deflate");
+        if self.server_no_context_takeover {
+            result.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            result.push_str("; client_no_context_takeover");
+        }
+        if self.server_max_window_bits < 15 {
+            result.push_str(&format!("; server_max_window_bits={}",
+                self.server_max_window_bits));
+        }
+        match self.client_max_window_bits {
+            Some(bits) if bits < 15 => {
+                result.push_str(&format!("; client_max_window_bits={}",
+                    bits));
+            }
+            _ => {}
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::websocket::Deflate;
+    use super::{accept, Params};
+
+    fn settings() -> Deflate {
+        Deflate {
+            server_max_window_bits: 15,
+            client_max_window_bits: 12,
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            min_message_size: 0,
+        }
+    }
+
+    fn offer(s: &str) -> Option<Params> {
+        accept(s.split(';').map(|x| x.trim()).filter(|x| x.len() > 0),
+               &settings())
+    }
+
+    #[test]
+    fn plain() {
+        let params = offer("").unwrap();
+        assert_eq!(params.client_max_window_bits, None);
+        assert_eq!(params.response_header(), "permessage-deflate");
+    }
+
+    #[test]
+    fn window_bits() {
+        let params = offer("client_max_window_bits").unwrap();
+        assert_eq!(params.response_header(),
+            "permessage-deflate; client_max_window_bits=12");
+        let params = offer(
+            "server_max_window_bits=10; client_max_window_bits=\"9\"")
+            .unwrap();
+        assert_eq!(params.response_header(),
+            "permessage-deflate; server_max_window_bits=10; \
+             client_max_window_bits=9");
+        assert!(offer("server_max_window_bits=8").is_none());
+        assert!(offer("server_max_window_bits=16").is_none());
+        assert!(offer("server_max_window_bits").is_none());
+    }
+
+    #[test]
+    fn context_takeover() {
+        let params = offer("client_no_context_takeover").unwrap();
+        assert!(params.client_no_context_takeover);
+        assert!(!params.server_no_context_takeover);
+        assert!(offer("client_no_context_takeover; \
+                       client_no_context_takeover").is_none());
+        assert!(offer("unknown_param").is_none());
+    }
+}
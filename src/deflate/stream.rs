@@ -0,0 +1,315 @@
+use std::cmp::{min, max};
+use std::io::{self, Read, Write};
+
+use byteorder::{BigEndian, ByteOrder};
+use flate2::{Compress, Decompress, Compression};
+use flate2::{FlushCompress, FlushDecompress};
+use futures::{Async, Poll};
+use netbuf::Buf;
+use tk_bufstream::{ReadBuf, WriteBuf};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::Params;
+
+
+/// Same as the default packet size limit of `tk_http`
+const MAX_MESSAGE_SIZE: usize = 10 << 20;
+/// Appended to the compressed message before inflating (RFC 7692 7.2.2)
+const TAIL: [u8; 4] = [0, 0, 0xff, 0xff];
+
+const OPCODE_CONTINUATION: u8 = 0;
+const OPCODE_TEXT: u8 = 1;
+const OPCODE_BINARY: u8 = 2;
+
+
+/// Transport that inflates incoming and deflates outgoing messages
+///
+/// It works with raw websocket frames, so the websocket implementation
+/// on top of it sees a plain connection without extensions.
+pub struct DeflateStream<S> {
+    read: ReadBuf<S>,
+    write: WriteBuf<S>,
+    params: Params,
+    compress: Compress,
+    decompress: Decompress,
+    /// Inflated frames that are not read yet
+    input: Buf,
+    /// Opcode and payload of the compressed message being received
+    message: Option<(u8, Vec<u8>)>,
+    /// Frames written by websocket implementation but not processed yet
+    output: Buf,
+}
+
+struct Header {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    size: usize,
+    payload: usize,
+}
+
+fn protocol_error(text: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, text)
+}
+
+/// Returns `None` until the whole frame is in the buffer
+fn parse_frame(data: &[u8], max_size: usize)
+    -> Result<Option<Header>, io::Error>
+{
+    if data.len() < 2 {
+        return Ok(None);
+    }
+    let (payload, mut size) = match data[1] & 0x7F {
+        126 if data.len() < 4 => return Ok(None),
+        126 => (BigEndian::read_u16(&data[2..4]) as u64, 4),
+        127 if data.len() < 10 => return Ok(None),
+        127 => (BigEndian::read_u64(&data[2..10]), 10),
+        len => (len as u64, 2),
+    };
+    if payload > max_size as u64 {
+        return Err(protocol_error("frame is too large"));
+    }
+    let mask = if data[1] & 0x80 != 0 {
+        if data.len() < size + 4 {
+            return Ok(None);
+        }
+        let mut mask = [0u8; 4];
+        mask.copy_from_slice(&data[size..size+4]);
+        size += 4;
+        Some(mask)
+    } else {
+        None
+    };
+    let payload = payload as usize;
+    if data.len() < size + payload {
+        return Ok(None);
+    }
+    Ok(Some(Header {
+        fin: data[0] & 0x80 != 0,
+        rsv1: data[0] & 0x40 != 0,
+        opcode: data[0] & 0x0F,
+        mask: mask,
+        size: size,
+        payload: payload,
+    }))
+}
+
+fn write_header(buf: &mut Buf, first_byte: u8, mask: bool, len: usize) {
+    let mask_bit = if mask { 0x80 } else { 0 };
+    if len < 126 {
+        buf.extend(&[first_byte, mask_bit | len as u8]);
+    } else if len <= 0xFFFF {
+        let mut bytes = [first_byte, mask_bit | 126, 0, 0];
+        BigEndian::write_u16(&mut bytes[2..], len as u16);
+        buf.extend(&bytes);
+    } else {
+        let mut bytes = [first_byte, mask_bit | 127, 0, 0, 0, 0, 0, 0, 0, 0];
+        BigEndian::write_u64(&mut bytes[2..], len as u64);
+        buf.extend(&bytes);
+    }
+    if mask {
+        // websocket implementation unmasks client frames anyway,
+        // and a zero mask keeps inflated data as is
+        buf.extend(&[0, 0, 0, 0]);
+    }
+}
+
+fn zlib_error<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl<S: AsyncRead + AsyncWrite> DeflateStream<S> {
+    pub fn new(write: WriteBuf<S>, read: ReadBuf<S>, params: Params)
+        -> DeflateStream<S>
+    {
+        // client window is at most 15 bits, if it didn't tell otherwise
+        let client_bits = params.client_max_window_bits.unwrap_or(15);
+        DeflateStream {
+            compress: Compress::new_with_window_bits(Compression::default(),
+                false, params.server_max_window_bits),
+            // 8 bits of window aren't supported by zlib, but decompressor
+            // with a bigger window can read the data anyway
+            decompress: Decompress::new_with_window_bits(false,
+                max(client_bits, 9)),
+            read: read,
+            write: write,
+            params: params,
+            input: Buf::new(),
+            message: None,
+            output: Buf::new(),
+        }
+    }
+
+    /// Moves complete frames from the socket into `input`
+    fn process_input(&mut self) -> Result<(), io::Error> {
+        while let Some(h) =
+            parse_frame(&self.read.in_buf[..], MAX_MESSAGE_SIZE)?
+        {
+            let total = h.size + h.payload;
+            if h.opcode >= 8 || (!h.rsv1 && self.message.is_none()) {
+                if h.rsv1 {
+                    return Err(protocol_error(
+                        "control frame can't be compressed"));
+                }
+                self.input.extend(&self.read.in_buf[..total]);
+                self.read.in_buf.consume(total);
+                continue;
+            }
+            match (h.opcode, h.rsv1, self.message.is_some()) {
+                (OPCODE_TEXT, true, false) | (OPCODE_BINARY, true, false) => {
+                    self.message = Some((h.opcode, Vec::new()));
+                }
+                (OPCODE_CONTINUATION, false, true) => {}
+                _ => return Err(protocol_error("unexpected frame")),
+            }
+            {
+                let data = &mut self.message.as_mut().unwrap().1;
+                if data.len() + h.payload > MAX_MESSAGE_SIZE {
+                    return Err(protocol_error("message is too large"));
+                }
+                let start = data.len();
+                data.extend(&self.read.in_buf[h.size..total]);
+                if let Some(mask) = h.mask {
+                    for (i, byte) in data[start..].iter_mut().enumerate() {
+                        *byte ^= mask[i % 4];
+                    }
+                }
+            }
+            self.read.in_buf.consume(total);
+            if h.fin {
+                let (opcode, mut data) = self.message.take().unwrap();
+                data.extend(&TAIL);
+                let data = self.inflate(&data)?;
+                write_header(&mut self.input, 0x80 | opcode,
+                    true, data.len());
+                self.input.extend(&data);
+            }
+        }
+        Ok(())
+    }
+
+    fn inflate(&mut self, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let start = self.decompress.total_in();
+        let mut result = Vec::with_capacity(data.len()*2 + 64);
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            self.decompress.decompress_vec(&data[consumed..], &mut result,
+                FlushDecompress::Sync).map_err(zlib_error)?;
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if result.len() > MAX_MESSAGE_SIZE {
+                return Err(protocol_error("message is too large"));
+            }
+            if consumed == data.len() && result.len() < result.capacity() {
+                break;
+            }
+            let more = result.capacity();
+            result.reserve(more);
+        }
+        if self.params.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(result)
+    }
+
+    /// Moves complete frames from `output` into the socket buffer
+    fn process_output(&mut self) -> Result<(), io::Error> {
+        // our own frames aren't limited
+        while let Some(h) = parse_frame(&self.output[..], usize::MAX)? {
+            let total = h.size + h.payload;
+            // fragmented messages are rare and are sent uncompressed
+            if h.fin && !h.rsv1 && h.mask.is_none() &&
+                (h.opcode == OPCODE_TEXT || h.opcode == OPCODE_BINARY) &&
+                h.payload >= self.params.min_message_size
+            {
+                let data = self.deflate(h.size, total)?;
+                write_header(&mut self.write.out_buf, 0xC0 | h.opcode,
+                    false, data.len());
+                self.write.out_buf.extend(&data);
+            } else {
+                self.write.out_buf.extend(&self.output[..total]);
+            }
+            self.output.consume(total);
+        }
+        Ok(())
+    }
+
+    fn deflate(&mut self, start: usize, end: usize)
+        -> Result<Vec<u8>, io::Error>
+    {
+        let data = &self.output[start..end];
+        let start = self.compress.total_in();
+        let mut result = Vec::with_capacity(data.len()/2 + 64);
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress.compress_vec(&data[consumed..], &mut result,
+                FlushCompress::Sync).map_err(zlib_error)?;
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && result.len() < result.capacity() {
+                break;
+            }
+            let more = result.capacity();
+            result.reserve(more);
+        }
+        if result.ends_with(&TAIL) {
+            let len = result.len();
+            result.truncate(len - TAIL.len());
+        }
+        if self.params.server_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(result)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Read for DeflateStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.input.len() > 0 {
+                let n = min(buf.len(), self.input.len());
+                buf[..n].copy_from_slice(&self.input[..n]);
+                self.input.consume(n);
+                return Ok(n);
+            }
+            self.process_input()?;
+            if self.input.len() > 0 {
+                continue;
+            }
+            if self.read.read()? == 0 {
+                if self.read.done() {
+                    return Ok(0);
+                }
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Write for DeflateStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.extend(buf);
+        self.process_output()?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.write.flush()?;
+        if self.write.out_buf.len() > 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for DeflateStream<S> {}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for DeflateStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.flush() {
+            Ok(()) => Ok(Async::Ready(())),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                Ok(Async::NotReady)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -5,6 +5,7 @@ use std::fmt::{self, Write};
 #[derive(Clone, Debug)]
 pub enum Destination {
     Http(String, String),
+    Https(String, String),
     Path(String),
 }
 
@@ -26,7 +27,11 @@ impl FromStr for Destination {
                 String::from(pair.next().unwrap_or("")),
             ))
         } else if data.starts_with("https://") {
-            unimplemented!();
+            let mut pair = data[8..].splitn(2, '/');
+            Ok(Destination::Https(
+                String::from(pair.next().unwrap()),
+                String::from(pair.next().unwrap_or("")),
+            ))
         } else {
             Ok(Destination::Path(String::from(data)))
         }
@@ -108,7 +113,8 @@ fn _generate_config(buf: &mut String, port: u16, routes: &[Route],
                     writeln!(buf, "      Access-Control-Allow-Origin: '*'")?;
                 }
             }
-            Route { destination: Destination::Http(_, ref path), .. } => {
+            Route { destination: Destination::Http(_, ref path), .. }
+            | Route { destination: Destination::Https(_, ref path), .. } => {
                 writeln!(buf, "")?;
                 writeln!(buf, "  h{}: !Proxy", idx)?;
                 writeln!(buf, "    mode: forward")?;
@@ -138,6 +144,14 @@ fn _generate_config(buf: &mut String, port: u16, routes: &[Route],
                 writeln!(buf, "    request-id-header: X-Request-Id")?;
                 writeln!(buf, "    addresses: [{}]", host)?;
             }
+            Route { destination: Destination::Https(ref host, _), .. } => {
+                writeln!(buf, "")?;
+                writeln!(buf, "  d{}:", idx)?;
+                writeln!(buf, "    load-balancing: queue")?;
+                writeln!(buf, "    request-id-header: X-Request-Id")?;
+                writeln!(buf, "    addresses: [{}]", host)?;
+                writeln!(buf, "    tls: true")?;
+            }
         }
     }
     Ok(())
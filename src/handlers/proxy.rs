@@ -4,11 +4,11 @@ use crate::proxy::frontend::Codec;
 use tk_http::Status;
 use tk_http::server::RequestTarget::Authority;
 use crate::config::proxy::Proxy;
-use crate::incoming::{Request, Input};
+use crate::incoming::{Request, Input, Transport};
 use crate::default_error_page::serve_error_page;
 
 
-pub fn serve<S: 'static>(settings: &Arc<Proxy>, inp: Input)
+pub fn serve<S: Transport>(settings: &Arc<Proxy>, inp: Input)
     -> Request<S>
 {
     if inp.headers.host().is_none() {
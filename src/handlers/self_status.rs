@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::io::BufWriter;
 use std::sync::Arc;
 
@@ -8,6 +9,7 @@ use serde_json;
 use tk_http::Status;
 
 use crate::config::self_status::SelfStatus;
+use crate::http_pools::{self, BackendStatus};
 use crate::incoming::{reply, Request, Input};
 use crate::metrics;
 
@@ -27,6 +29,7 @@ pub fn serve<S: 'static>(settings: &Arc<SelfStatus>, inp: Input)
             process: ProcessReport<'a>,
             threads: ThreadReport<'a>,
             metrics: Json<'a, Vec<Box<dyn Collection>>>,
+            backends: BTreeMap<String, Vec<BackendStatus>>,
             config_fingerprint: String,
             version: &'a str,
         }
@@ -42,6 +45,7 @@ pub fn serve<S: 'static>(settings: &Arc<SelfStatus>, inp: Input)
                 process: meter.process_report(),
                 threads: meter.thread_report(),
                 metrics: Json(&metrics::all(&runtime)),
+                backends: http_pools::backends(&runtime.http_pools),
                 config_fingerprint: fingerprint,
                 version: env!("CARGO_PKG_VERSION"),
             }).expect("report is serializable");
@@ -7,21 +7,23 @@ use futures::future::{Either};
 use tk_http::Status;
 use tk_http::server::{Error, Codec, RecvMode};
 use tk_http::server as http;
-use tk_http::websocket::{self, ServerCodec as WebsocketCodec, Packet, Accept};
+use tk_http::websocket::{self, ServerCodec as WebsocketCodec, Accept};
+use tk_http::websocket::Packet;
 use tk_bufstream::{ReadBuf, WriteBuf};
 use futures::future::{ok};
-use futures::sync::mpsc::{UnboundedReceiver as Receiver};
 use tokio_core::reactor::Handle;
 use tokio_io::{AsyncRead, AsyncWrite};
-use serde_json::{to_string as json_encode, Value as Json};
+use serde_json::{Value as Json};
 
-use crate::chat::ConnectionMessage::{Hello, FatalError};
+use crate::chat::ConnectionMessage::{Hello, FatalError, StopSock};
 use crate::chat::MessageError::HttpError;
-use crate::chat::{self, Cid, ConnectionMessage, ConnectionSender};
+use crate::chat::{self, Cid, ConnectionSender, Encoding};
+use crate::chat::ConnectionReceiver;
 use crate::chat::{json_err, good_status};
 use crate::chat::tangle_auth::{SwindonAuth, TangleAuth};
 use crate::config::chat::{Chat};
 use crate::default_error_page::serve_error_page;
+use crate::deflate::{self, Params as Deflate};
 use crate::incoming::{Context, IntoContext};
 use crate::incoming::{Request, Input, Reply, Encoder, Transport};
 use crate::runtime::Runtime;
@@ -32,7 +34,9 @@ struct WebsockReply {
     runtime: Arc<Runtime>,
     settings: Arc<Chat>,
     reply_data: Option<ReplyData>,
-    channel: Option<(ConnectionSender, Receiver<ConnectionMessage>)>,
+    channel: Option<(ConnectionSender, ConnectionReceiver)>,
+    encoding: Encoding,
+    deflate: Option<Deflate>,
 }
 
 struct ReplyData {
@@ -65,10 +69,29 @@ impl<S: AsyncRead + AsyncWrite + 'static> Codec<S> for WebsockReply {
         if let Some(proto) = proto {
             e.add_header("Sec-Websocket-Protocol", proto);
         }
+        if let Some(ref deflate) = self.deflate {
+            e.add_header("Sec-WebSocket-Extensions",
+                deflate.response_header());
+        }
         e.done_headers();
         Box::new(ok(e.done()))
     }
     fn hijack(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>) {
+        match self.deflate.take() {
+            Some(params) => {
+                let (write_buf, read_buf) = deflate::wrap(
+                    write_buf, read_buf, params);
+                self.start(write_buf, read_buf)
+            }
+            None => self.start(write_buf, read_buf),
+        }
+    }
+}
+
+impl WebsockReply {
+    fn start<S>(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>)
+        where S: AsyncRead + AsyncWrite + 'static
+    {
         let inp = read_buf.framed(WebsocketCodec);
         let out = write_buf.framed(WebsocketCodec);
 
@@ -91,6 +114,7 @@ impl<S: AsyncRead + AsyncWrite + 'static> Codec<S> for WebsockReply {
         let r1 = self.runtime.clone();
         let s1 = self.settings.clone();
         let cid = self.cid;
+        let encoding = self.encoding;
 
         let (tx, rx) = self.channel.take()
             .expect("hijack called only once");
@@ -107,16 +131,25 @@ impl<S: AsyncRead + AsyncWrite + 'static> Codec<S> for WebsockReply {
                         } else {
                             Arc::new(format!("{}", SwindonAuth(&session_id)))
                         };
+                    let limiter = r1.session_pools.rate_limits
+                        .limiter(&s1, &session_id);
                     Either::A(
-                        out.send(Packet::Text(
-                            json_encode(&Hello(session_id.clone(), data))
-                            .expect("every message can be encoded")))
+                        out.send(encoding.packet(
+                            &Hello(session_id.clone(), data)))
                         .map_err(|e| info!("error sending userinfo: {:?}", e))
                         .and_then(move |out| {
-                            let rx = rx.map(|x| {
+                            let rx = rx.map(move |x| {
                                 chat::FRAMES_SENT.incr(1);
-                                Packet::Text(json_encode(&x)
-                                    .expect("any data can be serialized"))
+                                let frame = match x {
+                                    StopSock(ref r) => r.close_frame(),
+                                    _ => None,
+                                };
+                                match frame {
+                                    Some((code, reason)) => {
+                                        Packet::Close(code, reason.into())
+                                    }
+                                    None => encoding.packet(&x),
+                                }
                             }).map_err(|_| -> &str {
                                 // There shouldn't be a real-life case for
                                 // this.  But in case session-pool has been
@@ -140,6 +173,8 @@ impl<S: AsyncRead + AsyncWrite + 'static> Codec<S> for WebsockReply {
                                     runtime: r1,
                                     settings: s1,
                                     channel: tx,
+                                    encoding: encoding,
+                                    rate_limit: limiter,
                                 }, &cfg, &h2)
                             .map_err(|e| debug!("websocket closed: {}", e))
                         }))
@@ -154,11 +189,11 @@ impl<S: AsyncRead + AsyncWrite + 'static> Codec<S> for WebsockReply {
                     };
                     Either::B(Either::A(
                         // TODO(tailhook) optimize json
-                        out.send(Packet::Text(json_encode(&Json::Array(vec![
+                        out.send(encoding.packet(&Json::Array(vec![
                             "fatal_error".into(),
                             json_err(err),
                             data,
-                        ])).expect("can always serialize error")))
+                        ])))
                         .map_err(log_err_io)
                         .and_then(move |out| {
                             websocket::Loop::<_, _, _>::closing(out, inp,
@@ -175,13 +210,13 @@ impl<S: AsyncRead + AsyncWrite + 'static> Codec<S> for WebsockReply {
                     error!("Aborted handshake because pool closed");
                     Either::B(Either::B(
                         // TODO(tailhook) optimize json
-                        out.send(Packet::Text(json_encode(&Json::Array(vec![
+                        out.send(encoding.packet(&Json::Array(vec![
                             "fatal_error".into(),
                             json!({
                                 "error_kind": "pool_closed",
                             }),
                             Json::Null,
-                        ])).expect("can always serialize")))
+                        ])))
                         .map_err(log_err_io)
                         .and_then(move |out| {
                             websocket::Loop::<_, _, _>::closing(out, inp,
@@ -194,8 +229,9 @@ impl<S: AsyncRead + AsyncWrite + 'static> Codec<S> for WebsockReply {
     }
 }
 
+/// Returns the first protocol of the client that we support
 fn choose_proto(h: &http::WebsocketHandshake, settings: &Arc<Chat>)
-    -> Result<Option<&'static str>, ()>
+    -> Result<Option<Encoding>, ()>
 {
     if h.protocols.len() == 0 {
         if settings.allow_empty_subprotocol() {
@@ -203,10 +239,10 @@ fn choose_proto(h: &http::WebsocketHandshake, settings: &Arc<Chat>)
         } else {
             Err(())
         }
-    } else if h.protocols.iter().any(|x| &x[..] == "v1.swindon-lattice+json") {
-        return Ok(Some("v1.swindon-lattice+json"));
     } else {
-        return Ok(None);
+        return Ok(h.protocols.iter()
+            .filter_map(|x| Encoding::from_protocol(&x[..]))
+            .next());
     }
 }
 
@@ -215,9 +251,18 @@ pub fn serve<S: Transport>(settings: &Arc<Chat>, inp: Input)
 {
     match inp.headers.get_websocket_upgrade() {
         Ok(Some(ws)) => {
-            if let Ok(proto) = choose_proto(&ws, settings) {
-                let (tx, rx) = ConnectionSender::new();
+            if let Ok(encoding) = choose_proto(&ws, settings) {
+                let (tx, rx) = match
+                    inp.config.session_pools.get(&settings.session_pool)
+                {
+                    Some(pool) => ConnectionSender::bounded(
+                        pool.outbound_queue_size,
+                        pool.outbound_queue_overflow),
+                    None => ConnectionSender::new(),
+                };
                 let cid = Cid::new();
+                let deflate = settings.permessage_deflate.as_ref()
+                    .and_then(|s| deflate::negotiate(inp.headers, s));
                 chat::start_authorize(&inp, cid, settings, tx.clone());
                 Ok(Box::new(WebsockReply {
                     cid: cid,
@@ -227,9 +272,12 @@ pub fn serve<S: Transport>(settings: &Arc<Chat>, inp: Input)
                     reply_data: Some(ReplyData {
                         context: inp.into_context(),
                         accept: ws.accept,
-                        proto: proto,
+                        proto: encoding.map(|e| e.protocol()),
                     }),
                     channel: Some((tx, rx)),
+                    // no subprotocol means json
+                    encoding: encoding.unwrap_or(Encoding::Json),
+                    deflate: deflate,
                 }))
             } else {
                 Ok(serve_error_page(Status::BadRequest, inp))
@@ -12,6 +12,8 @@ use tokio_core::reactor::Handle;
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use crate::config::Config;
+use crate::config::websocket::WebsocketEcho;
+use crate::deflate::{self, Params as Deflate};
 use crate::incoming::{Request, Input, Debug, Reply, Encoder};
 use crate::default_error_page::serve_error_page;
 
@@ -19,6 +21,21 @@ use crate::default_error_page::serve_error_page;
 struct WebsockReply {
     rdata: Option<(Arc<Config>, Debug, Accept)>,
     handle: Handle,
+    deflate: Option<Deflate>,
+}
+
+impl WebsockReply {
+    fn start<S>(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>)
+        where S: AsyncRead + AsyncWrite + 'static
+    {
+        let inp = read_buf.framed(WebsocketCodec);
+        let out = write_buf.framed(WebsocketCodec);
+        // TODO(tailhook) convert Ping to Pong (and Close ?) before echoing
+        self.handle.spawn(inp.forward(out)
+            .map(|_| ())
+            // TODO(tailhook) check error reporting
+            .map_err(|e| info!("Websocket error: {}", e)))
+    }
 }
 
 
@@ -42,26 +59,36 @@ impl<S: AsyncRead + AsyncWrite + 'static> Codec<S> for WebsockReply {
         e.add_header("Connection", "upgrade");
         e.add_header("Upgrade", "websocket");
         e.format_header("Sec-Websocket-Accept", &accept);
+        if let Some(ref deflate) = self.deflate {
+            e.add_header("Sec-WebSocket-Extensions",
+                deflate.response_header());
+        }
         e.done_headers();
         Box::new(ok(e.done()))
     }
     fn hijack(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>) {
-        let inp = read_buf.framed(WebsocketCodec);
-        let out = write_buf.framed(WebsocketCodec);
-        // TODO(tailhook) convert Ping to Pong (and Close ?) before echoing
-        self.handle.spawn(inp.forward(out)
-            .map(|_| ())
-            // TODO(tailhook) check error reporting
-            .map_err(|e| info!("Websocket error: {}", e)))
+        match self.deflate.take() {
+            Some(params) => {
+                let (write_buf, read_buf) = deflate::wrap(
+                    write_buf, read_buf, params);
+                self.start(write_buf, read_buf)
+            }
+            None => self.start(write_buf, read_buf),
+        }
     }
 }
 
-pub fn serve<S: AsyncRead + AsyncWrite + 'static>(inp: Input) -> Request<S> {
+pub fn serve<S>(settings: &Arc<WebsocketEcho>, inp: Input) -> Request<S>
+    where S: AsyncRead + AsyncWrite + 'static
+{
     match inp.headers.get_websocket_upgrade() {
         Ok(Some(ws)) => {
+            let deflate = settings.permessage_deflate.as_ref()
+                .and_then(|s| deflate::negotiate(inp.headers, s));
             Box::new(WebsockReply {
                 rdata: Some((inp.config.clone(), inp.debug, ws.accept)),
                 handle: inp.handle.clone(),
+                deflate: deflate,
             })
         }
         Ok(None) => {
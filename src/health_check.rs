@@ -0,0 +1,109 @@
+//! Active health checks of the backends of http destinations
+use futures::{Async, Future, Sink, Stream};
+use futures::future::{ok, Either, FutureResult};
+use futures::sync::oneshot;
+use tk_http::Version;
+use tk_http::client::{Codec, Encoder, EncoderDone, Error, Head, RecvMode};
+use tk_http::client::Proto;
+use tokio_core::reactor::{Handle, Interval, Timeout};
+
+use crate::config::http_destinations::HealthCheck;
+use crate::http_pools::{BackendMetrics, ClientStream};
+
+
+/// Body of the health check response is skipped by chunks of this size
+const BODY_CHUNK: usize = 16384;
+
+pub type Connect = Box<dyn Fn()
+    -> Box<dyn Future<Item=Proto<ClientStream, Check>, Error=Error>>>;
+
+/// Request to the health check path, sends status code of the response
+pub struct Check {
+    path: String,
+    host: Option<String>,
+    sender: Option<oneshot::Sender<u16>>,
+}
+
+/// Checks the backend every `interval` until returned sender is dropped
+pub fn spawn(settings: &HealthCheck, host: Option<String>,
+    metrics: &BackendMetrics, connect: Connect, handle: &Handle)
+    -> oneshot::Sender<()>
+{
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let settings = settings.clone();
+    let metrics = metrics.clone();
+    let h1 = handle.clone();
+    handle.spawn(Interval::new(settings.interval, handle)
+        .expect("interval created")
+        .map_err(|e| error!("Interval error: {}", e))
+        .for_each(move |()| {
+            let metrics = metrics.clone();
+            let settings = settings.clone();
+            check(&settings, &host, &connect, &h1)
+            .then(move |result| {
+                metrics.health_check(result, &settings);
+                Ok(())
+            })
+        })
+        .select(stop_rx.then(|_| Ok(())))
+        .map(|_| ())
+        .map_err(|_| ()));
+    stop_tx
+}
+
+fn check(settings: &HealthCheck, host: &Option<String>, connect: &Connect,
+    handle: &Handle)
+    -> impl Future<Item=(), Error=String>
+{
+    let (tx, rx) = oneshot::channel();
+    let codec = Check {
+        path: settings.path.clone(),
+        host: host.clone(),
+        sender: Some(tx),
+    };
+    let timeout = Timeout::new(settings.timeout, handle)
+        .expect("can always create timeout");
+    connect()
+    .and_then(move |proto| {
+        // connection is polled by the sink until response is received
+        proto.send(codec)
+        .join(rx.map_err(|_| Error::custom("no response")))
+    })
+    .select2(timeout)
+    .then(|result| match result {
+        Ok(Either::A(((_, status), _))) if status >= 200 && status < 300
+        => Ok(()),
+        Ok(Either::A(((_, status), _))) => Err(format!("status {}", status)),
+        Ok(Either::B(_)) => Err(String::from("timed out")),
+        Err(Either::A((e, _))) => Err(e.to_string()),
+        Err(Either::B((e, _))) => Err(e.to_string()),
+    })
+}
+
+impl<S> Codec<S> for Check {
+    type Future = FutureResult<EncoderDone<S>, Error>;
+    fn start_write(&mut self, mut e: Encoder<S>) -> Self::Future {
+        e.request_line("GET", &self.path, Version::Http11);
+        if let Some(ref host) = self.host {
+            e.add_header("Host", host).unwrap();
+        }
+        e.add_header("User-Agent", format!(
+            "swindon/{}", env!("CARGO_PKG_VERSION"))).unwrap();
+        e.add_length(0).unwrap();
+        e.done_headers().unwrap();
+        ok(e.done())
+    }
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<RecvMode, Error>
+    {
+        if let Some(sender) = self.sender.take() {
+            sender.send(headers.raw_status().0).ok();
+        }
+        Ok(RecvMode::progressive(BODY_CHUNK))
+    }
+    fn data_received(&mut self, data: &[u8], _end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        Ok(Async::Ready(data.len()))
+    }
+}
@@ -1,26 +1,47 @@
-use std::sync::{Arc, RwLock, RwLockWriteGuard};
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::sync::{Arc, Weak, Mutex, RwLock, RwLockWriteGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{SocketAddr, Ipv6Addr};
+use std::path::PathBuf;
+use std::time::Instant;
 
+use abstract_ns::Address;
 use ns_router::{Router};
+use rustls::ClientSession;
 use tk_http::client::{Codec, Config as HConfig, Proto, Error, EncoderDone};
+use tk_http::client::{Encoder, Head, RecvMode};
 use tk_pool::metrics::Collect;
 use tk_pool::config::{NewErrorLog, NewMetrics};
 use tk_pool::error_log::{ShutdownReason, ErrorLog};
 use tokio_core::net::TcpStream;
 use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsStream;
+use tokio_uds::UnixStream;
 use tk_pool::queue::Pool;
 use tk_pool::pool_for;
-use futures::future::FutureResult;
+use futures::{Async, AsyncSink, Future, Sink, StartSend, Stream, Poll};
+use futures::future::{self, Either, IntoFuture};
+use futures::stream;
+use futures::sync::oneshot;
 use libcantal::{Collection, Visitor};
+use void::Void;
 
 use crate::intern::Upstream;
-use crate::config::http_destinations::Destination;
+use crate::config::http_destinations::{Destination, LoadBalancing};
+use crate::config::http_destinations::{HealthCheck, OutlierDetection};
+use crate::health_check;
 use crate::metrics::{Counter, List, Metric, Integer};
+use crate::tls;
 
 lazy_static! {
     pub static ref REQUESTS: Counter = Counter::new();
     pub static ref FAILED_503: Counter = Counter::new();
+    pub static ref RETRIES: Counter = Counter::new();
 
     pub static ref CONNECTING: Integer = Integer::new();
     pub static ref CONNECTED: Integer = Integer::new();
@@ -40,22 +61,130 @@ lazy_static! {
     pub static ref POOLS: Integer = Integer::new();
     pub static ref POOLS_STARTED: Counter = Counter::new();
     pub static ref POOLS_STOPPED: Counter = Counter::new();
+
+    pub static ref UNHEALTHY: Integer = Integer::new();
+    pub static ref HEALTH_CHECKS: Counter = Counter::new();
+    pub static ref HEALTH_CHECKS_FAILED: Counter = Counter::new();
+    pub static ref EJECTIONS: Counter = Counter::new();
 }
 
+/// Number of points on the consistent hashing ring per unit of weight
+const RING_POINTS: u32 = 100;
+
 /// Future that is used for sending a client request
 ///
-/// While we only support fully buffered requests it's fine to use
-/// FutureResult, but we will probably change it to something
-pub type HttpFuture<S> = FutureResult<EncoderDone<S>, Error>;
-pub type PoolInner = Pool<
-    Box<dyn Codec<TcpStream, Future=HttpFuture<TcpStream>>+Send>,
-    PoolMetrics>;
+/// It's boxed because request body might be streamed from the client
+/// (see `stream-requests` setting of the proxy) so encoder can't be done
+/// immediately
+pub type HttpFuture<S> = Box<dyn Future<Item=EncoderDone<S>, Error=Error>>;
+pub type BoxCodec = Box<dyn BackendCodec+Send>;
+type BackendPool = Pool<Tracked, BackendMetrics>;
+/// Resolved addresses with the index in destination `addresses`
+type Addresses = Box<dyn Stream<Item=(usize, Address), Error=Void>>;
+
+/// Codec of the request sent through the connection pool
+pub trait BackendCodec
+    : Codec<ClientStream, Future=HttpFuture<ClientStream>>
+{
+    /// Called when request is assigned to a backend, the argument
+    /// is the address of the backend (or unix socket path)
+    fn set_upstream(&mut self, _upstream: &Arc<str>) {}
+    /// Backends which this request must not be sent to, usually the ones
+    /// that already failed to serve it
+    fn excluded(&self) -> &[Arc<str>] {
+        &[]
+    }
+    /// Hash of the request key for `hash` load balancing, requests
+    /// without a key are balanced like with `queue`
+    fn sticky_key(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Connection to a backend
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream, ClientSession>),
+    Unix(UnixStream),
+}
 
 pub struct HttpPool {
     pool: PoolInner,
     metrics: PoolMetrics,
+    destination: Arc<Destination>,
+    /// Stops following address changes when dropped
+    _addresses: oneshot::Sender<()>,
+}
+
+/// Connection pools of all the addresses of the destination
+///
+/// Every address has a pool of its own, so that request can be sent to
+/// a specific backend. In particular, a retried request is sent to the
+/// backend which wasn't tried yet.
+pub struct PoolInner {
+    backends: Arc<Mutex<Backends>>,
+}
+
+struct Backends {
+    list: Vec<Backend>,
+    /// Resolved addresses for every item of destination `addresses`
+    resolved: Vec<Vec<SocketAddr>>,
+    /// Requests received before addresses are resolved for the first time
+    pending: Option<VecDeque<BoxCodec>>,
+    strategy: LoadBalancing,
+    /// Points of the consistent hashing ring and indexes of backends
+    ring: Vec<(u64, usize)>,
+    /// Number of requests a backend handles without queueing
+    capacity: usize,
+    queue_size: usize,
+    next: usize,
+}
+
+struct Backend {
+    addr: SocketAddr,
+    upstream: Arc<str>,
+    pool: BackendPool,
+    metrics: BackendMetrics,
+    weight: u32,
+    /// Current weight for the smooth weighted round-robin
+    current: i64,
+    /// Stops health checks when dropped
+    _health_check: Option<oneshot::Sender<()>>,
+}
+
+/// Creates pools for backends as destination addresses are resolved
+struct Factory {
+    name: Upstream,
+    destination: Arc<Destination>,
+    tls: Option<tls::Client>,
+    unix: Arc<Vec<PathBuf>>,
+    config: Arc<HConfig>,
+    metrics: PoolMetrics,
+    handle: Handle,
 }
 
+/// Request assigned to a backend
+///
+/// It's counted as outstanding for the backend until it's dropped, i.e.
+/// until the response is received or the request is failed. Until the
+/// request is written to a connection, its codec may be taken out of the
+/// slot, so that requests queued for the backend which refuses connections
+/// are failed (and may be retried on another backend).
+///
+/// Requests which are written but get no response (i.e. timed out or
+/// connection is closed), and ones getting `5xx` response are reported
+/// as failed for outlier detection.
+pub struct Tracked {
+    slot: Arc<Slot>,
+    codec: Option<BoxCodec>,
+    responded: bool,
+    outstanding: Outstanding,
+}
+
+type Slot = Mutex<Option<BoxCodec>>;
+
+struct Outstanding(BackendMetrics);
+
 pub struct UpstreamRef<'a> {
     pools: &'a HttpPools,
     upstream: &'a Upstream,
@@ -66,17 +195,21 @@ pub struct UpstreamGuard<'a> {
     upstream: &'a Upstream,
 }
 
+/// Connection pools for http destinations, both plain and TLS ones
 #[derive(Clone)]
 pub struct HttpPools {
-    plain: Arc<RwLock<HashMap<Upstream, HttpPool>>>,
-    // TODO(tailhook) https pools
+    pools: Arc<RwLock<HashMap<Upstream, HttpPool>>>,
 }
 
 #[derive(Clone, Debug)]
 pub struct PoolMetrics(Arc<Metrics>);
 
+/// Metrics of a single backend, also reported to the destination
+#[derive(Clone)]
+pub struct BackendMetrics(Arc<BackendState>);
+
 #[derive(Clone, Debug)]
-pub struct PoolLog(Upstream);
+pub struct PoolLog(Upstream, Arc<str>);
 
 #[derive(Debug)]
 struct Metrics {
@@ -96,10 +229,62 @@ struct Metrics {
     blacklist_removed: Counter,
     requests_queued: Counter,
     requests_forwarded: Counter,
+
+    unhealthy: Integer,
+    health_checks: Counter,
+    health_checks_failed: Counter,
+    ejections: Counter,
+
+    addresses: Vec<AddressMetrics>,
+}
+
+/// Metrics of an item of destination `addresses`, show how requests are
+/// split between backends
+#[derive(Debug)]
+struct AddressMetrics {
+    address: String,
+    connected: Integer,
+    in_flight: Integer,
+    requests: Counter,
+}
+
+struct BackendState {
+    destination: PoolMetrics,
+    /// Index of the address in destination `addresses`
+    address: usize,
+    /// Name of the backend for logs
+    upstream: Arc<str>,
+    connected: AtomicUsize,
+    blacklisted: AtomicUsize,
+    queued: AtomicUsize,
+    outstanding: AtomicUsize,
+    /// Requests which are not written to a connection yet
+    waiting: Mutex<Vec<Weak<Slot>>>,
+    /// Result of active health checks, backend is considered healthy
+    /// until checks fail
+    healthy: AtomicBool,
+    /// Number of health checks in a row that disagree with `healthy`
+    checks: AtomicUsize,
+    outlier_detection: Option<OutlierDetection>,
+    /// Number of requests failed in a row
+    failures: AtomicUsize,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+/// State of the backend shown by `!SelfStatus`
+#[derive(Serialize, Debug)]
+pub struct BackendStatus {
+    address: String,
+    upstream: String,
+    healthy: bool,
+    ejected: bool,
+    blacklisted: bool,
+    connected: usize,
+    outstanding: usize,
 }
 
 impl Metrics {
-    fn new(name: &Upstream) -> Metrics {
+    fn new(name: &Upstream, addresses: &[String]) -> Metrics {
         POOLS.incr(1);
         POOLS_STARTED.incr(1);
         Metrics {
@@ -119,10 +304,31 @@ impl Metrics {
             blacklist_removed: Counter::new(),
             requests_queued: Counter::new(),
             requests_forwarded: Counter::new(),
+
+            unhealthy: Integer::new(),
+            health_checks: Counter::new(),
+            health_checks_failed: Counter::new(),
+            ejections: Counter::new(),
+
+            addresses: addresses.iter().map(|a| AddressMetrics {
+                address: a.clone(),
+                connected: Integer::new(),
+                in_flight: Integer::new(),
+                requests: Counter::new(),
+            }).collect(),
         }
     }
 }
 
+impl Drop for Metrics {
+    fn drop(&mut self) {
+        // backend pools keep metrics, so this happens when the last
+        // backend pool of the destination is closed
+        POOLS.decr(1);
+        POOLS_STOPPED.incr(1);
+    }
+}
+
 impl Collection for PoolMetrics {
     fn visit<'x>(&'x self, v: &mut dyn Visitor<'x>) {
         use crate::metrics::Metric as M;
@@ -143,12 +349,24 @@ impl Collection for PoolMetrics {
         v.metric(&M(&g, "blacklist_removed"), &s.blacklist_removed);
         v.metric(&M(&g, "requests_queued"), &s.requests_queued);
         v.metric(&M(&g, "requests_forwarded"), &s.requests_forwarded);
+
+        v.metric(&M(&g, "unhealthy"), &s.unhealthy);
+        v.metric(&M(&g, "health_checks"), &s.health_checks);
+        v.metric(&M(&g, "health_checks_failed"), &s.health_checks_failed);
+        v.metric(&M(&g, "ejections"), &s.ejections);
+
+        for a in &s.addresses {
+            let g = format!("http.pools.{}.addresses.{}", s.name, a.address);
+            v.metric(&M(&g, "connected"), &a.connected);
+            v.metric(&M(&g, "in_flight"), &a.in_flight);
+            v.metric(&M(&g, "requests"), &a.requests);
+        }
     }
 }
 
 impl PoolMetrics {
-    fn new(name: &Upstream) -> PoolMetrics {
-        PoolMetrics(Arc::new(Metrics::new(name)))
+    fn new(name: &Upstream, addresses: &[String]) -> PoolMetrics {
+        PoolMetrics(Arc::new(Metrics::new(name, addresses)))
     }
 }
 
@@ -210,16 +428,481 @@ impl Collect for PoolMetrics {
         self.0.requests_forwarded.incr(1);
     }
     fn pool_closed(&self) {
-        // TODO(tailhook) decrement global counters?
-        POOLS.decr(1);
-        POOLS_STOPPED.incr(1);
+        // counted when metrics are dropped, as every backend has a pool
+    }
+}
+
+impl BackendMetrics {
+    fn new(destination: &PoolMetrics, address: usize, upstream: &Arc<str>,
+        outlier_detection: &Option<OutlierDetection>)
+        -> BackendMetrics
+    {
+        BackendMetrics(Arc::new(BackendState {
+            destination: destination.clone(),
+            address: address,
+            upstream: upstream.clone(),
+            connected: AtomicUsize::new(0),
+            blacklisted: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            outstanding: AtomicUsize::new(0),
+            waiting: Mutex::new(Vec::new()),
+            healthy: AtomicBool::new(true),
+            checks: AtomicUsize::new(0),
+            outlier_detection: outlier_detection.clone(),
+            failures: AtomicUsize::new(0),
+            ejected_until: Mutex::new(None),
+        }))
+    }
+    fn blacklisted(&self) -> bool {
+        self.0.blacklisted.load(Ordering::SeqCst) > 0
+    }
+    fn healthy(&self) -> bool {
+        self.0.healthy.load(Ordering::SeqCst)
+    }
+    fn ejected(&self) -> bool {
+        self.0.ejected_until.lock().expect("ejection not poisoned")
+            .map(|deadline| deadline > Instant::now())
+            .unwrap_or(false)
+    }
+    /// Whether backend should get requests, other backends are only
+    /// used when no available ones are left
+    fn available(&self) -> bool {
+        !self.blacklisted() && self.healthy() && !self.ejected()
+    }
+    fn connected(&self) -> usize {
+        self.0.connected.load(Ordering::SeqCst)
+    }
+    fn queued(&self) -> usize {
+        self.0.queued.load(Ordering::SeqCst)
+    }
+    fn outstanding(&self) -> usize {
+        self.0.outstanding.load(Ordering::SeqCst)
+    }
+    fn address(&self) -> &AddressMetrics {
+        &(self.0.destination.0).addresses[self.0.address]
+    }
+    fn wait(&self, slot: &Arc<Slot>) {
+        let mut waiting = self.0.waiting.lock()
+            .expect("waiting list not poisoned");
+        waiting.retain(|s| {
+            s.upgrade().map(|s| {
+                s.lock().expect("slot not poisoned").is_some()
+            }).unwrap_or(false)
+        });
+        waiting.push(Arc::downgrade(slot));
+    }
+    /// Fails requests which are not written to a connection yet
+    fn cancel_waiting(&self) {
+        let waiting = mem::replace(
+            &mut *self.0.waiting.lock().expect("waiting list not poisoned"),
+            Vec::new());
+        let codecs = waiting.iter()
+            .filter_map(|s| s.upgrade())
+            .filter_map(|s| s.lock().expect("slot not poisoned").take())
+            .collect::<Vec<_>>();
+        if codecs.len() > 0 {
+            debug!("Failing {} requests waiting for connection",
+                codecs.len());
+        }
+        // codecs notify their senders when dropped
+        drop(codecs);
+    }
+    /// Records result of the active health check
+    pub fn health_check(&self, result: Result<(), String>,
+        settings: &HealthCheck)
+    {
+        let ref pool = (self.0.destination).0;
+        HEALTH_CHECKS.incr(1);
+        pool.health_checks.incr(1);
+        if let Err(ref e) = result {
+            HEALTH_CHECKS_FAILED.incr(1);
+            pool.health_checks_failed.incr(1);
+            debug!("{}: Health check of {} failed: {}",
+                pool.name, self.0.upstream, e);
+        }
+        let passed = result.is_ok();
+        if passed == self.healthy() {
+            self.0.checks.store(0, Ordering::SeqCst);
+            return;
+        }
+        let checks = self.0.checks.fetch_add(1, Ordering::SeqCst) + 1;
+        let threshold = if passed {
+            settings.healthy_threshold
+        } else {
+            settings.unhealthy_threshold
+        };
+        if checks < threshold as usize {
+            return;
+        }
+        self.0.checks.store(0, Ordering::SeqCst);
+        self.0.healthy.store(passed, Ordering::SeqCst);
+        if passed {
+            UNHEALTHY.decr(1);
+            pool.unhealthy.decr(1);
+            info!("{}: Backend {} is healthy after {} checks",
+                pool.name, self.0.upstream, checks);
+        } else {
+            UNHEALTHY.incr(1);
+            pool.unhealthy.incr(1);
+            warn!("{}: Backend {} is unhealthy after {} failed checks",
+                pool.name, self.0.upstream, checks);
+        }
+    }
+    /// Records result of the request for outlier detection
+    fn request_done(&self, success: bool) {
+        if success {
+            self.0.failures.store(0, Ordering::SeqCst);
+            return;
+        }
+        let failures = self.0.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        let settings = match self.0.outlier_detection {
+            Some(ref settings) => settings,
+            None => return,
+        };
+        if failures < settings.consecutive_failures as usize {
+            return;
+        }
+        self.0.failures.store(0, Ordering::SeqCst);
+        *self.0.ejected_until.lock().expect("ejection not poisoned") =
+            Some(Instant::now() + settings.ejection_time);
+        let ref pool = (self.0.destination).0;
+        EJECTIONS.incr(1);
+        pool.ejections.incr(1);
+        warn!("{}: Backend {} is ejected for {:?} after {} failed requests",
+            pool.name, self.0.upstream, settings.ejection_time, failures);
+    }
+}
+
+impl Drop for BackendState {
+    fn drop(&mut self) {
+        if !self.healthy.load(Ordering::SeqCst) {
+            UNHEALTHY.decr(1);
+            self.destination.0.unhealthy.decr(1);
+        }
+    }
+}
+
+impl Collect for BackendMetrics {
+    fn connection_attempt(&self) {
+        self.0.destination.connection_attempt();
+    }
+    fn connection_abort(&self) {
+        self.0.destination.connection_abort();
+    }
+    fn connection_error(&self) {
+        self.0.destination.connection_error();
+        if self.0.connected.load(Ordering::SeqCst) == 0 {
+            // requests would wait in the queue until backend is up again
+            self.cancel_waiting();
+        }
+    }
+    fn connection(&self) {
+        self.0.connected.fetch_add(1, Ordering::SeqCst);
+        self.address().connected.incr(1);
+        self.0.destination.connection();
+    }
+    fn disconnect(&self) {
+        self.0.connected.fetch_sub(1, Ordering::SeqCst);
+        self.address().connected.decr(1);
+        self.0.destination.disconnect();
+    }
+    fn blacklist_add(&self) {
+        self.0.blacklisted.fetch_add(1, Ordering::SeqCst);
+        self.0.destination.blacklist_add();
+    }
+    fn blacklist_remove(&self) {
+        self.0.blacklisted.fetch_sub(1, Ordering::SeqCst);
+        self.0.destination.blacklist_remove();
+    }
+    fn request_queued(&self) {
+        self.0.queued.fetch_add(1, Ordering::SeqCst);
+        self.0.destination.request_queued();
+    }
+    fn request_forwarded(&self) {
+        self.0.queued.fetch_sub(1, Ordering::SeqCst);
+        self.0.destination.request_forwarded();
+    }
+    fn pool_closed(&self) {
+        self.0.destination.pool_closed();
+    }
+}
+
+impl Outstanding {
+    fn new(metrics: &BackendMetrics) -> Outstanding {
+        metrics.0.outstanding.fetch_add(1, Ordering::SeqCst);
+        metrics.address().in_flight.incr(1);
+        Outstanding(metrics.clone())
+    }
+}
+
+impl Drop for Outstanding {
+    fn drop(&mut self) {
+        (self.0).0.outstanding.fetch_sub(1, Ordering::SeqCst);
+        self.0.address().in_flight.decr(1);
+    }
+}
+
+impl Factory {
+    /// Creates pool for the address resolved from `addresses[entry]`
+    fn backend(&self, addr: SocketAddr, entry: usize) -> Backend {
+        let upstream = upstream_name(addr, &self.unix);
+        let metrics = BackendMetrics::new(&self.metrics, entry, &upstream,
+            &self.destination.outlier_detection);
+        let health_check = self.destination.health_check.as_ref()
+            .map(|settings| self.health_check(addr, entry, &metrics, settings));
+        let tls = self.tls.clone();
+        let unix = self.unix.clone();
+        let config = self.config.clone();
+        let h2 = self.handle.clone();
+        // address of the backend never changes, but the stream must not
+        // end while pool is alive
+        let address = stream::once::<_, Void>(Ok(
+                [addr].iter().cloned().collect::<Address>()))
+            .chain(future::empty().into_stream());
+        let pool = pool_for(move |addr| {
+                connect::<Tracked>(addr, &tls, &unix, &config, &h2)
+            })
+            .connect_to(address)
+            .lazy_uniform_connections(
+                self.destination.backend_connections_per_ip_port)
+            .with_queue_size(self.destination.queue_size_for_503)
+            .metrics(metrics.clone())
+            .errors(PoolLog(self.name.clone(), upstream.clone()))
+            .spawn_on(&self.handle);
+        Backend {
+            addr, upstream, pool, metrics,
+            weight: self.destination.weight(entry),
+            current: 0,
+            _health_check: health_check,
+        }
+    }
+    /// Starts health checks of the backend, each check uses a new
+    /// connection
+    fn health_check(&self, addr: SocketAddr, entry: usize,
+        metrics: &BackendMetrics, settings: &HealthCheck)
+        -> oneshot::Sender<()>
+    {
+        let tls = self.tls.clone();
+        let unix = self.unix.clone();
+        let config = self.config.clone();
+        let h2 = self.handle.clone();
+        let host = match self.destination.override_host_header {
+            Some(ref host) => Some(host.clone()),
+            None if unix.is_empty() => {
+                Some(self.destination.addresses[entry].clone())
+            }
+            None => None,
+        };
+        health_check::spawn(settings, host, metrics,
+            Box::new(move || {
+                connect::<health_check::Check>(
+                    addr, &tls, &unix, &config, &h2)
+            }),
+            &self.handle)
+    }
+}
+
+impl Backend {
+    fn send(&mut self, mut codec: BoxCodec) -> AsyncSink<BoxCodec> {
+        codec.set_upstream(&self.upstream);
+        let slot = Arc::new(Mutex::new(Some(codec)));
+        let tracked = Tracked {
+            slot: slot.clone(),
+            codec: None,
+            responded: false,
+            outstanding: Outstanding::new(&self.metrics),
+        };
+        match self.pool.start_send(tracked) {
+            Ok(AsyncSink::Ready) => {
+                self.metrics.wait(&slot);
+                self.metrics.address().requests.incr(1);
+                AsyncSink::Ready
+            }
+            Ok(AsyncSink::NotReady(_)) => {
+                let codec = slot.lock().expect("slot not poisoned").take();
+                AsyncSink::NotReady(codec.expect("codec is not taken"))
+            }
+            Err(e) => {
+                // request is dropped, so the sender sees it as failed
+                error!("Error sending to {}: {}", self.upstream, e);
+                AsyncSink::Ready
+            }
+        }
+    }
+}
+
+impl Backends {
+    /// Updates resolved addresses of `addresses[entry]`, pools of the
+    /// backends which are gone are shut down when dropped
+    fn update(&mut self, entry: usize, addresses: Vec<SocketAddr>,
+        factory: &Factory)
+    {
+        self.resolved[entry] = addresses;
+        let mut old = mem::replace(&mut self.list, Vec::new());
+        for (entry, addresses) in self.resolved.iter().enumerate() {
+            for &addr in addresses {
+                if self.list.iter().any(|b| b.addr == addr) {
+                    continue;
+                }
+                match old.iter().position(|b| b.addr == addr) {
+                    Some(idx) => self.list.push(old.swap_remove(idx)),
+                    None => self.list.push(factory.backend(addr, entry)),
+                }
+            }
+        }
+        if self.strategy == LoadBalancing::hash {
+            self.ring = ring(&self.list);
+        }
+        if let Some(pending) = self.pending.take() {
+            for codec in pending {
+                if let AsyncSink::NotReady(_) = self.send(codec) {
+                    FAILED_503.incr(1);
+                }
+            }
+        }
+    }
+    fn queued(&self) -> usize {
+        self.list.iter().map(|b| b.metrics.queued()).sum()
+    }
+    /// Returns indexes of backends in the order of preference
+    fn order(&mut self, excluded: &[Arc<str>], key: Option<u64>)
+        -> Vec<usize>
+    {
+        let n = self.list.len();
+        // Blacklisted, unhealthy and ejected backends, and ones with zero
+        // weight are tried only when nothing else is left
+        let (mut order, rest): (Vec<_>, Vec<_>) = (0..n)
+            .map(|i| (self.next + i) % n)
+            .filter(|&i| !excluded.contains(&self.list[i].upstream))
+            .partition(|&i| {
+                self.list[i].metrics.available() && self.list[i].weight > 0
+            });
+        let capacity = self.capacity;
+        match (self.strategy, key) {
+            (LoadBalancing::hash, Some(key)) => {
+                order = self.ring_order(key, &order);
+            }
+            (LoadBalancing::weighted, _) => {
+                if let Some(pos) = self.pick_weighted(&order) {
+                    let first = order.remove(pos);
+                    order.insert(0, first);
+                }
+            }
+            (LoadBalancing::least_outstanding, _) => {
+                let list = &self.list;
+                order.sort_by_key(|&i| list[i].metrics.outstanding());
+            }
+            // Backends which have spare connections go first, in
+            // round-robin order, then the ones with the least requests
+            // in flight
+            (LoadBalancing::queue, _) | (LoadBalancing::hash, None) => {
+                let list = &self.list;
+                order.sort_by_key(|&i| {
+                    let outstanding = list[i].metrics.outstanding();
+                    if outstanding < capacity { 0 } else { outstanding }
+                });
+            }
+        }
+        order.extend(rest);
+        order
+    }
+    /// Smooth weighted round-robin, returns position in `candidates`
+    fn pick_weighted(&mut self, candidates: &[usize]) -> Option<usize> {
+        let total = candidates.iter()
+            .map(|&i| self.list[i].weight as i64)
+            .sum::<i64>();
+        if total == 0 {
+            return None;
+        }
+        for &i in candidates {
+            let backend = &mut self.list[i];
+            backend.current += backend.weight as i64;
+        }
+        let pos = (0..candidates.len())
+            .max_by_key(|&p| (self.list[candidates[p]].current, !p))?;
+        self.list[candidates[pos]].current -= total;
+        Some(pos)
+    }
+    /// Walks the hashing ring from the point of the key
+    fn ring_order(&self, key: u64, candidates: &[usize]) -> Vec<usize> {
+        let start = match self.ring.binary_search_by_key(&key, |&(p, _)| p) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        let mut order = Vec::with_capacity(candidates.len());
+        for j in 0..self.ring.len() {
+            let (_, idx) = self.ring[(start + j) % self.ring.len()];
+            if candidates.contains(&idx) && !order.contains(&idx) {
+                order.push(idx);
+                if order.len() == candidates.len() {
+                    break;
+                }
+            }
+        }
+        order
+    }
+    fn send(&mut self, codec: BoxCodec) -> AsyncSink<BoxCodec> {
+        if let Some(ref mut pending) = self.pending {
+            if pending.len() >= self.queue_size {
+                return AsyncSink::NotReady(codec);
+            }
+            pending.push_back(codec);
+            return AsyncSink::Ready;
+        }
+        if self.queued() >= self.queue_size {
+            return AsyncSink::NotReady(codec);
+        }
+        let order = self.order(codec.excluded(), codec.sticky_key());
+        self.next = self.next.wrapping_add(1);
+        let mut codec = codec;
+        for idx in order {
+            match self.list[idx].send(codec) {
+                AsyncSink::Ready => return AsyncSink::Ready,
+                AsyncSink::NotReady(c) => codec = c,
+            }
+        }
+        AsyncSink::NotReady(codec)
+    }
+}
+
+/// Builds consistent hashing ring, backend gets the number of points
+/// proportional to its weight
+///
+/// Points depend on the backend address only, so the same key is sent
+/// to the same backend by every swindon instance and keeps going to it
+/// when other backends are added or removed.
+fn ring(list: &[Backend]) -> Vec<(u64, usize)> {
+    let mut ring = Vec::new();
+    for (idx, backend) in list.iter().enumerate() {
+        for point in 0..backend.weight*RING_POINTS {
+            ring.push((sticky_hash(&(&*backend.upstream, point)), idx));
+        }
+    }
+    ring.sort();
+    ring
+}
+
+/// Hash of the key for `hash` load balancing
+pub fn sticky_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Sink for PoolInner {
+    type SinkItem = BoxCodec;
+    type SinkError = Void;
+    fn start_send(&mut self, codec: BoxCodec) -> StartSend<BoxCodec, Void> {
+        Ok(self.backends.lock().expect("backends not poisoned").send(codec))
+    }
+    fn poll_complete(&mut self) -> Poll<(), Void> {
+        Ok(Async::Ready(()))
     }
 }
 
 impl HttpPools {
     pub fn new() -> HttpPools {
         HttpPools {
-            plain: Arc::new(RwLock::new(HashMap::new())),
+            pools: Arc::new(RwLock::new(HashMap::new())),
         }
     }
     pub fn upstream<'x>(&'x self, dest: &'x Upstream) -> UpstreamRef<'x> {
@@ -231,20 +914,20 @@ impl HttpPools {
     pub fn update(&self, cfg: &HashMap<Upstream, Arc<Destination>>,
         resolver: &Router, handle: &Handle)
     {
-        let mut plain = self.plain.write().expect("pools not poisoned");
+        let mut pools = self.pools.write().expect("pools not poisoned");
         let mut to_delete = Vec::new();
-        for k in plain.keys() {
-            if !cfg.contains_key(k) {
+        for (k, pool) in pools.iter() {
+            // Pool is recreated if destination changed, so that new
+            // addresses or TLS settings are applied
+            if cfg.get(k).map(|d| d != &pool.destination).unwrap_or(true) {
                 to_delete.push(k.clone());
             }
         }
         for k in to_delete {
-            plain.remove(&k);
+            pools.remove(&k);
         }
         for (k, dest) in cfg {
-            // TODO(tailhook) compare destinations
-            if !plain.contains_key(k) {
-                let h2 = handle.clone();
+            if !pools.contains_key(k) {
                 let conn_config = HConfig::new()
                     .inflight_request_limit(
                         dest.in_flight_requests_per_backend_connection)
@@ -252,28 +935,220 @@ impl HttpPools {
                     .safe_pipeline_timeout(dest.safe_pipeline_timeout)
                     .max_request_timeout(dest.max_request_timeout)
                     .done();
-                let metrics = PoolMetrics::new(k);
-                let pool = pool_for(move |addr| {
-                        Proto::connect_tcp(addr, &conn_config, &h2)
+                let (tls, default_port) = if dest.tls {
+                    match tls::Client::new(dest) {
+                        Ok(tls) => (Some(tls), 443),
+                        Err(e) => {
+                            // config validator checks settings, but files
+                            // might be changed since then
+                            error!("{}: Can't initialize TLS: {}", k, e);
+                            continue;
+                        }
+                    }
+                } else {
+                    (None, 80)
+                };
+                let unix = Arc::new(dest.unix_sockets().unwrap_or_default());
+                // Every item of `addresses` is resolved separately, so
+                // backends know their weight and metrics
+                let addresses: Addresses = if unix.len() > 0 {
+                    // addresses never change, but the stream must not
+                    // end while pool is alive
+                    let items = (0..unix.len())
+                        .map(|i| {
+                            (i, [unix_key(i)].iter().cloned()
+                                .collect::<Address>())
+                        })
+                        .collect::<Vec<_>>();
+                    Box::new(stream::iter_ok::<_, Void>(items)
+                        .chain(future::empty().into_stream()))
+                } else {
+                    (0..dest.addresses.len()).map(|i| -> Addresses {
+                        Box::new(resolver.subscribe_many(
+                                &dest.addresses[i..i+1], default_port)
+                            .map(move |addr| (i, addr)))
+                    }).fold(Box::new(stream::empty()) as Addresses,
+                            |all, item| -> Addresses {
+                                Box::new(all.select(item))
+                            })
+                };
+                let metrics = PoolMetrics::new(k, &dest.addresses);
+                let factory = Factory {
+                    name: k.clone(),
+                    destination: dest.clone(),
+                    tls: tls,
+                    unix: unix,
+                    config: conn_config,
+                    metrics: metrics.clone(),
+                    handle: handle.clone(),
+                };
+                let backends = Arc::new(Mutex::new(Backends {
+                    list: Vec::new(),
+                    resolved: vec![Vec::new(); dest.addresses.len()],
+                    pending: Some(VecDeque::new()),
+                    strategy: dest.load_balancing,
+                    ring: Vec::new(),
+                    capacity: dest.backend_connections_per_ip_port as usize
+                        * dest.in_flight_requests_per_backend_connection,
+                    queue_size: dest.queue_size_for_503,
+                    next: 0,
+                }));
+                let (stop_tx, stop_rx) = oneshot::channel();
+                let list = backends.clone();
+                handle.spawn(addresses
+                    .for_each(move |(entry, address)| {
+                        list.lock().expect("backends not poisoned")
+                            .update(entry, address.addresses_at(0).collect(),
+                                    &factory);
+                        Ok(())
                     })
-                    .connect_to(resolver.subscribe_many(&dest.addresses, 80))
-                    .lazy_uniform_connections(
-                        dest.backend_connections_per_ip_port as u32)
-                    .with_queue_size(
-                        dest.queue_size_for_503)
-                    .metrics(metrics.clone())
-                    .errors(PoolLog(k.clone()))
-                    .spawn_on(handle);
-                plain.insert(k.clone(), HttpPool { pool, metrics });
+                    .select(stop_rx.then(|_| Ok(())))
+                    .map(|_| ())
+                    .map_err(|_| ()));
+                pools.insert(k.clone(), HttpPool {
+                    pool: PoolInner { backends },
+                    metrics,
+                    destination: dest.clone(),
+                    _addresses: stop_tx,
+                });
             }
         }
      }
 }
 
+/// Returns the address under which unix socket is known to the pool
+///
+/// Pool only works with socket addresses, so unix sockets get fake ones
+/// from the discard-only prefix `100::/64`, the last segment is the index
+/// of the socket in the list of destination addresses.
+fn unix_key(index: usize) -> SocketAddr {
+    SocketAddr::new(
+        Ipv6Addr::new(0x100, 0, 0, 0, 0, 0, 0, index as u16).into(), 0)
+}
+
+/// Returns name of the backend used in logs
+fn upstream_name(addr: SocketAddr, unix: &[PathBuf]) -> Arc<str> {
+    match unix_index(&addr).and_then(|idx| unix.get(idx)) {
+        Some(path) => Arc::from(format!("unix:{}", path.display())),
+        None => Arc::from(addr.to_string()),
+    }
+}
+
+fn unix_index(addr: &SocketAddr) -> Option<usize> {
+    match *addr {
+        SocketAddr::V6(ref a)
+        if a.ip().segments()[..7] == [0x100, 0, 0, 0, 0, 0, 0]
+        => {
+            Some(a.ip().segments()[7] as usize)
+        }
+        _ => None,
+    }
+}
+
+fn connect<C: Codec<ClientStream> + 'static>(addr: SocketAddr,
+    tls: &Option<tls::Client>, unix: &Arc<Vec<PathBuf>>, cfg: &Arc<HConfig>,
+    handle: &Handle)
+    -> Box<dyn Future<Item=Proto<ClientStream, C>, Error=Error>>
+{
+    let h1 = handle.clone();
+    let cfg = cfg.clone();
+    let tls = tls.clone();
+    if let Some(path) = unix_index(&addr).and_then(|idx| unix.get(idx)) {
+        return Box::new(UnixStream::connect(path, handle).into_future()
+            .map(move |sock| {
+                Proto::new(ClientStream::Unix(sock), &h1, &cfg)
+            })
+            .map_err(Error::custom));
+    }
+    Box::new(TcpStream::connect(&addr, handle)
+        .and_then(move |sock| match tls {
+            Some(tls) => Either::A(tls.connect(sock).map(ClientStream::Tls)),
+            None => Either::B(Ok(ClientStream::Plain(sock)).into_future()),
+        })
+        .map(move |sock| Proto::new(sock, &h1, &cfg))
+        .map_err(Error::custom))
+}
+
+impl Codec<ClientStream> for Tracked {
+    type Future = HttpFuture<ClientStream>;
+    fn start_write(&mut self, e: Encoder<ClientStream>) -> Self::Future {
+        self.codec = self.slot.lock().expect("slot not poisoned").take();
+        match self.codec {
+            Some(ref mut codec) => codec.start_write(e),
+            // Request is already failed. Nothing is written, so
+            // connection is closed, but it's only the case when connection
+            // is established after a failure
+            None => Box::new(future::err(
+                Error::custom("request is canceled"))),
+        }
+    }
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<RecvMode, Error>
+    {
+        self.responded = true;
+        self.outstanding.0.request_done(headers.raw_status().0 < 500);
+        self.codec.as_mut().expect("request is written")
+            .headers_received(headers)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        self.codec.as_mut().expect("request is written")
+            .data_received(data, end)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if self.codec.is_some() && !self.responded {
+            self.outstanding.0.request_done(false);
+        }
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            ClientStream::Plain(ref mut s) => s.read(buf),
+            ClientStream::Tls(ref mut s) => s.read(buf),
+            ClientStream::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            ClientStream::Plain(ref mut s) => s.write(buf),
+            ClientStream::Tls(ref mut s) => s.write(buf),
+            ClientStream::Unix(ref mut s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            ClientStream::Plain(ref mut s) => s.flush(),
+            ClientStream::Tls(ref mut s) => s.flush(),
+            ClientStream::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {}
+
+impl AsyncWrite for ClientStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match *self {
+            ClientStream::Plain(ref mut s) => AsyncWrite::shutdown(s),
+            ClientStream::Tls(ref mut s) => AsyncWrite::shutdown(s),
+            ClientStream::Unix(ref mut s) => AsyncWrite::shutdown(s),
+        }
+    }
+}
+
 impl<'a> UpstreamRef<'a> {
     pub fn get_mut(&mut self) -> UpstreamGuard<'a> {
         UpstreamGuard {
-            guard: self.pools.plain.write().expect("pools not poisoned"),
+            guard: self.pools.pools.write().expect("pools not poisoned"),
             upstream: self.upstream,
         }
     }
@@ -291,6 +1166,7 @@ pub fn metrics() -> List {
         // obeys cantal-py.RequestTracker
         (Metric(base, "requests"), &*REQUESTS),
         (Metric(base, "backpressure_failures"), &*FAILED_503),
+        (Metric(base, "retries"), &*RETRIES),
 
         (Metric(base, "connecting"), &*CONNECTING),
         (Metric(base, "connected"), &*CONNECTED),
@@ -310,16 +1186,40 @@ pub fn metrics() -> List {
         (Metric(base, "pools"), &*POOLS),
         (Metric(base, "pools_started"), &*POOLS_STARTED),
         (Metric(base, "pools_stopped"), &*POOLS_STOPPED),
+
+        (Metric(base, "unhealthy"), &*UNHEALTHY),
+        (Metric(base, "health_checks"), &*HEALTH_CHECKS),
+        (Metric(base, "health_checks_failed"), &*HEALTH_CHECKS_FAILED),
+        (Metric(base, "ejections"), &*EJECTIONS),
     ]
 }
 
 pub fn pool_metrics(h: &HttpPools) -> Vec<PoolMetrics> {
-    h.plain.read().expect("http pools are okay")
+    h.pools.read().expect("http pools are okay")
         .values()
         .map(|p| p.metrics.clone())
         .collect()
 }
 
+/// Returns state of the backends of every destination
+pub fn backends(h: &HttpPools) -> BTreeMap<String, Vec<BackendStatus>> {
+    h.pools.read().expect("http pools are okay")
+        .iter()
+        .map(|(name, p)| {
+            let backends = p.pool.backends.lock()
+                .expect("backends not poisoned");
+            (name.to_string(), backends.list.iter().map(|b| BackendStatus {
+                address: p.destination.addresses[b.metrics.0.address].clone(),
+                upstream: b.upstream.to_string(),
+                healthy: b.metrics.healthy(),
+                ejected: b.metrics.ejected(),
+                blacklisted: b.metrics.blacklisted(),
+                connected: b.metrics.connected(),
+                outstanding: b.metrics.outstanding(),
+            }).collect())
+        })
+        .collect()
+}
 
 impl NewErrorLog<Error, Error> for PoolLog {
     type ErrorLog = PoolLog;
@@ -328,32 +1228,34 @@ impl NewErrorLog<Error, Error> for PoolLog {
     }
 }
 
-impl NewMetrics for PoolMetrics {
-    type Collect = PoolMetrics;
+impl NewMetrics for BackendMetrics {
+    type Collect = BackendMetrics;
     fn construct(self) -> Self::Collect {
-        self.clone()
+        self
     }
 }
 
 impl ErrorLog for PoolLog {
     type ConnectionError = Error;
     type SinkError = Error;
-    fn connection_error(&self, addr: SocketAddr, e: Self::ConnectionError) {
-        warn!("{}: Connecting to {} failed: {}", self.0, addr, e);
+    fn connection_error(&self, _addr: SocketAddr, e: Self::ConnectionError)
+    {
+        warn!("{}: Connecting to {} failed: {}", self.0, self.1, e);
     }
-    fn sink_error(&self, addr: SocketAddr, e: Self::SinkError) {
+    fn sink_error(&self, _addr: SocketAddr, e: Self::SinkError) {
         if e.is_graceful() {
-            debug!("{}: Connection to {} errored: {}", self.0, addr, e);
+            debug!("{}: Connection to {} errored: {}", self.0, self.1, e);
         } else {
-            warn!("{}: Connection to {} errored: {}", self.0, addr, e);
+            warn!("{}: Connection to {} errored: {}", self.0, self.1, e);
         }
     }
     /// Starting to shut down pool
     fn pool_shutting_down(&self, reason: ShutdownReason) {
-        warn!("{}: Shutting down connection pool: {}", self.0, reason);
+        info!("{}: Shutting down connection pool of {}: {}",
+              self.0, self.1, reason);
     }
     /// This is triggered when pool done all the work and shut down entirely
     fn pool_closed(&self) {
-        info!("{}: Pool closed", self.0);
+        info!("{}: Pool of {} closed", self.0, self.1);
     }
 }
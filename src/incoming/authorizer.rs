@@ -2,17 +2,29 @@ use tk_http::server::{Error};
 
 use crate::incoming::{Input};
 use crate::config::{Authorizer};
-use crate::authorizers;
+use crate::authorizers::{self, Deferred};
+
+
+pub enum Check {
+    Allow,
+    Deny,
+    /// Decision is made asynchronously, so handler must be wrapped into
+    /// a codec that waits for it
+    Defer(Deferred),
+}
 
 // TODO(tailhook) this should eventually be a virtual method on Authorizer
 impl Authorizer {
-    pub fn check(&self, input: &mut Input) -> Result<bool, Error> {
+    pub fn check(&self, input: &mut Input) -> Result<Check, Error> {
         match *self {
-            Authorizer::AllowAll => Ok(true),
+            Authorizer::AllowAll => Ok(Check::Allow),
             Authorizer::SourceIp(ref cfg) => {
                 authorizers::source_ip::check(cfg, input)
+                .map(|ok| if ok { Check::Allow } else { Check::Deny })
+            }
+            Authorizer::Ldap(ref cfg) => {
+                authorizers::ldap::check(cfg, input).map(Check::Defer)
             }
-            Authorizer::Ldap(_) => unimplemented!(),
         }
     }
 }
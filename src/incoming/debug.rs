@@ -8,8 +8,16 @@ use crate::intern::{Authorizer};
 use crate::config::Config;
 use crate::routing::Route;
 use crate::request_id::RequestId;
+use crate::logging::http::ResponseLog;
 
-pub struct Debug(Option<Box<DebugInfo>>);
+/// Request info that is passed along to the encoder
+///
+/// Debug info is only kept when `debug-routing` is enabled, response log
+/// is always here as the encoder fills it in for the access log.
+pub struct Debug {
+    info: Option<Box<DebugInfo>>,
+    log: ResponseLog,
+}
 
 struct DebugInfo {
     route: Option<Route>,
@@ -22,59 +30,68 @@ struct DebugInfo {
 }
 
 impl Debug {
-    pub fn new(_head: &Head, request_id: RequestId, cfg: &Arc<Config>)
+    pub fn new(_head: &Head, request_id: RequestId, cfg: &Arc<Config>,
+        log: &ResponseLog)
         -> Debug
     {
-        if cfg.debug_routing {
-            Debug(Some(Box::new(DebugInfo {
+        let info = if cfg.debug_routing {
+            Some(Box::new(DebugInfo {
                 route: None,
                 fs_path: None,
                 config: cfg.clone(),
                 request_id: request_id,
                 allow: String::new(),
                 deny: String::new(),
-            })))
+            }))
         } else {
-            Debug(None)
+            None
+        };
+        Debug {
+            info: info,
+            log: log.clone(),
         }
     }
+
+    pub fn response_log(&self) -> &ResponseLog {
+        &self.log
+    }
     /// Add route information
     ///
     /// # Panics
     ///
     /// Panics if route is already set (only in debug mode)
     pub fn set_route(&mut self, route: &Route) {
-        if let Some(ref mut dinfo) = self.0 {
+        if let Some(ref mut dinfo) = self.info {
             debug_assert!(dinfo.route.is_none());
             dinfo.route = Some(route.clone());
         }
     }
 
     pub fn get_route(&self) -> Option<&str> {
-        self.0.as_ref().map(|dinfo| {
+        self.info.as_ref().map(|dinfo| {
             dinfo.route.as_ref().map(|x| &x.handler_name[..])
             .unwrap_or("-- no route --")
         })
     }
 
     pub fn set_fs_path<P: AsRef<Path>>(&mut self, path: P) {
-        if let Some(ref mut dinfo) = self.0 {
+        if let Some(ref mut dinfo) = self.info {
             dinfo.fs_path = Some(path.as_ref().to_path_buf());
         }
     }
 
     pub fn get_fs_path(&self) -> Option<&Path> {
-        self.0.as_ref().and_then(|dinfo| {
+        self.info.as_ref().and_then(|dinfo| {
             dinfo.fs_path.as_ref().map(|x| x as &Path)
         })
     }
 
     pub fn get_request_id(&self) -> Option<RequestId> {
-        self.0.as_ref().map(|dinfo| dinfo.request_id)
+        self.info.as_ref().map(|dinfo| dinfo.request_id)
     }
 
     pub fn add_allow<D: Display>(&mut self, s: D) {
-        if let Some(ref mut dinfo) = self.0 {
+        if let Some(ref mut dinfo) = self.info {
             if dinfo.allow.len() > 0 {
                 write!(&mut dinfo.allow, ", {}", s).unwrap();
             } else {
@@ -84,7 +101,7 @@ impl Debug {
     }
 
     pub fn get_allow(&self) -> Option<&str> {
-        self.0.as_ref().and_then(|dinfo| {
+        self.info.as_ref().and_then(|dinfo| {
             if dinfo.allow.len() == 0 {
                 None
             } else {
@@ -94,13 +111,13 @@ impl Debug {
     }
 
     pub fn set_deny<D: Display>(&mut self, s: D) {
-        if let Some(ref mut dinfo) = self.0 {
+        if let Some(ref mut dinfo) = self.info {
             dinfo.deny = s.to_string();
         }
     }
 
     pub fn get_deny(&self) -> Option<&str> {
-        self.0.as_ref().and_then(|dinfo| {
+        self.info.as_ref().and_then(|dinfo| {
             if dinfo.deny.len() == 0 {
                 None
             } else {
@@ -110,7 +127,7 @@ impl Debug {
     }
 
     pub fn get_authorizer(&self) -> Option<&Authorizer> {
-        self.0.as_ref().and_then(|dinfo| {
+        self.info.as_ref().and_then(|dinfo| {
             dinfo.route.as_ref().map(|x| &x.authorizer_name)
         })
     }
@@ -73,11 +73,17 @@ impl<S> Encoder<S> {
 
 impl<S> Encoder<S> {
     pub fn status(&mut self, status: Status) {
+        self.debug.response_log().set_status(status.code());
         self.enc.status(status);
     }
     pub fn custom_status(&mut self, code: u16, reason: &str) {
+        self.debug.response_log().set_status(code);
         self.enc.custom_status(code, reason);
     }
+    /// Address of the backend which served the request, for access log
+    pub fn set_upstream(&mut self, upstream: &Arc<str>) {
+        self.debug.response_log().set_upstream(upstream);
+    }
     pub fn add_length(&mut self, n: u64) {
         self.enc.add_length(n).unwrap();
     }
@@ -141,6 +147,7 @@ impl<S> Encoder<S> {
         enc.done_headers().unwrap()
     }
     pub fn write_body<T: AsRef<[u8]>>(&mut self, val: T) {
+        self.debug.response_log().add_bytes(val.as_ref().len());
         self.enc.write_body(val.as_ref())
     }
     pub fn done(self) -> EncoderDone<S> {
@@ -156,7 +163,9 @@ impl<S> Encoder<S> {
 
 impl<S> io::Write for Encoder<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.enc.write(buf)
+        let bytes = self.enc.write(buf)?;
+        self.debug.response_log().add_bytes(bytes);
+        Ok(bytes)
     }
     fn flush(&mut self) -> io::Result<()> {
         io::Write::flush(&mut self.enc)
@@ -37,8 +37,8 @@ impl Handler {
             Handler::VersionedStatic(ref settings) => {
                 Ok(handlers::files::serve_versioned(settings, input))
             }
-            Handler::WebsocketEcho => {
-                Ok(handlers::websocket_echo::serve(input))
+            Handler::WebsocketEcho(ref settings) => {
+                Ok(handlers::websocket_echo::serve(settings, input))
             }
             Handler::Proxy(ref settings) => {
                 Ok(handlers::proxy::serve(settings, input))
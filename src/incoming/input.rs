@@ -1,5 +1,8 @@
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::mem;
 use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use tk_http::server::Head;
 use tokio_core::reactor::Handle;
@@ -20,6 +23,24 @@ pub struct Input<'a> {
     pub suffix: &'a str,
     pub handle: &'a Handle,
     pub request_id: RequestId,
+    pub auth_headers: AuthHeaders,
+}
+
+/// Headers that authorizer adds to the request
+///
+/// These are filled in asynchronously, but always before request body is
+/// passed to the handler. Client-supplied headers with the same names are
+/// replaced.
+#[derive(Clone, Default)]
+pub struct AuthHeaders(Rc<RefCell<Vec<(String, Vec<u8>)>>>);
+
+impl AuthHeaders {
+    pub fn set(&self, headers: Vec<(String, Vec<u8>)>) {
+        *self.0.borrow_mut() = headers;
+    }
+    pub fn take(&self) -> Vec<(String, Vec<u8>)> {
+        mem::replace(&mut *self.0.borrow_mut(), Vec::new())
+    }
 }
 
 impl<'a> IntoContext for Input<'a> {
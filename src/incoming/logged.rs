@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use futures::{Future, Async};
+use tk_bufstream::{ReadBuf, WriteBuf};
+use tk_http::server::{Codec, Error, RecvMode};
+use tk_http::server as http;
+
+use crate::incoming::{Request, Reply};
+use crate::logging::{self, http::AccessLog};
+use crate::runtime::Runtime;
+
+
+/// Codec that writes access log when response is done
+struct Logged<S> {
+    inner: Request<S>,
+    log: Option<AccessLog>,
+    runtime: Arc<Runtime>,
+}
+
+pub fn wrap<S: 'static>(inner: Request<S>, log: AccessLog,
+    runtime: &Arc<Runtime>)
+    -> Request<S>
+{
+    Box::new(Logged {
+        inner: inner,
+        log: Some(log),
+        runtime: runtime.clone(),
+    })
+}
+
+impl<S: 'static> Codec<S> for Logged<S> {
+    type ResponseFuture = Reply<S>;
+    fn recv_mode(&mut self) -> RecvMode {
+        self.inner.recv_mode()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        self.inner.data_received(data, end)
+    }
+    fn start_response(&mut self, e: http::Encoder<S>) -> Reply<S> {
+        let log = self.log.take().expect("start response called once");
+        let runtime = self.runtime.clone();
+        // Response is logged even if it fails in the middle, status and
+        // bytes sent show how far it went
+        Box::new(self.inner.start_response(e).then(move |result| {
+            logging::log(&runtime, &log);
+            result
+        }))
+    }
+    fn hijack(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>) {
+        self.inner.hijack(write_buf, read_buf)
+    }
+}
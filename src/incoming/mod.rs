@@ -11,6 +11,7 @@ mod encoder;
 mod quick_reply;
 mod handler;
 mod authorizer;
+mod logged;
 
 pub type Request<S> = Box<dyn Codec<S, ResponseFuture=Reply<S>>>;
 pub type Reply<S> = Box<dyn Future<Item=EncoderDone<S>, Error=Error>>;
@@ -18,7 +19,7 @@ pub type Reply<S> = Box<dyn Future<Item=EncoderDone<S>, Error=Error>>;
 pub use self::debug::Debug;
 pub use tk_http::server::EncoderDone;
 pub use self::encoder::{Encoder, IntoContext, Context};
-pub use self::input::{Input};
+pub use self::input::{Input, AuthHeaders};
 pub use self::quick_reply::reply;
 pub use self::router::Router;
 
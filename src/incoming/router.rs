@@ -6,13 +6,15 @@ use tk_http::Status;
 use tk_http::server::{Dispatcher, Error as ServerError, Head};
 
 use crate::runtime::Runtime;
-use crate::incoming::{Request, Debug, Input, Transport};
+use crate::incoming::{Request, Debug, Input, Transport, AuthHeaders};
+use crate::incoming::authorizer::Check;
 use crate::routing::{parse_host, route};
 use crate::default_error_page::serve_error_page;
 use crate::request_id;
 
 use crate::metrics::{Counter};
-use crate::logging;
+use crate::incoming::logged;
+use crate::logging::http::AccessLog;
 use crate::request_id::RequestId;
 
 
@@ -47,7 +49,7 @@ impl Router {
 impl Router {
 
     fn start_request<S: Transport>(&mut self, headers: &Head,
-        request_id: RequestId)
+        request_id: RequestId, log: &mut AccessLog)
         -> Result<Request<S>, Error>
     {
         use self::Error::*;
@@ -55,7 +57,7 @@ impl Router {
         REQUESTS.incr(1);
         // Keep config same while processing a single request
         let cfg = self.runtime.config.get();
-        let mut debug = Debug::new(headers, request_id, &cfg);
+        let mut debug = Debug::new(headers, request_id, &cfg, &log.response);
 
         // No path means either CONNECT host, or OPTIONS *
         // in both cases we use root route for the domain to make decision
@@ -91,6 +93,7 @@ impl Router {
             return Err(Page(Status::NotFound, debug));
         };
         debug.set_route(route);
+        log.set_route(&route.authorizer_name, &route.log_format);
 
         let mut inp = Input {
             addr: self.addr,
@@ -102,13 +105,23 @@ impl Router {
             suffix: suf,
             handle: &self.handle,
             request_id: request_id,
+            auth_headers: AuthHeaders::default(),
         };
 
         match route.authorizer.check(&mut inp) {
-            Ok(true) => {}
-            Ok(false) => {
+            Ok(Check::Allow) => {}
+            Ok(Check::Deny) => {
                 return Err(Page(Status::Forbidden, inp.debug));
             }
+            Ok(Check::Defer(deferred)) => {
+                // Handler owns its debug info, so error page gets a copy
+                let mut debug = Debug::new(headers, request_id, &cfg,
+                                           &log.response);
+                debug.set_route(route);
+                return route.handler.serve(inp)
+                    .map(|codec| deferred.wrap(codec, (cfg.clone(), debug)))
+                    .map_err(Fallback);
+            }
             Err(e) => return Err(Fallback(e)),
         }
 
@@ -122,35 +135,14 @@ impl<S: Transport> Dispatcher<S> for Router {
         -> Result<Self::Codec, ServerError>
     {
         let request_id = request_id::new();
-        match self.start_request(headers, request_id) {
-            Ok(x) => {
-                // TODO(tailhook) request is not done yet, just a fake
-                logging::log(&self.runtime,
-                    logging::http::FakePage {
-                        request: logging::http::EarlyRequest {
-                            addr: self.addr,
-                            head: headers,
-                            request_id: request_id,
-                        },
-                        response: logging::http::FakeResponse {
-                        },
-                    });
-                Ok(x)
-            }
+        let mut log = AccessLog::new(self.addr, headers, request_id);
+        match self.start_request(headers, request_id, &mut log) {
+            Ok(x) => Ok(logged::wrap(x, log, &self.runtime)),
             Err(Error::Page(status, debug)) => {
-                logging::log(&self.runtime,
-                    logging::http::EarlyError {
-                        request: logging::http::EarlyRequest {
-                            addr: self.addr,
-                            head: headers,
-                            request_id: request_id,
-                        },
-                        response: logging::http::EarlyResponse {
-                            status: status.into(),
-                        }
-                    });
-                Ok(serve_error_page(status,
-                    (self.runtime.config.get(), debug)))
+                Ok(logged::wrap(
+                    serve_error_page(status,
+                        (self.runtime.config.get(), debug)),
+                    log, &self.runtime))
             }
             // Maybe return bad request?
             Err(Error::Fallback(e)) => Err(e),
@@ -0,0 +1,180 @@
+//! A tiny subset of BER (basic encoding rules) that is needed for LDAP
+//!
+//! We only support definite-length encoding and single-byte tags, which
+//! is what all LDAP servers use in practice.
+use crate::ldap::Error;
+
+
+pub const BOOLEAN: u8 = 0x01;
+pub const INTEGER: u8 = 0x02;
+pub const OCTET_STRING: u8 = 0x04;
+pub const ENUMERATED: u8 = 0x0a;
+pub const SEQUENCE: u8 = 0x30;
+
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Element<'a> {
+    pub tag: u8,
+    pub body: &'a [u8],
+}
+
+
+fn write_len(buf: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        buf.push(len as u8);
+    } else {
+        let bytes = (len as u64).to_be_bytes();
+        let skip = bytes.iter().take_while(|&&x| x == 0).count();
+        buf.push(0x80 | (bytes.len() - skip) as u8);
+        buf.extend(&bytes[skip..]);
+    }
+}
+
+pub fn octets(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(body.len() + 6);
+    buf.push(tag);
+    write_len(&mut buf, body.len());
+    buf.extend(body);
+    return buf;
+}
+
+pub fn constructed(tag: u8, parts: &[Vec<u8>]) -> Vec<u8> {
+    let len = parts.iter().map(|x| x.len()).sum();
+    let mut buf = Vec::with_capacity(len + 6);
+    buf.push(tag);
+    write_len(&mut buf, len);
+    for part in parts {
+        buf.extend(part);
+    }
+    return buf;
+}
+
+pub fn integer(tag: u8, value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Strip redundant leading bytes but keep the sign bit intact
+    let mut start = 0;
+    while start < bytes.len() - 1 {
+        let (cur, next) = (bytes[start], bytes[start+1]);
+        if cur == 0 && next & 0x80 == 0 || cur == 0xFF && next & 0x80 != 0 {
+            start += 1;
+        } else {
+            break;
+        }
+    }
+    octets(tag, &bytes[start..])
+}
+
+pub fn boolean(value: bool) -> Vec<u8> {
+    octets(BOOLEAN, &[if value { 0xFF } else { 0 }])
+}
+
+/// Parses single element from the start of the buffer
+///
+/// Returns `None` if there is not enough data yet, otherwise returns an
+/// element and a number of bytes it occupies.
+pub fn parse(data: &[u8]) -> Result<Option<(Element<'_>, usize)>, Error> {
+    if data.len() < 2 {
+        return Ok(None);
+    }
+    let tag = data[0];
+    if tag & 0x1F == 0x1F {
+        return Err(Error::Protocol("multi-byte tags are not supported"));
+    }
+    let (len, offset) = if data[1] & 0x80 == 0 {
+        (data[1] as usize, 2)
+    } else {
+        let nbytes = (data[1] & 0x7F) as usize;
+        if nbytes == 0 {
+            return Err(Error::Protocol("indefinite length is not supported"));
+        }
+        if nbytes > 4 {
+            return Err(Error::Protocol("element is too long"));
+        }
+        if data.len() < 2 + nbytes {
+            return Ok(None);
+        }
+        let len = data[2..2+nbytes].iter()
+            .fold(0usize, |acc, &x| (acc << 8) | x as usize);
+        (len, 2 + nbytes)
+    };
+    if data.len() < offset + len {
+        return Ok(None);
+    }
+    Ok(Some((Element { tag, body: &data[offset..offset+len] },
+             offset + len)))
+}
+
+/// Splits body of a constructed element into its children
+pub fn children(mut data: &[u8]) -> Result<Vec<Element<'_>>, Error> {
+    let mut result = Vec::new();
+    while data.len() > 0 {
+        match parse(data)? {
+            Some((el, len)) => {
+                result.push(el);
+                data = &data[len..];
+            }
+            None => return Err(Error::Protocol("truncated element")),
+        }
+    }
+    Ok(result)
+}
+
+pub fn parse_integer(el: &Element) -> Result<i64, Error> {
+    if el.body.len() == 0 || el.body.len() > 8 {
+        return Err(Error::Protocol("invalid integer"));
+    }
+    let init = if el.body[0] & 0x80 != 0 { -1i64 } else { 0 };
+    Ok(el.body.iter().fold(init, |acc, &x| (acc << 8) | x as i64))
+}
+
+pub fn parse_string(el: &Element) -> Result<String, Error> {
+    String::from_utf8(el.body.to_vec())
+        .map_err(|_| Error::Protocol("invalid utf-8 in string"))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn integers() {
+        assert_eq!(integer(INTEGER, 0), vec![0x02, 0x01, 0x00]);
+        assert_eq!(integer(INTEGER, 3), vec![0x02, 0x01, 0x03]);
+        assert_eq!(integer(INTEGER, 128), vec![0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(INTEGER, -1), vec![0x02, 0x01, 0xFF]);
+        assert_eq!(integer(INTEGER, 65536),
+            vec![0x02, 0x03, 0x01, 0x00, 0x00]);
+        for &val in &[0, 1, 127, 128, 255, 256, -1, -128, -129, 1 << 40] {
+            let data = integer(INTEGER, val);
+            let (el, len) = parse(&data).unwrap().unwrap();
+            assert_eq!(len, data.len());
+            assert_eq!(parse_integer(&el).unwrap(), val);
+        }
+    }
+
+    #[test]
+    fn long_length() {
+        let body = vec![b'x'; 300];
+        let data = octets(OCTET_STRING, &body);
+        assert_eq!(&data[..4], &[0x04, 0x82, 0x01, 0x2C]);
+        assert_eq!(parse(&data[..100]).unwrap(), None);
+        let (el, len) = parse(&data).unwrap().unwrap();
+        assert_eq!(len, 304);
+        assert_eq!(el.body, &body[..]);
+    }
+
+    #[test]
+    fn nested() {
+        let data = constructed(SEQUENCE, &[
+            integer(INTEGER, 1),
+            octets(OCTET_STRING, b"hello"),
+        ]);
+        let (el, _) = parse(&data).unwrap().unwrap();
+        assert_eq!(el.tag, SEQUENCE);
+        let items = children(el.body).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(parse_integer(&items[0]).unwrap(), 1);
+        assert_eq!(parse_string(&items[1]).unwrap(), "hello");
+    }
+}
@@ -0,0 +1,191 @@
+use futures::Future;
+use futures::future::{ok, err, loop_fn, Loop, Either};
+use ns_router::Router;
+use rand::{thread_rng, Rng};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::io::{read, write_all};
+
+use crate::ldap::{Error, Filter};
+use crate::ldap::proto::{self, Response, Entry, LdapResult};
+
+
+const DEFAULT_PORT: u16 = 389;
+const READ_CHUNK: usize = 4096;
+
+/// A single LDAP connection
+///
+/// Requests are sent one by one, every method consumes the connection and
+/// returns it back along with the result.
+pub struct Connection {
+    sock: TcpStream,
+    buf: Vec<u8>,
+    next_id: i32,
+}
+
+pub type Reply<T> = Box<dyn Future<Item=(Connection, T), Error=Error>>;
+
+
+/// Connects to one of the addresses
+///
+/// Addresses are tried in random order until connection succeeds
+pub fn connect(addresses: &[String], resolver: &Router, handle: &Handle)
+    -> Box<dyn Future<Item=Connection, Error=Error>>
+{
+    let mut addresses = addresses.to_vec();
+    thread_rng().shuffle(&mut addresses);
+    connect_any(addresses, resolver.clone(), handle.clone())
+}
+
+fn connect_any(mut addresses: Vec<String>, resolver: Router, handle: Handle)
+    -> Box<dyn Future<Item=Connection, Error=Error>>
+{
+    let name = match addresses.pop() {
+        Some(name) => name,
+        None => return Box::new(err(Error::NoAddress)),
+    };
+    let h1 = handle.clone();
+    Box::new(resolver.resolve_auto(&name, DEFAULT_PORT)
+        .map_err(|e| Error::Io(e.into_io()))
+        .and_then(|addr| addr.pick_one().ok_or(Error::NoAddress))
+        .and_then(move |addr| {
+            TcpStream::connect(&addr, &h1).map_err(Error::Io)
+        })
+        .map(|sock| Connection {
+            sock: sock,
+            buf: Vec::new(),
+            next_id: 1,
+        })
+        .or_else(move |e| {
+            if addresses.len() == 0 {
+                Either::A(err(e))
+            } else {
+                debug!("Error connecting to LDAP {:?}: {}", name, e);
+                Either::B(connect_any(addresses, resolver, handle))
+            }
+        }))
+}
+
+impl Connection {
+    fn next_id(&mut self) -> i32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        return id;
+    }
+
+    fn send(self, data: Vec<u8>)
+        -> Box<dyn Future<Item=Connection, Error=Error>>
+    {
+        let Connection { sock, buf, next_id } = self;
+        Box::new(write_all(sock, data)
+            .map_err(Error::Io)
+            .map(move |(sock, _)| Connection {
+                sock: sock,
+                buf: buf,
+                next_id: next_id,
+            }))
+    }
+
+    fn receive(self, id: i32) -> Reply<Response> {
+        Box::new(loop_fn(self, move |mut conn| {
+            match proto::parse(&conn.buf) {
+                Ok(Some((rid, response, len))) => {
+                    conn.buf.drain(..len);
+                    if rid != id {
+                        Either::A(err(
+                            Error::Protocol("unexpected message id")))
+                    } else {
+                        Either::A(ok(Loop::Break((conn, response))))
+                    }
+                }
+                Ok(None) => {
+                    let Connection { sock, mut buf, next_id } = conn;
+                    Either::B(read(sock, vec![0u8; READ_CHUNK])
+                        .map_err(Error::Io)
+                        .and_then(move |(sock, chunk, n)| {
+                            if n == 0 {
+                                return Err(Error::Closed);
+                            }
+                            buf.extend(&chunk[..n]);
+                            Ok(Loop::Continue(Connection {
+                                sock: sock,
+                                buf: buf,
+                                next_id: next_id,
+                            }))
+                        }))
+                }
+                Err(e) => Either::A(err(e)),
+            }
+        }))
+    }
+
+    /// Simple bind, returns result as is, so caller can check credentials
+    pub fn bind(mut self, dn: &str, password: &str) -> Reply<LdapResult> {
+        let id = self.next_id();
+        Box::new(self.send(proto::bind(id, dn, password))
+            .and_then(move |conn| conn.receive(id))
+            .and_then(|(conn, response)| match response {
+                Response::Bind(result) => Ok((conn, result)),
+                _ => Err(Error::Protocol("unexpected response to bind")),
+            }))
+    }
+
+    /// Searches whole subtree of the `base`
+    pub fn search(mut self, base: &str, filter: &Filter, attributes: &[&str])
+        -> Reply<Vec<Entry>>
+    {
+        let id = self.next_id();
+        Box::new(self.send(proto::search(id, base, filter, attributes))
+            .and_then(move |conn| loop_fn((conn, Vec::new()),
+                move |(conn, mut entries)| {
+                    conn.receive(id).and_then(move |(conn, response)| {
+                        match response {
+                            Response::SearchEntry(entry) => {
+                                entries.push(entry);
+                                Ok(Loop::Continue((conn, entries)))
+                            }
+                            Response::SearchReference => {
+                                Ok(Loop::Continue((conn, entries)))
+                            }
+                            Response::SearchDone(ref res)
+                            if res.code == proto::SUCCESS
+                            => {
+                                Ok(Loop::Break((conn, entries)))
+                            }
+                            Response::SearchDone(res) => {
+                                Err(Error::Ldap(res.code, res.message))
+                            }
+                            _ => Err(Error::Protocol(
+                                "unexpected response to search")),
+                        }
+                    })
+                })))
+    }
+
+    /// Compares attribute value, returns `true` if value matches
+    pub fn compare(mut self, dn: &str, attribute: &str, value: &str)
+        -> Reply<bool>
+    {
+        let id = self.next_id();
+        Box::new(self.send(proto::compare(id, dn, attribute, value))
+            .and_then(move |conn| conn.receive(id))
+            .and_then(|(conn, response)| match response {
+                Response::Compare(ref res)
+                if res.code == proto::COMPARE_TRUE
+                => Ok((conn, true)),
+                Response::Compare(ref res)
+                if res.code == proto::COMPARE_FALSE
+                => Ok((conn, false)),
+                Response::Compare(res) => {
+                    Err(Error::Ldap(res.code, res.message))
+                }
+                _ => Err(Error::Protocol("unexpected response to compare")),
+            }))
+    }
+
+    /// Sends unbind request, server closes connection after it
+    pub fn unbind(mut self) -> Box<dyn Future<Item=(), Error=Error>> {
+        let id = self.next_id();
+        Box::new(self.send(proto::unbind(id)).map(|_| ()))
+    }
+}
@@ -0,0 +1,221 @@
+//! Search filters in the string representation of RFC 4515
+//!
+//! Only `and`, `or`, `not`, equality and presence filters are supported.
+//! Assertion values may contain `${var}` placeholders which are replaced
+//! by `Filter::substitute` after the filter is parsed, so substituted values
+//! never need escaping.
+use std::str::from_utf8;
+
+use crate::ldap::ber;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Equal(String, String),
+    Present(String),
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+
+/// Parses a filter, surrounding parenthesis are optional
+pub fn parse(text: &str) -> Result<Filter, String> {
+    let text = text.trim();
+    let mut p = Parser { data: text.as_bytes(), pos: 0 };
+    let filter = if text.starts_with("(") {
+        p.filter()?
+    } else {
+        p.item(text.len())?
+    };
+    if p.pos != text.len() {
+        return Err(format!("unexpected data at {} in filter {:?}",
+            p.pos, text));
+    }
+    Ok(filter)
+}
+
+impl<'a> Parser<'a> {
+    fn filter(&mut self) -> Result<Filter, String> {
+        self.expect(b'(')?;
+        let result = match self.data.get(self.pos) {
+            Some(b'&') => {
+                self.pos += 1;
+                Filter::And(self.list()?)
+            }
+            Some(b'|') => {
+                self.pos += 1;
+                Filter::Or(self.list()?)
+            }
+            Some(b'!') => {
+                self.pos += 1;
+                Filter::Not(Box::new(self.filter()?))
+            }
+            Some(_) => {
+                let end = self.data[self.pos..].iter()
+                    .position(|&x| x == b')')
+                    .map(|x| x + self.pos)
+                    .ok_or_else(|| format!("unclosed parenthesis"))?;
+                self.item(end)?
+            }
+            None => return Err(format!("unexpected end of filter")),
+        };
+        self.expect(b')')?;
+        Ok(result)
+    }
+    fn list(&mut self) -> Result<Vec<Filter>, String> {
+        let mut items = Vec::new();
+        while self.data.get(self.pos) == Some(&b'(') {
+            items.push(self.filter()?);
+        }
+        if items.len() == 0 {
+            return Err(format!("empty filter list at {}", self.pos));
+        }
+        Ok(items)
+    }
+    fn item(&mut self, end: usize) -> Result<Filter, String> {
+        let chunk = &self.data[self.pos..end];
+        let eq = chunk.iter().position(|&x| x == b'=')
+            .ok_or_else(|| format!("no `=` in filter item at {}", self.pos))?;
+        let attr = from_utf8(&chunk[..eq]).unwrap();
+        if attr.len() == 0 || !attr.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' ||
+                     c == ';')
+        {
+            return Err(format!("invalid attribute {:?} in filter", attr));
+        }
+        let value = &chunk[eq+1..];
+        self.pos = end;
+        if value == b"*" {
+            return Ok(Filter::Present(attr.to_string()));
+        }
+        if value.contains(&b'*') {
+            return Err(format!("substring filters are not supported"));
+        }
+        Ok(Filter::Equal(attr.to_string(), unescape(value)?))
+    }
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.data.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected {:?} at {} in filter", c as char, self.pos))
+        }
+    }
+}
+
+fn unescape(value: &[u8]) -> Result<String, String> {
+    let mut result = Vec::with_capacity(value.len());
+    let mut iter = value.iter();
+    while let Some(&c) = iter.next() {
+        if c == b'\\' {
+            let hex = iter.next().and_then(|&h| iter.next().map(|&l| [h, l]))
+                .ok_or_else(|| format!("bad escape sequence in filter"))?;
+            let byte = from_utf8(&hex).ok()
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .ok_or_else(|| format!("bad escape sequence in filter"))?;
+            result.push(byte);
+        } else {
+            result.push(c);
+        }
+    }
+    String::from_utf8(result)
+        .map_err(|_| format!("filter value is not valid utf-8"))
+}
+
+impl Filter {
+    /// Replaces `${name}` placeholders in all assertion values
+    pub fn substitute(&self, vars: &[(&str, &str)]) -> Filter {
+        use self::Filter::*;
+        match *self {
+            And(ref items) => {
+                And(items.iter().map(|x| x.substitute(vars)).collect())
+            }
+            Or(ref items) => {
+                Or(items.iter().map(|x| x.substitute(vars)).collect())
+            }
+            Not(ref item) => Not(Box::new(item.substitute(vars))),
+            Equal(ref attr, ref value) => {
+                let mut value = value.clone();
+                for &(name, replacement) in vars {
+                    value = value.replace(&format!("${{{}}}", name),
+                                          replacement);
+                }
+                Equal(attr.clone(), value)
+            }
+            Present(ref attr) => Present(attr.clone()),
+        }
+    }
+    pub fn encode(&self) -> Vec<u8> {
+        use self::Filter::*;
+        match *self {
+            And(ref items) => ber::constructed(0xA0,
+                &items.iter().map(|x| x.encode()).collect::<Vec<_>>()),
+            Or(ref items) => ber::constructed(0xA1,
+                &items.iter().map(|x| x.encode()).collect::<Vec<_>>()),
+            Not(ref item) => ber::constructed(0xA2, &[item.encode()]),
+            Equal(ref attr, ref value) => ber::constructed(0xA3, &[
+                ber::octets(ber::OCTET_STRING, attr.as_bytes()),
+                ber::octets(ber::OCTET_STRING, value.as_bytes()),
+            ]),
+            Present(ref attr) => ber::octets(0x87, attr.as_bytes()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use super::Filter::*;
+
+    fn eq(a: &str, v: &str) -> super::Filter {
+        Equal(a.to_string(), v.to_string())
+    }
+
+    #[test]
+    fn simple() {
+        assert_eq!(parse("uid=john").unwrap(), eq("uid", "john"));
+        assert_eq!(parse("(uid=john)").unwrap(), eq("uid", "john"));
+        assert_eq!(parse("(cn=*)").unwrap(), Present("cn".into()));
+        assert_eq!(parse("member=${dn}").unwrap(), eq("member", "${dn}"));
+    }
+
+    #[test]
+    fn nested() {
+        assert_eq!(parse("(&(objectClass=group)(!(cn=x))(|(a=1)(b=2)))")
+            .unwrap(),
+            And(vec![
+                eq("objectClass", "group"),
+                Not(Box::new(eq("cn", "x"))),
+                Or(vec![eq("a", "1"), eq("b", "2")]),
+            ]));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse(r"(cn=a\28b\29)").unwrap(), eq("cn", "a(b)"));
+        assert!(parse(r"(cn=a\2)").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse("(uid=john").is_err());
+        assert!(parse("(&)").is_err());
+        assert!(parse("(cn=jo*n)").is_err());
+        assert!(parse("uid").is_err());
+        assert!(parse("(uid=a)(uid=b)").is_err());
+    }
+
+    #[test]
+    fn substitute() {
+        let f = parse("(&(member=${dn})(uid=${login}))").unwrap();
+        assert_eq!(f.substitute(&[("dn", "cn=x,dc=org"), ("login", "a)")]),
+            And(vec![eq("member", "cn=x,dc=org"), eq("uid", "a)")]));
+    }
+}
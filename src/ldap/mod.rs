@@ -0,0 +1,42 @@
+//! Minimal asynchronous LDAPv3 client
+//!
+//! Only simple bind, search and compare operations are implemented, which
+//! is all the `!Ldap` authorizer needs.
+use std::io;
+
+mod ber;
+mod client;
+mod filter;
+mod proto;
+
+pub use self::client::{Connection, connect};
+pub use self::filter::{Filter, parse as parse_filter};
+pub use self::proto::{SUCCESS, INVALID_CREDENTIALS};
+
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: io::Error) {
+            display("I/O error: {}", err)
+            description("I/O error")
+            from()
+        }
+        Protocol(msg: &'static str) {
+            display("protocol error: {}", msg)
+            description("LDAP protocol error")
+        }
+        Ldap(code: i64, message: String) {
+            display("LDAP error {}: {:?}", code, message)
+            description("LDAP error")
+        }
+        Closed {
+            display("connection closed by server")
+            description("connection closed by server")
+        }
+        NoAddress {
+            display("no address to connect to")
+            description("no address to connect to")
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! LDAPv3 messages (RFC 4511) that are needed for authorization
+use crate::ldap::ber::{self, Element};
+use crate::ldap::{Error, Filter};
+
+
+pub const SUCCESS: i64 = 0;
+pub const COMPARE_FALSE: i64 = 5;
+pub const COMPARE_TRUE: i64 = 6;
+pub const INVALID_CREDENTIALS: i64 = 49;
+
+const BIND_REQUEST: u8 = 0x60;
+const BIND_RESPONSE: u8 = 0x61;
+const UNBIND_REQUEST: u8 = 0x42;
+const SEARCH_REQUEST: u8 = 0x63;
+const SEARCH_RESULT_ENTRY: u8 = 0x64;
+const SEARCH_RESULT_DONE: u8 = 0x65;
+const SEARCH_RESULT_REFERENCE: u8 = 0x73;
+const COMPARE_REQUEST: u8 = 0x6E;
+const COMPARE_RESPONSE: u8 = 0x6F;
+
+const SIMPLE_AUTH: u8 = 0x80;
+const SCOPE_WHOLE_SUBTREE: i64 = 2;
+const NEVER_DEREF_ALIASES: i64 = 0;
+
+
+#[derive(Debug)]
+pub struct LdapResult {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub dn: String,
+    pub attributes: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug)]
+pub enum Response {
+    Bind(LdapResult),
+    SearchEntry(Entry),
+    SearchReference,
+    SearchDone(LdapResult),
+    Compare(LdapResult),
+}
+
+
+fn message(id: i32, op: Vec<u8>) -> Vec<u8> {
+    ber::constructed(ber::SEQUENCE, &[
+        ber::integer(ber::INTEGER, id as i64),
+        op,
+    ])
+}
+
+pub fn bind(id: i32, dn: &str, password: &str) -> Vec<u8> {
+    message(id, ber::constructed(BIND_REQUEST, &[
+        ber::integer(ber::INTEGER, 3),
+        ber::octets(ber::OCTET_STRING, dn.as_bytes()),
+        ber::octets(SIMPLE_AUTH, password.as_bytes()),
+    ]))
+}
+
+pub fn unbind(id: i32) -> Vec<u8> {
+    message(id, ber::octets(UNBIND_REQUEST, b""))
+}
+
+pub fn search(id: i32, base: &str, filter: &Filter, attributes: &[&str])
+    -> Vec<u8>
+{
+    message(id, ber::constructed(SEARCH_REQUEST, &[
+        ber::octets(ber::OCTET_STRING, base.as_bytes()),
+        ber::integer(ber::ENUMERATED, SCOPE_WHOLE_SUBTREE),
+        ber::integer(ber::ENUMERATED, NEVER_DEREF_ALIASES),
+        ber::integer(ber::INTEGER, 0),  // size limit
+        ber::integer(ber::INTEGER, 0),  // time limit
+        ber::boolean(false),  // types only
+        filter.encode(),
+        ber::constructed(ber::SEQUENCE, &attributes.iter()
+            .map(|a| ber::octets(ber::OCTET_STRING, a.as_bytes()))
+            .collect::<Vec<_>>()),
+    ]))
+}
+
+pub fn compare(id: i32, dn: &str, attribute: &str, value: &str) -> Vec<u8> {
+    message(id, ber::constructed(COMPARE_REQUEST, &[
+        ber::octets(ber::OCTET_STRING, dn.as_bytes()),
+        ber::constructed(ber::SEQUENCE, &[
+            ber::octets(ber::OCTET_STRING, attribute.as_bytes()),
+            ber::octets(ber::OCTET_STRING, value.as_bytes()),
+        ]),
+    ]))
+}
+
+fn parse_result(el: &Element) -> Result<LdapResult, Error> {
+    let items = ber::children(el.body)?;
+    if items.len() < 3 || items[0].tag != ber::ENUMERATED {
+        return Err(Error::Protocol("invalid ldap result"));
+    }
+    Ok(LdapResult {
+        code: ber::parse_integer(&items[0])?,
+        message: String::from_utf8_lossy(items[2].body).into_owned(),
+    })
+}
+
+fn parse_entry(el: &Element) -> Result<Entry, Error> {
+    let items = ber::children(el.body)?;
+    if items.len() != 2 {
+        return Err(Error::Protocol("invalid search result entry"));
+    }
+    let mut attributes = Vec::new();
+    for attr in ber::children(items[1].body)? {
+        let parts = ber::children(attr.body)?;
+        if parts.len() != 2 {
+            return Err(Error::Protocol("invalid attribute in search result"));
+        }
+        let values = ber::children(parts[1].body)?.iter()
+            .map(|v| String::from_utf8_lossy(v.body).into_owned())
+            .collect();
+        attributes.push((ber::parse_string(&parts[0])?, values));
+    }
+    Ok(Entry {
+        dn: ber::parse_string(&items[0])?,
+        attributes: attributes,
+    })
+}
+
+/// Parses a single message from the start of the buffer
+///
+/// Returns message id, response and number of bytes consumed
+pub fn parse(data: &[u8]) -> Result<Option<(i32, Response, usize)>, Error> {
+    let (msg, len) = match ber::parse(data)? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    if msg.tag != ber::SEQUENCE {
+        return Err(Error::Protocol("ldap message must be a sequence"));
+    }
+    let items = ber::children(msg.body)?;
+    if items.len() < 2 || items[0].tag != ber::INTEGER {
+        return Err(Error::Protocol("invalid ldap message"));
+    }
+    let id = ber::parse_integer(&items[0])? as i32;
+    let op = &items[1];
+    let response = match op.tag {
+        BIND_RESPONSE => Response::Bind(parse_result(op)?),
+        SEARCH_RESULT_ENTRY => Response::SearchEntry(parse_entry(op)?),
+        SEARCH_RESULT_REFERENCE => Response::SearchReference,
+        SEARCH_RESULT_DONE => Response::SearchDone(parse_result(op)?),
+        COMPARE_RESPONSE => Response::Compare(parse_result(op)?),
+        _ => return Err(Error::Protocol("unexpected ldap operation")),
+    };
+    Ok(Some((id, response, len)))
+}
@@ -0,0 +1,321 @@
+//! Accepting connections on plain, TLS, unix and inherited sockets
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{remove_file, set_permissions, Permissions};
+use std::io::{self, Read, Write};
+use std::net::{self, SocketAddr};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net as unix;
+use std::path::Path;
+use std::time::Duration;
+
+use async_slot as slot;
+use futures::{Future, Stream, Sink, Poll};
+use futures::future::ok;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use futures::sync::oneshot;
+use libc;
+use ns_router::Router;
+use rustls::ServerSession;
+use tk_listen::{BindMany, ListenExt};
+use tokio_core::net::{TcpStream, TcpListener};
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsStream;
+use tokio_uds::{UnixStream, UnixListener};
+use void::Void;
+
+use crate::config::listen::{Listen, ListenSocket, UnixSocket};
+use crate::privileges::{user_ids, group_id};
+use crate::tls::Acceptor;
+
+
+/// Incoming connection
+pub enum ServerStream {
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream, ServerSession>),
+    Unix(UnixStream),
+}
+
+/// Connection which is ready to be served when future resolves
+///
+/// For TLS sockets the future includes the handshake, so it's counted
+/// against the connection limit of the listener.
+pub type Accept = Box<dyn Future<Item=(ServerStream, SocketAddr), Error=()>>;
+
+pub type Incoming = Box<dyn Stream<Item=Accept, Error=()>>;
+
+/// Updates addresses of the listener
+pub struct Listener {
+    plain: slot::Sender<Listen>,
+    tls: slot::Sender<Listen>,
+    /// Unix sockets and file descriptors, dropping the sender stops
+    /// accepting connections on the socket
+    local: RefCell<HashMap<ListenSocket, oneshot::Sender<()>>>,
+    local_tx: UnboundedSender<Accept>,
+    error_timeout: Duration,
+    handle: Handle,
+    verbose: bool,
+}
+
+/// Listening socket that is bound by ourselves rather than by `BindMany`
+enum Local {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+
+/// Address reported for connections on unix sockets
+///
+/// Peers of unix sockets are always on the local machine.
+fn unix_peer() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 0))
+}
+
+fn bind(rx: slot::Receiver<Listen>, port: u16, label: &'static str,
+    resolver: &Router, error_timeout: Duration, handle: &Handle,
+    verbose: bool)
+    -> impl Stream<Item=(TcpStream, SocketAddr), Error=()>
+{
+    BindMany::new(
+        resolver.subscribe_stream(
+            rx.map_err(|()| -> Void { unreachable!() }), port)
+        .map(move |addr| {
+            if verbose && addr.addresses_at(0).next().is_some() {
+                println!("Listening at {}{}",
+                    addr.addresses_at(0)
+                        .map(|x| x.to_string())
+                        .collect::<Vec<_>>().join(", "),
+                    label);
+            }
+            addr.addresses_at(0)
+        }), handle)
+    .sleep_on_error(error_timeout, handle)
+}
+
+fn set_owner(path: &Path, sock: &UnixSocket) -> Result<(), io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    if let Some(mode) = sock.mode {
+        set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    if sock.user.is_none() && sock.group.is_none() {
+        return Ok(());
+    }
+    // -1 means "don't change" for chown
+    let mut uid = !0;
+    let mut gid = !0;
+    if let Some(ref user) = sock.user {
+        uid = user_ids(user)?.0;
+    }
+    if let Some(ref group) = sock.group {
+        gid = group_id(group)?;
+    }
+    let cpath = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::chown(cpath.as_ptr(), uid, gid) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn is_unix_socket(fd: RawFd) -> bool {
+    unsafe {
+        let mut addr: libc::sockaddr_storage = ::std::mem::zeroed();
+        let mut len = ::std::mem::size_of::<libc::sockaddr_storage>()
+            as libc::socklen_t;
+        let res = libc::getsockname(fd,
+            &mut addr as *mut _ as *mut libc::sockaddr, &mut len);
+        res == 0 && addr.ss_family as libc::c_int == libc::AF_UNIX
+    }
+}
+
+impl Local {
+    fn bind(sock: &ListenSocket, handle: &Handle) -> Result<Local, io::Error>
+    {
+        match *sock {
+            ListenSocket::Unix(ref sock) => {
+                // stale socket from the previous run
+                if sock.path.exists() {
+                    remove_file(&sock.path)?;
+                }
+                let listener = UnixListener::bind(&sock.path, handle)?;
+                set_owner(&sock.path, sock)?;
+                Ok(Local::Unix(listener))
+            }
+            ListenSocket::Fd(fd) => {
+                let fd = fd as RawFd;
+                if is_unix_socket(fd) {
+                    let listener = unsafe {
+                        unix::UnixListener::from_raw_fd(fd)
+                    };
+                    listener.set_nonblocking(true)?;
+                    Ok(Local::Unix(
+                        UnixListener::from_listener(listener, handle)?))
+                } else {
+                    let listener = unsafe {
+                        net::TcpListener::from_raw_fd(fd)
+                    };
+                    listener.set_nonblocking(true)?;
+                    let addr = listener.local_addr()?;
+                    Ok(Local::Tcp(
+                        TcpListener::from_listener(listener, &addr, handle)?))
+                }
+            }
+            ListenSocket::Tcp(_) | ListenSocket::Tls(_) => unreachable!(),
+        }
+    }
+    fn incoming(self, error_timeout: Duration, handle: &Handle)
+        -> Box<dyn Stream<Item=Accept, Error=()>>
+    {
+        match self {
+            Local::Tcp(listener) => {
+                Box::new(listener.incoming()
+                    .sleep_on_error(error_timeout, handle)
+                    .map(|(sock, addr)| -> Accept {
+                        Box::new(ok((ServerStream::Plain(sock), addr)))
+                    }))
+            }
+            Local::Unix(listener) => {
+                Box::new(listener.incoming()
+                    .sleep_on_error(error_timeout, handle)
+                    .map(|(sock, _)| -> Accept {
+                        Box::new(ok((ServerStream::Unix(sock), unix_peer())))
+                    }))
+            }
+        }
+    }
+}
+
+impl Listener {
+    /// Creates listener, sockets are bound on first `update`
+    pub fn new(resolver: &Router, acceptor: &Acceptor,
+        error_timeout: Duration, handle: &Handle, verbose: bool)
+        -> (Listener, Incoming)
+    {
+        let (plain_tx, plain_rx) = slot::channel();
+        let (tls_tx, tls_rx) = slot::channel();
+        let (local_tx, local_rx) = unbounded();
+        let acceptor = acceptor.clone();
+        let plain = bind(plain_rx, 80, "",
+                         resolver, error_timeout, handle, verbose)
+            .map(|(sock, addr)| -> Accept {
+                Box::new(ok((ServerStream::Plain(sock), addr)))
+            });
+        let tls = bind(tls_rx, 443, " (tls)",
+                       resolver, error_timeout, handle, verbose)
+            .map(move |(sock, addr)| -> Accept {
+                Box::new(acceptor.accept(sock)
+                    .map(move |sock| (ServerStream::Tls(sock), addr))
+                    .map_err(move |e| {
+                        debug!("TLS handshake error from {}: {}", addr, e)
+                    }))
+            });
+        let listener = Listener {
+            plain: plain_tx,
+            tls: tls_tx,
+            local: RefCell::new(HashMap::new()),
+            local_tx: local_tx,
+            error_timeout: error_timeout,
+            handle: handle.clone(),
+            verbose: verbose,
+        };
+        (listener, Box::new(plain.select(tls).select(local_rx)))
+    }
+    pub fn update(&self, listen: &Listen) -> Result<(), ()> {
+        self.plain.swap(listen.plain()).map_err(|_| ())?;
+        self.tls.swap(listen.tls()).map_err(|_| ())?;
+        self.update_local(listen.local());
+        Ok(())
+    }
+    fn update_local(&self, sockets: Vec<ListenSocket>) {
+        let mut local = self.local.borrow_mut();
+        // Stop old ones first, so that unix socket that changed its
+        // permissions can be bound again at the same path
+        let removed = local.keys()
+            .filter(|s| !sockets.contains(s))
+            .cloned().collect::<Vec<_>>();
+        for sock in removed {
+            local.remove(&sock);
+            if let ListenSocket::Unix(ref sock) = sock {
+                remove_file(&sock.path)
+                    .map_err(|e| warn!("Can't remove {:?}: {}",
+                                       sock.path, e))
+                    .ok();
+            }
+        }
+        for sock in sockets {
+            if local.contains_key(&sock) {
+                continue;
+            }
+            let listener = match Local::bind(&sock, &self.handle) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Can't listen at {:?}: {}", sock, e);
+                    continue;
+                }
+            };
+            if self.verbose {
+                match sock {
+                    ListenSocket::Unix(ref u) => {
+                        println!("Listening at {}", u.path.display());
+                    }
+                    ListenSocket::Fd(fd) => {
+                        println!("Listening at file descriptor {}", fd);
+                    }
+                    _ => {}
+                }
+            }
+            let (stop_tx, stop_rx) = oneshot::channel();
+            self.handle.spawn(
+                listener.incoming(self.error_timeout, &self.handle)
+                .forward(self.local_tx.clone().sink_map_err(|_| ()))
+                .map(|_| ())
+                .select(stop_rx.then(|_| Ok(())))
+                .map(|_| ())
+                .map_err(|_| ()));
+            local.insert(sock, stop_tx);
+        }
+    }
+}
+
+impl Read for ServerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            ServerStream::Plain(ref mut s) => s.read(buf),
+            ServerStream::Tls(ref mut s) => s.read(buf),
+            ServerStream::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for ServerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            ServerStream::Plain(ref mut s) => s.write(buf),
+            ServerStream::Tls(ref mut s) => s.write(buf),
+            ServerStream::Unix(ref mut s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            ServerStream::Plain(ref mut s) => s.flush(),
+            ServerStream::Tls(ref mut s) => s.flush(),
+            ServerStream::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+impl AsyncRead for ServerStream {}
+
+impl AsyncWrite for ServerStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match *self {
+            ServerStream::Plain(ref mut s) => AsyncWrite::shutdown(s),
+            ServerStream::Tls(ref mut s) => AsyncWrite::shutdown(s),
+            ServerStream::Unix(ref mut s) => AsyncWrite::shutdown(s),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc;
+
+use crate::config::log::{Format, Output};
+use crate::intern::LogFormatName;
+
+
+static REOPEN: AtomicBool = AtomicBool::new(false);
+
+/// Log files opened for writing
+///
+/// Files are opened when configuration is (re)loaded, and are reopened on
+/// the next write after `SIGHUP` is received, so that rotated files are
+/// released.
+pub struct Files {
+    files: RefCell<HashMap<PathBuf, File>>,
+}
+
+extern "C" fn sighup(_: libc::c_int) {
+    REOPEN.store(true, Ordering::SeqCst);
+}
+
+/// Installs `SIGHUP` handler that makes log files reopen
+pub fn reopen_on_sighup() {
+    unsafe {
+        libc::signal(libc::SIGHUP, sighup as libc::sighandler_t);
+    }
+}
+
+fn open(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+impl Files {
+    pub fn new() -> Files {
+        Files {
+            files: RefCell::new(HashMap::new()),
+        }
+    }
+    /// Opens files of new log formats and closes ones not used any more
+    pub fn update(&self, formats: &HashMap<LogFormatName, Format>) {
+        let mut files = self.files.borrow_mut();
+        let mut old = ::std::mem::replace(&mut *files, HashMap::new());
+        for fmt in formats.values() {
+            if let Output::File(ref path) = fmt.output {
+                if files.contains_key(path) {
+                    continue;
+                }
+                if let Some(file) = old.remove(path) {
+                    files.insert(path.clone(), file);
+                    continue;
+                }
+                match open(path) {
+                    Ok(file) => {
+                        files.insert(path.clone(), file);
+                    }
+                    Err(e) => error!("Can't open log file {:?}: {}", path, e),
+                }
+            }
+        }
+    }
+    pub fn write(&self, path: &Path, line: &[u8]) -> Result<(), io::Error> {
+        let mut files = self.files.borrow_mut();
+        if REOPEN.swap(false, Ordering::SeqCst) {
+            for (path, file) in files.iter_mut() {
+                match open(path) {
+                    Ok(new) => *file = new,
+                    Err(e) => error!("Can't reopen log file {:?}: {}",
+                                     path, e),
+                }
+            }
+        }
+        if !files.contains_key(path) {
+            // failed to open on config reload, retry on each request
+            files.insert(path.to_path_buf(), open(path)?);
+        }
+        files.get_mut(path).expect("file just opened").write_all(line)
+    }
+}
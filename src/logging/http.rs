@@ -1,62 +1,141 @@
 use std::fmt;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use tk_http::Status;
+use tk_http::Version;
 use tk_http::server::Head;
 use trimmer::{Variable, Var, DataError, Output};
 
+use crate::intern::{Authorizer, LogFormatName};
 use crate::request_id::RequestId;
 use crate::logging::context::{Context, AsContext};
 
 
-pub struct EarlyRequest<'a> {
-    pub addr: SocketAddr,
-    pub head: &'a Head<'a>,
-    pub request_id: RequestId,
+/// Request details that are kept until response is written
+pub struct Request {
+    addr: SocketAddr,
+    host: Option<String>,
+    method: String,
+    path: Option<String>,
+    version: Version,
+    request_id: RequestId,
+    authorizer: Option<Authorizer>,
+    start: Instant,
 }
 
-#[derive(Debug)]
-pub struct EarlyResponse {
-    pub status: Status,
+/// Response details filled in by the encoder while response is written
+///
+/// The encoder is sent to disk threads when serving files, so this is
+/// behind a mutex.
+#[derive(Clone, Debug, Default)]
+pub struct ResponseLog(Arc<Mutex<ResponseInfo>>);
+
+#[derive(Clone, Debug, Default)]
+struct ResponseInfo {
+    status: Option<u16>,
+    bytes_sent: u64,
+    upstream: Option<Arc<str>>,
 }
 
-#[derive(Debug)]
-pub struct FakeResponse {
+/// A record for the access log, written when response is done
+pub struct AccessLog {
+    pub request: Request,
+    pub response: ResponseLog,
+    /// Log format of the route, `None` when only debug log is written
+    pub format: Option<LogFormatName>,
 }
 
-// Temporary page object
-pub struct FakePage<'a> {
-    pub request: EarlyRequest<'a>,
-    pub response: FakeResponse,
+/// Snapshot of the request and response that is rendered into a log line
+pub struct Page<'a> {
+    request: &'a Request,
+    response: Response,
 }
 
-pub struct EarlyError<'a> {
-    pub request: EarlyRequest<'a>,
-    pub response: EarlyResponse,
+#[derive(Debug)]
+pub struct Response {
+    info: ResponseInfo,
+    duration: f64,
 }
 
 #[derive(Debug)]
 pub struct Display<D: fmt::Display + fmt::Debug>(D);
 
-impl<'a> AsContext for EarlyError<'a> {
-    fn as_context(&self) -> Context {
-        let mut ctx = Context::new();
-        ctx.set("request", &self.request);
-        ctx.set("response", &self.response);
-        ctx
+impl Request {
+    pub fn new(addr: SocketAddr, head: &Head, request_id: RequestId)
+        -> Request
+    {
+        Request {
+            addr: addr,
+            host: head.host().map(|x| x.to_string()),
+            method: head.method().to_string(),
+            path: head.path().map(|x| x.to_string()),
+            version: head.version(),
+            request_id: request_id,
+            authorizer: None,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl ResponseLog {
+    pub fn new() -> ResponseLog {
+        ResponseLog::default()
+    }
+    pub fn set_status(&self, code: u16) {
+        self.0.lock().expect("response log not poisoned")
+            .status = Some(code);
+    }
+    pub fn add_bytes(&self, n: usize) {
+        self.0.lock().expect("response log not poisoned")
+            .bytes_sent += n as u64;
+    }
+    pub fn set_upstream(&self, upstream: &Arc<str>) {
+        self.0.lock().expect("response log not poisoned")
+            .upstream = Some(upstream.clone());
+    }
+}
+
+impl AccessLog {
+    pub fn new(addr: SocketAddr, head: &Head, request_id: RequestId)
+        -> AccessLog
+    {
+        AccessLog {
+            request: Request::new(addr, head, request_id),
+            response: ResponseLog::new(),
+            format: None,
+        }
+    }
+    pub fn set_route(&mut self, authorizer: &Authorizer,
+        format: &Option<LogFormatName>)
+    {
+        self.request.authorizer = Some(authorizer.clone());
+        self.format = format.clone();
+    }
+    pub fn page(&self) -> Page {
+        let elapsed = self.request.start.elapsed();
+        Page {
+            request: &self.request,
+            response: Response {
+                info: self.response.0.lock()
+                    .expect("response log not poisoned").clone(),
+                duration: elapsed.as_secs() as f64 +
+                    elapsed.subsec_nanos() as f64 / 1e9,
+            },
+        }
     }
 }
 
-impl<'a> AsContext for FakePage<'a> {
+impl<'a> AsContext for Page<'a> {
     fn as_context(&self) -> Context {
         let mut ctx = Context::new();
-        ctx.set("request", &self.request);
+        ctx.set("request", self.request);
         ctx.set("response", &self.response);
         ctx
     }
 }
 
-impl<'a> Variable<'a> for EarlyRequest<'a> {
+impl<'a> Variable<'a> for Request {
     fn attr<'x>(&'x self, attr: &str) -> Result<Var<'x, 'a>, DataError>
         where 'a: 'x
     {
@@ -64,52 +143,55 @@ impl<'a> Variable<'a> for EarlyRequest<'a> {
             // TODO(tailhook) return just IP when trimmer is updated
             "client_ip" => Ok(Var::owned(self.addr.ip())),
             "host" => Ok(Var::owned(
-                self.head.host()
+                self.host.as_ref().map(|x| &x[..])
+                .unwrap_or("-")
+                .to_string())),
+            "method" => Ok(Var::owned(self.method.clone())),
+            "path" => Ok(Var::owned(
+                self.path.as_ref().map(|x| &x[..])
+                .unwrap_or("")
+                .to_string())),
+            "version" => Ok(Var::owned(Display(self.version))),
+            "id" => Ok(Var::owned(Display(self.request_id))),
+            "authorizer" => Ok(Var::owned(
+                self.authorizer.as_ref().map(|x| &x[..])
                 .unwrap_or("-")
                 .to_string())),
-            "method" => Ok(Var::owned(self.head.method())),
-            "path" => Ok(Var::owned(self.head.path())),
-            "version" => Ok(Var::owned(Display(self.head.version()))),
             _ => Err(DataError::AttrNotFound),
         }
     }
     fn typename(&self) -> &'static str {
-        "EarlyRequest"
+        "Request"
     }
 }
 
-impl<'a> fmt::Debug for EarlyRequest<'a> {
+impl fmt::Debug for Request {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("EarlyRequest")
+        f.debug_struct("Request")
+         .field("request_id", &self.request_id)
          .finish()
     }
 }
 
-impl<'a> Variable<'a> for EarlyResponse {
-    fn attr<'x>(&'x self, attr: &str) -> Result<Var<'x, 'a>, DataError>
-        where 'a: 'x
-    {
-        match attr {
-            "status_code" => Ok(Var::owned(self.status.code())),
-            _ => Err(DataError::AttrNotFound),
-        }
-    }
-    fn typename(&self) -> &'static str {
-        "EarlyResponse"
-    }
-}
-
-impl<'a> Variable<'a> for FakeResponse {
+impl<'a> Variable<'a> for Response {
     fn attr<'x>(&'x self, attr: &str) -> Result<Var<'x, 'a>, DataError>
         where 'a: 'x
     {
         match attr {
-            "status_code" => Ok(Var::str("non-404")),
+            // zero means response was never started, e.g. connection
+            // is closed by the client
+            "status_code" => Ok(Var::owned(self.info.status.unwrap_or(0))),
+            "bytes_sent" => Ok(Var::owned(self.info.bytes_sent)),
+            "duration" => Ok(Var::owned(format!("{:.3}", self.duration))),
+            "upstream" => Ok(Var::owned(
+                self.info.upstream.as_ref().map(|x| &x[..])
+                .unwrap_or("-")
+                .to_string())),
             _ => Err(DataError::AttrNotFound),
         }
     }
     fn typename(&self) -> &'static str {
-        "FakeResponse"
+        "Response"
     }
 }
 
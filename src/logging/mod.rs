@@ -1,33 +1,56 @@
 
 mod context;
+mod files;
 pub mod http;
 
 pub use self::context::AsContext;
+pub use self::files::{Files, reopen_on_sighup};
 
 
 use std::io::{stdout, Write};
 use std::sync::Arc;
 
+use crate::config::log::{Format, Output};
 use crate::runtime::Runtime;
 
 
-pub fn log<C: AsContext>(runtime: &Arc<Runtime>, ctx: C) {
+/// Writes request to the log of the route, and to the `debug-log` if
+/// `debug-logging` is enabled
+pub fn log(runtime: &Arc<Runtime>, entry: &http::AccessLog) {
     let cfg = runtime.config.get();
-    if cfg.debug_logging {
-        if let Some(ref fmt) = cfg.log_formats.get("debug-log") {
-            let ctx = ctx.as_context();
-            match fmt.template.render(&ctx) {
-                Ok(mut line) => {
-                    line.push('\n');
-                    stdout().write_all(line.as_bytes())
-                        .map_err(|e| {
-                            warn!("Can't write debug log: {}", e)
-                        }).ok();
-                }
-                Err(e) => {
-                    warn!("Can't log request: {:?}", e);
+    let debug = if cfg.debug_logging {
+        cfg.log_formats.get("debug-log")
+    } else {
+        None
+    };
+    let route = entry.format.as_ref()
+        .and_then(|name| cfg.log_formats.get(name))
+        // don't write same line twice
+        .filter(|fmt| debug.map(|d| !std::ptr::eq(*fmt, d)).unwrap_or(true));
+    if debug.is_none() && route.is_none() {
+        return;
+    }
+    let page = entry.page();
+    let ctx = page.as_context();
+    for fmt in debug.into_iter().chain(route) {
+        write(runtime, fmt, &ctx);
+    }
+}
+
+fn write(runtime: &Arc<Runtime>, fmt: &Format, ctx: &context::Context) {
+    match fmt.template.render(ctx) {
+        Ok(mut line) => {
+            line.push('\n');
+            let result = match fmt.output {
+                Output::Stdout => stdout().write_all(line.as_bytes()),
+                Output::File(ref path) => {
+                    runtime.log_files.write(path, line.as_bytes())
                 }
             };
+            result.map_err(|e| warn!("Can't write log: {}", e)).ok();
+        }
+        Err(e) => {
+            warn!("Can't log request: {:?}", e);
         }
     }
 }
@@ -12,19 +12,25 @@ mod base64;
 mod chat;
 mod config;
 mod default_error_page;
+mod deflate;
 mod dev;
 mod handlers;
+mod health_check;
 mod http_pools;  // TODO(tailhook) move to proxy?
 mod incoming;
 mod intern;
+mod ldap;
+mod listener;
 mod logging;
 mod metrics;
+mod privileges;
 mod proxy;
 mod request_id;
 mod routing;
 mod runtime;
 mod startup;
 mod template;
+mod tls;
 
 use std::process::exit;
 use std::env;
@@ -65,7 +71,9 @@ pub fn main() {
                 (d) `DEST` -- is the same as `/=DEST`.
                 Destinations can be:
                 (i) `./local/dir` -- serves files from filesystem,
-                (ii) `http://host:12345/` -- proxy requests to specified host.
+                (ii) `http://host:12345/` -- proxy requests to specified host,
+                (iii) `https://host:12345/` -- same as above but connects
+                    to the host using TLS.
                 Note: unlike in original `devd` we currently keep original
                 `Host` in requests to proxy.
             ");
@@ -10,10 +10,14 @@ mod base64;
 mod chat;
 mod config;
 mod default_error_page;
+mod deflate;
 mod handlers;
+mod health_check;
 mod http_pools;  // TODO(tailhook) move to proxy?
 mod incoming;
 mod intern;
+mod ldap;
+mod listener;
 mod logging;
 mod metrics;
 mod privileges;
@@ -23,6 +27,7 @@ mod routing;
 mod runtime;
 mod startup;
 mod template;
+mod tls;
 mod updater;
 
 use std::env;
@@ -2,60 +2,178 @@ use std::mem;
 use std::sync::Arc;
 
 use futures::Async;
-use futures::future::{FutureResult, ok};
+use futures::future::ok;
 use futures::sync::oneshot;
+use tk_http::Status;
 use tk_http::client as http;
+use tokio_io::AsyncWrite;
 
 use crate::config::http_destinations::Destination;
-use crate::proxy::{RepReq, HalfResp, Response};
+use crate::http_pools::{HttpFuture, BackendCodec};
+use crate::proxy::body::{self, BodySender};
+use crate::proxy::{RepReq, StreamReq, HalfResp, Response};
+
+/// Minimum size of the response chunk passed to the frontend (unless
+/// it's the end of the body)
+const RESPONSE_CHUNK: usize = 16384;
+
+pub type ResponseSender = oneshot::Sender<Result<Response, Failure>>;
 
 enum State {
     Init(RepReq),
+    InitStream(StreamReq),
     Wait,
-    Headers(HalfResp),
-    #[allow(dead_code)]
-    Done(Response),
+    Body(BodySender),
+    /// Response is replaced by a retry, so body is skipped
+    Discard,
     Void,
 }
 
+/// Reason the backend didn't serve the request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// Request is not written to the backend connection
+    NotSent,
+    /// Connection is closed or errored before response is received
+    Connection,
+    /// Backend responded with 502 or 503 and the request will be retried
+    Status(Status),
+}
+
+/// Request failed before response is started
+#[derive(Debug)]
+pub struct Failure {
+    pub reason: Reason,
+    /// Backend which the request was sent to, if any
+    pub upstream: Option<Arc<str>>,
+}
+
 
 pub struct Codec {
     state: State,
     destination: Arc<Destination>,
-    sender: Option<oneshot::Sender<Response>>,
+    buffer_size: usize,
+    sender: Option<ResponseSender>,
+    upstream: Option<Arc<str>>,
+    /// Backends which already failed this request
+    excluded: Vec<Arc<str>>,
+    /// Whether 502 and 503 responses are replaced by a retry
+    retry_on_status: bool,
+    sticky_key: Option<u64>,
 }
 
 impl Codec {
     pub fn new(req: RepReq, destination: &Arc<Destination>,
-        tx: oneshot::Sender<Response>)
+        buffer_size: usize, tx: ResponseSender)
         -> Codec
     {
         Codec {
+            sticky_key: req.sticky_key(destination),
             state: State::Init(req),
             destination: destination.clone(),
+            buffer_size: buffer_size,
+            sender: Some(tx),
+            upstream: None,
+            excluded: Vec::new(),
+            retry_on_status: false,
+        }
+    }
+    pub fn streaming(req: StreamReq, destination: &Arc<Destination>,
+        buffer_size: usize, tx: ResponseSender)
+        -> Codec
+    {
+        Codec {
+            sticky_key: req.sticky_key(destination),
+            state: State::InitStream(req),
+            destination: destination.clone(),
+            buffer_size: buffer_size,
             sender: Some(tx),
+            upstream: None,
+            excluded: Vec::new(),
+            retry_on_status: false,
+        }
+    }
+    /// Sets backends that already failed and whether 502 and 503
+    /// responses should be reported as failures
+    pub fn retry(mut self, excluded: &[Arc<str>], on_status: bool) -> Codec {
+        self.excluded = excluded.to_vec();
+        self.retry_on_status = on_status;
+        self
+    }
+}
+
+impl Failure {
+    /// Status returned to the client if request is not retried
+    pub fn status(&self) -> Status {
+        match self.reason {
+            Reason::NotSent | Reason::Connection => Status::BadGateway,
+            Reason::Status(status) => status,
+        }
+    }
+}
+
+impl BackendCodec for Codec {
+    fn set_upstream(&mut self, upstream: &Arc<str>) {
+        self.upstream = Some(upstream.clone());
+    }
+    fn excluded(&self) -> &[Arc<str>] {
+        &self.excluded
+    }
+    fn sticky_key(&self) -> Option<u64> {
+        self.sticky_key
+    }
+}
+
+impl Drop for Codec {
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            let reason = match self.state {
+                State::Init(..) | State::InitStream(..) => Reason::NotSent,
+                _ => Reason::Connection,
+            };
+            sender.send(Err(Failure {
+                reason: reason,
+                upstream: self.upstream.take(),
+            })).ok();
         }
     }
 }
 
-impl<S> http::Codec<S> for Codec {
-    type Future = FutureResult<http::EncoderDone<S>, http::Error>;
+impl<S: AsyncWrite + 'static> http::Codec<S> for Codec {
+    type Future = HttpFuture<S>;
 
     fn start_write(&mut self, e: http::Encoder<S>) -> Self::Future {
-        if let State::Init(req) = mem::replace(&mut self.state, State::Void) {
-            self.state = State::Wait;
-            ok(req.encode(e, &self.destination))
-        } else {
-            panic!("wrong state");
+        match mem::replace(&mut self.state, State::Wait) {
+            State::Init(req) => Box::new(ok(req.encode(e, &self.destination))),
+            State::InitStream(req) => req.encode(e, &self.destination),
+            _ => panic!("wrong state"),
         }
     }
     fn headers_received(&mut self, headers: &http::Head)
         -> Result<http::RecvMode, http::Error>
     {
         if let State::Wait = mem::replace(&mut self.state, State::Void) {
-            self.state = State::Headers(HalfResp::from_headers(headers));
-            // TODO(tailhook) limit and streaming
-            Ok(http::RecvMode::buffered(10_485_760))
+            match headers.status() {
+                Some(status@Status::BadGateway) |
+                Some(status@Status::ServiceUnavailable)
+                if self.retry_on_status
+                => {
+                    self.sender.take().unwrap().send(Err(Failure {
+                        reason: Reason::Status(status),
+                        upstream: self.upstream.take(),
+                    })).ok();
+                    self.state = State::Discard;
+                    return Ok(http::RecvMode::progressive(RESPONSE_CHUNK));
+                }
+                _ => {}
+            }
+            let (tx, rx) = body::channel(self.buffer_size);
+            let resp = HalfResp::from_headers(headers)
+                .stream(rx, self.upstream.take());
+            // if client is gone, body is discarded by the sender
+            self.sender.take().unwrap().send(Ok(resp)).ok();
+            self.state = State::Body(tx);
+            Ok(http::RecvMode::progressive(RESPONSE_CHUNK))
         } else {
             panic!("wrong state");
         }
@@ -63,15 +181,20 @@ impl<S> http::Codec<S> for Codec {
     fn data_received(&mut self, data: &[u8], end: bool)
         -> Result<Async<usize>, http::Error>
     {
-        // TODO(tailhook) streaming
-        assert!(end);
-        match mem::replace(&mut self.state, State::Void) {
-            State::Headers(hr) => {
-                let resp = hr.complete(data.to_vec());
-                self.sender.take().unwrap().send(resp).ok();
-            }
+        let bytes = match self.state {
+            State::Discard => return Ok(Async::Ready(data.len())),
+            State::Body(ref mut body) => match body.write(data) {
+                Async::Ready(bytes) => bytes,
+                Async::NotReady => return Ok(Async::NotReady),
+            },
             _ => unreachable!(),
+        };
+        if end && bytes == data.len() {
+            match mem::replace(&mut self.state, State::Void) {
+                State::Body(body) => body.finish(),
+                _ => unreachable!(),
+            }
         }
-        Ok(Async::Ready(data.len()))
+        Ok(Async::Ready(bytes))
     }
 }
@@ -0,0 +1,124 @@
+//! Response body buffer shared by backend and frontend connections
+use std::cmp::min;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Stream};
+use futures::task::{self, Task};
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum State {
+    Receiving,
+    Done,
+    /// Backend connection is closed before the whole body is received
+    Aborted,
+}
+
+struct Inner {
+    data: Vec<u8>,
+    limit: usize,
+    state: State,
+    receiver_alive: bool,
+    reader: Option<Task>,
+    writer: Option<Task>,
+}
+
+/// Backend side of the buffer
+pub struct BodySender(Arc<Mutex<Inner>>);
+
+/// Frontend side of the buffer, yields chunks of the body as they arrive
+pub struct BodyReceiver(Arc<Mutex<Inner>>);
+
+
+/// Creates a buffer which holds at most `limit` bytes of the body
+///
+/// Anyway the single chunk is accepted if buffer is empty, so the limit of
+/// zero means no buffering rather than a deadlock.
+pub fn channel(limit: usize) -> (BodySender, BodyReceiver) {
+    let inner = Arc::new(Mutex::new(Inner {
+        data: Vec::new(),
+        limit: limit,
+        state: State::Receiving,
+        receiver_alive: true,
+        reader: None,
+        writer: None,
+    }));
+    (BodySender(inner.clone()), BodyReceiver(inner))
+}
+
+impl BodySender {
+    /// Puts data into the buffer, returns number of bytes consumed
+    ///
+    /// Returns `NotReady` when the buffer is full, current task is notified
+    /// when frontend takes some data from the buffer. If the frontend is
+    /// gone, data is discarded.
+    pub fn write(&mut self, data: &[u8]) -> Async<usize> {
+        let mut inner = self.0.lock().expect("body buffer is not poisoned");
+        if !inner.receiver_alive {
+            return Async::Ready(data.len());
+        }
+        if data.len() == 0 {
+            return Async::Ready(0);
+        }
+        let room = inner.limit.saturating_sub(inner.data.len());
+        let bytes = if inner.data.len() == 0 {
+            data.len()
+        } else if room == 0 {
+            inner.writer = Some(task::current());
+            return Async::NotReady;
+        } else {
+            min(room, data.len())
+        };
+        inner.data.extend(&data[..bytes]);
+        inner.reader.take().map(|t| t.notify());
+        Async::Ready(bytes)
+    }
+    /// Marks body as fully received
+    pub fn finish(self) {
+        self.close(State::Done);
+    }
+    fn close(&self, state: State) {
+        let mut inner = self.0.lock().expect("body buffer is not poisoned");
+        if inner.state == State::Receiving {
+            inner.state = state;
+            inner.reader.take().map(|t| t.notify());
+        }
+    }
+}
+
+impl Drop for BodySender {
+    fn drop(&mut self) {
+        self.close(State::Aborted);
+    }
+}
+
+impl Stream for BodyReceiver {
+    type Item = Vec<u8>;
+    type Error = ();
+    fn poll(&mut self) -> Result<Async<Option<Vec<u8>>>, ()> {
+        let mut inner = self.0.lock().expect("body buffer is not poisoned");
+        if inner.data.len() > 0 {
+            inner.writer.take().map(|t| t.notify());
+            return Ok(Async::Ready(Some(mem::replace(&mut inner.data,
+                                                     Vec::new()))));
+        }
+        match inner.state {
+            State::Receiving => {
+                inner.reader = Some(task::current());
+                Ok(Async::NotReady)
+            }
+            State::Done => Ok(Async::Ready(None)),
+            State::Aborted => Err(()),
+        }
+    }
+}
+
+impl Drop for BodyReceiver {
+    fn drop(&mut self) {
+        let mut inner = self.0.lock().expect("body buffer is not poisoned");
+        inner.receiver_alive = false;
+        inner.data = Vec::new();
+        inner.writer.take().map(|t| t.notify());
+    }
+}
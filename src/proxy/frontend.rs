@@ -1,46 +1,70 @@
 use std::sync::Arc;
 use std::mem;
+use std::time::Instant;
 
 use futures::{Async, Future, AsyncSink};
-use futures::future::{ok};
+use futures::future::{Either, Loop, loop_fn};
 use futures::sink::{Sink};
-use futures::sync::oneshot;
+use futures::sync::{oneshot, mpsc};
 use tk_http::Status;
 use tk_http::server::{Error, RecvMode};
 use tk_http::server as http;
 
 use crate::config::proxy::Proxy;
+use crate::config::http_destinations::Destination;
 use crate::incoming::{Input, Reply, Encoder, Context, IntoContext};
+use crate::incoming::Transport;
 use crate::default_error_page::error_page;
-use crate::http_pools::{HttpPools, REQUESTS, FAILED_503};
-use crate::proxy:: {RepReq, HalfReq, Response, backend};
+use crate::http_pools::{HttpPools, REQUESTS, FAILED_503, RETRIES};
+use crate::proxy:: {RepReq, HalfReq, Response, BodyChunk, backend};
+use crate::proxy::backend::{Failure, Reason, ResponseSender};
 
 
+/// Minimum size of the request chunk forwarded to the backend (unless
+/// it's the end of the body)
+const STREAM_CHUNK: usize = 16384;
+/// Number of chunks that may wait for the backend connection
+const STREAM_QUEUE: usize = 4;
+
 enum State {
     Headers(HalfReq),
     Sent {
-        /// Mostly to resend the request
-        #[allow(dead_code)]
-        request: RepReq,
-        response: oneshot::Receiver<Response>,
+        /// Resends the request if it fails, `None` for streamed requests
+        /// as they can't be resent
+        retry: Option<Retry>,
+        response: oneshot::Receiver<Result<Response, Failure>>,
     },
     Error(Status),
     Void,
 }
 
+/// Sends failed request to the backends which weren't tried yet
+struct Retry {
+    request: RepReq,
+    settings: Arc<Proxy>,
+    pools: HttpPools,
+    destination: Arc<Destination>,
+    /// Backends which failed the request
+    tried: Vec<Arc<str>>,
+    retries: u32,
+    started: Instant,
+}
+
 
 pub struct Codec {
     settings: Arc<Proxy>,
     pools: HttpPools,
     state: State,
+    /// Request body for streaming requests, until the whole body is sent
+    body: Option<mpsc::Sender<BodyChunk>>,
     context: Option<Context>,
 }
 
-impl<S: 'static> http::Codec<S> for Codec {
+impl<S: Transport> http::Codec<S> for Codec {
     type ResponseFuture = Reply<S>;
     fn recv_mode(&mut self) -> RecvMode {
         if self.settings.stream_requests {
-            unimplemented!();
+            RecvMode::progressive(STREAM_CHUNK)
         } else {
             RecvMode::buffered_upfront(self.settings.max_payload_size)
         }
@@ -49,54 +73,18 @@ impl<S: 'static> http::Codec<S> for Codec {
         -> Result<Async<usize>, Error>
     {
         if self.settings.stream_requests {
-            unimplemented!();
+            return Ok(self.stream_body(data, end));
         }
         self.state = match mem::replace(&mut self.state, State::Void) {
             State::Error(e) => State::Error(e),
             State::Headers(r) => {
                 assert!(end);
                 let r = r.upgrade(data.to_vec());
-                let dest_name = &self.settings.destination.upstream;
-                let mut up = self.pools.upstream(dest_name);
-                let (tx, rx) = oneshot::channel();
-                let ref cfg = self.context.as_ref().unwrap().0;
-                let opt_dest = cfg.http_destinations.get(dest_name);
-                if let Some(dest_settings) = opt_dest {
-                    let codec = Box::new(backend::Codec::new(r.clone(),
-                        dest_settings, tx));
-                    match up.get_mut().get_mut() {
-                        Some(pool) => {
-                            match pool.start_send(codec) {
-                                Ok(AsyncSink::NotReady(_)) => {
-                                    FAILED_503.incr(1);
-                                    State::Error(Status::ServiceUnavailable)
-                                }
-                                Ok(AsyncSink::Ready) => {
-                                    debug!("Sent request {:?} to proxy", r);
-                                    REQUESTS.incr(1);
-                                    State::Sent {
-                                        request: r,
-                                        response: rx,
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Error sending to pool {:?}: {}",
-                                        self.settings.destination.upstream, e);
-                                    State::Error(Status::InternalServerError)
-                                }
-                            }
-                        }
-                        None => {
-                            error!("No such pool {:?}",
-                                self.settings.destination.upstream);
-                            State::Error(Status::NotFound)
-                        }
-                    }
-                } else {
-                    error!("No such destination {:?}",
-                        self.settings.destination.upstream);
-                    State::Error(Status::NotFound)
-                }
+                let req = r.clone();
+                let buffer_size = self.settings.response_buffer_size;
+                self.send(Some(r), move |dest, tx| {
+                    backend::Codec::new(req, dest, buffer_size, tx)
+                })
             }
             State::Sent { .. } => unimplemented!(),
             State::Void => unreachable!(),
@@ -105,29 +93,28 @@ impl<S: 'static> http::Codec<S> for Codec {
     }
     fn start_response(&mut self, e: http::Encoder<S>) -> Reply<S> {
         if self.settings.stream_requests {
-            unimplemented!();
-        } else {
-            let ctx = self.context.take().unwrap();
-            match mem::replace(&mut self.state, State::Void) {
-                State::Sent { response, .. } => {
-                    Box::new(response.then(move |result| {
-                        let e = Encoder::new(e, ctx);
-                        match result {
-                            Ok(resp) => {
-                                ok(resp.encode(e))
-                            }
-                            Err(err) => {
-                                debug!("Proxy request error: {:?}", err);
-                                error_page(Status::BadGateway, e)
-                            }
+            // response might be started before any data is received
+            self.start_stream();
+        }
+        let ctx = self.context.take().unwrap();
+        match mem::replace(&mut self.state, State::Void) {
+            State::Sent { response, retry } => {
+                Box::new(wait_response(response, retry).then(move |result| {
+                    let e = Encoder::new(e, ctx);
+                    match result {
+                        Ok(resp) => {
+                            Either::A(resp.encode(e))
                         }
-                    }))
-                }
-                State::Error(status) => {
-                    Box::new(error_page(status, Encoder::new(e, ctx)))
-                }
-                _ => unreachable!(),
+                        Err(status) => {
+                            Either::B(error_page(status, e))
+                        }
+                    }
+                }))
+            }
+            State::Error(status) => {
+                Box::new(error_page(status, Encoder::new(e, ctx)))
             }
+            _ => unreachable!(),
         }
     }
 }
@@ -138,7 +125,191 @@ impl Codec {
             state: State::Headers(HalfReq::from_input(&inp, &settings)),
             pools: inp.runtime.http_pools.clone(),
             settings: settings.clone(),
+            body: None,
             context: Some(inp.into_context()),
         }
     }
+    /// Sends request to the connection pool, returns the new state
+    fn send<F>(&mut self, request: Option<RepReq>, codec: F) -> State
+        where F: FnOnce(&Arc<Destination>, ResponseSender) -> backend::Codec
+    {
+        let dest_name = &self.settings.destination.upstream;
+        let (tx, rx) = oneshot::channel();
+        let ref cfg = self.context.as_ref().unwrap().0;
+        let opt_dest = cfg.http_destinations.get(dest_name);
+        if let Some(dest_settings) = opt_dest {
+            let retry = request.map(|r| Retry {
+                request: r,
+                settings: self.settings.clone(),
+                pools: self.pools.clone(),
+                destination: dest_settings.clone(),
+                tried: Vec::new(),
+                retries: 0,
+                started: Instant::now(),
+            });
+            let on_status = retry.as_ref()
+                .map(|r| r.on_status()).unwrap_or(false);
+            let codec = codec(dest_settings, tx).retry(&[], on_status);
+            match start_send(&self.pools, &self.settings, codec) {
+                Ok(()) => {
+                    debug!("Sent request {:?} to proxy",
+                        retry.as_ref().map(|r| &r.request));
+                    State::Sent {
+                        retry: retry,
+                        response: rx,
+                    }
+                }
+                Err(status) => State::Error(status),
+            }
+        } else {
+            error!("No such destination {:?}",
+                self.settings.destination.upstream);
+            State::Error(Status::NotFound)
+        }
+    }
+    /// Sends streaming request to the backend if not sent yet
+    fn start_stream(&mut self) {
+        match mem::replace(&mut self.state, State::Void) {
+            State::Headers(r) => {
+                let (tx, rx) = mpsc::channel(STREAM_QUEUE);
+                let req = r.stream(rx);
+                let buffer_size = self.settings.response_buffer_size;
+                self.state = self.send(None, move |dest, resp_tx| {
+                    backend::Codec::streaming(req, dest, buffer_size, resp_tx)
+                });
+                if let State::Sent { .. } = self.state {
+                    self.body = Some(tx);
+                }
+            }
+            state => self.state = state,
+        }
+    }
+    /// Forwards a chunk of the request body to the backend
+    ///
+    /// Returns `NotReady` when backend doesn't keep up with the client, so
+    /// no more data is read from the client connection until backend
+    /// connection consumes queued chunks.
+    fn stream_body(&mut self, data: &[u8], end: bool) -> Async<usize> {
+        self.start_stream();
+        if data.len() == 0 && !end {
+            return Async::Ready(0);
+        }
+        let result = match self.body {
+            Some(ref mut body) => body.start_send((data.to_vec(), end)),
+            // Request is failed or response is already sent, so body
+            // is discarded
+            None => return Async::Ready(data.len()),
+        };
+        match result {
+            Ok(AsyncSink::Ready) => {
+                if end {
+                    self.body = None;
+                }
+            }
+            Ok(AsyncSink::NotReady(_)) => return Async::NotReady,
+            Err(_) => {
+                debug!("Backend connection closed while streaming request");
+                self.body = None;
+            }
+        }
+        Async::Ready(data.len())
+    }
+}
+
+impl Retry {
+    fn allowed(&self) -> bool {
+        self.retries < self.destination.max_retries &&
+            self.started.elapsed() < self.destination.retry_timeout
+    }
+    fn method_allowed(&self) -> bool {
+        self.destination.retry_unsafe_methods || self.request.is_safe()
+    }
+    /// Whether 502 and 503 responses of the next backend are replaced by
+    /// a retry rather than returned to the client
+    fn on_status(&self) -> bool {
+        self.allowed() && self.method_allowed()
+    }
+    /// Sends the request to another backend after failure
+    ///
+    /// Returns status for the client if request can't be retried
+    fn resend(&mut self, failure: Failure)
+        -> Result<oneshot::Receiver<Result<Response, Failure>>, Status>
+    {
+        let status = failure.status();
+        if let Some(upstream) = failure.upstream {
+            self.tried.push(upstream);
+        }
+        // request that isn't written to the connection is safe to resend
+        // whatever the method is
+        if !self.allowed() ||
+            !(failure.reason == Reason::NotSent || self.method_allowed())
+        {
+            return Err(status);
+        }
+        self.retries += 1;
+        let (tx, rx) = oneshot::channel();
+        let codec = backend::Codec::new(self.request.clone(),
+                &self.destination, self.settings.response_buffer_size, tx)
+            .retry(&self.tried, self.on_status());
+        start_send(&self.pools, &self.settings, codec).map_err(|_| status)?;
+        debug!("Retrying request {:?}, tried {:?}",
+            self.request, self.tried);
+        RETRIES.incr(1);
+        Ok(rx)
+    }
+}
+
+/// Sends request to the connection pool of the destination
+fn start_send(pools: &HttpPools, settings: &Proxy, codec: backend::Codec)
+    -> Result<(), Status>
+{
+    let dest_name = &settings.destination.upstream;
+    let mut up = pools.upstream(dest_name);
+    match up.get_mut().get_mut() {
+        Some(pool) => {
+            match pool.start_send(Box::new(codec)) {
+                Ok(AsyncSink::NotReady(_)) => {
+                    FAILED_503.incr(1);
+                    Err(Status::ServiceUnavailable)
+                }
+                Ok(AsyncSink::Ready) => {
+                    REQUESTS.incr(1);
+                    Ok(())
+                }
+                Err(e) => {
+                    error!("Error sending to pool {:?}: {}", dest_name, e);
+                    Err(Status::InternalServerError)
+                }
+            }
+        }
+        None => {
+            error!("No such pool {:?}", dest_name);
+            Err(Status::NotFound)
+        }
+    }
+}
+
+/// Waits for response of the backend, retrying the request if it fails
+fn wait_response(response: oneshot::Receiver<Result<Response, Failure>>,
+    retry: Option<Retry>)
+    -> impl Future<Item=Response, Error=Status>
+{
+    loop_fn((response, retry), |(response, mut retry)| {
+        response.then(move |result| {
+            let failure = match result {
+                Ok(Ok(resp)) => return Ok(Loop::Break(resp)),
+                Ok(Err(failure)) => failure,
+                Err(_) => {
+                    debug!("Proxy request is canceled");
+                    return Err(Status::BadGateway);
+                }
+            };
+            debug!("Proxy request error: {:?}", failure);
+            let response = match retry {
+                Some(ref mut retry) => retry.resend(failure)?,
+                None => return Err(failure.status()),
+            };
+            Ok(Loop::Continue((response, retry)))
+        })
+    })
 }
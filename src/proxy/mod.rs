@@ -1,7 +1,8 @@
 pub mod frontend;
 pub mod backend;
+mod body;
 mod response;
 mod request;
 
 pub use self::response::{HalfResp, Response};
-pub use self::request::{HalfReq, RepReq};
+pub use self::request::{HalfReq, RepReq, StreamReq, BodyChunk};
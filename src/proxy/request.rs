@@ -1,19 +1,44 @@
 use std::sync::Arc;
+use std::str::from_utf8;
 use std::net::SocketAddr;
 
+use futures::{Future, Stream};
+use futures::future::{ok, Either};
+use futures::sync::mpsc;
 use tk_http::Version;
-use tk_http::client::{Encoder, EncoderDone};
+use tokio_io::AsyncWrite;
+use tk_http::client::{Encoder, EncoderDone, Error};
 
-use crate::config::http_destinations::Destination;
+use crate::config::http_destinations::{Destination, HashBy};
 use crate::config::proxy::Proxy;
-use crate::incoming::{Input};
+use crate::http_pools::{HttpFuture, sticky_hash};
+use crate::incoming::{Input, AuthHeaders};
 use crate::request_id::RequestId;
 
 
+/// Chunk of request body and whether it's the last one
+pub type BodyChunk = (Vec<u8>, bool);
+
+/// Bytes of the body that might be buffered by the backend connection
+/// before we stop forwarding more request chunks
+const BODY_WATERMARK: usize = 65536;
+
 /// A repeatable (so fully-buffered) request structure
 #[derive(Clone, Debug)]
 pub struct RepReq(Arc<ReqData>);
 
+/// A request which body is forwarded while it's being read from the client
+///
+/// Unlike `RepReq` this request can't be repeated: chunks of the body are
+/// dropped as soon as they are written to the backend connection. So
+/// streamed requests are never resent to another backend.
+pub struct StreamReq {
+    data: ReqData,
+    /// Content-Length of the body, `None` means chunked encoding
+    length: Option<u64>,
+    body: mpsc::Receiver<BodyChunk>,
+}
+
 pub struct HalfReq {
     settings: Arc<Proxy>,
     method: String,
@@ -22,6 +47,8 @@ pub struct HalfReq {
     headers: Vec<(String, Vec<u8>)>,
     addr: SocketAddr,
     request_id: RequestId,
+    auth_headers: AuthHeaders,
+    length: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -57,26 +84,92 @@ impl HalfReq {
             }).collect(),
             addr: inp.addr,
             request_id: inp.request_id,
+            auth_headers: inp.auth_headers.clone(),
+            length: body_length(inp),
         }
     }
     pub fn upgrade(self, body: Vec<u8>) -> RepReq {
-        RepReq(Arc::new(ReqData {
+        RepReq(Arc::new(self.into_data(body)))
+    }
+    pub fn stream(self, body: mpsc::Receiver<BodyChunk>) -> StreamReq {
+        StreamReq {
+            length: self.length,
+            data: self.into_data(Vec::new()),
+            body: body,
+        }
+    }
+    fn into_data(self, body: Vec<u8>) -> ReqData {
+        let mut headers = self.headers;
+        let auth_headers = self.auth_headers.take();
+        if auth_headers.len() > 0 {
+            headers.retain(|&(ref name, _)| {
+                !auth_headers.iter().any(|&(ref a, _)| {
+                    a.eq_ignore_ascii_case(name)
+                })
+            });
+            headers.extend(auth_headers);
+        }
+        ReqData {
             settings: self.settings,
             method: self.method,
             path: self.path,
             host: self.host,
-            headers: self.headers,
+            headers: headers,
             addr: self.addr,
             request_id: self.request_id,
             body: body,
-        }))
+        }
     }
 }
-impl RepReq {
-    pub fn encode<S>(&self, mut e: Encoder<S>, dest: &Arc<Destination>)
-        -> EncoderDone<S>
+
+/// Returns length of the request body, `None` if body is chunked
+fn body_length(inp: &Input) -> Option<u64> {
+    let mut chunked = false;
+    for h in inp.headers.all_headers() {
+        if h.name.eq_ignore_ascii_case("Content-Length") {
+            // tk-http has already validated the value
+            return from_utf8(h.value).ok()
+                .and_then(|x| x.trim().parse().ok());
+        } else if h.name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = true;
+        }
+    }
+    if chunked { None } else { Some(0) }
+}
+
+impl ReqData {
+    /// Hash of the key for `hash` load balancing
+    fn sticky_key(&self, dest: &Destination) -> Option<u64> {
+        match dest.hash_by {
+            Some(HashBy::Header(ref name)) => {
+                self.headers.iter()
+                    .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+                    .map(|&(_, ref v)| sticky_hash(&v[..]))
+            }
+            Some(HashBy::Cookie(ref name)) => {
+                self.headers.iter()
+                    .filter(|&&(ref k, _)| k.eq_ignore_ascii_case("Cookie"))
+                    .filter_map(|&(_, ref v)| from_utf8(v).ok())
+                    .flat_map(|v| v.split(';'))
+                    .filter_map(|pair| {
+                        let mut pair = pair.splitn(2, '=');
+                        match (pair.next(), pair.next()) {
+                            (Some(k), Some(v)) if k.trim() == &name[..]
+                            => Some(v.trim()),
+                            _ => None,
+                        }
+                    })
+                    .next()
+                    .map(sticky_hash)
+            }
+            Some(HashBy::ClientIp) => Some(sticky_hash(&self.addr.ip())),
+            None => None,
+        }
+    }
+    fn encode_head<S>(&self, e: &mut Encoder<S>, dest: &Arc<Destination>,
+        length: Option<u64>)
     {
-        let ref r = *self.0;
+        let r = self;
         if r.settings.destination.path == "/" {
             e.request_line(&r.method, &r.path, Version::Http11);
         } else {
@@ -103,11 +196,73 @@ impl RepReq {
         for &(ref k, ref v) in &r.headers {
             e.add_header(k, v).unwrap();
         }
-        e.add_length(r.body.len() as u64).unwrap();
+        match length {
+            Some(n) => e.add_length(n).unwrap(),
+            None => e.add_chunked().unwrap(),
+        }
         e.done_headers().unwrap();
+    }
+}
+
+impl RepReq {
+    pub fn sticky_key(&self, dest: &Destination) -> Option<u64> {
+        self.0.sticky_key(dest)
+    }
+    /// Whether request method is safe, i.e. it doesn't change anything
+    /// on the backend, so request can be retried
+    pub fn is_safe(&self) -> bool {
+        match &self.0.method[..] {
+            "GET" | "HEAD" | "OPTIONS" | "TRACE" => true,
+            _ => false,
+        }
+    }
+    pub fn encode<S>(&self, mut e: Encoder<S>, dest: &Arc<Destination>)
+        -> EncoderDone<S>
+    {
+        let ref r = *self.0;
+        r.encode_head(&mut e, dest, Some(r.body.len() as u64));
         if r.body.len() != 0 {
             e.write_body(&r.body);
         }
         return e.done();
     }
 }
+
+impl StreamReq {
+    pub fn sticky_key(&self, dest: &Destination) -> Option<u64> {
+        self.data.sticky_key(dest)
+    }
+    /// Writes headers and then forwards body chunks as they arrive
+    ///
+    /// If the client disconnects before sending the whole body, request
+    /// fails, so that the backend connection is closed instead of being
+    /// reused with an incomplete request.
+    pub fn encode<S: AsyncWrite + 'static>(self, mut e: Encoder<S>,
+        dest: &Arc<Destination>)
+        -> HttpFuture<S>
+    {
+        let StreamReq { data, length, body } = self;
+        data.encode_head(&mut e, dest, length);
+        Box::new(body
+            .map_err(|()| Error::custom("request body channel failed"))
+            .fold((e, false), |(mut e, _), (chunk, end)| {
+                if chunk.len() != 0 {
+                    e.write_body(&chunk);
+                }
+                if end {
+                    Either::A(ok((e, true)))
+                } else {
+                    Either::B(e.wait_flush(BODY_WATERMARK)
+                        .map(|e| (e, false))
+                        .map_err(Error::custom))
+                }
+            })
+            .and_then(|(e, complete)| {
+                if complete {
+                    Ok(e.done())
+                } else {
+                    Err(Error::custom("request body is incomplete"))
+                }
+            }))
+    }
+}
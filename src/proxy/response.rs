@@ -1,8 +1,15 @@
+use std::str::from_utf8;
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use futures::future::ok;
 use tk_http::{Status};
 use tk_http::client::Head;
-use tk_http::server::{EncoderDone};
+use tk_http::server::Error;
+use tokio_io::AsyncWrite;
 
-use crate::incoming::Encoder;
+use crate::incoming::{Encoder, Reply};
+use crate::proxy::body::BodyReceiver;
 
 
 #[derive(Debug)]
@@ -15,12 +22,17 @@ pub enum RespStatus {
 pub struct HalfResp {
     status: RespStatus,
     headers: Vec<(String, Vec<u8>)>,
+    /// Content-Length of the body, `None` means length is unknown
+    length: Option<u64>,
 }
 
 pub struct Response {
     status: RespStatus,
     headers: Vec<(String, Vec<u8>)>,
-    body: Vec<u8>,
+    length: Option<u64>,
+    body: BodyReceiver,
+    /// Backend address, for access log
+    upstream: Option<Arc<str>>,
 }
 
 impl HalfResp {
@@ -30,25 +42,46 @@ impl HalfResp {
                 let (c, v) = head.raw_status();
                 RespStatus::Custom(c, v.to_string())
             });
+        let length = head.all_headers().iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Content-Length"))
+            .and_then(|h| from_utf8(h.value).ok())
+            .and_then(|x| x.trim().parse().ok());
         HalfResp {
             status: status,
             headers: head.headers().map(|(k, v)| {
                 (k.to_string(), v.to_vec())
             }).collect(),
+            length: length,
         }
     }
-    pub fn complete(self, body: Vec<u8>) -> Response {
+    pub fn stream(self, body: BodyReceiver, upstream: Option<Arc<str>>)
+        -> Response
+    {
         Response {
             status: self.status,
             headers: self.headers,
+            length: self.length,
             body: body,
+            upstream: upstream,
         }
     }
 }
 
 impl Response {
-    pub fn encode<S>(&self, mut e: Encoder<S>) -> EncoderDone<S>{
-        let body = match self.status {
+    /// Writes response headers and then body chunks as they arrive from
+    /// the backend
+    ///
+    /// If backend connection is closed before the whole body is received,
+    /// the future fails, so client connection is closed too, as the status
+    /// line is already sent at that point.
+    pub fn encode<S: AsyncWrite + 'static>(self, mut e: Encoder<S>)
+        -> Reply<S>
+    {
+        let Response { status, headers, length, body, upstream } = self;
+        if let Some(ref upstream) = upstream {
+            e.set_upstream(upstream);
+        }
+        let has_body = match status {
             RespStatus::Normal(s) => {
                 e.status(s);
                 s.response_has_body()
@@ -58,18 +91,30 @@ impl Response {
                 true
             }
         };
-        for &(ref k, ref v) in &self.headers {
+        for &(ref k, ref v) in &headers {
             e.add_header(k, v);
         }
-        if body {
-            e.add_length(self.body.len() as u64);
+        if has_body {
+            match length {
+                Some(n) => e.add_length(n),
+                None => e.add_chunked(),
+            }
             if e.done_headers() {
-                e.write_body(&self.body);
+                return Box::new(body
+                    .map_err(|()| {
+                        Error::custom("backend connection closed \
+                                       before response is complete")
+                    })
+                    .fold(e, |mut e, chunk| {
+                        e.write_body(&chunk);
+                        e.wait_flush(0).map_err(Error::custom)
+                    })
+                    .map(|e| e.done()));
             }
         } else {
             let res = e.done_headers();
             assert!(res == false);
         }
-        return e.done();
+        return Box::new(ok(e.done()));
     }
 }
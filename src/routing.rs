@@ -3,6 +3,7 @@ use std::collections::{HashMap};
 use regex::{self, RegexSet};
 
 use crate::intern::{HandlerName, Authorizer as AuthorizerName};
+use crate::intern::LogFormatName;
 use crate::config::{ConfigSource, Error};
 use crate::config::routing::{Host, HostPath, RouteDef};
 use crate::config::handlers::Handler::{self, StripWWWRedirect};
@@ -14,6 +15,7 @@ pub struct Route {
     pub handler: Handler,
     pub authorizer_name: AuthorizerName,
     pub authorizer: Authorizer,
+    pub log_format: Option<LogFormatName>,
 }
 
 #[derive(Debug)]
@@ -51,17 +53,25 @@ fn inherit(to: &mut RouteDef, from: &RouteDef) {
         }
         _ => {}
     }
+    match (&mut to.log, &from.log) {
+        (&mut ref mut dest @ None, &Some(ref x)) => {
+            *dest = Some(x.clone());
+        }
+        _ => {}
+    }
 }
 fn is_done(item: &RouteDef) -> bool {
     matches!(*item, RouteDef {
         handler: _,
         authorizer: Some(_),
+        log: Some(_),
     })
 }
 fn default() -> RouteDef {
     RouteDef {
         handler: HandlerName::from("default"),
         authorizer: None,
+        log: None,
     }
 }
 
@@ -85,17 +95,37 @@ impl PathTable {
 trait Resolver {
     fn handler(&self, _: &HandlerName) -> Option<Handler>;
     fn authorizer(&self, _: &AuthorizerName) -> Option<Authorizer>;
+    fn has_log_format(&self, _: &LogFormatName) -> bool;
     fn route(&self, route: &RouteDef) -> Result<Route, Error> {
         let auth = route.authorizer.clone()
             .unwrap_or(AuthorizerName::from("default"));
-        Ok(Route {
+        let route = Route {
             handler: self.handler(&route.handler)
                 .ok_or_else(|| Error::NoHandler(route.handler.clone()))?,
             handler_name: route.handler.clone(),
             authorizer: self.authorizer(&auth)
                 .ok_or_else(|| Error::NoAuthorizer(auth.clone()))?,
             authorizer_name: auth,
-        })
+            log_format: route.log.clone(),
+        };
+        if let Some(ref name) = route.log_format {
+            if !self.has_log_format(name) {
+                return Err(Error::NoLogFormat(name.clone()));
+            }
+        }
+        // Ldap authorizer delays request body until credentials are
+        // checked, websockets have no body to delay
+        match (&route.authorizer, &route.handler) {
+            (&Authorizer::Ldap(_), &Handler::SwindonLattice(_)) |
+            (&Authorizer::Ldap(_), &Handler::WebsocketEcho(_)) => {
+                return Err(Error::Routing(format!(
+                    "authorizer {:?} can't be used for websocket \
+                     handler {:?}",
+                    route.authorizer_name, route.handler_name)));
+            }
+            _ => {}
+        }
+        Ok(route)
     }
 }
 
@@ -106,6 +136,9 @@ impl<'a> Resolver for &'a ConfigSource {
     fn authorizer(&self, n: &AuthorizerName) -> Option<Authorizer> {
         self.authorizers.get(n).cloned()
     }
+    fn has_log_format(&self, n: &LogFormatName) -> bool {
+        self.log_formats.contains_key(n)
+    }
 }
 
 impl RoutingTable {
@@ -358,6 +391,7 @@ mod route_test {
     use std::str::FromStr;
     use super::{route, RoutingTable, Resolver};
     use crate::intern::{HandlerName, Authorizer as AuthorizerName};
+    use crate::intern::LogFormatName;
     use crate::config::routing::{HostPath, RouteDef};
     use crate::config::handlers::Handler;
     use crate::config::authorizers::Authorizer;
@@ -371,6 +405,9 @@ mod route_test {
         fn authorizer(&self, _: &AuthorizerName) -> Option<Authorizer> {
             Some(Authorizer::AllowAll)
         }
+        fn has_log_format(&self, _: &LogFormatName) -> bool {
+            true
+        }
     }
 
     fn table(table: Vec<(&'static str, &'static str, &'static str)>)
@@ -380,7 +417,8 @@ mod route_test {
             (HostPath::from_str(r).unwrap(), RouteDef {
                 handler: HandlerName::from(h),
                 authorizer: if a == "" { None }
-                    else { Some(AuthorizerName::from(a)) }
+                    else { Some(AuthorizerName::from(a)) },
+                log: None,
             })
         }).collect::<Vec<_>>();
         RoutingTable::_create(items.iter().map(|&(ref x, ref y)| (x, y)),
@@ -403,6 +441,13 @@ mod route_test {
         .unwrap_or("default")
     }
 
+    pub fn route_l<'x>(host: &str, path: &'x str,
+        table: &'x RoutingTable) -> Option<&'x str>
+    {
+        route(host, path, table)
+        .and_then(|(x, _, _)| x.log_format.as_ref().map(|x| &x[..]))
+    }
+
     #[test]
     fn route_host() {
         let table = table(vec![
@@ -544,4 +589,24 @@ mod route_test {
         assert_eq!(route_h("example.org", "/two", &table), None);
     }
 
+    #[test]
+    fn inherit_log_format() {
+        let items = vec![
+            ("*.ex.com", "1 ->access"),
+            ("www.ex.com/api", "2 ->api"),
+            ("www.ex.com/api/v1", "3"),
+            ("www.ex.com/static", "4"),
+        ].into_iter().map(|(r, d)| {
+            (HostPath::from_str(r).unwrap(), RouteDef::from_str(d).unwrap())
+        }).collect::<Vec<_>>();
+        let table = RoutingTable::_create(
+            items.iter().map(|&(ref x, ref y)| (x, y)), Fake).unwrap();
+        assert_eq!(route_l("www.ex.com", "/api/v1/x", &table), Some("api"));
+        assert_eq!(route_l("www.ex.com", "/api", &table), Some("api"));
+        assert_eq!(route_l("www.ex.com", "/static", &table), Some("access"));
+        assert_eq!(route_l("www.ex.com", "/", &table), Some("access"));
+        assert_eq!(route_l("test.ex.com", "/", &table), Some("access"));
+        assert_eq!(route_l("ex.org", "/", &table), None);
+    }
+
 }
@@ -4,8 +4,10 @@ use crate::chat;
 use crate::config::ConfigCell;
 use crate::handlers::files;
 use crate::http_pools::HttpPools;
+use crate::logging;
 use self_meter_http::Meter;
 use crate::request_id::RequestId;
+use crate::tls;
 use ns_router::Router;
 
 
@@ -18,6 +20,8 @@ pub struct Runtime {
     pub meter: Meter,
     pub server_id: ServerId,
     pub resolver: Router,
+    pub tls: tls::Acceptor,
+    pub log_files: logging::Files,
 }
 
 /// Runtime server identifier.
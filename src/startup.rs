@@ -2,45 +2,43 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use abstract_ns::HostResolve;
-use async_slot as slot;
 use futures::Stream;
 use futures::future::{Future};
 use futures_cpupool;
 use ns_router::{self, SubscribeExt};
-use ns_router::future::AddrStream;
 use ns_std_threaded;
 use self_meter_http::Meter;
 use tk_http::server::Proto;
 use tk_http;
-use tk_listen::{BindMany, ListenExt};
+use tk_listen::ListenExt;
 use tokio_core::reactor::{Handle};
-use void::Void;
 
-use crate::config::listen::Listen;
 use crate::config::{ConfigCell};
 use crate::incoming::Router;
+use crate::listener::{Listener, Incoming};
 use crate::chat;
 use crate::runtime::Runtime;
 use crate::http_pools::{HttpPools};
 use crate::handlers::files::{DiskPools};
+use crate::logging;
 use crate::request_id;
+use crate::tls;
 
 
 pub struct State {
     http_pools: HttpPools,
     session_pools: chat::SessionPools,
     disk_pools: DiskPools,
-    listener_channel: slot::Sender<Listen>,
+    listener: Listener,
     replication_session: chat::ReplicationSession,
     pub runtime: Arc<Runtime>,
 }
 
-pub fn spawn_listener(addr_stream: AddrStream, handle: &Handle,
-    runtime: &Arc<Runtime>, verbose: bool)
+pub fn spawn_listener(incoming: Incoming, handle: &Handle,
+    runtime: &Arc<Runtime>)
 {
     let root = runtime.config.get();
     let r1 = runtime.clone();
-    let r2 = runtime.clone();
     // TODO(tailhook) how to update?
     let hcfg = tk_http::server::Config::new()
         .inflight_request_limit(root.pipeline_depth)
@@ -56,20 +54,16 @@ pub fn spawn_listener(addr_stream: AddrStream, handle: &Handle,
         .done();
     let h1 = handle.clone();
     handle.spawn(
-        BindMany::new(addr_stream.map(move |addr| {
-                if verbose {
-                    println!("Listening at {}",
-                        addr.addresses_at(0)
-                            .map(|x| x.to_string())
-                            .collect::<Vec<_>>().join(", "));
-                }
-                addr.addresses_at(0)
-            }), handle)
-        .sleep_on_error(r1.config.get().listen_error_timeout, &r1.handle)
-        .map(move |(socket, saddr)| {
-             Proto::new(socket, &hcfg,
-                Router::new(saddr, r2.clone(), h1.clone()), &h1)
-             .map_err(|e| debug!("Http protocol error: {}", e))
+        incoming
+        .map(move |accept| {
+            let hcfg = hcfg.clone();
+            let r1 = r1.clone();
+            let h1 = h1.clone();
+            accept.and_then(move |(socket, saddr)| {
+                Proto::new(socket, &hcfg,
+                    Router::new(saddr, r1, h1.clone()), &h1)
+                .map_err(|e| debug!("Http protocol error: {}", e))
+            })
         })
         .listen(root.max_connections)
         .map(move |()| panic!("Main listener exited"))
@@ -109,13 +103,17 @@ pub fn populate_loop(handle: &Handle, cfg: &ConfigCell, verbose: bool)
 
     let server_id = request_id::new();
     let http_pools = HttpPools::new();
+    let tls = tls::Acceptor::new(&cfg.get().tls_certificates);
     let processor = chat::Processor::new();
     let mut replication_session = chat::ReplicationSession::new(
-        processor.clone(), &resolver, handle, &server_id,
+        processor.clone(), &resolver, &tls, handle, &server_id,
         &cfg.get().replication);
     let session_pools = chat::SessionPools::new(
         processor, replication_session.remote_sender.clone());
     let disk_pools = DiskPools::new(&meter);
+    let log_files = logging::Files::new();
+    log_files.update(&cfg.get().log_formats);
+    logging::reopen_on_sighup();
     let runtime = Arc::new(Runtime {
         config: cfg.clone(),
         handle: handle.clone(),
@@ -125,19 +123,18 @@ pub fn populate_loop(handle: &Handle, cfg: &ConfigCell, verbose: bool)
         meter: meter,
         server_id: server_id,
         resolver: resolver.clone(),
+        tls: tls,
+        log_files: log_files,
     });
     let root = cfg.get();
 
     warn!("Started with server_id {}, config {}",
         server_id, cfg.fingerprint());
 
-    let (listen_tx, listen_rx) = slot::channel();
-    listen_tx.swap(root.listen.clone()).unwrap();
-
-    spawn_listener(
-        resolver.subscribe_stream(
-            listen_rx.map_err(|()| -> Void { unreachable!() }), 80),
-        handle, &runtime, verbose);
+    let (listener, incoming) = Listener::new(&resolver, &runtime.tls,
+        root.listen_error_timeout, handle, verbose);
+    listener.update(&root.listen).unwrap();
+    spawn_listener(incoming, handle, &runtime);
 
     disk_pools.update(&root.disk_pools);
     http_pools.update(&root.http_destinations, &resolver, handle);
@@ -147,7 +144,7 @@ pub fn populate_loop(handle: &Handle, cfg: &ConfigCell, verbose: bool)
         http_pools: http_pools,
         session_pools: session_pools,
         replication_session: replication_session,
-        listener_channel: listen_tx,
+        listener: listener,
         runtime: runtime,
         disk_pools: disk_pools,
     }
@@ -155,7 +152,9 @@ pub fn populate_loop(handle: &Handle, cfg: &ConfigCell, verbose: bool)
 
 #[allow(dead_code)]
 pub fn update_loop(state: &mut State, cfg: &ConfigCell, handle: &Handle) {
-    state.listener_channel.swap(cfg.get().listen.clone())
+    state.runtime.tls.update(&cfg.get().tls_certificates);
+    state.runtime.log_files.update(&cfg.get().log_formats);
+    state.listener.update(&cfg.get().listen)
         .map_err(|_| error!("Can't update listening sockets")).ok();
     state.disk_pools.update(&cfg.get().disk_pools);
     state.http_pools.update(&cfg.get().http_destinations,
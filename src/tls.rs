@@ -0,0 +1,289 @@
+//! TLS support: loading certificates and keys, backend connections and
+//! termination of TLS on listening sockets
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use futures::Future;
+use rustls::{ClientConfig, ClientSession, Certificate, PrivateKey};
+use rustls::{ServerConfig, ServerSession, NoClientAuth, ResolvesServerCert};
+use rustls::{SignatureScheme};
+use rustls::internal::pemfile::{certs, rsa_private_keys, pkcs8_private_keys};
+use rustls::sign::{CertifiedKey, RSASigningKey, SigningKey};
+use tokio_core::net::TcpStream;
+use tokio_rustls::{ClientConfigExt, ServerConfigExt, TlsStream};
+use untrusted::Input;
+use webpki::{DNSNameRef, EndEntityCert};
+use webpki_roots::TLS_SERVER_ROOTS;
+
+use crate::config::http_destinations::Destination;
+
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Read(path: PathBuf, err: io::Error) {
+            display("can't read {:?}: {}", path, err)
+            description("can't read TLS file")
+        }
+        NoCertificates(path: PathBuf) {
+            display("no certificates found in {:?}", path)
+            description("no certificates found")
+        }
+        BadCertificate(path: PathBuf, reason: String) {
+            display("bad certificate in {:?}: {}", path, reason)
+            description("bad certificate")
+        }
+        NoKey(path: PathBuf) {
+            display("no private key found in {:?}", path)
+            description("no private key found")
+        }
+        KeyWithoutCertificate {
+            display("both `tls-client-certificate` and `tls-client-key` \
+                     must be specified")
+            description("client key without certificate")
+        }
+        UnsupportedKey(path: PathBuf) {
+            display("private key in {:?} is not supported, \
+                     only RSA keys can be used for TLS termination", path)
+            description("unsupported private key")
+        }
+        ServerName(name: String) {
+            display("{:?} is not a valid TLS server name, \
+                     use `tls-server-name` to override", name)
+            description("invalid TLS server name")
+        }
+    }
+}
+
+
+/// Everything needed to establish TLS connection to a backend
+#[derive(Clone)]
+pub struct Client {
+    config: Arc<ClientConfig>,
+    server_name: String,
+}
+
+/// Certificate chain and a private key for TLS termination
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyPair {
+    certificates: Vec<Certificate>,
+    key: PrivateKey,
+}
+
+/// Accepts TLS connections on listening sockets
+///
+/// Clones share certificates, so `update` affects all the listeners.
+#[derive(Clone)]
+pub struct Acceptor {
+    config: Arc<ServerConfig>,
+    resolver: Arc<CertResolver>,
+}
+
+/// Picks certificate by the server name sent by client (SNI)
+struct CertResolver {
+    certificates: RwLock<Vec<(String, CertifiedKey)>>,
+}
+
+
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut buf))
+        .map_err(|e| Error::Read(path.to_path_buf(), e))?;
+    Ok(buf)
+}
+
+/// Reads all PEM-encoded certificates from the file
+pub fn read_certificates(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let data = read_file(path)?;
+    match certs(&mut BufReader::new(&data[..])) {
+        Ok(ref certs) if certs.len() == 0 => {
+            Err(Error::NoCertificates(path.to_path_buf()))
+        }
+        Ok(certs) => Ok(certs),
+        Err(()) => Err(Error::NoCertificates(path.to_path_buf())),
+    }
+}
+
+/// Reads PEM-encoded private key, both PKCS#8 and RSA keys are supported
+pub fn read_private_key(path: &Path) -> Result<PrivateKey, Error> {
+    let data = read_file(path)?;
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(&data[..]))
+        .unwrap_or_else(|()| Vec::new());
+    if keys.len() == 0 {
+        keys = rsa_private_keys(&mut BufReader::new(&data[..]))
+            .unwrap_or_else(|()| Vec::new());
+    }
+    keys.into_iter().next().ok_or_else(|| Error::NoKey(path.to_path_buf()))
+}
+
+/// Returns server name used for SNI and certificate verification
+///
+/// It's either `tls-server-name`, or `override-host-header`, or the host
+/// name of the first address.
+fn server_name(dest: &Destination) -> Result<String, Error> {
+    let name = dest.tls_server_name.as_ref()
+        .or(dest.override_host_header.as_ref())
+        .or(dest.addresses.get(0))
+        .map(|name| strip_port(name))
+        .unwrap_or("");
+    DNSNameRef::try_from_ascii_str(name)
+        .map_err(|()| Error::ServerName(name.to_string()))?;
+    Ok(name.to_string())
+}
+
+fn strip_port(name: &str) -> &str {
+    match name.rfind(':') {
+        Some(idx) if name[idx+1..].bytes().all(|x| x.is_ascii_digit()) => {
+            &name[..idx]
+        }
+        _ => name,
+    }
+}
+
+impl Client {
+    /// Creates TLS client settings for the http destination
+    pub fn new(dest: &Destination) -> Result<Client, Error> {
+        let mut cfg = ClientConfig::new();
+        match dest.tls_ca_bundle {
+            Some(ref path) => {
+                for cert in read_certificates(path)? {
+                    cfg.root_store.add(&cert)
+                        .map_err(|e| Error::BadCertificate(
+                            path.to_path_buf(), format!("{:?}", e)))?;
+                }
+            }
+            None => {
+                cfg.root_store.add_server_trust_anchors(&TLS_SERVER_ROOTS);
+            }
+        }
+        match (&dest.tls_client_certificate, &dest.tls_client_key) {
+            (&Some(ref cert), &Some(ref key)) => {
+                cfg.set_single_client_cert(
+                    read_certificates(cert)?,
+                    read_private_key(key)?);
+            }
+            (&None, &None) => {}
+            _ => return Err(Error::KeyWithoutCertificate),
+        }
+        Ok(Client {
+            config: Arc::new(cfg),
+            server_name: server_name(dest)?,
+        })
+    }
+    pub fn connect(&self, sock: TcpStream)
+        -> Box<dyn Future<Item=TlsStream<TcpStream, ClientSession>,
+                          Error=io::Error>>
+    {
+        let name = DNSNameRef::try_from_ascii_str(&self.server_name)
+            .expect("server name is validated");
+        Box::new(self.config.connect_async(name, sock))
+    }
+}
+
+impl KeyPair {
+    /// Reads certificate chain and key, and checks that they are usable
+    pub fn read(certificate: &Path, key: &Path) -> Result<KeyPair, Error> {
+        let pair = KeyPair {
+            certificates: read_certificates(certificate)?,
+            key: read_private_key(key)?,
+        };
+        EndEntityCert::from(Input::from(&pair.certificates[0].0))
+            .map_err(|e| Error::BadCertificate(
+                certificate.to_path_buf(), format!("{:?}", e)))?;
+        RSASigningKey::new(&pair.key)
+            .map_err(|()| Error::UnsupportedKey(key.to_path_buf()))?;
+        Ok(pair)
+    }
+    /// Returns true if certificate can be used for the host name
+    pub fn valid_for(&self, name: &str) -> bool {
+        DNSNameRef::try_from_ascii_str(name)
+            .map(|name| valid_for(&self.certificates, name))
+            .unwrap_or(false)
+    }
+    fn certified_key(&self) -> CertifiedKey {
+        let key = RSASigningKey::new(&self.key)
+            .expect("key is validated on read");
+        CertifiedKey::new(self.certificates.clone(),
+            Arc::new(Box::new(key) as Box<dyn SigningKey>))
+    }
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // never print the private key
+        f.debug_struct("KeyPair")
+            .field("certificates", &self.certificates.len())
+            .finish()
+    }
+}
+
+fn valid_for(certificates: &[Certificate], name: DNSNameRef) -> bool {
+    certificates.get(0)
+        .and_then(|c| EndEntityCert::from(Input::from(&c.0)).ok())
+        .map(|c| c.verify_is_valid_for_dns_name(name).is_ok())
+        .unwrap_or(false)
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, server_name: Option<DNSNameRef>, _: &[SignatureScheme])
+        -> Option<CertifiedKey>
+    {
+        let certs = self.certificates.read()
+            .expect("certificates are not poisoned");
+        server_name
+            .and_then(|name| certs.iter()
+                .find(|&&(_, ref key)| valid_for(&key.cert, name)))
+            .or_else(|| certs.iter().find(|&&(ref name, _)| name == "default"))
+            .or_else(|| certs.get(0))
+            .map(|&(_, ref key)| key.clone())
+    }
+}
+
+impl Acceptor {
+    pub fn new(certificates: &BTreeMap<String, KeyPair>) -> Acceptor {
+        let resolver = Arc::new(CertResolver {
+            certificates: RwLock::new(Vec::new()),
+        });
+        let mut cfg = ServerConfig::new(NoClientAuth::new());
+        cfg.cert_resolver = resolver.clone();
+        let acceptor = Acceptor {
+            config: Arc::new(cfg),
+            resolver: resolver,
+        };
+        acceptor.update(certificates);
+        return acceptor;
+    }
+    /// Replaces certificates, connections that are already established
+    /// are not affected
+    pub fn update(&self, certificates: &BTreeMap<String, KeyPair>) {
+        let list = certificates.iter()
+            .map(|(name, pair)| (name.clone(), pair.certified_key()))
+            .collect();
+        *self.resolver.certificates.write()
+            .expect("certificates are not poisoned") = list;
+    }
+    pub fn accept(&self, sock: TcpStream)
+        -> Box<dyn Future<Item=TlsStream<TcpStream, ServerSession>,
+                          Error=io::Error>>
+    {
+        Box::new(self.config.accept_async(sock))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::strip_port;
+
+    #[test]
+    fn strip() {
+        assert_eq!(strip_port("example.org"), "example.org");
+        assert_eq!(strip_port("example.org:8443"), "example.org");
+        assert_eq!(strip_port("localhost:"), "localhost");
+    }
+}
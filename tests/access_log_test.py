@@ -0,0 +1,52 @@
+import asyncio
+import uuid
+
+import aiohttp
+
+
+async def read_log_line(path, marker):
+    for _ in range(50):
+        with open(path, 'rt') as f:
+            for line in f:
+                if marker in line:
+                    return line.split()
+        await asyncio.sleep(0.02)
+    raise AssertionError("no log line for {!r}".format(marker))
+
+
+async def test_access_log(swindon, swindon_access_log, loop):
+    marker = uuid.uuid4().hex
+    url = swindon.url / 'empty-w-log.gif' % {'q': marker}
+    async with aiohttp.ClientSession(loop=loop) as s:
+        async with s.get(url) as resp:
+            assert resp.status == 200
+            assert len(await resp.read()) == 26
+    line = await read_log_line(swindon_access_log, marker)
+    assert line == [
+        'GET', '/empty-w-log.gif?q=' + marker, '200', '26', '-']
+
+
+async def test_not_logged(swindon, swindon_access_log, loop):
+    marker = uuid.uuid4().hex
+    url = swindon.url / 'empty.gif' % {'q': marker}
+    async with aiohttp.ClientSession(loop=loop) as s:
+        async with s.get(url) as resp:
+            assert resp.status == 200
+            await resp.read()
+    await asyncio.sleep(0.1)
+    with open(swindon_access_log, 'rt') as f:
+        assert marker not in f.read()
+
+
+async def test_proxy_upstream(proxy_server, swindon, swindon_access_log):
+    marker = uuid.uuid4().hex
+    url = swindon.url / 'proxy-w-log/hello' % {'q': marker}
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5)
+        await handler.request()
+        resp, body = await handler.response(b'OK')
+        assert resp.status == 200
+    line = await read_log_line(swindon_access_log, marker)
+    assert line == [
+        'GET', '/proxy-w-log/hello?q=' + marker, '200', '2',
+        '127.0.0.1:{}'.format(swindon.proxy.port)]
@@ -37,3 +37,87 @@ async def test_forwarded_bad(swindon, http_request, debug_routing):
     if debug_routing:
         assert resp.headers['X-Swindon-Authorizer'] == 'by-header'
         assert resp.headers['X-Swindon-Deny'] == 'source-ip 4.4.4.4'
+
+
+def basic_auth(login, password):
+    return aiohttp.BasicAuth(login, password).encode()
+
+
+async def test_ldap_no_credentials(swindon, ldap_server, http_request,
+                                   debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/ldap')
+    assert resp.status == 401
+    assert resp.headers['WWW-Authenticate'] == 'Basic realm="swindon"'
+    if debug_routing:
+        assert resp.headers['X-Swindon-Authorizer'] == 'ldap'
+        assert resp.headers['X-Swindon-Deny'] == 'no-credentials'
+
+
+async def test_ldap_ok(swindon, ldap_server, http_request, debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/ldap',
+        headers={"Authorization": basic_auth('john', 'secret')})
+    assert_gif(resp, data, debug_routing)
+    assert ('bind', 'uid=john,ou=People,dc=example,dc=org') \
+        in ldap_server.requests
+
+
+async def test_ldap_bad_password(swindon, ldap_server, http_request,
+                                 debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/ldap',
+        headers={"Authorization": basic_auth('john', 'wrong')})
+    assert resp.status == 401
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == \
+            'ldap-invalid-password john'
+
+
+async def test_ldap_empty_password(swindon, ldap_server, http_request):
+    resp, data = await http_request(swindon.url / 'auth/ldap',
+        headers={"Authorization": basic_auth('john', '')})
+    assert resp.status == 401
+
+
+async def test_ldap_unknown_user(swindon, ldap_server, http_request,
+                                 debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/ldap',
+        headers={"Authorization": basic_auth('bob', 'secret')})
+    assert resp.status == 401
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == 'ldap-no-user bob'
+
+
+async def test_ldap_compare(swindon, ldap_server, http_request,
+                            debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/ldap-compare',
+        headers={"Authorization": basic_auth('jane', 'qwerty')})
+    assert_gif(resp, data, debug_routing)
+    assert ('compare', 'uid=jane,ou=People,dc=example,dc=org') \
+        in ldap_server.requests
+
+    resp, data = await http_request(swindon.url / 'auth/ldap-compare',
+        headers={"Authorization": basic_auth('jane', 'secret')})
+    assert resp.status == 401
+
+
+async def test_ldap_server_down(swindon, http_request):
+    resp, data = await http_request(swindon.url / 'auth/ldap-down',
+        headers={"Authorization": basic_auth('john', 'secret')})
+    assert resp.status == 503
+
+
+async def test_ldap_headers(swindon, ldap_server, proxy_server):
+    url = swindon.url / 'auth/ldap-proxy/hello'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5, headers={
+            "Authorization": basic_auth('john', 'secret'),
+            "X-User-Uid": "admin",
+            "X-User-Groups": "fake",
+        })
+        req = await handler.request()
+        assert req.headers.getall('X-User-Uid') == ['john']
+        assert req.headers.getall('X-User-Groups') == ['admins, devs']
+        assert req.headers.getall('X-User-Nothing') == ['']
+
+        resp, body = await handler.response(b'OK', content_type='text/test')
+        assert resp.status == 200
+        assert body == b'OK'
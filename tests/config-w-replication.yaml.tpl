@@ -64,6 +64,7 @@ routing:
 
   ### !WebsocketEcho routes ###
   localhost/websocket-echo: websocket_echo
+  localhost/websocket-echo-deflate: websocket_echo_deflate
 
   ### !BaseRedirect routes ###
   example.com: base_redirect
@@ -181,6 +182,9 @@ handlers:
 
   ### WebsocketEcho handlers ###
   websocket_echo: !WebsocketEcho
+  websocket_echo_deflate: !WebsocketEcho
+    permessage_deflate:
+      min_message_size: 0
 
   ### BaseRedirect handler ###
 
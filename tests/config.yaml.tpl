@@ -2,13 +2,20 @@ _VARS:
   - &LISTEN ${listen_address}
   - &DEBUG_ROUTING ${debug_routing}
   - &PROXY_ADDRESS ${proxy_address}
+  - &DEAD_PROXY_ADDRESS ${dead_proxy_address}
+  - &TLS_PROXY_ADDRESS ${tls_proxy_address}
   - &SPOOL_ADDRESS1 ${spool_address1}
   - &SPOOL_ADDRESS2 ${spool_address2}
   - &SPOOL_ADDRESS3 ${spool_address3}
   - &SPOOL_ADDRESS4 ${spool_address4}
+  - &LDAP_ADDRESS ${ldap_address}
 
 listen:
 - *LISTEN
+- !Tls ${tls_listen_address}
+- !Unix
+  path: ${unix_listen_path}
+  mode: 0o660
 
 # These are defaults
 #
@@ -17,8 +24,20 @@ listen:
 # listen_error_timeout: 100ms
 
 server_name: swindon/func-tests
+
+tls-certificates:
+  default:
+    certificate: ${TESTS_DIR}/tls/listen.crt
+    key: ${TESTS_DIR}/tls/listen.key
 debug-routing: *DEBUG_ROUTING
 
+log-formats:
+  access:
+    template: "{{ request.method }} {{ request.path }}
+      {{ response.status_code }} {{ response.bytes_sent }}
+      {{ response.upstream }}"
+    output: !File ${access_log_path}
+
 # Configure all possible routing?
 routing:
 
@@ -26,6 +45,7 @@ routing:
   localhost/empty.gif: empty_gif
   localhost/empty-w-headers.gif: empty_gif_w_headers
   localhost/empty-w-content-length.gif: empty_gif_w_clen
+  localhost/empty-w-log.gif: empty_gif ->access
 
   ### !SingleFile routes ###
   localhost/static-file: single_file
@@ -60,6 +80,13 @@ routing:
   localhost/proxy-w-request-id: proxy_w_request_id
   localhost/proxy-w-host: proxy_w_host
   localhost/proxy-w-timeout: proxy_w_timeout
+  localhost/proxy-w-streaming: proxy_w_streaming
+  localhost/proxy-w-tls: proxy_w_tls
+  localhost/proxy-w-unix: proxy_w_unix
+  localhost/proxy-w-log: proxy ->access
+  localhost/proxy-w-retry: proxy_w_retry
+  localhost/proxy-w-weights: proxy_w_weights
+  localhost/proxy-w-hash: proxy_w_hash
 
   ### !SwindonLattice compatibility routes ###
   localhost/swindon-chat: swindon_chat
@@ -70,9 +97,11 @@ routing:
   localhost/swindon-lattice: swindon_lattice
   localhost/swindon-lattice-w-timeouts: swindon_lattice_w_timeouts
   localhost/swindon-lattice-w-client-timeout: swindon_lattice_w_client_timeout
+  localhost/swindon-lattice-w-rate-limits: swindon_lattice_w_rate_limits
 
   ### !WebsocketEcho routes ###
   localhost/websocket-echo: websocket_echo
+  localhost/websocket-echo-deflate: websocket_echo_deflate
 
   ### !BaseRedirect routes ###
   example.com: base_redirect
@@ -83,6 +112,10 @@ routing:
   ### !Authorized routes ###
   localhost/auth/local: empty_gif @only-127-0-0-1
   localhost/auth/by-header: empty_gif @by-header
+  localhost/auth/ldap: empty_gif @ldap
+  localhost/auth/ldap-compare: empty_gif @ldap-compare
+  localhost/auth/ldap-proxy: proxy @ldap
+  localhost/auth/ldap-down: empty_gif @ldap-down
 
 # Configure all possible handlers?
 handlers:
@@ -184,6 +217,19 @@ handlers:
     destination: proxy_host
   proxy_w_timeout: !Proxy
     destination: proxy_timeout
+  proxy_w_streaming: !Proxy
+    destination: proxy_dest
+    stream-requests: true
+  proxy_w_tls: !Proxy
+    destination: proxy_tls
+  proxy_w_unix: !Proxy
+    destination: proxy_unix
+  proxy_w_retry: !Proxy
+    destination: proxy_retry
+  proxy_w_weights: !Proxy
+    destination: proxy_weighted
+  proxy_w_hash: !Proxy
+    destination: proxy_hash
   swindon_proxy: !Proxy
     destination: swindon_http_dest
 
@@ -225,9 +271,21 @@ handlers:
     http_route: swindon_proxy
     message_handlers:
       "*": swindon_lattice_w_timeout/
+  swindon_lattice_w_rate_limits: !SwindonLattice
+    session_pool: swindon_pool_new
+    message_handlers:
+      "*": swindon_lattice_dest/
+    rate_limits:
+      connection: {calls: 1, per: 1h, burst: 2}
+      methods:
+        "chat.typing": {connection: {calls: 1, per: 1h}}
+      close_after: 2
 
   ### WebsocketEcho handlers ###
   websocket_echo: !WebsocketEcho
+  websocket_echo_deflate: !WebsocketEcho
+    permessage_deflate:
+      min_message_size: 0
 
   ### BaseRedirect handler ###
 
@@ -267,6 +325,8 @@ session-pools:
     new_connection_idle_timeout: 1s
     client_min_idle_timeout: 1s
     client_max_idle_timeout: 10s
+    topic_history:
+      max_messages: 2
 
 http-destinations:
   ### Proxy destintations ###
@@ -283,6 +343,38 @@ http-destinations:
     addresses:
     - *PROXY_ADDRESS
     max-request-timeout: 1s
+  proxy_tls:
+    addresses:
+    - *TLS_PROXY_ADDRESS
+    tls: true
+    tls-ca-bundle: ${TESTS_DIR}/tls/ca.crt
+    tls-client-certificate: ${TESTS_DIR}/tls/client.crt
+    tls-client-key: ${TESTS_DIR}/tls/client.key
+    tls-server-name: localhost
+
+  proxy_unix:
+    addresses:
+    - unix:${unix_proxy_path}
+
+  proxy_retry:
+    addresses:
+    - *DEAD_PROXY_ADDRESS
+    - *PROXY_ADDRESS
+    max-retries: 1
+
+  proxy_weighted:
+    load-balancing: weighted
+    weights:
+      ${dead_proxy_address}: 0
+    addresses:
+    - *DEAD_PROXY_ADDRESS
+    - *PROXY_ADDRESS
+
+  proxy_hash:
+    load-balancing: hash
+    hash-by: !Cookie session
+    addresses:
+    - *PROXY_ADDRESS
 
   ### SwindonLattice compatibility destinations ###
   swindon_http_dest:
@@ -329,6 +421,41 @@ authorizers:
     forwarded-ip-header: X-Real-Ip
     accept-forwarded-headers-from: only-127-0-0-1
 
+  ldap: !Ldap
+    destination: local-ldap
+    search-base: ou=People,dc=example,dc=org
+    login-attribute: uid
+    login-header: X-User-Uid
+    additional-queries:
+      X-User-Groups:
+        search-base: cn=Group,dc=example,dc=org
+        fetch-attribute: dn
+        filter: "(&(objectClass=group)(member=$${dn}))"
+        dn-attribute-strip-base: cn=Group,dc=example,dc=org
+      X-User-Nothing:
+        search-base: cn=Group,dc=example,dc=org
+        fetch-attribute: cn
+        filter: "member=nobody"
+
+  ldap-compare: !Ldap
+    destination: local-ldap
+    search-base: dc=example,dc=org
+    login-attribute: uid
+    password-attribute: userPassword
+
+  ldap-down: !Ldap
+    destination: down-ldap
+    search-base: dc=example,dc=org
+    login-attribute: uid
+
+ldap-destinations:
+  local-ldap:
+    addresses:
+    - *LDAP_ADDRESS
+  down-ldap:
+    addresses:
+    - 127.0.0.1:1
+
 networks:
   only-127-0-0-1:
   - 127.0.0.1
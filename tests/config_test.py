@@ -209,6 +209,71 @@ def test_no_handler(check_config):
     assert err != ''
 
 
+def test_unknown_log_format(check_config):
+    cfg = """
+        listen:
+        - 127.0.0.1:8080
+        routing:
+            localhost/path: empty ->unknown-log
+        handlers:
+            empty: !EmptyGif
+        log-formats:
+            access:
+                template: "{{ request.path }}"
+    """
+    err = check_config(cfg)
+    assert 'log format' in err and 'unknown-log' in err
+
+
+def test_hash_balancing(check_config):
+    cfg = """
+        http-destinations:
+            dummy:
+                load-balancing: hash
+                addresses:
+                - 1.2.3.4:5
+    """
+    err = check_config(cfg)
+    assert "`load-balancing: hash` requires `hash-by`" in err
+
+    cfg = """
+        http-destinations:
+            dummy:
+                hash-by: !Header X-User-Id
+                addresses:
+                - 1.2.3.4:5
+    """
+    err = check_config(cfg)
+    assert "`hash-by` requires `load-balancing: hash`" in err
+
+
+def test_unknown_weight(check_config):
+    cfg = """
+        http-destinations:
+            dummy:
+                load-balancing: weighted
+                weights:
+                    1.2.3.4:6: 2
+                addresses:
+                - 1.2.3.4:5
+    """
+    err = check_config(cfg)
+    assert 'weight for unknown address "1.2.3.4:6"' in err
+
+
+def test_health_check_path(check_config):
+    cfg = """
+        http-destinations:
+            dummy:
+                health-check:
+                    path: health
+                addresses:
+                - 1.2.3.4:5
+    """
+    err = check_config(cfg)
+    assert "health check path must start with a slash" in err
+
+
 def test_invalid_routing(check_config):
     err = check_config("""
         listen:
@@ -6,6 +6,7 @@ import pytest
 import re
 import shutil
 import socket
+import ssl
 import string
 import subprocess
 import tempfile
@@ -28,6 +29,8 @@ from multidict import CIMultiDictProxy, CIMultiDict
 from werkzeug.wrappers import Request, Response
 from werkzeug.serving import BaseWSGIServer
 
+from ldap_stub import LdapServer
+
 
 ROOT = pathlib.Path('/work')
 
@@ -170,11 +173,15 @@ def swindon_ports(unused_port, debug_routing, swindon_bin):
             self[key] = val = {
                 'main': unused_port(),
                 'proxy': unused_port(),
+                'dead_proxy': unused_port(),
                 'session_pool_1': unused_port(),
                 'session_pool_2': unused_port(),
                 'session_pool_3': unused_port(),
                 'session_pool_4': unused_port(),
                 'replication': unused_port(),
+                'ldap': unused_port(),
+                'tls_proxy': unused_port(),
+                'tls_main': unused_port(),
             }
             return val
     return Dict()
@@ -190,6 +197,31 @@ def swindon_bin(request):
         return p
 
 
+def unix_listen_path(port):
+    return os.path.join(tempfile.gettempdir(),
+                        'swindon-{}.sock'.format(port))
+
+
+def unix_proxy_path(port):
+    return os.path.join(tempfile.gettempdir(),
+                        'swindon-proxy-{}.sock'.format(port))
+
+
+def access_log_path(port):
+    return os.path.join(tempfile.gettempdir(),
+                        'swindon-access-{}.log'.format(port))
+
+
+@pytest.fixture(scope='module')
+def swindon_unix_path(swindon_ports):
+    return unix_listen_path(swindon_ports['default']['main'])
+
+
+@pytest.fixture(scope='module')
+def swindon_access_log(swindon_ports):
+    return access_log_path(swindon_ports['default']['main'])
+
+
 @pytest.fixture(scope='module')
 def swindon(_proc, request, debug_routing,
             swindon_bin, swindon_ports, TESTS_DIR):
@@ -211,14 +243,21 @@ def swindon(_proc, request, debug_routing,
         listen_address=to_addr(default['main']),
         debug_routing=str(debug_routing).lower(),
         proxy_address=to_addr(default['proxy']),
+        dead_proxy_address=to_addr(default['dead_proxy']),
         spool_address1=to_addr(default['session_pool_1']),
         spool_address2=to_addr(default['session_pool_2']),
         spool_address3=to_addr(default['session_pool_3']),
         spool_address4=to_addr(default['session_pool_4']),
+        ldap_address=to_addr(default['ldap']),
+        tls_proxy_address=to_addr(default['tls_proxy']),
+        tls_listen_address=to_addr(default['tls_main']),
+        unix_listen_path=unix_listen_path(default['main']),
+        unix_proxy_path=unix_proxy_path(default['proxy']),
+        access_log_path=access_log_path(default['main']),
         TESTS_DIR=TESTS_DIR,
     )
     with run_swindon(_proc, swindon_bin, config, rust_log, default['main'],
-                     **options) as inst_info:
+                     default['tls_main'], **options) as inst_info:
         yield inst_info
 
 
@@ -521,10 +560,9 @@ class _BaseServer:
         return _HandlerTuple((handler, client_resp))
 
     def start_ws(self, url, **kwargs):
+        kwargs.setdefault('protocols', ['v1.swindon-lattice+json'])
         ws_fut = asyncio.ensure_future(
-            self.ws_connect(url,
-                            protocols=['v1.swindon-lattice+json'],
-                            **kwargs),
+            self.ws_connect(url, **kwargs),
             loop=self.loop)
         req_fut = asyncio.ensure_future(
             self.wait_request(),
@@ -597,11 +635,12 @@ class _HandlerTuple(tuple):
 
 class AiohttpServer(_BaseServer):
 
-    def __init__(self, loop, **kwargs):
+    def __init__(self, loop, ssl=None, **kwargs):
         super().__init__(loop, **kwargs)
         self._server = None
+        self._ssl = ssl
 
-    async def start_server(self, port, **kwargs):
+    async def start_server(self, port, path=None, **kwargs):
         await super().start_server(**kwargs)
         assert self._server is None
 
@@ -615,8 +654,15 @@ class AiohttpServer(_BaseServer):
             return web.Response(**kw, **kwargs)
 
         self._factory = web.Server(handler, loop=self.loop)
+        if path is not None:
+            if os.path.exists(path):
+                os.unlink(path)
+            self._server = await self.loop.create_unix_server(
+                self._factory, path)
+            return
         self._server = await self.loop.create_server(
             self._factory, '127.0.0.1', port,
+            ssl=self._ssl,
             reuse_address=True,
             reuse_port=hasattr(socket, 'SO_REUSEPORT'))
 
@@ -759,6 +805,89 @@ def proxy_server(request, swindon, loop):
     return _ServerWrapper
 
 
+@pytest.fixture(scope='module')
+def tls_proxy_server(swindon, swindon_ports, loop, TESTS_DIR):
+    """Upstream server that requires TLS with a client certificate"""
+    tls_dir = pathlib.Path(TESTS_DIR) / 'tls'
+    ctx = ssl.create_default_context(ssl.Purpose.CLIENT_AUTH,
+                                     cafile=str(tls_dir / 'ca.crt'))
+    ctx.load_cert_chain(str(tls_dir / 'server.crt'),
+                        str(tls_dir / 'server.key'))
+    ctx.verify_mode = ssl.CERT_REQUIRED
+    server = AiohttpServer(loop, ssl=ctx)
+
+    class _ServerWrapper:
+        def __init__(self, port=swindon_ports['default']['tls_proxy']):
+            self.port = port
+
+        async def __aenter__(self):
+            await server.start_server(port=self.port)
+            await server.__aenter__()
+            return self
+
+        __aexit__ = server.__aexit__
+        send = server.send
+
+    return _ServerWrapper
+
+
+@pytest.fixture(scope='module')
+def unix_proxy_server(swindon, swindon_ports, loop):
+    """Upstream server listening on a unix socket"""
+    server = AiohttpServer(loop)
+
+    class _ServerWrapper:
+        def __init__(self):
+            self.path = unix_proxy_path(swindon_ports['default']['proxy'])
+
+        async def __aenter__(self):
+            await server.start_server(port=None, path=self.path)
+            await server.__aenter__()
+            return self
+
+        __aexit__ = server.__aexit__
+        send = server.send
+
+    return _ServerWrapper
+
+
+LDAP_ENTRIES = {
+    'uid=john,ou=People,dc=example,dc=org': {
+        'objectClass': ['person'],
+        'uid': ['john'],
+        'userPassword': ['secret'],
+    },
+    'uid=jane,ou=People,dc=example,dc=org': {
+        'objectClass': ['person'],
+        'uid': ['jane'],
+        'userPassword': ['qwerty'],
+    },
+    'cn=admins,cn=Group,dc=example,dc=org': {
+        'objectClass': ['group'],
+        'cn': ['admins'],
+        'member': ['uid=john,ou=People,dc=example,dc=org'],
+    },
+    'cn=devs,cn=Group,dc=example,dc=org': {
+        'objectClass': ['group'],
+        'cn': ['devs'],
+        'member': ['uid=john,ou=People,dc=example,dc=org',
+                   'uid=jane,ou=People,dc=example,dc=org'],
+    },
+}
+
+
+@pytest.fixture(scope='module')
+def ldap_server(loop, swindon_ports):
+    """In-process LDAP server listening on address from swindon config"""
+    server = LdapServer(loop, LDAP_ENTRIES)
+    loop.run_until_complete(
+        server.start_server(swindon_ports['default']['ldap']))
+    try:
+        yield server
+    finally:
+        loop.run_until_complete(server.stop_server())
+
+
 @pytest.fixture(scope='module')
 def loop():
     loop = asyncio.new_event_loop()
@@ -0,0 +1,191 @@
+"""In-process LDAP server stub
+
+Implements just enough of LDAPv3 for swindon's ``!Ldap`` authorizer:
+simple bind, search (and, or, not, equality and presence filters),
+compare and unbind.
+"""
+import asyncio
+
+
+SUCCESS = 0
+NO_SUCH_OBJECT = 32
+COMPARE_FALSE = 5
+COMPARE_TRUE = 6
+INVALID_CREDENTIALS = 49
+PROTOCOL_ERROR = 2
+
+
+def encode_length(n):
+    if n < 0x80:
+        return bytes([n])
+    data = n.to_bytes((n.bit_length() + 7) // 8, 'big')
+    return bytes([0x80 | len(data)]) + data
+
+
+def tlv(tag, body):
+    return bytes([tag]) + encode_length(len(body)) + body
+
+
+def integer(tag, value):
+    length = max(1, (value.bit_length() + 8) // 8)
+    return tlv(tag, value.to_bytes(length, 'big', signed=True))
+
+
+def octets(value, tag=0x04):
+    if isinstance(value, str):
+        value = value.encode('utf-8')
+    return tlv(tag, value)
+
+
+def sequence(*items, tag=0x30):
+    return tlv(tag, b''.join(items))
+
+
+def parse(data):
+    """Returns (tag, body, rest) or None if there is not enough data"""
+    if len(data) < 2:
+        return None
+    tag, length, offset = data[0], data[1], 2
+    if length & 0x80:
+        nbytes = length & 0x7F
+        if len(data) < 2 + nbytes:
+            return None
+        length = int.from_bytes(data[2:2+nbytes], 'big')
+        offset += nbytes
+    if len(data) < offset + length:
+        return None
+    return tag, data[offset:offset+length], data[offset+length:]
+
+
+def children(data):
+    result = []
+    while data:
+        tag, body, data = parse(data)
+        result.append((tag, body))
+    return result
+
+
+def ldap_result(tag, code, message=''):
+    return sequence(
+        integer(0x0a, code),
+        octets(''),
+        octets(message),
+        tag=tag)
+
+
+class LdapServer:
+
+    def __init__(self, loop, entries):
+        self.loop = loop
+        # dn -> {attribute: [values]}
+        self.entries = entries
+        self.requests = []
+        self._server = None
+
+    async def start_server(self, port):
+        assert self._server is None
+        self._server = await asyncio.start_server(
+            self._connection, '127.0.0.1', port,
+            loop=self.loop, reuse_address=True)
+
+    async def stop_server(self):
+        server, self._server = self._server, None
+        server.close()
+        await server.wait_closed()
+
+    async def _connection(self, reader, writer):
+        buf = b''
+        try:
+            while True:
+                parsed = parse(buf)
+                if parsed is None:
+                    chunk = await reader.read(4096)
+                    if not chunk:
+                        return
+                    buf += chunk
+                    continue
+                _, body, buf = parsed
+                items = children(body)
+                msgid = int.from_bytes(items[0][1], 'big', signed=True)
+                op_tag, op_body = items[1]
+                if op_tag == 0x42:  # unbind
+                    self.requests.append(('unbind',))
+                    return
+                for response in self._handle(op_tag, op_body):
+                    writer.write(sequence(integer(0x02, msgid), response))
+                await writer.drain()
+        finally:
+            writer.close()
+
+    def _handle(self, tag, body):
+        items = children(body)
+        if tag == 0x60:  # bind
+            dn = items[1][1].decode('utf-8')
+            password = items[2][1].decode('utf-8')
+            self.requests.append(('bind', dn))
+            entry = self._find(dn)
+            if entry is not None and password and \
+                    password in entry.get('userPassword', []):
+                yield ldap_result(0x61, SUCCESS)
+            else:
+                yield ldap_result(0x61, INVALID_CREDENTIALS)
+        elif tag == 0x63:  # search
+            base = items[0][1].decode('utf-8')
+            flt = items[6]
+            attrs = [a.decode('utf-8') for _, a in children(items[7][1])]
+            self.requests.append(('search', base))
+            for dn in sorted(self.entries):
+                if not dn.lower().endswith(base.lower()):
+                    continue
+                entry = self.entries[dn]
+                if not self._match(flt, entry):
+                    continue
+                values = [
+                    sequence(octets(name),
+                             sequence(*map(octets, vals), tag=0x31))
+                    for name, vals in sorted(entry.items())
+                    if any(name.lower() == a.lower() for a in attrs)
+                ]
+                yield sequence(octets(dn), sequence(*values), tag=0x64)
+            yield ldap_result(0x65, SUCCESS)
+        elif tag == 0x6E:  # compare
+            dn = items[0][1].decode('utf-8')
+            attr, value = [v.decode('utf-8') for _, v in children(items[1][1])]
+            self.requests.append(('compare', dn))
+            entry = self._find(dn)
+            if entry is None:
+                yield ldap_result(0x6F, NO_SUCH_OBJECT)
+            elif value in self._values(entry, attr):
+                yield ldap_result(0x6F, COMPARE_TRUE)
+            else:
+                yield ldap_result(0x6F, COMPARE_FALSE)
+        else:
+            yield ldap_result(0x78, PROTOCOL_ERROR, 'unsupported')
+
+    def _find(self, dn):
+        for name, entry in self.entries.items():
+            if name.lower() == dn.lower():
+                return entry
+        return None
+
+    def _values(self, entry, attr):
+        for name, values in entry.items():
+            if name.lower() == attr.lower():
+                return values
+        return []
+
+    def _match(self, flt, entry):
+        tag, body = flt
+        if tag == 0xA0:
+            return all(self._match(f, entry) for f in children(body))
+        elif tag == 0xA1:
+            return any(self._match(f, entry) for f in children(body))
+        elif tag == 0xA2:
+            return not self._match(children(body)[0], entry)
+        elif tag == 0xA3:
+            attr, value = [v.decode('utf-8') for _, v in children(body)]
+            return any(v.lower() == value.lower()
+                       for v in self._values(entry, attr))
+        elif tag == 0x87:
+            return bool(self._values(entry, body.decode('utf-8')))
+        raise NotImplementedError(hex(tag))
@@ -152,3 +152,130 @@ async def test_request_timeout(proxy_server, swindon, loop):
         with async_timeout.timeout(5, loop=loop):
             resp, _ = await client_resp
         assert resp.status == 502
+
+
+async def test_streaming_request(proxy_server, swindon):
+    url = swindon.url / 'proxy-w-streaming'
+    body = b'x' * (1 << 20)
+    async with proxy_server() as proxy:
+        handler = proxy.send('POST', url, data=body, timeout=5)
+
+        req = await handler.request()
+        assert req.headers['Content-Length'] == str(len(body))
+        assert await req.read() == body
+        resp, _ = await handler.response('OK')
+        assert resp.status == 200
+
+
+async def test_streaming_request_chunked(proxy_server, swindon, loop):
+    url = swindon.url / 'proxy-w-streaming'
+    tail = asyncio.Event(loop=loop)
+
+    async def body():
+        yield b'first chunk;'
+        await tail.wait()
+        yield b'second chunk'
+
+    async with proxy_server() as proxy:
+        handler = proxy.send('POST', url, data=body(), timeout=5)
+
+        # backend gets the request before client sent the whole body
+        req = await handler.request()
+        assert req.headers['Transfer-Encoding'] == 'chunked'
+        assert await req.content.readexactly(12) == b'first chunk;'
+        tail.set()
+        assert await req.read() == b'second chunk'
+        resp, _ = await handler.response('OK')
+        assert resp.status == 200
+
+
+async def test_big_response(proxy_server, swindon):
+    url = swindon.url / 'proxy/big'
+    body = b'x' * (12 << 20)
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=10)
+        req = await handler.request()
+        assert req.path == '/proxy/big'
+
+        resp, data = await handler.response(body)
+        assert resp.status == 200
+        assert resp.headers['Content-Length'] == str(len(body))
+        assert data == body
+
+
+async def test_tls_upstream(tls_proxy_server, swindon):
+    url = swindon.url / 'proxy-w-tls/hello'
+    async with tls_proxy_server() as proxy:
+        handler = proxy.send('POST', url, data=b'Some body', timeout=5)
+
+        req = await handler.request()
+        assert req.path == '/proxy-w-tls/hello'
+        assert req.secure
+        assert await req.read() == b'Some body'
+        resp, body = await handler.response(b'OK')
+        assert resp.status == 200
+        assert body == b'OK'
+
+
+async def test_unix_upstream(unix_proxy_server, swindon):
+    url = swindon.url / 'proxy-w-unix/hello'
+    async with unix_proxy_server() as proxy:
+        handler = proxy.send('POST', url, data=b'Some body', timeout=5)
+
+        req = await handler.request()
+        assert req.path == '/proxy-w-unix/hello'
+        assert await req.read() == b'Some body'
+        resp, body = await handler.response(b'OK')
+        assert resp.status == 200
+        assert body == b'OK'
+
+
+async def test_retry_refused(proxy_server, swindon):
+    # first address of the destination refuses connections, so every
+    # request ends up at the second one
+    url = swindon.url / 'proxy-w-retry/hello'
+    async with proxy_server() as proxy:
+        for i in range(4):
+            handler = proxy.send('GET', url, timeout=5)
+            req = await handler.request()
+            assert req.path == '/proxy-w-retry/hello'
+            resp, body = await handler.response(b'OK')
+            assert resp.status == 200
+            assert body == b'OK'
+
+
+async def test_no_retry_unsafe(proxy_server, swindon):
+    url = swindon.url / 'proxy-w-retry/hello'
+    async with proxy_server() as proxy:
+        handler = proxy.send('POST', url, data=b'Some body', timeout=5)
+        req = await handler.request()
+        assert await req.read() == b'Some body'
+        resp, body = await handler.response(b'Backend is down', status=503)
+        assert resp.status == 503
+        assert body == b'Backend is down'
+
+
+async def test_zero_weight(proxy_server, swindon):
+    # address with zero weight is only used if nothing else is left
+    url = swindon.url / 'proxy-w-weights/hello'
+    async with proxy_server() as proxy:
+        for i in range(4):
+            handler = proxy.send('POST', url, data=b'body', timeout=5)
+            req = await handler.request()
+            assert req.path == '/proxy-w-weights/hello'
+            resp, body = await handler.response(b'OK')
+            assert resp.status == 200
+            assert body == b'OK'
+
+
+async def test_hash_by_cookie(proxy_server, swindon):
+    url = swindon.url / 'proxy-w-hash/hello'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5,
+                             headers={'Cookie': 'a=b; session=123'})
+        req = await handler.request()
+        assert req.path == '/proxy-w-hash/hello'
+        assert req.cookies['session'] == '123'
+        resp, body = await handler.response(b'OK')
+        assert resp.status == 200
+        assert body == b'OK'
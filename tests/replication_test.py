@@ -176,6 +176,115 @@ async def test_topic_unsubscribe(swindon_two, proxy_server, loop,
                 assert await ws.receive_json() is None
 
 
+@pytest.mark.parametrize("through", ["peerA", "peerB"])
+async def test_prefix_subscribe(swindon_two, proxy_server, loop,
+                                user_id, through):
+    peerA, peerB = swindon_two
+    if through == 'peerA':
+        control = peerA
+    else:
+        control = peerB
+    urlA = peerA.url / 'swindon-lattice'
+    async with proxy_server(port=peerA.proxy.port) as proxy:
+        handlerA = proxy.swindon_lattice(urlA, timeout=1)
+        cid, ws = await auth(handlerA, {"user_id": user_id})
+
+        topic_url = control.api3 / 'v1/connection'
+        topic_url = topic_url / cid / 'subscriptions/orders/*'
+        await put(topic_url, loop)
+        await asyncio.sleep(.05, loop=loop)
+
+        data = b'{"order": 1}'
+        await post(peerB.api3 / 'v1/publish/orders/1', data, loop)
+        msg = await ws.receive_json()
+        assert msg == ["message", {"topic": "orders.1"}, {"order": 1}]
+
+        await delete(topic_url, loop)
+        await asyncio.sleep(.05, loop=loop)
+
+        await post(peerA.api3 / 'v1/publish/orders/2', data, loop)
+        await post(peerB.api3 / 'v1/publish/orders/2', data, loop)
+        with pytest.raises(asyncio.TimeoutError):
+            with timeout(1, loop=loop):
+                assert await ws.receive_json() is None
+
+
+async def test_direct_session_message(swindon_two, proxy_server, loop,
+                                      user_id):
+    peerA, peerB = swindon_two
+    urlA = peerA.url / 'swindon-lattice'
+    urlB = peerB.url / 'swindon-lattice'
+    async with proxy_server(port=peerA.proxy.port) as proxy:
+        handlerA = proxy.swindon_lattice(urlA, timeout=1)
+        cid1, ws1 = await auth(handlerA, {"user_id": user_id})
+        handlerB = proxy.swindon_lattice(urlB, timeout=1)
+        cid2, ws2 = await auth(handlerB, {"user_id": user_id})
+
+        url = peerA.api3 / 'v1/session' / user_id / 'message'
+        await post(url, b'{"hello": "session"}', loop)
+        url = peerA.api3 / 'v1/connection' / cid2 / 'message'
+        await post(url, b'{"hello": "connection"}', loop)
+
+        with timeout(1, loop=loop):
+            assert await ws1.receive_json() == [
+                'direct', {}, {'hello': 'session'}]
+            assert await ws2.receive_json() == [
+                'direct', {}, {'hello': 'session'}]
+            assert await ws2.receive_json() == [
+                'direct', {}, {'hello': 'connection'}]
+        # cid2 belongs to peerB, so peerA must not deliver it locally
+        with pytest.raises(asyncio.TimeoutError):
+            with timeout(0.5, loop=loop):
+                assert await ws1.receive_json() is None
+
+
+async def test_batch(swindon_two, proxy_server, loop, user_id):
+    peerA, peerB = swindon_two
+    urlA = peerA.url / 'swindon-lattice'
+    async with proxy_server(port=peerA.proxy.port) as proxy:
+        handlerA = proxy.swindon_lattice(urlA, timeout=1)
+        cid, ws = await auth(handlerA, {"user_id": user_id})
+
+        data = json.dumps([
+            {'method': 'PUT',
+             'path': '/v1/connection/{}/subscriptions/batch/2'.format(cid)},
+            {'method': 'POST', 'path': '/v1/publish/batch/2',
+             'body': {'order': 1}},
+        ])
+        async with ClientSession(loop=loop) as s:
+            async with s.post(peerB.api3 / 'v1/batch', headers=H,
+                              data=data) as resp:
+                assert resp.status == 200
+
+        with timeout(1, loop=loop):
+            msg = await ws.receive_json()
+            assert msg == ["message", {"topic": "batch.2"}, {"order": 1}]
+
+
+async def test_close_session(swindon_two, proxy_server, loop, user_id):
+    peerA, peerB = swindon_two
+    urlA = peerA.url / 'swindon-lattice'
+    urlB = peerB.url / 'swindon-lattice'
+    async with proxy_server(port=peerA.proxy.port) as proxy:
+        handlerA = proxy.swindon_lattice(urlA, timeout=1)
+        cid1, ws1 = await auth(handlerA, {"user_id": user_id})
+        handlerB = proxy.swindon_lattice(urlB, timeout=1)
+        cid2, ws2 = await auth(handlerB, {"user_id": user_id})
+
+        async with ClientSession(loop=loop) as s:
+            url = peerA.api3 / 'v1/session' / user_id
+            data = b'{"code": 4003, "reason": "banned"}'
+            async with s.delete(url, data=data) as resp:
+                assert resp.status == 204
+
+        for ws in [ws1, ws2]:
+            with timeout(1, loop=loop):
+                msg = await ws.receive()
+            assert msg.type == aiohttp.WSMsgType.CLOSE
+            assert msg.data == 4003
+            assert msg.extra == 'banned'
+
+
 @pytest.mark.parametrize('by_user_id', [True, False])
 async def test_swindon_user(
         proxy_server, swindon_two, loop,
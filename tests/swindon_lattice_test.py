@@ -2,6 +2,7 @@ import pytest
 import aiohttp
 import json
 import asyncio
+import msgpack
 import re
 
 from unittest import mock
@@ -100,6 +101,53 @@ async def test_backend_errors(proxy_server, swindon, user_id,
         assert msg == ["error", meta, data]
 
 
+async def test_msgpack_protocol(proxy_server, swindon, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1, protocols=[
+            'v1.swindon-lattice+msgpack', 'v1.swindon-lattice+json'])
+        req = await handler.request()
+        assert_auth(req)
+        ws = await handler.json_response({"user_id": user_id})
+        assert ws.protocol == 'v1.swindon-lattice+msgpack'
+        msg = msgpack.unpackb(await ws.receive_bytes(), raw=False)
+        assert msg == ['hello', {}, {'user_id': user_id}]
+
+        await ws.send_bytes(msgpack.packb(
+            ['chat.echo_message', {'request_id': '1'}, ['hello'], {}],
+            use_bin_type=True))
+        req = await handler.request()
+        assert req.path == '/chat/echo_message'
+        # backend still receives json
+        assert_headers(req)
+        assert await req.json() == [
+            {'request_id': '1', 'connection_id': mock.ANY},
+            ['hello'],
+            {},
+        ]
+        await handler.json_response({'echo': "hello"})
+        msg = msgpack.unpackb(await ws.receive_bytes(), raw=False)
+        assert msg == ['result', {'request_id': '1'}, {'echo': "hello"}]
+
+
+async def test_binary_without_msgpack(proxy_server, swindon, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        ws = await handler.json_response({"user_id": user_id})
+        assert ws.protocol == 'v1.swindon-lattice+json'
+        msg = await ws.receive_json()
+        assert msg == ['hello', {}, {'user_id': user_id}]
+
+        await ws.send_bytes(msgpack.packb(
+            ['chat.echo_message', {'request_id': '1'}, [], {}],
+            use_bin_type=True))
+        msg = await ws.receive()
+        assert msg.type == WSMsgType.CLOSE
+
+
 @pytest.mark.xfail(reason="shutdown is not implemented yet")
 async def test_ws_close_timeout(proxy_server, swindon, user_id, loop):
     url = swindon.url / 'swindon-lattice'
@@ -398,6 +446,48 @@ async def test_topic_subscribe_publish(proxy_server, swindon, loop, user_id):
         assert msg == ['message', {'topic': 'some.topic'}, 'other message']
 
 
+async def test_prefix_subscribe(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        meta, args, kwargs = await req.json()
+        cid = meta['connection_id']
+        ws = await handler.json_response({"user_id": user_id})
+        hello = await ws.receive_json()
+        assert hello == ['hello', {}, {'user_id': user_id}]
+
+        headers = {'Content-Type': 'application/json'}
+        sub_url = swindon.api3 / 'v1/connection' / cid / 'subscriptions'
+        async with aiohttp.ClientSession(loop=loop) as s:
+            async with s.put(sub_url / 'orders/*') as resp:
+                assert resp.status == 204
+            async with s.put(sub_url / 'orders/1') as resp:
+                assert resp.status == 204
+            for topic in ['orders', 'orders/1', 'orders/2/items']:
+                publish_url = swindon.api3 / 'v1/publish' / topic
+                async with s.post(publish_url, headers=headers,
+                                  data=b'null') as resp:
+                    assert resp.status == 204
+
+        # connection is subscribed to `orders.1` twice but gets it once
+        assert await ws.receive_json() == [
+            'message', {'topic': 'orders.1'}, None]
+        assert await ws.receive_json() == [
+            'message', {'topic': 'orders.2.items'}, None]
+
+        async with aiohttp.ClientSession(loop=loop) as s:
+            async with s.delete(sub_url / 'orders/*') as resp:
+                assert resp.status == 204
+            async with s.post(swindon.api3 / 'v1/publish/orders/2',
+                              headers=headers, data=b'null') as resp:
+                assert resp.status == 204
+        with pytest.raises(asyncio.TimeoutError):
+            with timeout(0.5):
+                await ws.receive_json()
+
+
 async def test_lattice_subscribe_update(proxy_server, swindon, loop, user_id):
     url = swindon.url / 'swindon-lattice'
     async with proxy_server() as proxy:
@@ -638,6 +728,343 @@ async def test_lattice_register(proxy_server, swindon, loop, user_id,
         ]
 
 
+async def test_lattice_orset(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        meta, args, kwargs = await req.json()
+        cid = meta['connection_id']
+        u = swindon.api3 / 'v1/connection' / cid / 'lattices'
+        u = u / 'lattice/namespace'
+        room_id = 'room:{}'.format(user_id)
+
+        async with aiohttp.ClientSession(loop=loop) as s:
+            data = json.dumps({
+                'shared': {
+                    room_id: {'members_orset': {
+                        'alice': {'added': ['a1']},
+                        'bob': {'added': ['b1']},
+                    }},
+                },
+                'private': {
+                    user_id: {room_id: {}},
+                },
+            })
+            async with s.put(u, headers={"Content-Type": "application/json"},
+                             data=data) as resp:
+                assert resp.status == 204
+
+        ws = await handler.json_response({
+            "user_id": user_id, "username": "Jim"})
+        hello = await ws.receive_json()
+        assert hello == [
+            'hello', {}, {'user_id': user_id, 'username': 'Jim'}]
+        up = await ws.receive_json()
+        assert up == [
+            'lattice',
+            {'namespace': 'lattice.namespace'},
+            {room_id: {'members_orset': {
+                'alice': {'added': ['a1']},
+                'bob': {'added': ['b1']},
+            }}},
+        ]
+
+        async with aiohttp.ClientSession(loop=loop) as s:
+            # first update is outdated, so only removal is sent
+            for update in [{'added': ['a1']}, {'removed': ['a1']}]:
+                data = json.dumps({
+                    'shared': {
+                        room_id: {'members_orset': {'alice': update}},
+                    },
+                })
+                async with s.put(u, data=data, headers={
+                        'Content-Type': 'application/json'}) as resp:
+                    assert resp.status == 204
+
+        up = await ws.receive_json()
+        assert up == [
+            'lattice',
+            {'namespace': 'lattice.namespace'},
+            {room_id: {'members_orset': {'alice': {'removed': ['a1']}}}},
+        ]
+
+
+async def test_lattice_pncounter_map(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        meta, args, kwargs = await req.json()
+        cid = meta['connection_id']
+        u = swindon.api3 / 'v1/connection' / cid / 'lattices'
+        u = u / 'lattice/namespace'
+        room_id = 'room:{}'.format(user_id)
+
+        async with aiohttp.ClientSession(loop=loop) as s:
+            data = json.dumps({
+                'shared': {
+                    room_id: {'roles_map': {'alice': [1, 'admin']}},
+                },
+                'private': {
+                    user_id: {room_id: {
+                        'unread_pncounter': {'p': {'db1': 5}},
+                    }},
+                },
+            })
+            async with s.put(u, headers={"Content-Type": "application/json"},
+                             data=data) as resp:
+                assert resp.status == 204
+
+        ws = await handler.json_response({
+            "user_id": user_id, "username": "Jim"})
+        hello = await ws.receive_json()
+        assert hello == [
+            'hello', {}, {'user_id': user_id, 'username': 'Jim'}]
+        up = await ws.receive_json()
+        assert up == [
+            'lattice',
+            {'namespace': 'lattice.namespace'},
+            {room_id: {
+                'roles_map': {'alice': [1.0, 'admin']},
+                'unread_pncounter': {'p': {'db1': 5}, 'n': {}},
+            }},
+        ]
+
+        async with aiohttp.ClientSession(loop=loop) as s:
+            data = json.dumps({
+                'shared': {
+                    room_id: {'roles_map': {'alice': [2, None]}},
+                },
+                'private': {
+                    user_id: {room_id: {
+                        'unread_pncounter': {'n': {'db1': 2}},
+                    }},
+                },
+            })
+            async with s.put(u, headers={"Content-Type": "application/json"},
+                             data=data) as resp:
+                assert resp.status == 204
+
+        up = await ws.receive_json()
+        assert up == [
+            'lattice',
+            {'namespace': 'lattice.namespace'},
+            {room_id: {
+                'roles_map': {'alice': [2.0, None]},
+                'unread_pncounter': {'p': {}, 'n': {'db1': 2}},
+            }},
+        ]
+
+
+async def test_direct_messages(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        meta, args, kwargs = await req.json()
+        cid = meta['connection_id']
+        ws = await handler.json_response({"user_id": user_id})
+        hello = await ws.receive_json()
+        assert hello == ['hello', {}, {'user_id': user_id}]
+
+        headers = {'Content-Type': 'application/json'}
+        async with aiohttp.ClientSession(loop=loop) as s:
+            msg_url = swindon.api3 / 'v1/connection' / cid / 'message'
+            async with s.post(msg_url, headers=headers,
+                              data=b'{"to": "connection"}') as resp:
+                assert resp.status == 204
+            msg_url = swindon.api3 / 'v1/session' / user_id / 'message'
+            async with s.post(msg_url, headers=headers,
+                              data=b'{"to": "session"}') as resp:
+                assert resp.status == 204
+            async with s.post(msg_url, headers=headers,
+                              data=b'invalid') as resp:
+                assert resp.status == 400
+
+        assert await ws.receive_json() == [
+            'direct', {}, {'to': 'connection'}]
+        assert await ws.receive_json() == [
+            'direct', {}, {'to': 'session'}]
+
+
+async def test_close_connection(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        meta, args, kwargs = await req.json()
+        cid = meta['connection_id']
+        ws = await handler.json_response({"user_id": user_id})
+        hello = await ws.receive_json()
+        assert hello == ['hello', {}, {'user_id': user_id}]
+
+        close_url = swindon.api3 / 'v1/connection' / cid
+        async with aiohttp.ClientSession(loop=loop) as s:
+            async with s.delete(close_url, data=b'{"code": 1}') as resp:
+                assert resp.status == 400
+            async with s.delete(close_url,
+                    data=b'{"code": 4003, "reason": "banned"}') as resp:
+                assert resp.status == 204
+        msg = await ws.receive()
+        assert msg.type == WSMsgType.CLOSE
+        assert msg.data == 4003
+        assert msg.extra == 'banned'
+
+
+async def test_close_session(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        sockets = []
+        for _ in range(2):
+            handler = proxy.swindon_lattice(url, timeout=1)
+            req = await handler.request()
+            assert_auth(req)
+            ws = await handler.json_response({"user_id": user_id})
+            hello = await ws.receive_json()
+            assert hello == ['hello', {}, {'user_id': user_id}]
+            sockets.append(ws)
+
+        async with aiohttp.ClientSession(loop=loop) as s:
+            close_url = swindon.api3 / 'v1/session' / user_id
+            async with s.delete(close_url) as resp:
+                assert resp.status == 204
+        for ws in sockets:
+            msg = await ws.receive()
+            assert msg.type == WSMsgType.CLOSE
+            assert msg.data == 1000
+            assert msg.extra == 'disconnected'
+
+
+async def test_introspection(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        meta, args, kwargs = await req.json()
+        cid = meta['connection_id']
+        ws = await handler.json_response({"user_id": user_id})
+        hello = await ws.receive_json()
+        assert hello == ['hello', {}, {'user_id': user_id}]
+
+        headers = {'Content-Type': 'application/json'}
+        topic = 'inspect/{}'.format(user_id)
+        room_id = 'room:{}'.format(user_id)
+        conn_url = swindon.api3 / 'v1/connection' / cid
+        async with aiohttp.ClientSession(loop=loop) as s:
+            async with s.put(conn_url / 'subscriptions' / topic) as resp:
+                assert resp.status == 204
+            async with s.put(conn_url / 'subscriptions/inspect/*') as resp:
+                assert resp.status == 204
+            data = json.dumps({
+                'shared': {room_id: {'last_message_counter': 1}},
+                'private': {user_id: {room_id: {'last_seen_counter': 1}}},
+            })
+            async with s.put(conn_url / 'lattices/inspect/rooms',
+                             headers=headers, data=data) as resp:
+                assert resp.status == 204
+
+            async with s.get(conn_url) as resp:
+                assert resp.status == 200
+                assert await resp.json() == {
+                    'session_id': user_id,
+                    'topics': ['inspect.' + user_id],
+                    'prefixes': ['inspect'],
+                    'lattices': ['inspect.rooms'],
+                    'users_lattice': False,
+                }
+            async with s.get(swindon.api3 / 'v1/session' / user_id) as resp:
+                assert resp.status == 200
+                info = await resp.json()
+                assert info['connections'] == [cid]
+                assert info['lattices'] == ['inspect.rooms']
+                assert info['metadata'] == {'user_id': user_id}
+            async with s.get(swindon.api3 / 'v1/topic' / topic) as resp:
+                assert resp.status == 200
+                assert await resp.json() == {
+                    'subscribers': 1, 'wildcard_subscribers': 0}
+            lattice_url = swindon.api3 / 'v1/lattice/inspect/rooms'
+            async with s.get(lattice_url.with_query(
+                    session_id=user_id)) as resp:
+                assert resp.status == 200
+                info = await resp.json()
+                assert info['shared'][room_id] == {
+                    'last_message_counter': 1}
+                assert info['private'] == {
+                    user_id: {room_id: {'last_seen_counter': 1}}}
+
+            async with s.get(swindon.api3 / 'v1/session/u:unknown') as resp:
+                assert resp.status == 404
+            async with s.get(swindon.api3 / 'v1/lattice/unknown') as resp:
+                assert resp.status == 404
+
+
+async def test_batch(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        meta, args, kwargs = await req.json()
+        cid = meta['connection_id']
+        ws = await handler.json_response({"user_id": user_id})
+        hello = await ws.receive_json()
+        assert hello == ['hello', {}, {'user_id': user_id}]
+
+        headers = {'Content-Type': 'application/json'}
+        room_id = 'room:{}'.format(user_id)
+        conn_path = '/v1/connection/{}'.format(cid)
+        batch_url = swindon.api3 / 'v1/batch'
+        async with aiohttp.ClientSession(loop=loop) as s:
+            # the last operation is invalid, so nothing is applied
+            data = json.dumps([
+                {'method': 'PUT',
+                 'path': conn_path + '/subscriptions/batch/room'},
+                {'method': 'PUT', 'path': conn_path + '/subscriptions/a.b'},
+            ])
+            async with s.post(batch_url, headers=headers,
+                              data=data) as resp:
+                assert resp.status == 400
+                assert await resp.json() == [
+                    {'status': 204}, {'status': 404}]
+
+            data = json.dumps([
+                {'method': 'PUT',
+                 'path': conn_path + '/subscriptions/batch/room'},
+                {'method': 'PUT',
+                 'path': conn_path + '/lattices/batch/rooms',
+                 'body': {
+                     'shared': {room_id: {'last_message_counter': 1}},
+                     'private': {},
+                 }},
+                {'method': 'POST', 'path': '/v1/publish/batch/room',
+                 'body': {'hello': 'room'}},
+            ])
+            async with s.post(batch_url, headers=headers,
+                              data=data) as resp:
+                assert resp.status == 200
+                assert await resp.json() == [
+                    {'status': 204}, {'status': 204}, {'status': 204}]
+
+            async with s.post(batch_url, headers=headers,
+                              data=b'{}') as resp:
+                assert resp.status == 400
+
+        assert await ws.receive_json() == [
+            'lattice', {'namespace': 'batch.rooms'},
+            {room_id: {'last_message_counter': 1}}]
+        assert await ws.receive_json() == [
+            'message', {'topic': 'batch.room'}, {'hello': 'room'}]
+        with pytest.raises(asyncio.TimeoutError):
+            with timeout(0.5):
+                await ws.receive_json()
+
+
 @pytest.mark.parametrize('path', [
     '', '/vvvv', '/v1/',
     '/v1/connection',
@@ -888,3 +1315,94 @@ async def test_swindon_user(proxy_server, swindon, loop,
             {'namespace': 'swindon.user'},
             {user_id: {'status_register': [mock.ANY, 'active']},
              user_id2: {'status_register': [mock.ANY, 'active']}}]
+
+
+async def test_rate_limits(proxy_server, swindon, user_id):
+    url = swindon.url / 'swindon-lattice-w-rate-limits'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        ws = await handler.json_response({"user_id": user_id})
+        hello = await ws.receive_json()
+        assert hello == ['hello', {}, {'user_id': user_id}]
+
+        await ws.send_json(['chat.typing', {'request_id': '1'}, [], {}])
+        req = await handler.request()
+        assert req.path == '/chat/typing'
+        await handler.json_response(True)
+        assert await ws.receive_json() == ['result', {'request_id': '1'}, True]
+
+        # method limit is exhausted, connection limit isn't
+        await ws.send_json(['chat.typing', {'request_id': '2'}, [], {}])
+        assert await ws.receive_json() == [
+            'error', {'request_id': '2', 'error_kind': 'rate_limit'},
+            'rate_limit_exceeded']
+
+        await ws.send_json(['chat.send', {'request_id': '3'}, [], {}])
+        req = await handler.request()
+        assert req.path == '/chat/send'
+        await handler.json_response(True)
+        assert await ws.receive_json() == ['result', {'request_id': '3'}, True]
+
+        # second rejected call in a row closes the connection
+        for request_id in ['4', '5']:
+            await ws.send_json(['chat.send', {'request_id': request_id},
+                                [], {}])
+            assert await ws.receive_json() == [
+                'error',
+                {'request_id': request_id, 'error_kind': 'rate_limit'},
+                'rate_limit_exceeded']
+
+        msg = await ws.receive()
+        assert msg.type == WSMsgType.CLOSE
+        assert msg.data == 1008
+        assert msg.extra == 'rate_limit'
+
+
+async def test_topic_history(proxy_server, swindon, loop, user_id):
+    url = swindon.url / 'swindon-lattice-w-timeouts'
+    async with proxy_server() as proxy:
+        handler = proxy.swindon_lattice(url, timeout=1)
+        req = await handler.request()
+        assert_auth(req)
+        meta, args, kwargs = await req.json()
+        cid = meta['connection_id']
+        ws = await handler.json_response({"user_id": user_id})
+        hello = await ws.receive_json()
+        assert hello == ['hello', {}, {'user_id': user_id}]
+
+        headers = {'Content-Type': 'application/json'}
+        sub_url = swindon.api4 / 'v1/connection' / cid / 'subscriptions'
+        sub_url = sub_url / 'history/topic'
+        publish_url = swindon.api4 / 'v1/publish' / 'history/topic'
+        async with aiohttp.ClientSession(loop=loop) as s:
+            async with s.put(sub_url) as resp:
+                assert resp.status == 204
+            for data in [b'"first"', b'"second"', b'"third"']:
+                async with s.post(publish_url, headers=headers,
+                                  data=data) as resp:
+                    assert resp.status == 204
+        seqs = []
+        for text in ['first', 'second', 'third']:
+            kind, meta, data = await ws.receive_json()
+            assert kind == 'message'
+            assert meta['topic'] == 'history.topic'
+            assert data == text
+            seqs.append(meta['seq'])
+        assert seqs == sorted(seqs)
+
+        # only two messages are kept, so the first one can't be replayed
+        async with aiohttp.ClientSession(loop=loop) as s:
+            url = sub_url.with_query({'since': str(seqs[0] - 1)})
+            async with s.put(url) as resp:
+                assert resp.status == 204
+        assert await ws.receive_json() == [
+            'message', {'topic': 'history.topic', 'seq': seqs[1]}, 'second']
+        assert await ws.receive_json() == [
+            'message', {'topic': 'history.topic', 'seq': seqs[2]}, 'third']
+
+        async with aiohttp.ClientSession(loop=loop) as s:
+            url = sub_url.with_query({'since': 'invalid'})
+            async with s.put(url) as resp:
+                assert resp.status == 400
@@ -0,0 +1,25 @@
+import pathlib
+import ssl
+
+import pytest
+import yarl
+
+
+@pytest.fixture
+def tls_url(swindon_ports):
+    port = swindon_ports['default']['tls_main']
+    return yarl.URL('https://localhost:{}'.format(port))
+
+
+@pytest.fixture
+def client_ssl(TESTS_DIR):
+    return ssl.create_default_context(
+        cafile=str(pathlib.Path(TESTS_DIR) / 'tls' / 'listen.crt'))
+
+
+async def test_tls_listen(swindon, http_request, tls_url, client_ssl):
+    resp, data = await http_request(tls_url / 'empty.gif', ssl=client_ssl)
+    assert resp.status == 200
+    assert resp.headers['Content-Type'] == 'image/gif'
+    assert len(data) == 26
+
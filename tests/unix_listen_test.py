@@ -0,0 +1,17 @@
+import os
+import stat
+
+import aiohttp
+
+
+async def test_unix_listen(swindon, swindon_unix_path, loop):
+    path = swindon_unix_path
+    assert stat.S_ISSOCK(os.stat(path).st_mode)
+    assert stat.S_IMODE(os.stat(path).st_mode) == 0o660
+    conn = aiohttp.UnixConnector(path=path, loop=loop)
+    async with aiohttp.ClientSession(connector=conn, loop=loop) as s:
+        async with s.get('http://localhost/empty.gif') as resp:
+            assert resp.status == 200
+            assert resp.headers['Content-Type'] == 'image/gif'
+            data = await resp.read()
+            assert len(data) == 26
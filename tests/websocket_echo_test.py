@@ -22,3 +22,33 @@ async def test_echo_chat(swindon, loop):
             await ws.ping()
             with pytest.raises(asyncio.TimeoutError):
                 assert not await ws.receive(timeout=.1)
+
+
+async def test_echo_deflate(swindon, loop):
+    url = swindon.url / 'websocket-echo-deflate'
+    async with aiohttp.ClientSession(loop=loop) as s:
+        async with s.ws_connect(url, compress=15) as ws:
+            assert ws.compress == 15
+            await ws.send_str('Hello')
+            assert await ws.receive_str() == 'Hello'
+
+            data = b'How are you?' * 10000
+            await ws.send_bytes(data)
+            assert await ws.receive_bytes() == data
+
+            await ws.send_json(["I'm", "fine", "thanks!"])
+            assert await ws.receive_json() == ["I'm", "fine", "thanks!"]
+
+        async with s.ws_connect(url) as ws:
+            assert ws.compress == 0
+            await ws.send_str('Hello')
+            assert await ws.receive_str() == 'Hello'
+
+
+async def test_echo_deflate_disabled(swindon, loop):
+    url = swindon.url / 'websocket-echo'
+    async with aiohttp.ClientSession(loop=loop) as s:
+        async with s.ws_connect(url, compress=15) as ws:
+            assert ws.compress == 0
+            await ws.send_str('Hello')
+            assert await ws.receive_str() == 'Hello'